@@ -1,151 +1,333 @@
-use anyhow::{Context, Result};
-use log::{debug, error, info};
-use sigma_tcp_rs::{ProtocolCommand, ProtocolHandler, ProtocolResponse};
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+use sigma_tcp_rs::auth::AuthToken;
+use sigma_tcp_rs::backend::{
+    parse_address_range, AllowlistBackend, Backend, CaptureBackend, CountingBackend, DebugBackend,
+    FileBackend, MemoryBackend, PatternBackend, ProxyBackend, ReadOnlyBackend, VerifyingBackend,
+};
+use sigma_tcp_rs::metrics::{serve_metrics_with, RawEndpoint};
+use sigma_tcp_rs::server::{bind_all, load_tls_acceptor, serve_listener_with, ServerOptions};
+use std::fs::OpenOptions;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
-
-mod backend;
-
-use backend::debug::{self, DebugBackend};
-use backend::Backend;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
 const PORT: u16 = 8086;
-const MAX_BUF_SIZE: usize = 2048;
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
+const METRICS_PORT: u16 = 9186;
 
-    let backend = Arc::new(Mutex::new(DebugBackend::new()));
+const USAGE: &str = "Usage: debug [--backend debug|pattern|memory|file|proxy] [--path <file>] \
+                     [--upstream <host:port>] [--read-only] [--max-transfer <bytes>] \
+                     [--read-range <start-end>]... [--write-range <start-end>]... \
+                     [--tls-cert <pem> --tls-key <pem>]";
 
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", PORT))
-        .await
-        .context("Failed to bind to port")?;
+/// Command line options
+struct Args {
+    backend: String,
+    path: Option<String>,
+    upstream: Option<String>,
+    read_only: bool,
+    max_transfer: Option<usize>,
+    read_ranges: Vec<RangeInclusive<u16>>,
+    write_ranges: Vec<RangeInclusive<u16>>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+}
 
-    info!("Waiting for connections on port {}...", PORT);
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut parsed = Self {
+            backend: "debug".to_string(),
+            path: None,
+            upstream: None,
+            read_only: false,
+            max_transfer: None,
+            read_ranges: env_ranges("SIGMA_TCP_READ_RANGES")?,
+            write_ranges: env_ranges("SIGMA_TCP_WRITE_RANGES")?,
+            tls_cert: None,
+            tls_key: None,
+        };
 
-    loop {
-        match listener.accept().await {
-            Ok((stream, addr)) => {
-                info!("New connection from {}", addr);
-                let backend = backend.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(stream, backend).await {
-                        error!("Error handling connection: {}", e);
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--backend" => {
+                    parsed.backend = args
+                        .next()
+                        .with_context(|| format!("--backend needs a value\n{}", USAGE))?;
+                }
+                "--path" => {
+                    parsed.path = Some(
+                        args.next()
+                            .with_context(|| format!("--path needs a value\n{}", USAGE))?,
+                    );
+                }
+                "--upstream" => {
+                    parsed.upstream = Some(
+                        args.next()
+                            .with_context(|| format!("--upstream needs a value\n{}", USAGE))?,
+                    );
+                }
+                "--read-only" => parsed.read_only = true,
+                "--max-transfer" => {
+                    let value = args
+                        .next()
+                        .with_context(|| format!("--max-transfer needs a value\n{}", USAGE))?;
+                    parsed.max_transfer = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("Invalid --max-transfer {}", value))?,
+                    );
+                }
+                "--read-range" | "--write-range" => {
+                    let value = args
+                        .next()
+                        .with_context(|| format!("{} needs a value\n{}", arg, USAGE))?;
+                    let range = parse_address_range(&value)?;
+                    if arg == "--read-range" {
+                        parsed.read_ranges.push(range);
+                    } else {
+                        parsed.write_ranges.push(range);
                     }
-                });
+                }
+                "--tls-cert" | "--tls-key" => {
+                    let value = args
+                        .next()
+                        .with_context(|| format!("{} needs a value\n{}", arg, USAGE))?;
+                    if arg == "--tls-cert" {
+                        parsed.tls_cert = Some(value.into());
+                    } else {
+                        parsed.tls_key = Some(value.into());
+                    }
+                }
+                other => bail!("Unknown argument {}\n{}", other, USAGE),
             }
-            Err(e) => {
-                error!("Failed to accept connection: {}", e);
+        }
+
+        Ok(parsed)
+    }
+
+    fn create_backend(&self) -> Result<Box<dyn Backend>> {
+        if self.upstream.is_some() && self.backend != "proxy" {
+            bail!("--upstream is only valid with --backend proxy");
+        }
+        // --max-transfer simula un dispositivo che accetta solo trasferimenti brevi
+        if self.max_transfer.is_some() && !matches!(self.backend.as_str(), "debug" | "memory") {
+            bail!("--max-transfer is only valid with --backend debug or memory");
+        }
+
+        let backend: Box<dyn Backend> = match (self.backend.as_str(), &self.path) {
+            ("debug", None) => match self.max_transfer {
+                Some(max) => Box::new(DebugBackend::new().max_transfer(max)),
+                None => Box::new(DebugBackend::new()),
+            },
+            ("pattern", None) => Box::new(PatternBackend::new()),
+            ("memory", None) => match self.max_transfer {
+                Some(max) => Box::new(MemoryBackend::with_max_transfer(max)),
+                None => Box::new(MemoryBackend::new()),
+            },
+            ("file", Some(path)) => Box::new(FileBackend::open(path)?),
+            ("file", None) => bail!("--backend file needs --path <file>"),
+            ("proxy", None) => match &self.upstream {
+                Some(upstream) => Box::new(ProxyBackend::new(upstream)),
+                None => bail!("--backend proxy needs --upstream <host:port>"),
+            },
+            ("debug" | "pattern" | "memory" | "proxy", Some(_)) => {
+                bail!("--path is only valid with --backend file")
             }
+            ("i2c", _) => bail!("The i2c backend is only available in the ESP32 firmware"),
+            (other, _) => bail!("Unknown backend {}\n{}", other, USAGE),
+        };
+
+        match self.path.as_ref().or(self.upstream.as_ref()) {
+            Some(target) => info!("Using {} backend ({})", self.backend, target),
+            None => info!("Using {} backend", self.backend),
         }
+
+        Ok(backend)
     }
 }
 
-async fn handle_connection(mut stream: TcpStream, backend: Arc<Mutex<dyn Backend>>) -> Result<()> {
-    let mut buf = [0u8; MAX_BUF_SIZE];
-    let mut count = 0;
+#[tokio::main]
+async fn main() -> Result<()> {
+    init_tracing()?;
 
-    loop {
-        let n = stream.read(&mut buf[count..]).await?;
-        if n == 0 {
-            break;
-        }
-        count += n;
+    let args = Args::parse()?;
 
-        debug!("rx {:x?}", &buf[..count]);
+    // SIGMA_TCP_VERIFY=1 reads back every write and reports mismatches as errors
+    let verify = std::env::var("SIGMA_TCP_VERIFY").is_ok_and(|v| v == "1");
+    // --read-only or SIGMA_TCP_READ_ONLY=1 logs writes instead of performing them
+    let read_only = args.read_only || std::env::var("SIGMA_TCP_READ_ONLY").is_ok_and(|v| v == "1");
 
-        let mut processed_bytes = 0;
-        while processed_bytes < count {
-            let (response, bytes_read) =
-                process_command(&buf[processed_bytes..count], &backend).await?;
-            if bytes_read == 0 {
-                // Non ci sono abbastanza dati per un comando completo
-                break;
+    let mut backend = args.create_backend()?;
+    // SIGMA_TCP_COUNT=1 counts the reads and writes of each address and
+    // prints the tally on Ctrl-C
+    if std::env::var("SIGMA_TCP_COUNT").is_ok_and(|v| v == "1") {
+        let counting = CountingBackend::new(backend);
+        let counts = counting.counts();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                info!("Accesses per address:\n{}", counts.dump());
+                std::process::exit(0);
             }
+        });
+        backend = Box::new(counting);
+    }
+    if verify {
+        info!("Write verification enabled");
+        backend = Box::new(VerifyingBackend::new(backend));
+    }
+    if read_only {
+        info!("Read-only mode, writes will be logged and skipped");
+        backend = Box::new(ReadOnlyBackend::new(backend));
+    }
+    // --read-range/--write-range, or SIGMA_TCP_READ_RANGES/SIGMA_TCP_WRITE_RANGES,
+    // reject transfers outside the given ranges
+    if !args.read_ranges.is_empty() || !args.write_ranges.is_empty() {
+        info!(
+            "Allowed read ranges: {:04x?}, write ranges: {:04x?}",
+            args.read_ranges, args.write_ranges
+        );
+        let mut allowlist = AllowlistBackend::new(backend);
+        for range in &args.read_ranges {
+            allowlist = allowlist.allow_read(range.clone());
+        }
+        for range in &args.write_ranges {
+            allowlist = allowlist.allow_write(range.clone());
+        }
+        backend = Box::new(allowlist);
+    }
+    // SIGMA_TCP_CAPTURE=path appends every transaction to a log for examples/replay.rs
+    if let Ok(path) = std::env::var("SIGMA_TCP_CAPTURE") {
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open capture log {}", path))?;
+        info!("Capturing transactions to {}", path);
+        backend = Box::new(CaptureBackend::new(backend, log));
+    }
+    let backend: Arc<Mutex<dyn Backend>> = Arc::new(Mutex::new(backend));
 
-            processed_bytes += bytes_read;
+    // SIGMA_TCP_TOKEN=secret requires clients to send it before any command
+    let token = AuthToken::from_env()?;
+    if token.is_some() {
+        info!("Clients must authenticate with the token in SIGMA_TCP_TOKEN");
+    }
 
-            let response_bytes = response.to_bytes();
+    let mut options = ServerOptions::new();
+    if let Some(token) = &token {
+        options = options.token(token.clone());
+    }
+    // SIGMA_TCP_MAX_READ_LEN=bytes lowers the largest read a client can request
+    if let Ok(len) = std::env::var("SIGMA_TCP_MAX_READ_LEN") {
+        let len = len
+            .parse()
+            .with_context(|| format!("Invalid SIGMA_TCP_MAX_READ_LEN: {}", len))?;
+        info!("Reads are limited to {} bytes", len);
+        options = options.max_read_len(len);
+    }
+    // --tls-cert/--tls-key serve TLS instead of plaintext
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            options = options.tls(load_tls_acceptor(cert, key)?);
+            info!("Serving TLS with the certificate in {}", cert.display());
+        }
+        (None, None) => {}
+        _ => bail!("--tls-cert and --tls-key must be given together\n{}", USAGE),
+    }
 
-            debug!("tx {:x?}", &response_bytes);
+    // SIGMA_TCP_RAW=1 also serves POST /raw, which runs any command on the
+    // backend, writes included. The metrics endpoint is plaintext HTTP, so
+    // it's refused along with TLS
+    let raw = std::env::var("SIGMA_TCP_RAW").is_ok_and(|v| v == "1");
+    if raw && args.tls_cert.is_some() {
+        bail!("SIGMA_TCP_RAW=1 serves plaintext HTTP, it can't be used with --tls-cert/--tls-key");
+    }
 
-            println!();
-        }
+    let listeners = bind_all(&listen_addrs()?).await?;
 
-        // Sposta i dati non processati all'inizio del buffer
-        if processed_bytes > 0 {
-            if processed_bytes < count {
-                buf.copy_within(processed_bytes..count, 0);
-            }
-            count -= processed_bytes;
+    // SIGMA_TCP_METRICS_ADDR sets where /metrics (and POST /raw) are served, "off" disables them
+    match std::env::var("SIGMA_TCP_METRICS_ADDR").as_deref() {
+        Ok("off") => {}
+        addr => {
+            let addr: SocketAddr = match addr {
+                Ok(addr) => addr
+                    .parse()
+                    .with_context(|| format!("Invalid SIGMA_TCP_METRICS_ADDR: {}", addr))?,
+                Err(_) => SocketAddr::from(([0, 0, 0, 0], METRICS_PORT)),
+            };
+            let raw = raw.then(|| RawEndpoint {
+                backend: backend.clone(),
+                options: options.clone(),
+            });
+            tokio::spawn(async move {
+                if let Err(e) = serve_metrics_with(addr, raw).await {
+                    error!("{:#}", e);
+                }
+            });
         }
     }
 
-    Ok(())
-}
+    let accept_loops: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            tokio::spawn(serve_listener_with(
+                backend.clone(),
+                listener,
+                options.clone(),
+            ))
+        })
+        .collect();
 
-async fn process_command(
-    buf: &[u8],
-    backend: &Arc<Mutex<dyn Backend>>,
-) -> Result<(ProtocolResponse, usize)> {
-    let parse_result = ProtocolHandler::parse_command(buf);
+    for accept_loop in accept_loops {
+        accept_loop.await??;
+    }
 
-    match parse_result {
-        Ok((command, bytes_read)) => {
-            debug!("Parsed command: {:?}", command);
+    Ok(())
+}
 
-            let response = match command {
-                ProtocolCommand::Read { header } => {
-                    let mut backend = backend.lock().await;
-                    let data = backend.read(header.param_addr, header.data_len).await?;
+/// Logs through tracing, filtered by RUST_LOG. The `log` records of the
+/// library and backends are forwarded, so they show up inside the connection
+/// and command spans. A command span logs its elapsed time when it closes.
+fn init_tracing() -> Result<()> {
+    tracing_log::LogTracer::init()?;
 
-                    info!(
-                        "read at addr 0x{:04x} size {:?} resp {:02x?}",
-                        header.param_addr, header.data_len, data
-                    );
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_span_events(FmtSpan::CLOSE)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
 
-                    ProtocolHandler::create_read_response(
-                        header.chip_addr,
-                        header.data_len,
-                        header.param_addr,
-                        data,
-                    )
-                }
-                ProtocolCommand::Write { header, data } => {
-                    let mut backend = backend.lock().await;
-                    backend.write(header.param_addr, &data).await?;
+    Ok(())
+}
 
-                    info!(
-                        "write at addr 0x{:04x} size {:?}",
-                        header.param_addr, header.data_len
-                    );
+/// Comma separated address ranges from an environment variable, empty if unset
+fn env_ranges(name: &str) -> Result<Vec<RangeInclusive<u16>>> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .split(',')
+            .map(|range| parse_address_range(range).with_context(|| format!("Invalid {}", name)))
+            .collect(),
+        Err(_) => Ok(Vec::new()),
+    }
+}
 
-                    ProtocolResponse::Write
-                }
-                ProtocolCommand::Unknown(cmd) => {
-                    error!("Unknown command: 0x{:02x}", cmd);
-                    ProtocolHandler::create_error_response(format!(
-                        "Unknown command: 0x{:02x}",
-                        cmd
-                    ))
-                }
-            };
+/// Addresses to listen on, from the comma separated SIGMA_TCP_ADDR list.
+/// Defaults to both the IPv6 and IPv4 wildcard addresses.
+fn listen_addrs() -> Result<Vec<SocketAddr>> {
+    let addrs = std::env::var("SIGMA_TCP_ADDR")
+        .unwrap_or_else(|_| format!("[::]:{},0.0.0.0:{}", PORT, PORT));
 
-            Ok((response, bytes_read))
-        }
-        Err(e) if buf.len() < 3 => {
-            // Non ci sono abbastanza dati per un comando completo
-            Ok((ProtocolResponse::Error("Incomplete command".to_string()), 0))
-        }
-        Err(e) => {
-            error!("Failed to parse command: {}", e);
-            Ok((
-                ProtocolHandler::create_error_response(format!("Parse error: {}", e)),
-                0,
-            ))
-        }
-    }
+    addrs
+        .split(',')
+        .map(|addr| {
+            addr.trim()
+                .parse()
+                .with_context(|| format!("Invalid address in SIGMA_TCP_ADDR: {}", addr))
+        })
+        .collect()
 }
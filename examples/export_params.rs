@@ -0,0 +1,27 @@
+use anyhow::{bail, Context, Result};
+use sigma_tcp_rs::backend::ProxyBackend;
+use sigma_tcp_rs::sigmastudio::{export_params, parse_registers};
+
+/// Reads the registers listed in a JSON file from a running server and
+/// prints them in SigmaStudio's .params layout, to bring a tuned device
+/// back into the project.
+///
+/// Usage: export_params <registers.json> [host:port]
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        bail!("Usage: export_params <registers.json> [host:port]");
+    };
+    let upstream = args.next().unwrap_or_else(|| "127.0.0.1:8086".to_string());
+
+    let json =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+    let registers = parse_registers(&json)?;
+
+    let mut backend = ProxyBackend::new(upstream);
+    print!("{}", export_params(&mut backend, &registers).await?);
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use anyhow::{bail, Context, Result};
+use log::info;
+use sigma_tcp_rs::backend::{read_capture, replay, Backend, DebugBackend, MemoryBackend};
+use std::fs::File;
+use std::io::BufReader;
+
+// same limit as the debug server, captured reads can't be larger than this
+const MAX_READ_LEN: u32 = 20480 * 4;
+
+/// Replays a capture log recorded with SIGMA_TCP_CAPTURE against a backend,
+/// failing if any response differs from the captured one.
+///
+/// Usage: replay <capture.jsonl> [debug|memory]
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        bail!("Usage: replay <capture.jsonl> [debug|memory]");
+    };
+
+    let mut backend: Box<dyn Backend> = match args.next().as_deref() {
+        None | Some("memory") => Box::new(MemoryBackend::new()),
+        Some("debug") => Box::new(DebugBackend::new()),
+        Some(other) => bail!("Unknown backend: {}", other),
+    };
+
+    let file = File::open(&path).with_context(|| format!("Failed to open {}", path))?;
+    let records = read_capture(BufReader::new(file))?;
+
+    info!("Replaying {} records from {}", records.len(), path);
+
+    replay(&records, &mut backend, MAX_READ_LEN).await?;
+
+    println!("All {} responses match", records.len());
+    Ok(())
+}
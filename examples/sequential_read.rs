@@ -0,0 +1,42 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use sigma_tcp_rs::backend::{Backend, FaultInjectingBackend, MemoryBackend};
+
+const WORDS: usize = 64;
+const LATENCY: Duration = Duration::from_millis(1);
+
+/// Compares one read per address with a single sequential read, on a memory
+/// backend with a fixed latency per transaction like an I2C bus.
+///
+/// Usage: sequential_read
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut backend = FaultInjectingBackend::builder(MemoryBackend::new())
+        .latency(LATENCY)
+        .build();
+
+    let start = Instant::now();
+    let mut single = Vec::new();
+    for i in 0..WORDS {
+        single.extend(backend.read(i as u16, 4).await?);
+    }
+    let single_elapsed = start.elapsed();
+
+    let start = Instant::now();
+    let sequential = backend.read_sequential(0, WORDS, 4).await?;
+    let sequential_elapsed = start.elapsed();
+
+    assert_eq!(single, sequential);
+    println!(
+        "{} words, {:?} per transaction: {} reads {:?}, read_sequential {:?} ({:.0}x)",
+        WORDS,
+        LATENCY,
+        WORDS,
+        single_elapsed,
+        sequential_elapsed,
+        single_elapsed.as_secs_f64() / sequential_elapsed.as_secs_f64()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use esp_idf_svc::hal::i2c::I2cDriver;
+use log::error;
+
+use crate::watchdog;
+
+type Job = Box<dyn FnOnce(&mut I2cDriver<'static>) + Send>;
+
+/// Handle to the I2C worker thread, the only owner of the driver
+///
+/// HTTP handlers, /ws streams and TCP clients queue their transactions here
+/// and the worker runs them one at a time in arrival order. A job is never
+/// interleaved with another, so a batch queued as a single job reaches the
+/// bus in one piece, and it runs to the end even if whoever queued it has
+/// stopped waiting, like a TCP client that disconnected mid-write.
+#[derive(Clone)]
+pub struct I2cBus {
+    jobs: Sender<Job>,
+}
+
+impl I2cBus {
+    /// Moves `driver` to a new worker thread, subscribed to the watchdog and
+    /// woken up every `feed_interval` while idle to feed it
+    pub fn spawn(driver: I2cDriver<'static>, feed_interval: Duration) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+
+        thread::spawn(move || {
+            if let Err(e) = watchdog::subscribe() {
+                error!("Failed to subscribe to watchdog: {e}");
+            }
+
+            let mut driver = driver;
+            loop {
+                watchdog::feed();
+
+                match queue.recv_timeout(feed_interval) {
+                    Ok(job) => job(&mut driver),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // ogni handle è stato droppato, nessuno può più mandare lavori
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+
+            watchdog::unsubscribe();
+        });
+
+        Self { jobs }
+    }
+
+    /// Queues `job` and waits for its result
+    pub fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut I2cDriver<'static>) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        // canale usato una volta sola, per la risposta di questo lavoro
+        let (reply, result) = mpsc::sync_channel(1);
+
+        let job: Job = Box::new(move |driver: &mut I2cDriver<'static>| {
+            // chi ha chiesto il lavoro può non aspettare più la risposta
+            let _ = reply.send(job(driver));
+        });
+        self.jobs
+            .send(job)
+            .map_err(|_| anyhow!("I2C worker stopped"))?;
+
+        result.recv().map_err(|_| anyhow!("I2C worker stopped"))?
+    }
+}
@@ -1,3 +1,5 @@
+mod i2c_worker;
+mod watchdog;
 mod wifi_handler;
 
 /*
@@ -17,26 +19,30 @@ mod wifi_handler;
  *    Read data from a DSP register.
  *    Parameters:
  *    - addr: Register address (hex or decimal)
- *    - len: Number of bytes to read (hex or decimal)
+ *    - len: Number of bytes to read (hex or decimal), at most 256
  *    Example: /read?addr=0x3B&len=4
  *    Returns: JSON with address, length, and data in hex format
  *    Example response:
  *    {
  *      "addr": "0x003b",
  *      "len": 4,
- *      "data": "[0x01, 0x02, 0x03, 0x04]"
+ *      "data": "01020304"
  *    }
+ *    "data" is contiguous hex, two digits per byte, the same encoding as the
+ *    /write data parameter. The body is streamed with a Content-Length header.
  *
  *    Error response:
  *    {
- *      "error": "Failed to read from I2C: Device not found"
+ *      "error": "Failed to read from I2C: I2C NACK: ESP_FAIL",
+ *      "code": "i2c_nack"
  *    }
  *
  * 3. GET /write
  *    Write data to a DSP register.
  *    Parameters:
  *    - addr: Register address (hex or decimal)
- *    - data: Data to write as hex string
+ *    - data: Data to write as hex string, optionally 0x prefixed
+ *      Odd-length or non-hex data is rejected with a bad_param error, nothing is written
  *    Example: /write?addr=0x3B&data=01020304
  *    Returns: JSON with status, address, written data, and length
  *    Example response:
@@ -49,357 +55,2269 @@ mod wifi_handler;
  *
  *    Error response:
  *    {
- *      "error": "Failed to write to I2C: Device not found"
+ *      "error": "Failed to write to I2C: I2C timeout after 100 ms",
+ *      "code": "i2c_timeout"
  *    }
+ *
+ * 4. GET /read_multi
+ *    Read several DSP registers in one request, as a single I2C job.
+ *    Parameters:
+ *    - regs: Comma separated list of addr:len pairs (hex or decimal),
+ *      at most 32 entries of at most 256 bytes each
+ *    Example: /read_multi?regs=0x3d:4,0x4f:4
+ *    Returns: JSON array with one /read style object per entry, in order
+ *    Example response:
+ *    [
+ *      { "addr": "0x003d", "len": 4, "data": "01020304" },
+ *      { "addr": "0x004f", "len": 4, "data": "00000000" }
+ *    ]
+ *
+ *    If any read fails the whole request fails with a single error object.
+ *
+ * 5. POST /write_multi
+ *    Write several DSP registers in one request, as a single I2C job so
+ *    no other request runs in the middle of the batch.
+ *    Body: JSON array of at most 32 { "addr", "data" } objects, addr and data
+ *    as in /write
+ *    Example body:
+ *    [
+ *      { "addr": "0x43", "data": "01000000" },
+ *      { "addr": "0x44", "data": "00800000" }
+ *    ]
+ *    Returns: JSON array with one result per entry, in order
+ *    Example response:
+ *    [
+ *      { "addr": "0x0043", "status": "ok" },
+ *      { "addr": "0x0044", "status": "error",
+ *        "error": "Failed to write to I2C: I2C NACK: ESP_FAIL", "code": "i2c_nack" }
+ *    ]
+ *
+ *    A malformed body or entry rejects the whole batch with a single error
+ *    object and nothing is written. I2C failures are reported per entry and
+ *    the remaining entries are still written.
+ *
+ *    With ?safeload=1 the entries are loaded through the DSP safeload
+ *    registers and take effect in the same audio frame, e.g. the five
+ *    coefficients of a biquad. At most 5 entries of one 4-byte word each, at
+ *    any addresses in any order. The ADAU1701 has an address register per
+ *    word and loads them all with one trigger; the ADAU1452 has a single
+ *    target address, so each run of consecutive addresses is a safeload of
+ *    its own, atomic within the run. Any other batch is rejected with
+ *    bad_param, and an I2C failure fails the whole batch with a single
+ *    error object.
+ *
+ * 6. GET /config
+ *    Show or change the device configuration.
+ *    Parameters (all optional, without any the configuration is only returned):
+ *    - chip_map: SigmaStudio IC index to I2C address, as ic:addr pairs
+ *      Example: /config?chip_map=1:0x3b,2:0x3a
+ *      TCP commands for an IC without an entry fail instead of reaching
+ *      another DSP. The default maps IC 1 to 0x3b, 0x34 with the adau1701
+ *      feature. Saved in NVS.
+ *    - freeze: 1 to block every write, from HTTP and from SigmaStudio, 0 to
+ *      allow them again. Reads keep working. Not saved, the device always
+ *      boots unfrozen.
+ *      Example: /config?freeze=1
+ *    - token: shared secret every request must carry from now on, empty to
+ *      disable authentication (the default). Saved in NVS.
+ *      Example: /config?token=s3cret
+ *    - log: log level of the firmware, one of off, error, warn, info (the
+ *      default), debug or trace. Not saved, the device always boots at info.
+ *      Example: /config?log=debug
+ *    Example response:
+ *    { "chip_map": "1:0x3b,2:0x3a", "frozen": true, "auth": true, "log": "debug" }
+ *
+ * 7. GET /identify
+ *    Report which DSP part is connected, read from its ID register.
+ *    Example response:
+ *    { "id": "1452", "part": "ADAU1452" }
+ *    "part" is null for an ID missing from the firmware table. Both fields
+ *    are null when the part has no ID register (the ADAU1452 doesn't
+ *    document one, see DSP_PART_ID). The same information is returned to
+ *    TCP clients by the identify command (0x0d).
+ *
+ *    Error response:
+ *    {
+ *      "error": "Failed to read the ID register: I2C NACK: ESP_FAIL",
+ *      "code": "i2c_nack"
+ *    }
+ *
+ * 8. GET /capabilities
+ *    Report the optional features of this firmware and its configuration,
+ *    so clients can adapt. Needs no token, even with authentication enabled.
+ *    Example response:
+ *    { "capabilities": ["safeload", "batch", "checksum", "identify", "ping", "stream"],
+ *      "bits": "0x000000bd" }
+ *    "multi_chip" is listed when the chip map has more than one IC, "auth"
+ *    when a token is required. TCP clients get the same flags, as a
+ *    big-endian u32 after the command byte, from the capabilities command
+ *    (0x0e).
+ *
+ * 9. WebSocket /ws
+ *    Stream register values without polling.
+ *    After connecting, send a text message with the registers to watch, in
+ *    the /read_multi format:
+ *    { "regs": "0x3d:4,0x4f:4" }
+ *    With authentication enabled the message also carries the token, as
+ *    browsers can't set headers on a WebSocket:
+ *    { "regs": "0x3d:4,0x4f:4", "token": "s3cret" }
+ *    The device then pushes the values every 100 ms, or an error object if
+ *    the read fails. A new subscribe message replaces the previous list; a
+ *    malformed one is answered with an error object.
+ *    Example message:
+ *    { "seq": 12, "t_ms": 1200, "read_us": 850,
+ *      "values": [{ "addr": "0x003d", "len": 4, "data": "00800000" }, ...] }
+ *    "values" is the /read_multi array. Tick n is read at n * 100 ms from
+ *    the subscription, "t_ms", whatever the previous reads took: when a read
+ *    takes longer than the interval the ticks it overran are skipped, so a
+ *    gap in "seq" and a "read_us" close to 100000 tell the client the device
+ *    can't keep up with the registers it asked for.
+ *
+ * 10. GET /i2c_raw_read
+ *    Plain I2C read from any device on the bus (EEPROM, codec...), without
+ *    the 2-byte register address /read sends to the DSP first.
+ *    Parameters:
+ *    - addr: 7-bit I2C device address, 0x08 to 0x77 (hex or decimal)
+ *    - len: Number of bytes to read, at most 256
+ *    Example: /i2c_raw_read?addr=0x50&len=4
+ *    Example response:
+ *    { "dev": "0x50", "len": 4, "data": "01020304" }
+ *
+ * 11. GET /i2c_raw_write
+ *    Plain I2C write to any device on the bus, the bytes are sent as given.
+ *    Refused with the frozen error like every other write.
+ *    Parameters:
+ *    - addr: 7-bit I2C device address, 0x08 to 0x77 (hex or decimal)
+ *    - data: Bytes to write as hex string, optionally 0x prefixed, not empty
+ *    Example: /i2c_raw_write?addr=0x50&data=0010ff
+ *    Example response:
+ *    { "status": "ok", "dev": "0x50", "length": 3 }
+ *
+ * 12. GET /gpio
+ *    Read or drive a board GPIO, e.g. the mute, standby or reset line of the DSP.
+ *    Parameters:
+ *    - pin: GPIO number, only the pins in GPIO_ALLOWED_PINS are accepted
+ *    - level: optional, 1 or 0. The pin is configured as an output the first
+ *      time and keeps driving the level until the next change or a reboot.
+ *      Refused with the frozen error like every other write.
+ *    Without level the pin is only read: the level being driven for an
+ *    output set by /gpio, the input level otherwise.
+ *    Example: /gpio?pin=4&level=1
+ *    Example response:
+ *    { "pin": 4, "level": 1 }
+ *
+ * 13. GET /selftest
+ *    Check the wiring of the DSP: it must answer on the I2C bus and, if the
+ *    part has an ID register (DSP_PART_ID), its ID must be DSP_EXPECTED_ID.
+ *    The same test runs whenever the DSP is found, at boot or after it was
+ *    missing. With cached=1 the last result is returned without touching
+ *    the bus.
+ *    Example response:
+ *    { "i2c_ok": true, "chip_id": "1452", "expected": "1452", "match": true,
+ *      "pass": true, "error": null }
+ *    chip_id is null when the part has no ID register, expected when any ID
+ *    is accepted, match when either is null.
+ *
+ * 14. POST /raw
+ *    Run a raw sigma-tcp frame, as SigmaStudio would send it over TCP, and
+ *    return the exact response bytes. Meant for protocol debugging.
+ *    Body: the frame as hex, whitespace between the digits is ignored. It can
+ *    hold several commands, run in order as on a new TCP connection.
+ *    Example body (read 4 bytes at 0x0043 of IC 1):
+ *    0a 0000000e 01 00000004 0043 0000
+ *    Example response:
+ *    { "response": "0b0000001101000000040043000000800000", "length": 18 }
+ *    "response" is every response concatenated, empty for writes. A partial
+ *    or malformed command is a bad_param error.
+ *
+ * 15. GET /status
+ *    Runtime state of the device.
+ *    Example response:
+ *    { "dsp_present": true, "frozen": false, "log": "info" }
+ *    Wi-Fi, HTTP and TCP come up even without a DSP, so the device can be
+ *    reached to find out why. dsp_present is false until the DSP answers on
+ *    the I2C bus, checked every second while it's missing and every 5
+ *    seconds once found; meanwhile HTTP and TCP commands for it fail at once
+ *    with no_device. log is the current log level, set with /config?log=.
+ *
+ * The firmware is built for one DSP part, selected with a Cargo feature:
+ * "adau1452" (the default) or "adau1701", e.g.
+ * cargo build --release --no-default-features --features adau1701
+ * The part sets the largest TCP read and write, its biggest memory block:
+ * 81920 bytes on the ADAU1452, 5120 on the ADAU1701. Larger reads are
+ * answered with an error, larger writes close the connection.
+ *
+ * When built with the "gzip" feature, JSON responses of 512 bytes or more are
+ * gzip compressed (Content-Encoding: gzip) for clients sending
+ * Accept-Encoding: gzip. Browsers decompress them transparently.
+ *
+ * Successful /read and /read_multi responses carry a Server-Timing header
+ * with the time spent on the I2C bus, e.g. "Server-Timing: i2c;dur=1.234"
+ * (milliseconds), so clients can tell it apart from the network time.
+ *
+ * Authentication is disabled by default. Once a token is set with
+ * /config?token=..., every endpoint except /, /capabilities and OPTIONS answers 401 unless
+ * the request carries it as "Authorization: Bearer <token>" or
+ * "X-Token: <token>". SigmaStudio can't send it: TCP clients must start the
+ * connection with the prelude of sigma_tcp_rs::auth ("SIGMAKEY", the token
+ * length and the token) or it is closed.
+ *
+ * OPTIONS on any path answers CORS preflight requests with 204 No Content
+ * and the same Access-Control-Allow-* headers as the other endpoints.
+ *
+ * Errors are always a JSON object with a human readable "error" and a
+ * machine readable "code", returned with a 4xx/5xx status:
+ *    - bad_param: a parameter is missing or malformed (400)
+ *    - out_of_range: a parameter is outside the accepted bounds (400)
+ *    - i2c_nack: the DSP did not acknowledge or the bus reported an error (500)
+ *    - i2c_timeout: the I2C transaction did not complete in time (500)
+ *    - storage_error: the configuration could not be saved in NVS (500)
+ *    - gpio_error: the GPIO driver refused to configure or drive the pin (500)
+ *    - no_device: the DSP isn't answering on the I2C bus, see /status (500)
+ *    - frozen: writes are blocked by /config?freeze=1 (409), nothing is written
+ *    - unauthorized: the auth token is missing or wrong (401)
+ *
+ * Threading model
+ * ===============
+ *
+ * The HTTP server, every /ws stream and every TCP client run on their own
+ * threads, but none of them touches the I2C driver. It belongs to a single
+ * worker thread (see i2c_worker::I2cBus) that takes jobs from a queue and
+ * runs them one at a time, in the order they were sent, each with a reply
+ * channel for its result. A job is never interleaved with another: a
+ * /read_multi or /write_multi batch and a safeload sequence are one job each,
+ * while a SigmaStudio download is a job per TCP command, so HTTP requests
+ * land between its commands and never inside one. Another thread checks
+ * whether the DSP is present with a job of its own every few seconds.
  */
 
 use anyhow::{bail, Context, Result};
-use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::delay::TickType;
 use esp_idf_hal::io::EspIOError;
 use esp_idf_hal::prelude::*;
+use esp_idf_svc::sys::{EspError, ESP_ERR_TIMEOUT};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::{
-        gpio::AnyIOPin,
+        gpio::{AnyIOPin, InputOutput, Level, PinDriver},
         i2c::{I2c, I2cConfig, I2cDriver},
         peripheral::Peripheral,
         peripherals::Peripherals,
         units::Hertz,
     },
-    http::{server::EspHttpServer, Method},
+    http::{
+        server::{EspHttpConnection, EspHttpServer, Request},
+        Method,
+    },
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    ws::FrameType,
 };
-use log::{error, info};
+use i2c_worker::I2cBus;
+use log::{debug, error, info, warn, LevelFilter};
+use serde_json::{json, Value};
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
+    fmt,
     io::{self, Read, Write},
     net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
     thread,
+    time::{Duration, Instant},
 };
 use wifi_handler::my_wifi;
 
-use sigma_tcp_rs::{ProtocolCommand, ProtocolHandler, ProtocolResponse};
+use sigma_tcp_rs::auth::{self, AuthToken};
+use sigma_tcp_rs::backend::split_read;
+use sigma_tcp_rs::capabilities::Capabilities;
+use sigma_tcp_rs::chip_map::ChipMap;
+use sigma_tcp_rs::identify::{DeviceInfo, PartId};
+use sigma_tcp_rs::query::QueryParams;
+use sigma_tcp_rs::safeload::{SafeloadBatch, SafeloadConfig};
+use sigma_tcp_rs::ticker::Ticker;
+use sigma_tcp_rs::{
+    ConnectionFraming, IncompleteCommand, ProtocolCommand, ProtocolHandler, ProtocolResponse,
+    Resync, ResyncError, MAX_DATA_LEN, STATUS_BACKEND_ERROR, STATUS_READ_TOO_LONG, STATUS_TIMEOUT,
+};
+
+// Definizione dell'indirizzo I2C del DSP, IC 1 se la mappa in NVS non dice altro.
+// Dipende dalla parte: l'ADAU1701 risponde a 0x34-0x37 secondo i pin ADDR0/ADDR1
+#[cfg(feature = "adau1452")]
+const DSP_I2C_ADDR: u8 = 0x3b;
+#[cfg(feature = "adau1701")]
+const DSP_I2C_ADDR: u8 = 0x34;
+
+// Interblocco di /config?freeze=1: finché è attivo ogni scrittura viene rifiutata
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
+// Livello di log impostato con /config?log=..., al boot quello di default di ESP-IDF
+static LOG_LEVEL: Mutex<LevelFilter> = Mutex::new(LevelFilter::Info);
+// Target dei messaggi di log di questo firmware e della libreria
+const LOG_TARGETS: &[&str] = &["sigmadsp_esp32", "sigma_tcp_rs"];
+
+// Namespace e chiavi NVS della configurazione
+const NVS_NAMESPACE: &str = "sigma_tcp";
+const NVS_CHIP_MAP_KEY: &str = "chip_map";
+const NVS_AUTH_TOKEN_KEY: &str = "auth_token";
+
+// Registri di safeload del DSP montato sulla scheda, scelti dalla feature della parte
+#[cfg(feature = "adau1452")]
+const DSP_SAFELOAD: SafeloadConfig = SafeloadConfig::ADAU1452;
+#[cfg(feature = "adau1701")]
+const DSP_SAFELOAD: SafeloadConfig = SafeloadConfig::ADAU1701;
+
+// Registro di identificazione del DSP montato sulla scheda. L'ADAU1452 non ne
+// documenta uno, con None /identify riporta la parte come sconosciuta
+const DSP_PART_ID: Option<PartId> = None;
+// ID che il self-test si aspetta da DSP_PART_ID, da cambiare con la parte
+// montata. None accetta qualunque ID
+const DSP_EXPECTED_ID: Option<&[u8]> = None;
+
+// Ultimo esito del self-test, quando il DSP compare o da /selftest
+static SELF_TEST: Mutex<Option<SelfTest>> = Mutex::new(None);
+
+// A server thread that doesn't report back within this time reboots the device
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(10);
+// How often idle threads wake up to feed the watchdog, must be well below WATCHDOG_TIMEOUT
+const WATCHDOG_FEED_INTERVAL: Duration = Duration::from_secs(2);
+
+// The DSP answered the last check of watch_dsp. While it's missing, commands
+// for it fail at once instead of waiting for the I2C timeout
+static DSP_PRESENT: AtomicBool = AtomicBool::new(false);
+// How often watch_dsp checks the DSP, while it's missing and once found
+const DSP_ABSENT_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const DSP_PRESENT_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Upper bound for a single I2C transaction, a stuck bus fails instead of hanging the thread
+const I2C_TIMEOUT_MS: u64 = 100;
+// Missing devices NACK immediately, the scan only needs to survive a stuck bus
+const I2C_SCAN_TIMEOUT_MS: u64 = 10;
+
+// Device addresses accepted by /i2c_raw_*, the 7-bit range without the reserved ones
+const I2C_RAW_DEV_RANGE: RangeInclusive<u16> = 0x08..=0x77;
+
+// GPIO pilotabili da /gpio, da adattare alla scheda. Mai i pin di strapping
+// dell'ESP32-S3 (0, 3, 45, 46), quelli dell'I2C (2, 5), dell'USB (19, 20) o
+// della flash/PSRAM (26-37)
+const GPIO_ALLOWED_PINS: &[i32] = &[4, 6, 7, 15, 16, 17, 18];
+
+// Largest read accepted on the HTTP API, the UI only reads a few words at a time
+const HTTP_MAX_READ_LEN: u16 = 256;
+// Largest block the DSP takes in a single transfer, set by the part feature
+// (see Cargo.toml). SigmaStudio downloads each memory with one write:
+// - adau1452: a data memory partition, 20480 words of 4 bytes (80 KB)
+// - adau1701: the program memory, 1024 words of 5 bytes (5 KB)
+#[cfg(feature = "adau1452")]
+const DSP_MAX_TRANSFER_LEN: u32 = 20480 * 4;
+#[cfg(feature = "adau1701")]
+const DSP_MAX_TRANSFER_LEN: u32 = 1024 * 5;
+#[cfg(not(any(feature = "adau1452", feature = "adau1701")))]
+compile_error!("Select the DSP part with one of the features \"adau1452\", \"adau1701\"");
+#[cfg(all(feature = "adau1452", feature = "adau1701"))]
+compile_error!("Only one DSP part feature can be enabled");
+// the protocol parser rejects larger writes before they reach us
+const _: () = assert!(DSP_MAX_TRANSFER_LEN <= MAX_DATA_LEN);
+
+// Largest read accepted over TCP
+const TCP_MAX_READ_LEN: u32 = DSP_MAX_TRANSFER_LEN;
+// Longest single I2C read, longer reads are split in several transactions
+const I2C_MAX_READ_CHUNK: u32 = 256;
+// Bytes per sub-address of the DSP memory at `addr`, the memories are the only
+// ones read in large blocks. Set by the part feature: the ADAU1701 program RAM
+// (0x0400-0x07ff) has 5-byte words, its parameter RAM 4-byte ones
+#[cfg(feature = "adau1452")]
+const fn dsp_word_len(_addr: u16) -> u32 {
+    4
+}
+#[cfg(feature = "adau1701")]
+const fn dsp_word_len(addr: u16) -> u32 {
+    match addr {
+        0x0400..=0x07ff => 5,
+        _ => 4,
+    }
+}
+
+// Initial size of the per-connection TCP buffer, doubled on demand up to TCP_MAX_BUF_LEN
+const TCP_INITIAL_BUF_LEN: usize = 256;
+// Largest command accepted over TCP: a write header plus the largest transfer,
+// plus 4 bytes for the CRC32 trailer of a connection that negotiated checksums,
+// or the largest download would never fit in the buffer
+const TCP_MAX_BUF_LEN: usize = DSP_MAX_TRANSFER_LEN as usize + 14 + 4;
+// Longest a response write can block on a client that doesn't read, below WATCHDOG_TIMEOUT
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// Largest number of registers in a single /read_multi request
+const HTTP_MAX_BATCH_READS: usize = 32;
+// Registri al massimo in una richiesta /write_multi
+const HTTP_MAX_BATCH_WRITES: usize = 32;
+// Corpo massimo di /write_multi, 32 scritture di qualche parola
+const HTTP_MAX_WRITE_BODY_LEN: usize = 8192;
+// Largest /ws subscribe message, enough for HTTP_MAX_BATCH_READS entries
+const WS_MAX_MESSAGE_LEN: usize = 512;
+// How often a /ws subscription pushes its register values, the ticks are
+// kept on absolute deadlines from the subscription
+const WS_PUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+enum I2cError {
+    // the transaction didn't complete in time, usually SDA/SCL held low or disconnected
+    Timeout,
+    // the device didn't acknowledge or the driver reported another failure
+    Nack(EspError),
+    // the DSP didn't answer the last presence check, the bus isn't touched
+    NoDevice,
+}
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cError::Timeout => write!(f, "I2C timeout after {I2C_TIMEOUT_MS} ms"),
+            I2cError::Nack(e) => write!(f, "I2C NACK: {e}"),
+            I2cError::NoDevice => write!(f, "No DSP at 0x{DSP_I2C_ADDR:02x} on the I2C bus"),
+        }
+    }
+}
+
+impl std::error::Error for I2cError {}
+
+impl From<EspError> for I2cError {
+    fn from(e: EspError) -> Self {
+        if e.code() == ESP_ERR_TIMEOUT as i32 {
+            I2cError::Timeout
+        } else {
+            I2cError::Nack(e)
+        }
+    }
+}
+
+// Parse HTTP query parameters, percent-decoded; a repeated key keeps its first value
+fn parse_http_params(uri: &str) -> QueryParams {
+    QueryParams::from_uri(uri)
+}
+
+// Helper function to parse a string to u16, supporting both hex (0x prefix) and decimal
+fn parse_number_to_u16(value: &str) -> Option<u16> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        // Parse as hex
+        u16::from_str_radix(value.trim_start_matches("0x").trim_start_matches("0X"), 16).ok()
+    } else {
+        // Parse as decimal
+        value.parse::<u16>().ok()
+    }
+}
+
+// Parse hex data from string, with an optional 0x prefix
+// Odd-length input or non-hex characters are rejected rather than silently dropped
+fn parse_hex_data(hex_str: &str) -> Result<Vec<u8>, String> {
+    // Clean the input string
+    let clean_value = hex_str
+        .strip_prefix("0x")
+        .or_else(|| hex_str.strip_prefix("0X"))
+        .unwrap_or(hex_str);
+
+    if clean_value.len() % 2 != 0 {
+        return Err(format!(
+            "Hex data has an odd number of digits ({})",
+            clean_value.len()
+        ));
+    }
+
+    // from_str_radix would also accept a '+' sign, so check the digits up front
+    if let Some(c) = clean_value.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid hex character: {c:?}"));
+    }
+
+    // Convert hex string to bytes
+    Ok((0..clean_value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&clean_value[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+// Parse a batch read list like "0x3d:4,0x4f:4" into (address, length) pairs
+fn parse_read_list(value: &str) -> Result<Vec<(u16, u16)>, String> {
+    value
+        .split(',')
+        .map(|entry| {
+            let (addr, len) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("Expected addr:len, got {entry:?}"))?;
+            let addr =
+                parse_number_to_u16(addr).ok_or_else(|| format!("Invalid address {addr:?}"))?;
+            let len = parse_number_to_u16(len).ok_or_else(|| format!("Invalid length {len:?}"))?;
+            Ok((addr, len))
+        })
+        .collect()
+}
+
+/// Parses a /ws subscribe message, {"regs": "addr:len,..."}, which must
+/// carry `token` as "token" when one is configured
+fn parse_subscribe_message(
+    message: &[u8],
+    token: Option<&AuthToken>,
+) -> Result<Vec<(u16, u16)>, (ErrorCode, String)> {
+    // esp-idf termina i frame di testo con uno 0
+    let message = std::str::from_utf8(message)
+        .map_err(|_| (ErrorCode::BadParam, "Message is not UTF-8".to_string()))?
+        .trim_end_matches('\0');
+    let message = serde_json::from_str::<Value>(message).ok();
+
+    // il browser non può mettere header sul WebSocket, il token sta nel messaggio
+    if let Some(token) = token {
+        let given = message.as_ref().and_then(|v| v.get("token")?.as_str());
+        if !given.is_some_and(|given| token.matches(given.as_bytes())) {
+            return Err((
+                ErrorCode::Unauthorized,
+                "Missing or wrong auth token".to_string(),
+            ));
+        }
+    }
+
+    let regs = message
+        .and_then(|v| v.get("regs")?.as_str().map(parse_read_list))
+        .ok_or_else(|| {
+            (
+                ErrorCode::BadParam,
+                "Expected {\"regs\": \"addr:len,...\"}".to_string(),
+            )
+        })?
+        .map_err(|e| (ErrorCode::BadParam, e))?;
+
+    check_read_list(&regs).map_err(|e| (ErrorCode::OutOfRange, e))?;
+    Ok(regs)
+}
+
+/// Parses a /write_multi body, [{"addr": "0x43", "data": "01000000"}, ...]
+///
+/// Any malformed entry rejects the whole list.
+fn parse_write_list(body: &[u8]) -> Result<Vec<(u16, Vec<u8>)>, (ErrorCode, String)> {
+    let entries = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|v| v.as_array().cloned())
+        .ok_or_else(|| {
+            (
+                ErrorCode::BadParam,
+                "Expected a JSON array of {\"addr\", \"data\"} objects".to_string(),
+            )
+        })?;
+
+    if entries.len() > HTTP_MAX_BATCH_WRITES {
+        return Err((
+            ErrorCode::OutOfRange,
+            format!(
+                "{} writes exceed maximum of {HTTP_MAX_BATCH_WRITES}",
+                entries.len()
+            ),
+        ));
+    }
+
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let addr = entry
+                .get("addr")
+                .and_then(Value::as_str)
+                .and_then(parse_number_to_u16)
+                .ok_or_else(|| {
+                    (
+                        ErrorCode::BadParam,
+                        format!("Entry {i}: missing or invalid addr"),
+                    )
+                })?;
+            let data = entry
+                .get("data")
+                .and_then(Value::as_str)
+                .ok_or_else(|| (ErrorCode::BadParam, format!("Entry {i}: missing data")))?;
+            let data = parse_hex_data(data)
+                .map_err(|e| (ErrorCode::BadParam, format!("Entry {i}: {e}")))?;
+            Ok((addr, data))
+        })
+        .collect()
+}
+
+/// Reads the whole request body, refusing bodies longer than `max_len`
+fn read_body(
+    request: &mut Request<&mut EspHttpConnection<'_>>,
+    max_len: usize,
+) -> Result<Vec<u8>, (ErrorCode, String)> {
+    let len = request.content_len().unwrap_or(0) as usize;
+    if len > max_len {
+        return Err((
+            ErrorCode::OutOfRange,
+            format!("Body of {len} bytes exceeds maximum of {max_len} bytes"),
+        ));
+    }
+
+    let mut body = vec![0; len];
+    let mut filled = 0;
+    while filled < len {
+        match esp_idf_hal::io::Read::read(request, &mut body[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) => {
+                return Err((ErrorCode::BadParam, format!("Failed to read body: {e:?}")));
+            }
+        }
+    }
+    body.truncate(filled);
+    Ok(body)
+}
+
+// Machine readable error codes, sent in the "code" field of HTTP error bodies
+#[derive(Debug, Clone, Copy)]
+enum ErrorCode {
+    I2cNack,
+    I2cTimeout,
+    BadParam,
+    OutOfRange,
+    Storage,
+    Gpio,
+    NoDevice,
+    Frozen,
+    Unauthorized,
+}
+
+impl ErrorCode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::I2cNack => "i2c_nack",
+            ErrorCode::I2cTimeout => "i2c_timeout",
+            ErrorCode::BadParam => "bad_param",
+            ErrorCode::OutOfRange => "out_of_range",
+            ErrorCode::Storage => "storage_error",
+            ErrorCode::Gpio => "gpio_error",
+            ErrorCode::NoDevice => "no_device",
+            ErrorCode::Frozen => "frozen",
+            ErrorCode::Unauthorized => "unauthorized",
+        }
+    }
+}
+
+/// Cambia a runtime il livello di log di LOG_TARGETS
+fn set_log_level(level: LevelFilter) {
+    for target in LOG_TARGETS {
+        if let Err(e) = esp_idf_svc::log::set_target_level(*target, level) {
+            error!("Failed to set the log level of {target}: {e}");
+        }
+    }
+    *lock(&LOG_LEVEL) = level;
+}
+
+fn error_body(code: ErrorCode, message: impl fmt::Display) -> Value {
+    json!({
+        "error": message.to_string(),
+        "code": code.as_str(),
+    })
+}
+
+fn frozen_body() -> Value {
+    error_body(ErrorCode::Frozen, "Device frozen, writes are disabled")
+}
+
+/// true se non è configurato un token o se la richiesta porta quello giusto
+fn authorized(
+    request: &Request<&mut EspHttpConnection<'_>>,
+    token: &Mutex<Option<AuthToken>>,
+) -> bool {
+    use esp_idf_svc::http::Headers;
+
+    match &*lock(token) {
+        None => true,
+        Some(token) => {
+            token.matches_headers(request.header("Authorization"), request.header("X-Token"))
+        }
+    }
+}
+
+fn send_unauthorized(request: Request<&mut EspHttpConnection<'_>>) -> Result<(), EspIOError> {
+    send_json(
+        request,
+        401,
+        &error_body(ErrorCode::Unauthorized, "Missing or wrong auth token"),
+    )
+}
+
+fn i2c_error_code(e: &anyhow::Error) -> ErrorCode {
+    match e.downcast_ref::<I2cError>() {
+        Some(I2cError::Timeout) => ErrorCode::I2cTimeout,
+        Some(I2cError::NoDevice) => ErrorCode::NoDevice,
+        _ => ErrorCode::I2cNack,
+    }
+}
+
+const CORS_HEADERS: [(&str, &str); 4] = [
+    ("Access-Control-Allow-Origin", "*"),
+    ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
+    (
+        "Access-Control-Allow-Headers",
+        "Content-Type, Authorization, X-Token",
+    ),
+    ("Access-Control-Expose-Headers", "Server-Timing"),
+];
+
+/// Server-Timing header value for the time spent on the I2C bus
+fn i2c_server_timing(i2c_time: Duration) -> String {
+    format!("i2c;dur={:.3}", i2c_time.as_secs_f64() * 1000.0)
+}
+
+fn send_json(
+    request: Request<&mut EspHttpConnection<'_>>,
+    status: u16,
+    body: &Value,
+) -> Result<(), EspIOError> {
+    send_json_with_headers(request, status, body, &[])
+}
+
+fn send_json_with_headers(
+    request: Request<&mut EspHttpConnection<'_>>,
+    status: u16,
+    body: &Value,
+    extra_headers: &[(&str, &str)],
+) -> Result<(), EspIOError> {
+    let body = body.to_string();
+    let mut headers = CORS_HEADERS.to_vec();
+    headers.push(("Content-Type", "application/json"));
+    headers.extend_from_slice(extra_headers);
+
+    #[cfg(feature = "gzip")]
+    if let Some(compressed) = gzip_body(&request, body.as_bytes()) {
+        headers.push(("Content-Encoding", "gzip"));
+        headers.push(("Vary", "Accept-Encoding"));
+
+        let mut response = request.into_response(status, None, &headers)?;
+        esp_idf_hal::io::Write::write_all(&mut response, &compressed)?;
+        return Ok(());
+    }
+
+    let mut response = request.into_response(status, None, &headers)?;
+    esp_idf_hal::io::Write::write_all(&mut response, body.as_bytes())?;
+    Ok(())
+}
+
+// Bodies shorter than this are sent as is, gzip would barely shrink them
+#[cfg(feature = "gzip")]
+const GZIP_MIN_LEN: usize = 512;
+
+/// Compresses `body` if the client sent `Accept-Encoding: gzip` and the body is
+/// long enough to be worth it
+#[cfg(feature = "gzip")]
+fn gzip_body(request: &Request<&mut EspHttpConnection<'_>>, body: &[u8]) -> Option<Vec<u8>> {
+    use esp_idf_svc::http::Headers;
+    use flate2::{write::GzEncoder, Compression};
+
+    if body.len() < GZIP_MIN_LEN {
+        return None;
+    }
+
+    let accepts_gzip = request
+        .header("Accept-Encoding")
+        .is_some_and(|value| value.split(',').any(|enc| enc.trim().starts_with("gzip")));
+    if !accepts_gzip {
+        return None;
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(body).ok()?;
+    encoder.finish().ok()
+}
+
+/// "data" of read responses: contiguous lowercase hex, the same encoding /write accepts
+fn hex_string(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Legge l'ID del DSP all'indirizzo I2C `dev`, se la parte ha un registro di identificazione
+fn identify_dsp(i2c: &I2cBus, dev: u8) -> Result<DeviceInfo> {
+    let Some(part_id) = DSP_PART_ID else {
+        return Ok(DeviceInfo::Unknown);
+    };
+    let id = read_i2c_register(i2c, dev, part_id.addr, part_id.len as usize)?;
+    Ok(part_id.device_info(id))
+}
+
+/// Esito del self-test sul DSP
+#[derive(Clone, Debug)]
+struct SelfTest {
+    i2c_ok: bool,
+    chip_id: Option<Vec<u8>>,
+    error: Option<String>,
+}
+
+impl SelfTest {
+    /// None se non c'è un ID letto o atteso da confrontare
+    fn id_matches(&self) -> Option<bool> {
+        Some(self.chip_id.as_deref()? == DSP_EXPECTED_ID?)
+    }
+
+    fn passed(&self) -> bool {
+        self.i2c_ok && self.error.is_none() && self.id_matches() != Some(false)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "i2c_ok": self.i2c_ok,
+            "chip_id": self.chip_id.as_deref().map(hex_string),
+            "expected": DSP_EXPECTED_ID.map(hex_string),
+            "match": self.id_matches(),
+            "pass": self.passed(),
+            "error": self.error,
+        })
+    }
+}
+
+/// Controlla che il DSP all'indirizzo `dev` risponda e, se la parte ha un
+/// registro di identificazione, che l'ID sia quello atteso. L'esito viene
+/// scritto nel log e in SELF_TEST
+fn self_test(i2c: &I2cBus, dev: u8) -> SelfTest {
+    let result = match (probe_i2c(i2c, dev), DSP_PART_ID) {
+        (Err(e), _) => SelfTest {
+            i2c_ok: false,
+            chip_id: None,
+            error: Some(format!("No answer from the DSP at 0x{dev:02x}: {e}")),
+        },
+        (Ok(()), None) => SelfTest {
+            i2c_ok: true,
+            chip_id: None,
+            error: None,
+        },
+        (Ok(()), Some(part_id)) => {
+            match read_i2c_register(i2c, dev, part_id.addr, part_id.len as usize) {
+                Ok(id) => SelfTest {
+                    i2c_ok: true,
+                    chip_id: Some(id),
+                    error: None,
+                },
+                Err(e) => SelfTest {
+                    i2c_ok: true,
+                    chip_id: None,
+                    error: Some(format!("Failed to read the ID register: {e}")),
+                },
+            }
+        }
+    };
+
+    if result.passed() {
+        info!("Self-test passed: {}", result.to_json());
+    } else {
+        error!(
+            "Self-test FAILED, check the DSP wiring: {}",
+            result.to_json()
+        );
+    }
+    *lock(&SELF_TEST) = Some(result.clone());
+
+    result
+}
+
+/// Il dispositivo all'indirizzo `dev` dà l'ACK, come nella scansione del bus
+fn probe_i2c(i2c: &I2cBus, dev: u8) -> Result<(), anyhow::Error> {
+    i2c.run(move |i2c| {
+        i2c.read(
+            dev,
+            &mut [0u8; 1],
+            TickType::new_millis(I2C_TIMEOUT_MS).ticks(),
+        )
+        .map_err(I2cError::from)?;
+        Ok(())
+    })
+}
+
+/// Indirizzi di tutti i dispositivi che danno l'ACK, in un solo lavoro
+fn scan_i2c(i2c: &I2cBus) -> Result<Vec<u8>, anyhow::Error> {
+    i2c.run(|i2c| {
+        let timeout = TickType::new_millis(I2C_SCAN_TIMEOUT_MS).ticks();
+        Ok((0..127)
+            .filter(|&dev| i2c.read(dev, &mut [0u8; 1], timeout).is_ok())
+            .collect())
+    })
+}
+
+/// Thread che tiene aggiornato DSP_PRESENT: scansione del bus all'avvio, poi
+/// un controllo del DSP ogni DSP_ABSENT_PROBE_INTERVAL finché manca e ogni
+/// DSP_PRESENT_PROBE_INTERVAL quando c'è. Ogni volta che il DSP compare
+/// parte il self-test
+fn watch_dsp(i2c: I2cBus) {
+    thread::spawn(move || {
+        match scan_i2c(&i2c) {
+            Ok(found) if found.is_empty() => error!("No I2C devices found"),
+            Ok(found) => {
+                for dev in found {
+                    info!("Found I2C device at address: {dev:#04x}");
+                }
+            }
+            Err(e) => error!("I2C scan failed: {e}"),
+        }
+
+        let mut reported_missing = false;
+        loop {
+            let present = probe_i2c(&i2c, DSP_I2C_ADDR).is_ok();
+            match (DSP_PRESENT.swap(present, Ordering::Relaxed), present) {
+                (false, true) => {
+                    info!("DSP found at 0x{DSP_I2C_ADDR:02x}");
+                    reported_missing = false;
+                    self_test(&i2c, DSP_I2C_ADDR);
+                }
+                (true, false) => error!("DSP at 0x{DSP_I2C_ADDR:02x} stopped answering"),
+                (false, false) if !reported_missing => {
+                    error!("No DSP at 0x{DSP_I2C_ADDR:02x}, retrying until it answers");
+                    reported_missing = true;
+                }
+                _ => {}
+            }
+
+            thread::sleep(if present {
+                DSP_PRESENT_PROBE_INTERVAL
+            } else {
+                DSP_ABSENT_PROBE_INTERVAL
+            });
+        }
+    });
+}
+
+/// Un comando per il DSP mentre manca fallisce subito, senza aspettare il
+/// timeout dell'I2C. Gli altri indirizzi della chip map non sono controllati
+fn check_dsp_present(dev: u8) -> Result<(), I2cError> {
+    if dev == DSP_I2C_ADDR && !DSP_PRESENT.load(Ordering::Relaxed) {
+        return Err(I2cError::NoDevice);
+    }
+    Ok(())
+}
+
+fn device_info_body(info: &DeviceInfo) -> Value {
+    match info {
+        DeviceInfo::Unknown => json!({ "id": null, "part": null }),
+        DeviceInfo::Detected { id, part } => json!({ "id": hex_string(id), "part": part }),
+    }
+}
+
+/// Funzioni del firmware con la configurazione attuale
+fn device_capabilities(chip_map: &ChipMap, auth: bool) -> Capabilities {
+    (Capabilities::PROTOCOL | Capabilities::BATCH | Capabilities::STREAM)
+        .with_if(Capabilities::MULTI_CHIP, chip_map.entries().len() > 1)
+        .with_if(Capabilities::AUTH, auth)
+}
+
+fn capabilities_body(capabilities: Capabilities) -> Value {
+    json!({
+        "capabilities": capabilities.names(),
+        "bits": format!("0x{:08x}", capabilities.bits()),
+    })
+}
+
+/// Sends a /read response, written piece by piece so a large read never
+/// needs the whole body in memory. The body length is known upfront and sent
+/// as Content-Length.
+fn send_read_response(
+    request: Request<&mut EspHttpConnection<'_>>,
+    addr: u16,
+    data: &[u8],
+    i2c_time: Duration,
+) -> Result<(), EspIOError> {
+    const SUFFIX: &str = "\"}";
+    // bytes encoded per write
+    const BYTES_PER_WRITE: usize = 64;
+
+    let prefix = format!(r#"{{"addr":"0x{addr:04x}","len":{},"data":""#, data.len());
+    let body_len = prefix.len() + data.len() * 2 + SUFFIX.len();
+    let content_length = body_len.to_string();
+    let server_timing = i2c_server_timing(i2c_time);
+
+    let mut headers = CORS_HEADERS.to_vec();
+    headers.push(("Content-Type", "application/json"));
+    headers.push(("Content-Length", &content_length));
+    headers.push(("Server-Timing", &server_timing));
+
+    let mut response = request.into_response(200, None, &headers)?;
+    esp_idf_hal::io::Write::write_all(&mut response, prefix.as_bytes())?;
+    for chunk in data.chunks(BYTES_PER_WRITE) {
+        esp_idf_hal::io::Write::write_all(&mut response, hex_string(chunk).as_bytes())?;
+    }
+    esp_idf_hal::io::Write::write_all(&mut response, SUFFIX.as_bytes())?;
+    Ok(())
+}
+
+/// Bounds of a /read_multi or /ws register list
+fn check_read_list(regs: &[(u16, u16)]) -> Result<(), String> {
+    if regs.len() > HTTP_MAX_BATCH_READS {
+        return Err(format!(
+            "At most {HTTP_MAX_BATCH_READS} registers per request"
+        ));
+    }
+
+    if let Some((addr, len)) = regs.iter().find(|(_, len)| *len > HTTP_MAX_READ_LEN) {
+        return Err(format!(
+            "len {len} at 0x{addr:04x} exceeds maximum of {HTTP_MAX_READ_LEN} bytes"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads every register of the list, one /read style object each
+fn read_register_list(
+    i2c: &mut I2cDriver<'static>,
+    regs: &[(u16, u16)],
+) -> Result<Vec<Value>, anyhow::Error> {
+    regs.iter()
+        .map(|&(addr, len)| {
+            read_i2c(i2c, DSP_I2C_ADDR, addr, len as usize).map(|data| {
+                json!({
+                    "addr": format!("0x{addr:04x}"),
+                    "len": len,
+                    "data": hex_string(&data),
+                })
+            })
+        })
+        .collect()
+}
+
+/// Locks a mutex, recovering it if a thread panicked while holding it
+///
+/// The mutexes only guard configuration (chip map, token, log level...),
+/// replaced as a whole, so a panic can't leave them half updated.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| {
+        warn!("Recovering a mutex poisoned by a panicked thread");
+        mutex.clear_poison();
+        e.into_inner()
+    })
+}
+
+// I2C abstraction functions
+fn read_i2c_register(
+    i2c: &I2cBus,
+    dev: u8,
+    addr: u16,
+    len: usize,
+) -> Result<Vec<u8>, anyhow::Error> {
+    i2c.run(move |i2c| read_i2c(i2c, dev, addr, len))
+}
+
+// Same as read_i2c_register, for jobs already running on the I2C worker
+//
+// Reads longer than I2C_MAX_READ_CHUNK are split in several transactions, each
+// starting at the sub-address the DSP would have auto-incremented to
+fn read_i2c(
+    i2c: &mut I2cDriver<'static>,
+    dev: u8,
+    addr: u16,
+    len: usize,
+) -> Result<Vec<u8>, anyhow::Error> {
+    check_dsp_present(dev)?;
+    let mut data = Vec::with_capacity(len);
+    for (chunk_addr, chunk_len) in
+        split_read(addr, len as u32, I2C_MAX_READ_CHUNK, dsp_word_len(addr))
+    {
+        data.extend(read_i2c_chunk(i2c, dev, chunk_addr, chunk_len as usize)?);
+    }
+    Ok(data)
+}
+
+// A single I2C read transaction
+fn read_i2c_chunk(
+    i2c: &mut I2cDriver<'static>,
+    dev: u8,
+    addr: u16,
+    len: usize,
+) -> Result<Vec<u8>, anyhow::Error> {
+    // Converti l'indirizzo del parametro in un buffer di 2 byte (formato big-endian)
+    let param_addr_bytes = addr.to_be_bytes();
+
+    let timeout = TickType::new_millis(I2C_TIMEOUT_MS).ticks();
+
+    // Scrivi l'indirizzo del parametro al DSP
+    i2c.write(dev, &param_addr_bytes, timeout)
+        .map_err(I2cError::from)?;
+
+    // Ora leggi i dati dal DSP
+    let mut data = vec![0u8; len];
+    i2c.read(dev, &mut data, timeout).map_err(I2cError::from)?;
+
+    Ok(data)
+}
+
+fn write_i2c_register(i2c: &I2cBus, dev: u8, addr: u16, data: &[u8]) -> Result<(), anyhow::Error> {
+    let data = data.to_vec();
+    i2c.run(move |i2c| write_i2c(i2c, dev, addr, &data))
+}
+
+// Same as write_i2c_register, for jobs already running on the I2C worker
+fn write_i2c(
+    i2c: &mut I2cDriver<'static>,
+    dev: u8,
+    addr: u16,
+    data: &[u8],
+) -> Result<(), anyhow::Error> {
+    check_dsp_present(dev)?;
+    // Crea un buffer che contiene l'indirizzo del parametro + i dati da scrivere
+    let mut write_buf = Vec::with_capacity(2 + data.len());
+    write_buf.extend_from_slice(&addr.to_be_bytes());
+    write_buf.extend_from_slice(data);
+
+    i2c.write(
+        dev,
+        &write_buf,
+        TickType::new_millis(I2C_TIMEOUT_MS).ticks(),
+    )
+    .map_err(I2cError::from)?;
+
+    Ok(())
+}
+
+/// Lettura I2C semplice, senza indirizzo di registro, per /i2c_raw_read
+fn read_i2c_raw(i2c: &I2cBus, dev: u8, len: usize) -> Result<Vec<u8>, anyhow::Error> {
+    i2c.run(move |i2c| {
+        let mut data = vec![0u8; len];
+        i2c.read(dev, &mut data, TickType::new_millis(I2C_TIMEOUT_MS).ticks())
+            .map_err(I2cError::from)?;
+        Ok(data)
+    })
+}
+
+/// Scrittura I2C semplice, i bytes vanno sul bus così come sono, per /i2c_raw_write
+fn write_i2c_raw(i2c: &I2cBus, dev: u8, data: &[u8]) -> Result<(), anyhow::Error> {
+    let data = data.to_vec();
+    i2c.run(move |i2c| {
+        i2c.write(dev, &data, TickType::new_millis(I2C_TIMEOUT_MS).ticks())
+            .map_err(I2cError::from)?;
+        Ok(())
+    })
+}
+
+/// Indirizzo del dispositivo di /i2c_raw_*, errore e codice già pronti per la risposta
+fn parse_i2c_raw_dev(value: Option<&str>) -> Result<u8, (ErrorCode, String)> {
+    let Some(dev) = value.and_then(parse_number_to_u16) else {
+        return Err((ErrorCode::BadParam, "Missing or invalid addr".to_string()));
+    };
+    if !I2C_RAW_DEV_RANGE.contains(&dev) {
+        return Err((
+            ErrorCode::OutOfRange,
+            format!(
+                "addr 0x{dev:02x} is not a 7-bit device address (0x{:02x} to 0x{:02x})",
+                I2C_RAW_DEV_RANGE.start(),
+                I2C_RAW_DEV_RANGE.end()
+            ),
+        ));
+    }
+    Ok(dev as u8)
+}
+
+type GpioOutput = PinDriver<'static, AnyIOPin, InputOutput>;
+
+/// Porta `pin` al livello `high`, configurandolo come uscita alla prima chiamata.
+/// Restituisce il livello letto dopo la modifica
+fn set_gpio(
+    outputs: &mut HashMap<i32, GpioOutput>,
+    pin: i32,
+    high: bool,
+) -> Result<bool, EspError> {
+    let driver = match outputs.entry(pin) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            // SAFETY: il pin è in GPIO_ALLOWED_PINS, che esclude quelli usati da altre periferiche
+            entry.insert(PinDriver::input_output(unsafe { AnyIOPin::new(pin) })?)
+        }
+    };
+    driver.set_level(Level::from(high))?;
+    Ok(driver.is_high())
+}
+
+/// Livello di `pin`: quello pilotato se è un'uscita di /gpio, altrimenti letto come ingresso
+fn read_gpio(outputs: &HashMap<i32, GpioOutput>, pin: i32) -> Result<bool, EspError> {
+    if let Some(driver) = outputs.get(&pin) {
+        return Ok(driver.is_high());
+    }
+    // SAFETY: come in set_gpio, il driver viene rilasciato subito dopo la lettura
+    let driver = PinDriver::input(unsafe { AnyIOPin::new(pin) })?;
+    Ok(driver.is_high())
+}
+
+/// Safeload: dati nei registri di safeload, indirizzo di destinazione, poi il
+/// numero di parole che fa partire il trasferimento al frame successivo.
+/// Un solo lavoro sull'I2C, nessun'altra scrittura si infila a metà sequenza
+fn safeload_i2c_write(i2c: &I2cBus, dev: u8, addr: u16, data: &[u8]) -> Result<(), anyhow::Error> {
+    let sequence = DSP_SAFELOAD.sequence(addr, data)?;
+    write_i2c_sequence(i2c, dev, sequence)
+}
+
+/// Scritture di una sequenza di safeload, in ordine e in un solo lavoro
+fn write_i2c_sequence(
+    i2c: &I2cBus,
+    dev: u8,
+    sequence: Vec<(u16, Vec<u8>)>,
+) -> Result<(), anyhow::Error> {
+    i2c.run(move |i2c| {
+        for (reg, bytes) in sequence {
+            write_i2c(i2c, dev, reg, &bytes)?;
+        }
+        Ok(())
+    })
+}
+
+/// Mappa IC -> indirizzo I2C salvata in NVS, solo IC 1 su DSP_I2C_ADDR se
+/// manca o non è valida
+fn load_chip_map(nvs: &EspNvs<NvsDefault>) -> ChipMap {
+    let mut buf = [0u8; 128];
+    match nvs.get_str(NVS_CHIP_MAP_KEY, &mut buf) {
+        Ok(Some(value)) => match ChipMap::parse(value) {
+            Ok(chip_map) => return chip_map,
+            Err(e) => warn!("Ignoring invalid chip map {value:?} in NVS: {e}"),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Failed to read the chip map from NVS: {e}"),
+    }
+    ChipMap::single(DSP_I2C_ADDR)
+}
+
+/// Token richiesto ai client, None (autenticazione disabilitata) se non è in NVS
+fn load_auth_token(nvs: &EspNvs<NvsDefault>) -> Option<AuthToken> {
+    let mut buf = [0u8; 260];
+    match nvs.get_str(NVS_AUTH_TOKEN_KEY, &mut buf) {
+        Ok(Some(value)) => match AuthToken::new(value) {
+            Ok(token) => return Some(token),
+            Err(e) => warn!("Ignoring invalid auth token in NVS: {e}"),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Failed to read the auth token from NVS: {e}"),
+    }
+    None
+}
+
+fn i2c_master_init<'d>(
+    i2c: impl Peripheral<P = impl I2c> + 'd,
+    sda: AnyIOPin,
+    scl: AnyIOPin,
+    baudrate: Hertz,
+) -> anyhow::Result<I2cDriver<'d>> {
+    let config = I2cConfig::new().baudrate(baudrate);
+    let driver = I2cDriver::new(i2c, sda, scl, &config)?;
+    Ok(driver)
+}
+
+fn main() -> Result<()> {
+    // It is necessary to call this function once. Otherwise some patches to the runtime
+    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
+    esp_idf_svc::sys::link_patches();
+
+    // Bind the log crate to the ESP Logging facilities
+    esp_idf_svc::log::EspLogger::initialize_default();
+
+    let sysloop = EspSystemEventLoop::take()?;
+
+    let peripherals = Peripherals::take().unwrap();
+
+    // Inizializza I2C master
+    let i2c_master = i2c_master_init(
+        peripherals.i2c0,
+        peripherals.pins.gpio2.into(),
+        peripherals.pins.gpio5.into(),
+        400.kHz().into(),
+    )?;
+
+    log::info!("I2C initialized");
+
+    // il DSP si cerca in background, Wi-Fi e server partono anche senza
+    let _wifi = match my_wifi(peripherals.modem, sysloop) {
+        Ok(inner) => inner,
+        Err(err) => {
+            bail!("Could not connect to Wi-Fi network: {:?}", err)
+        }
+    };
+
+    watchdog::init(WATCHDOG_TIMEOUT)?;
+
+    let nvs = EspNvs::new(EspDefaultNvsPartition::take()?, NVS_NAMESPACE, true)?;
+    let chip_map = load_chip_map(&nvs);
+    info!("SigmaStudio IC to I2C address map: {chip_map}");
+    let chip_map = Arc::new(Mutex::new(chip_map));
+    let auth_token = load_auth_token(&nvs);
+    if auth_token.is_some() {
+        info!("HTTP and TCP clients must send the auth token");
+    }
+    let auth_token = Arc::new(Mutex::new(auth_token));
+    let nvs = Arc::new(Mutex::new(nvs));
+
+    // da qui in poi l'I2C appartiene al worker, gli altri thread gli mandano i lavori
+    let i2c = I2cBus::spawn(i2c_master, WATCHDOG_FEED_INTERVAL);
+    watch_dsp(i2c.clone());
+    let i2c_http = i2c.clone();
+    let chip_map_http = chip_map.clone();
+    let auth_http = auth_token.clone();
+
+    thread::spawn(move || {
+        watchdog::subscribe().unwrap();
+
+        let mut server = EspHttpServer::new(&esp_idf_svc::http::server::Configuration {
+            // serve a far corrispondere "/*" dell'handler OPTIONS a tutti i path
+            uri_match_wildcard: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        server
+            .fn_handler("/", Method::Get, |request| {
+                let mut response = request.into_response(200, Some("OK"), &CORS_HEADERS)?;
+
+                esp_idf_hal::io::Write::write_all(&mut response, "ok".as_bytes())?;
+                Ok::<(), EspIOError>(())
+            })
+            .unwrap();
+
+        // Read endpoint
+        let i2c_read = i2c_http.clone();
+        let auth_read = auth_http.clone();
+        server
+            .fn_handler("/read", Method::Get, move |request| {
+                if !authorized(&request, &auth_read) {
+                    return send_unauthorized(request);
+                }
+
+                // Get the URI as a string
+                let uri = request.uri().to_string();
+
+                // Parse parameters using our abstracted function
+                let params = parse_http_params(&uri);
+
+                // Extract and parse specific parameters
+                let Some(addr) = params.get("addr").and_then(|v| parse_number_to_u16(v)) else {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(ErrorCode::BadParam, "Missing or invalid addr"),
+                    );
+                };
+
+                let Some(len) = params.get("len").and_then(|v| parse_number_to_u16(v)) else {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(ErrorCode::BadParam, "Missing or invalid len"),
+                    );
+                };
+
+                if len > HTTP_MAX_READ_LEN {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(
+                            ErrorCode::OutOfRange,
+                            format!("len {len} exceeds maximum of {HTTP_MAX_READ_LEN} bytes"),
+                        ),
+                    );
+                }
+
+                info!("Reading from I2C address: 0x{:04x} length: {}", addr, len);
+
+                // Use the abstracted I2C read function
+                let start = Instant::now();
+                let result = read_i2c_register(&i2c_read, DSP_I2C_ADDR, addr, len as usize);
+                let i2c_time = start.elapsed();
+
+                match result {
+                    Ok(data) => send_read_response(request, addr, &data, i2c_time),
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(i2c_error_code(&e), format!("Failed to read from I2C: {e}")),
+                    ),
+                }
+            })
+            .unwrap();
+
+        // Batch read endpoint
+        let i2c_read_multi = i2c_http.clone();
+        let auth_read_multi = auth_http.clone();
+        server
+            .fn_handler("/read_multi", Method::Get, move |request| {
+                if !authorized(&request, &auth_read_multi) {
+                    return send_unauthorized(request);
+                }
+
+                let params = parse_http_params(request.uri());
+
+                let regs = match params.get("regs").map(|v| parse_read_list(v)) {
+                    Some(Ok(regs)) => regs,
+                    Some(Err(e)) => {
+                        return send_json(request, 400, &error_body(ErrorCode::BadParam, e));
+                    }
+                    None => {
+                        return send_json(
+                            request,
+                            400,
+                            &error_body(ErrorCode::BadParam, "Missing regs"),
+                        );
+                    }
+                };
+
+                if let Err(e) = check_read_list(&regs) {
+                    return send_json(request, 400, &error_body(ErrorCode::OutOfRange, e));
+                }
+
+                info!("Batch reading {} registers", regs.len());
+
+                // tutta la lista in un solo lavoro, il tempo non conta l'attesa in coda
+                let result = i2c_read_multi.run(move |i2c| {
+                    let start = Instant::now();
+                    read_register_list(i2c, &regs).map(|results| (results, start.elapsed()))
+                });
+
+                match result {
+                    Ok((results, i2c_time)) => send_json_with_headers(
+                        request,
+                        200,
+                        &Value::Array(results),
+                        &[("Server-Timing", &i2c_server_timing(i2c_time))],
+                    ),
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(i2c_error_code(&e), format!("Failed to read from I2C: {e}")),
+                    ),
+                }
+            })
+            .unwrap();
+
+        // Write endpoint
+        let i2c_write = i2c_http.clone();
+        let auth_write = auth_http.clone();
+        server
+            .fn_handler("/write", Method::Get, move |request| {
+                if !authorized(&request, &auth_write) {
+                    return send_unauthorized(request);
+                }
+
+                // Get the URI as a string
+                let uri = request.uri().to_string();
+
+                // Parse parameters using our abstracted function
+                let params = parse_http_params(&uri);
+
+                // Extract and parse specific parameters
+                let Some(addr) = params.get("addr").and_then(|v| parse_number_to_u16(v)) else {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(ErrorCode::BadParam, "Missing or invalid addr"),
+                    );
+                };
+
+                // Parse data from hex string
+                let data = match params.get("data").map(|v| parse_hex_data(v)) {
+                    Some(Ok(data)) => data,
+                    Some(Err(e)) => {
+                        return send_json(request, 400, &error_body(ErrorCode::BadParam, e));
+                    }
+                    None => Vec::new(),
+                };
+
+                if FROZEN.load(Ordering::Relaxed) {
+                    return send_json(request, 409, &frozen_body());
+                }
+
+                info!(
+                    "Writing to I2C address: 0x{:04x} length: {}",
+                    addr,
+                    data.len()
+                );
+
+                // Use the abstracted I2C write function
+                match write_i2c_register(&i2c_write, DSP_I2C_ADDR, addr, &data) {
+                    Ok(_) => send_json(
+                        request,
+                        200,
+                        &json!({
+                            "status": "ok",
+                            "addr": format!("0x{addr:04x}"),
+                            "data_written": format!("{data:02X?}"),
+                            "length": data.len(),
+                        }),
+                    ),
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(i2c_error_code(&e), format!("Failed to write to I2C: {e}")),
+                    ),
+                }
+            })
+            .unwrap();
+
+        // Batch write endpoint
+        let i2c_write_multi = i2c_http.clone();
+        let auth_write_multi = auth_http.clone();
+        server
+            .fn_handler("/write_multi", Method::Post, move |mut request| {
+                if !authorized(&request, &auth_write_multi) {
+                    return send_unauthorized(request);
+                }
+                let safeload = parse_http_params(request.uri()).get("safeload") == Some("1");
+
+                let writes = match read_body(&mut request, HTTP_MAX_WRITE_BODY_LEN)
+                    .and_then(|body| parse_write_list(&body))
+                {
+                    Ok(writes) => writes,
+                    Err((code, e)) => return send_json(request, 400, &error_body(code, e)),
+                };
+
+                if FROZEN.load(Ordering::Relaxed) {
+                    return send_json(request, 409, &frozen_body());
+                }
+
+                if safeload {
+                    // le parole nello stesso frame audio, o nessuna
+                    let sequence = match SafeloadBatch::from_writes(&writes)
+                        .and_then(|batch| DSP_SAFELOAD.batch_sequence(&batch))
+                    {
+                        Ok(sequence) => sequence,
+                        Err(e) => {
+                            return send_json(request, 400, &error_body(ErrorCode::BadParam, e))
+                        }
+                    };
+                    info!("Safeloading {} registers", writes.len());
+
+                    return match write_i2c_sequence(&i2c_write_multi, DSP_I2C_ADDR, sequence) {
+                        Ok(()) => {
+                            let results = writes
+                                .iter()
+                                .map(|(addr, _)| {
+                                    json!({
+                                        "addr": format!("0x{addr:04x}"),
+                                        "status": "ok",
+                                    })
+                                })
+                                .collect();
+                            send_json(request, 200, &Value::Array(results))
+                        }
+                        Err(e) => send_json(
+                            request,
+                            500,
+                            &error_body(i2c_error_code(&e), format!("Failed to safeload: {e}")),
+                        ),
+                    };
+                }
+
+                info!("Batch writing {} registers", writes.len());
+
+                // tutte le scritture in un solo lavoro, un errore non ferma le successive
+                let results = i2c_write_multi.run(move |i2c| {
+                    Ok(writes
+                        .iter()
+                        .map(
+                            |(addr, data)| match write_i2c(i2c, DSP_I2C_ADDR, *addr, data) {
+                                Ok(()) => json!({
+                                    "addr": format!("0x{addr:04x}"),
+                                    "status": "ok",
+                                }),
+                                Err(e) => json!({
+                                    "addr": format!("0x{addr:04x}"),
+                                    "status": "error",
+                                    "error": format!("Failed to write to I2C: {e}"),
+                                    "code": i2c_error_code(&e).as_str(),
+                                }),
+                            },
+                        )
+                        .collect())
+                });
+
+                match results {
+                    Ok(results) => send_json(request, 200, &Value::Array(results)),
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(i2c_error_code(&e), format!("Failed to write to I2C: {e}")),
+                    ),
+                }
+            })
+            .unwrap();
+
+        // Raw protocol frame endpoint, the same path as TCP commands
+        let i2c_raw = i2c_http.clone();
+        let chip_map_raw = chip_map_http.clone();
+        let auth_raw = auth_http.clone();
+        server
+            .fn_handler("/raw", Method::Post, move |mut request| {
+                if !authorized(&request, &auth_raw) {
+                    return send_unauthorized(request);
+                }
+
+                let frame =
+                    match read_body(&mut request, HTTP_MAX_WRITE_BODY_LEN).and_then(|body| {
+                        let hex: String = String::from_utf8_lossy(&body)
+                            .chars()
+                            .filter(|c| !c.is_ascii_whitespace())
+                            .collect();
+                        parse_hex_data(&hex).map_err(|e| (ErrorCode::BadParam, e))
+                    }) {
+                        Ok(frame) if !frame.is_empty() => frame,
+                        Ok(_) => {
+                            return send_json(
+                                request,
+                                400,
+                                &error_body(ErrorCode::BadParam, "Empty frame"),
+                            );
+                        }
+                        Err((code, e)) => return send_json(request, 400, &error_body(code, e)),
+                    };
+
+                let chip_map = lock(&chip_map_raw).clone();
+                let auth = lock(&auth_raw).is_some();
+                let mut resync = Resync::default();
+                let mut framing = ConnectionFraming::default();
+                let mut response = Vec::new();
+
+                let mut offset = 0;
+                while offset < frame.len() {
+                    match process_command(
+                        &frame[offset..],
+                        &i2c_raw,
+                        &chip_map,
+                        auth,
+                        &mut resync,
+                        &mut framing,
+                    ) {
+                        Ok((command_response, bytes_read)) => {
+                            offset += bytes_read;
+                            response.extend(command_response.to_bytes_with(framing.checksum()));
+                        }
+                        Err(e) => {
+                            return send_json(
+                                request,
+                                400,
+                                &error_body(
+                                    ErrorCode::BadParam,
+                                    format!("Command at byte {offset}: {e:#}"),
+                                ),
+                            );
+                        }
+                    }
+                }
+
+                send_json(
+                    request,
+                    200,
+                    &json!({ "response": hex_string(&response), "length": response.len() }),
+                )
+            })
+            .unwrap();
 
-// Definizione dell'indirizzo I2C del DSP
-const DSP_I2C_ADDR: u8 = 0x3b;
+        // Identify endpoint
+        let i2c_identify = i2c_http.clone();
+        let auth_identify = auth_http.clone();
+        server
+            .fn_handler("/identify", Method::Get, move |request| {
+                if !authorized(&request, &auth_identify) {
+                    return send_unauthorized(request);
+                }
 
-// Parse HTTP query parameters into a HashMap with smart value parsing
-fn parse_http_params(uri: &str) -> HashMap<String, String> {
-    let mut params = HashMap::new();
-
-    // Extract query part (after ?)
-    if let Some(query) = uri.split('?').nth(1) {
-        // Split by & to get individual parameters
-        for param in query.split('&') {
-            // Split by = to get key-value pairs
-            if let Some((key, value)) = param.split_once('=') {
-                params.insert(key.to_string(), value.to_string());
-            }
-        }
-    }
+                match identify_dsp(&i2c_identify, DSP_I2C_ADDR) {
+                    Ok(info) => send_json(request, 200, &device_info_body(&info)),
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(
+                            i2c_error_code(&e),
+                            format!("Failed to read the ID register: {e}"),
+                        ),
+                    ),
+                }
+            })
+            .unwrap();
 
-    params
-}
+        // Self-test endpoint, the same check as at boot
+        let i2c_self_test = i2c_http.clone();
+        let auth_self_test = auth_http.clone();
+        server
+            .fn_handler("/selftest", Method::Get, move |request| {
+                if !authorized(&request, &auth_self_test) {
+                    return send_unauthorized(request);
+                }
 
-// Helper function to parse a string to u16, supporting both hex (0x prefix) and decimal
-fn parse_number_to_u16(value: &str) -> Option<u16> {
-    if value.starts_with("0x") || value.starts_with("0X") {
-        // Parse as hex
-        u16::from_str_radix(value.trim_start_matches("0x").trim_start_matches("0X"), 16).ok()
-    } else {
-        // Parse as decimal
-        value.parse::<u16>().ok()
-    }
-}
+                let params = parse_http_params(request.uri());
+                let cached = match params.get("cached") {
+                    Some("1") => lock(&SELF_TEST).clone(),
+                    _ => None,
+                };
+                let result = cached.unwrap_or_else(|| self_test(&i2c_self_test, DSP_I2C_ADDR));
 
-// Parse hex data from string, supporting both hex (0x prefix) and space-separated bytes
-fn parse_hex_data(hex_str: &str) -> Vec<u8> {
-    let mut data = Vec::new();
+                send_json(request, 200, &result.to_json())
+            })
+            .unwrap();
 
-    // Clean the input string
-    let clean_value = hex_str.trim_start_matches("0x").trim_start_matches("0X");
+        // Status endpoint, reachable even without a DSP
+        let auth_status = auth_http.clone();
+        server
+            .fn_handler("/status", Method::Get, move |request| {
+                if !authorized(&request, &auth_status) {
+                    return send_unauthorized(request);
+                }
 
-    // Convert hex string to bytes
-    for i in (0..clean_value.len()).step_by(2) {
-        if i + 1 < clean_value.len() {
-            if let Ok(byte) = u8::from_str_radix(&clean_value[i..i + 2], 16) {
-                data.push(byte);
-            }
-        }
-    }
+                send_json(
+                    request,
+                    200,
+                    &json!({
+                        "dsp_present": DSP_PRESENT.load(Ordering::Relaxed),
+                        "frozen": FROZEN.load(Ordering::Relaxed),
+                        "log": lock(&LOG_LEVEL).as_str().to_lowercase(),
+                    }),
+                )
+            })
+            .unwrap();
 
-    data
-}
+        // Raw I2C read endpoint, for devices other than the DSP
+        let i2c_raw_read = i2c_http.clone();
+        let auth_raw_read = auth_http.clone();
+        server
+            .fn_handler("/i2c_raw_read", Method::Get, move |request| {
+                if !authorized(&request, &auth_raw_read) {
+                    return send_unauthorized(request);
+                }
 
-// I2C abstraction functions
-fn read_i2c_register(
-    i2c: &Arc<Mutex<I2cDriver<'static>>>,
-    addr: u16,
-    len: u16,
-) -> Result<Vec<u8>, anyhow::Error> {
-    let mut i2c = i2c.lock().unwrap();
+                let params = parse_http_params(request.uri());
 
-    // Converti l'indirizzo del parametro in un buffer di 2 byte (formato big-endian)
-    let param_addr_bytes = addr.to_be_bytes();
+                let dev = match parse_i2c_raw_dev(params.get("addr")) {
+                    Ok(dev) => dev,
+                    Err((code, e)) => return send_json(request, 400, &error_body(code, e)),
+                };
 
-    // Scrivi l'indirizzo del parametro al DSP
-    i2c.write(DSP_I2C_ADDR, &param_addr_bytes, BLOCK)?;
+                let Some(len) = params.get("len").and_then(parse_number_to_u16) else {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(ErrorCode::BadParam, "Missing or invalid len"),
+                    );
+                };
 
-    // Ora leggi i dati dal DSP
-    let mut data = vec![0u8; len as usize];
-    i2c.read(DSP_I2C_ADDR, &mut data, BLOCK)?;
+                if len == 0 || len > HTTP_MAX_READ_LEN {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(
+                            ErrorCode::OutOfRange,
+                            format!("len must be between 1 and {HTTP_MAX_READ_LEN} bytes"),
+                        ),
+                    );
+                }
 
-    Ok(data)
-}
+                info!("Raw I2C read from device 0x{:02x} length: {}", dev, len);
+
+                match read_i2c_raw(&i2c_raw_read, dev, len as usize) {
+                    Ok(data) => send_json(
+                        request,
+                        200,
+                        &json!({
+                            "dev": format!("0x{dev:02x}"),
+                            "len": data.len(),
+                            "data": hex_string(&data),
+                        }),
+                    ),
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(i2c_error_code(&e), format!("Failed to read from I2C: {e}")),
+                    ),
+                }
+            })
+            .unwrap();
 
-fn write_i2c_register(
-    i2c: &Arc<Mutex<I2cDriver<'static>>>,
-    addr: u16,
-    data: &[u8],
-) -> Result<(), anyhow::Error> {
-    let mut i2c = i2c.lock().unwrap();
+        // Raw I2C write endpoint, for devices other than the DSP
+        let i2c_raw_write = i2c_http.clone();
+        let auth_raw_write = auth_http.clone();
+        server
+            .fn_handler("/i2c_raw_write", Method::Get, move |request| {
+                if !authorized(&request, &auth_raw_write) {
+                    return send_unauthorized(request);
+                }
 
-    // Crea un buffer che contiene l'indirizzo del parametro + i dati da scrivere
-    let mut write_buf = Vec::with_capacity(2 + data.len());
-    write_buf.extend_from_slice(&addr.to_be_bytes());
-    write_buf.extend_from_slice(data);
+                let params = parse_http_params(request.uri());
 
-    i2c.write(DSP_I2C_ADDR, &write_buf, BLOCK)?;
+                let dev = match parse_i2c_raw_dev(params.get("addr")) {
+                    Ok(dev) => dev,
+                    Err((code, e)) => return send_json(request, 400, &error_body(code, e)),
+                };
 
-    Ok(())
-}
+                let data = match params.get("data").map(parse_hex_data) {
+                    Some(Ok(data)) if !data.is_empty() => data,
+                    Some(Err(e)) => {
+                        return send_json(request, 400, &error_body(ErrorCode::BadParam, e));
+                    }
+                    _ => {
+                        return send_json(
+                            request,
+                            400,
+                            &error_body(ErrorCode::BadParam, "Missing or empty data"),
+                        );
+                    }
+                };
 
-fn i2c_master_init<'d>(
-    i2c: impl Peripheral<P = impl I2c> + 'd,
-    sda: AnyIOPin,
-    scl: AnyIOPin,
-    baudrate: Hertz,
-) -> anyhow::Result<I2cDriver<'d>> {
-    let config = I2cConfig::new().baudrate(baudrate);
-    let driver = I2cDriver::new(i2c, sda, scl, &config)?;
-    Ok(driver)
-}
+                if FROZEN.load(Ordering::Relaxed) {
+                    return send_json(request, 409, &frozen_body());
+                }
 
-fn main() -> Result<()> {
-    // It is necessary to call this function once. Otherwise some patches to the runtime
-    // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
-    esp_idf_svc::sys::link_patches();
+                info!(
+                    "Raw I2C write to device 0x{:02x} length: {}",
+                    dev,
+                    data.len()
+                );
+
+                match write_i2c_raw(&i2c_raw_write, dev, &data) {
+                    Ok(()) => send_json(
+                        request,
+                        200,
+                        &json!({
+                            "status": "ok",
+                            "dev": format!("0x{dev:02x}"),
+                            "length": data.len(),
+                        }),
+                    ),
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(i2c_error_code(&e), format!("Failed to write to I2C: {e}")),
+                    ),
+                }
+            })
+            .unwrap();
 
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+        // GPIO endpoint, pins are configured on first use and kept in the map
+        let auth_gpio = auth_http.clone();
+        let gpio_outputs: Arc<Mutex<HashMap<i32, GpioOutput>>> = Arc::default();
+        server
+            .fn_handler("/gpio", Method::Get, move |request| {
+                if !authorized(&request, &auth_gpio) {
+                    return send_unauthorized(request);
+                }
 
-    let sysloop = EspSystemEventLoop::take()?;
+                let params = parse_http_params(request.uri());
 
-    let peripherals = Peripherals::take().unwrap();
+                let Some(pin) = params.get("pin").and_then(|v| v.parse::<i32>().ok()) else {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(ErrorCode::BadParam, "Missing or invalid pin"),
+                    );
+                };
 
-    // Inizializza I2C master
-    let mut i2c_master = i2c_master_init(
-        peripherals.i2c0,
-        peripherals.pins.gpio2.into(),
-        peripherals.pins.gpio5.into(),
-        400.kHz().into(),
-    )?;
+                if !GPIO_ALLOWED_PINS.contains(&pin) {
+                    return send_json(
+                        request,
+                        400,
+                        &error_body(
+                            ErrorCode::OutOfRange,
+                            format!("GPIO {pin} is not one of {GPIO_ALLOWED_PINS:?}"),
+                        ),
+                    );
+                }
 
-    log::info!("I2C initialized");
+                let result = match params.get("level") {
+                    Some(value) => {
+                        let high = match value {
+                            "1" => true,
+                            "0" => false,
+                            _ => {
+                                return send_json(
+                                    request,
+                                    400,
+                                    &error_body(ErrorCode::BadParam, "level must be 0 or 1"),
+                                );
+                            }
+                        };
+
+                        if FROZEN.load(Ordering::Relaxed) {
+                            return send_json(request, 409, &frozen_body());
+                        }
 
-    // scan all I2C devices
+                        info!("Setting GPIO {pin} to {}", high as u8);
+                        set_gpio(&mut lock(&gpio_outputs), pin, high)
+                    }
+                    None => read_gpio(&lock(&gpio_outputs), pin),
+                };
+
+                match result {
+                    Ok(high) => {
+                        send_json(request, 200, &json!({ "pin": pin, "level": high as u8 }))
+                    }
+                    Err(e) => send_json(
+                        request,
+                        500,
+                        &error_body(ErrorCode::Gpio, format!("Failed to access GPIO {pin}: {e}")),
+                    ),
+                }
+            })
+            .unwrap();
 
-    loop {
-        let mut found = false;
+        // Capabilities endpoint, open so a client can find out it needs a token
+        let chip_map_capabilities = chip_map_http.clone();
+        let auth_capabilities = auth_http.clone();
+        server
+            .fn_handler("/capabilities", Method::Get, move |request| {
+                let capabilities = device_capabilities(
+                    &lock(&chip_map_capabilities),
+                    lock(&auth_capabilities).is_some(),
+                );
+                send_json(request, 200, &capabilities_body(capabilities))
+            })
+            .unwrap();
 
-        for i in 0..127 {
-            let mut buf = [0u8; 1];
-            match i2c_master.read(i, &mut buf, BLOCK) {
-                Ok(_) => {
-                    log::info!("Found I2C device at address: {i:#04x}");
-                    found = true;
+        // Configuration endpoint
+        let auth_config = auth_http.clone();
+        server
+            .fn_handler("/config", Method::Get, move |request| {
+                if !authorized(&request, &auth_config) {
+                    return send_unauthorized(request);
                 }
-                Err(_e) => {
-                    // log::error!("Error reading I2C device at address {:#04x}: {:?}", i, e);
+
+                let params = parse_http_params(request.uri());
+
+                if let Some(value) = params.get("chip_map") {
+                    let new_map = match ChipMap::parse(value) {
+                        Ok(new_map) => new_map,
+                        Err(e) => {
+                            return send_json(
+                                request,
+                                400,
+                                &error_body(ErrorCode::BadParam, format!("Invalid chip_map: {e}")),
+                            );
+                        }
+                    };
+
+                    if let Err(e) = lock(&nvs).set_str(NVS_CHIP_MAP_KEY, &new_map.to_string()) {
+                        return send_json(
+                            request,
+                            500,
+                            &error_body(
+                                ErrorCode::Storage,
+                                format!("Failed to save the chip map: {e}"),
+                            ),
+                        );
+                    }
+
+                    info!("SigmaStudio IC to I2C address map: {new_map}");
+                    *lock(&chip_map_http) = new_map;
                 }
-            }
-        }
 
-        if found {
-            break;
-        } else {
-            log::error!("No I2C devices found");
-            std::thread::sleep(std::time::Duration::from_secs(1));
-        }
-    }
+                if let Some(value) = params.get("freeze") {
+                    let frozen = match value {
+                        "1" | "true" => true,
+                        "0" | "false" => false,
+                        _ => {
+                            return send_json(
+                                request,
+                                400,
+                                &error_body(ErrorCode::BadParam, "freeze must be 0 or 1"),
+                            );
+                        }
+                    };
+                    FROZEN.store(frozen, Ordering::Relaxed);
+                    warn!("Writes {}", if frozen { "frozen" } else { "unfrozen" });
+                }
 
-    let _wifi = match my_wifi(peripherals.modem, sysloop) {
-        Ok(inner) => inner,
-        Err(err) => {
-            bail!("Could not connect to Wi-Fi network: {:?}", err)
-        }
-    };
+                if let Some(value) = params.get("token") {
+                    // un token vuoto disabilita l'autenticazione
+                    let token = if value.is_empty() {
+                        None
+                    } else {
+                        match AuthToken::new(value) {
+                            Ok(token) => Some(token),
+                            Err(e) => {
+                                return send_json(
+                                    request,
+                                    400,
+                                    &error_body(ErrorCode::BadParam, format!("Invalid token: {e}")),
+                                );
+                            }
+                        }
+                    };
+
+                    let saved = match &token {
+                        Some(token) => lock(&nvs).set_str(NVS_AUTH_TOKEN_KEY, token.as_str()),
+                        None => lock(&nvs).remove(NVS_AUTH_TOKEN_KEY).map(|_| ()),
+                    };
+                    if let Err(e) = saved {
+                        return send_json(
+                            request,
+                            500,
+                            &error_body(
+                                ErrorCode::Storage,
+                                format!("Failed to save the auth token: {e}"),
+                            ),
+                        );
+                    }
 
-    let i2c = Arc::new(Mutex::new(i2c_master));
-    let i2c_http = i2c.clone();
+                    warn!(
+                        "Authentication {}",
+                        if token.is_some() {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                    *lock(&auth_config) = token;
+                }
 
-    thread::spawn(move || {
-        let mut server =
-            EspHttpServer::new(&esp_idf_svc::http::server::Configuration::default()).unwrap();
+                if let Some(value) = params.get("log") {
+                    let Ok(level) = value.parse::<LevelFilter>() else {
+                        return send_json(
+                            request,
+                            400,
+                            &error_body(
+                                ErrorCode::BadParam,
+                                "log must be off, error, warn, info, debug or trace",
+                            ),
+                        );
+                    };
+                    set_log_level(level);
+                    warn!("Log level set to {}", level);
+                }
 
-        server
-            .fn_handler("/", Method::Get, |request| {
-                let mut response = request.into_response(
+                let chip_map = lock(&chip_map_http).to_string();
+                send_json(
+                    request,
                     200,
-                    Some("OK"),
-                    &[
-                        ("Access-Control-Allow-Origin", "*"),
-                        ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
-                        ("Access-Control-Allow-Headers", "Content-Type"),
-                    ],
-                )?;
-
-                esp_idf_hal::io::Write::write_all(&mut response, "ok".as_bytes())?;
-                Ok::<(), EspIOError>(())
+                    &json!({
+                        "chip_map": chip_map,
+                        "frozen": FROZEN.load(Ordering::Relaxed),
+                        "auth": lock(&auth_config).is_some(),
+                        "log": lock(&LOG_LEVEL).as_str().to_lowercase(),
+                    }),
+                )
             })
             .unwrap();
 
-        // Read endpoint
-        let i2c_read = i2c_http.clone();
+        // Live register stream, see the API documentation for the protocol
+        let i2c_ws = i2c_http.clone();
+        let auth_ws = auth_http.clone();
+        let ws_streams: Arc<Mutex<HashMap<i32, Arc<AtomicBool>>>> = Arc::default();
         server
-            .fn_handler("/read", Method::Get, move |request| {
-                // Get the URI as a string
-                let uri = request.uri().to_string();
-
-                // Parse parameters using our abstracted function
-                let params = parse_http_params(&uri);
+            .ws_handler("/ws", move |ws| {
+                let session = ws.session();
 
-                // Extract and parse specific parameters
-                let addr = params
-                    .get("addr")
-                    .and_then(|v| parse_number_to_u16(v))
-                    .unwrap_or(0);
+                if ws.is_new() {
+                    info!("WebSocket session {session} opened");
+                    return Ok::<(), EspError>(());
+                }
 
-                let len = params
-                    .get("len")
-                    .and_then(|v| parse_number_to_u16(v))
-                    .unwrap_or(0);
+                if ws.is_closed() {
+                    info!("WebSocket session {session} closed");
+                    if let Some(stop) = lock(&ws_streams).remove(&session) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    return Ok(());
+                }
 
-                info!("Reading from I2C address: 0x{:04x} length: {}", addr, len);
+                let (_, len) = ws.recv(&mut [])?;
+                if len > WS_MAX_MESSAGE_LEN {
+                    let body = error_body(ErrorCode::OutOfRange, "Message too long");
+                    return ws.send(FrameType::Text(false), body.to_string().as_bytes());
+                }
 
-                let mut response = request.into_response(
-                    200,
-                    Some("OK"),
-                    &[
-                        ("Access-Control-Allow-Origin", "*"),
-                        ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
-                        ("Access-Control-Allow-Headers", "Content-Type"),
-                    ],
-                )?;
+                let mut buf = [0; WS_MAX_MESSAGE_LEN];
+                ws.recv(&mut buf)?;
 
-                // Use the abstracted I2C read function
-                let result = match read_i2c_register(&i2c_read, addr, len) {
-                    Ok(data) => {
-                        format!(
-                            "{{\"addr\": \"0x{:04x}\", \"len\": {}, \"data\": \"{:02X?}\" }}",
-                            addr, len, data
-                        )
-                    }
-                    Err(e) => {
-                        format!("{{\"error\": \"Failed to read from I2C: {}\"}}", e)
+                let token = lock(&auth_ws).clone();
+                let regs = match parse_subscribe_message(&buf[..len], token.as_ref()) {
+                    Ok(regs) => regs,
+                    Err((code, e)) => {
+                        let body = error_body(code, e);
+                        return ws.send(FrameType::Text(false), body.to_string().as_bytes());
                     }
                 };
 
-                esp_idf_hal::io::Write::write_all(&mut response, result.as_bytes())?;
-                Ok::<(), EspIOError>(())
-            })
-            .unwrap();
-
-        // Write endpoint
-        let i2c_write = i2c_http.clone();
-        server.fn_handler("/write", Method::Get, move |request| {
-            // Get the URI as a string
-            let uri = request.uri().to_string();
+                info!(
+                    "WebSocket session {session} subscribed to {} registers",
+                    regs.len()
+                );
 
-            // Parse parameters using our abstracted function
-            let params = parse_http_params(&uri);
+                let mut sender = ws.create_detached_sender()?;
+                let stop = Arc::new(AtomicBool::new(false));
 
-            // Extract and parse specific parameters
-            let addr = params.get("addr")
-                .and_then(|v| parse_number_to_u16(v))
-                .unwrap_or(0);
+                // a new subscription replaces the previous one of the same session
+                if let Some(previous) = lock(&ws_streams).insert(session, stop.clone()) {
+                    previous.store(true, Ordering::Relaxed);
+                }
 
-            // Parse data from hex string
-            let data = params.get("data")
-                .map(|v| parse_hex_data(v))
-                .unwrap_or_else(Vec::new);
+                let i2c = i2c_ws.clone();
+                thread::spawn(move || {
+                    if let Err(e) = watchdog::subscribe() {
+                        error!("Failed to subscribe to watchdog: {e}");
+                    }
 
-            info!("Writing to I2C address: 0x{:04x} length: {}", addr, data.len());
+                    // scadenze assolute: una lettura lenta non sposta i tick successivi
+                    let mut ticker = Ticker::new(Instant::now(), WS_PUSH_INTERVAL);
 
-            let mut response = request.into_response(200, Some("OK"), &[
-                ("Access-Control-Allow-Origin", "*"),
-                ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
-                ("Access-Control-Allow-Headers", "Content-Type"),
-            ])?;
+                    loop {
+                        let (tick, wait) = ticker.next_tick(Instant::now());
+                        thread::sleep(wait);
+                        if stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        watchdog::feed();
+
+                        let regs = regs.clone();
+                        let start = Instant::now();
+                        let result = i2c.run(move |i2c| read_register_list(i2c, &regs));
+                        let read_time = start.elapsed();
+
+                        let body = match result {
+                            Ok(values) => json!({
+                                "seq": tick,
+                                "t_ms": ticker.offset(tick).as_millis() as u64,
+                                "read_us": read_time.as_micros() as u64,
+                                "values": values,
+                            }),
+                            Err(e) => error_body(
+                                i2c_error_code(&e),
+                                format!("Failed to read from I2C: {e}"),
+                            ),
+                        };
+
+                        if let Err(e) =
+                            sender.send(FrameType::Text(false), body.to_string().as_bytes())
+                        {
+                            info!("WebSocket session {session} stream stopped: {e}");
+                            break;
+                        }
+                    }
 
-            // Use the abstracted I2C write function
-            let result = match write_i2c_register(&i2c_write, addr, &data) {
-                Ok(_) => {
-                    format!("{{\"status\": \"ok\", \"addr\": \"0x{:04x}\", \"data_written\": \"{:02X?}\", \"length\": {} }}", 
-                        addr, data, data.len())
-                },
-                Err(e) => {
-                    format!("{{\"error\": \"Failed to write to I2C: {}\"}}", e)
-                }
-            };
+                    watchdog::unsubscribe();
+                });
 
-            esp_idf_hal::io::Write::write_all(&mut response, result.as_bytes())?;
-            Ok::<(), EspIOError>(())
-        }).unwrap();
+                Ok(())
+            })
+            .unwrap();
 
-        // Add OPTIONS handler to support preflight requests
+        // CORS preflight: 204 with the same Allow-* headers as the other handlers
         server
-            .fn_handler("/*", Method::Options, |request| Ok::<(), EspIOError>(()))
+            .fn_handler("/*", Method::Options, |request| {
+                request.into_response(204, Some("No Content"), &CORS_HEADERS)?;
+                Ok::<(), EspIOError>(())
+            })
             .unwrap();
 
         loop {
-            std::thread::sleep(std::time::Duration::from_millis(5000));
+            watchdog::feed();
+            std::thread::sleep(WATCHDOG_FEED_INTERVAL);
         }
     });
 
     // Passa l'I2C master al server TCP
-    tcp_server(i2c)?;
+    tcp_server(i2c, chip_map, auth_token)?;
 
     Ok(())
 }
 
-fn tcp_server(i2c: Arc<Mutex<I2cDriver<'static>>>) -> Result<(), io::Error> {
-    fn accept(i2c: Arc<Mutex<I2cDriver<'static>>>) -> Result<(), io::Error> {
+fn tcp_server(
+    i2c: I2cBus,
+    chip_map: Arc<Mutex<ChipMap>>,
+    auth_token: Arc<Mutex<Option<AuthToken>>>,
+) -> Result<(), io::Error> {
+    fn accept(
+        i2c: I2cBus,
+        chip_map: Arc<Mutex<ChipMap>>,
+        auth_token: Arc<Mutex<Option<AuthToken>>>,
+    ) -> Result<(), io::Error> {
         let listener = TcpListener::bind("0.0.0.0:8086")?;
 
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
+        // poll the listener so the accept loop can keep feeding the watchdog while idle
+        listener.set_nonblocking(true)?;
+        watchdog::subscribe().map_err(io::Error::other)?;
+
+        loop {
+            watchdog::feed();
+
+            match listener.accept() {
+                Ok((stream, _)) => {
                     info!("Accepted client");
+                    // an error here only drops this client, the accept loop goes on
+                    if let Err(e) = stream.set_nonblocking(false) {
+                        error!("Failed to configure client socket: {e}");
+                        continue;
+                    }
                     let i2c_clone = i2c.clone();
+                    let chip_map_clone = chip_map.clone();
+                    // un token cambiato da /config vale dalla connessione successiva
+                    let token = lock(&auth_token).clone();
                     thread::spawn(move || {
-                        handle(stream, i2c_clone);
+                        if let Err(e) = watchdog::subscribe() {
+                            error!("Failed to subscribe to watchdog: {e}");
+                        }
+                        handle(stream, i2c_clone, chip_map_clone, token);
+                        info!("Client disconnected");
+                        watchdog::unsubscribe();
                     });
                 }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
                 Err(e) => {
                     error!("Error: {e}");
                 }
             }
         }
-
-        unreachable!()
     }
 
-    fn handle(mut stream: TcpStream, i2c: Arc<Mutex<I2cDriver<'static>>>) {
-        // we size the buffer to the size of the ADAU1452 memory partition
-        // this is very wasteful, a proper implementation would just stream the data
-        let mut buf = Box::new([0u8; 20480 * 4 + 14]);
+    fn handle(
+        mut stream: TcpStream,
+        i2c: I2cBus,
+        chip_map: Arc<Mutex<ChipMap>>,
+        token: Option<AuthToken>,
+    ) {
+        // wake up periodically while the client is idle to feed the watchdog
+        if let Err(e) = stream.set_read_timeout(Some(WATCHDOG_FEED_INTERVAL)) {
+            error!("Failed to set read timeout: {e}");
+            return;
+        }
+        // a client that stops reading fails the write instead of blocking the thread
+        if let Err(e) = stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT)) {
+            error!("Failed to set write timeout: {e}");
+            return;
+        }
+
+        // most commands are a few bytes, the buffer grows only for large writes
+        let mut buf = vec![0u8; TCP_INITIAL_BUF_LEN];
 
         let mut count = 0;
+        let mut resync = Resync::default();
+        let mut framing = ConnectionFraming::default();
+        let mut authenticated = token.is_none();
 
         loop {
-            let n = stream.read(&mut buf[count..]).unwrap();
+            watchdog::feed();
+
+            if count == buf.len() {
+                if buf.len() >= TCP_MAX_BUF_LEN {
+                    error!("Command larger than {TCP_MAX_BUF_LEN} bytes, closing connection");
+                    return;
+                }
+                let new_len = (buf.len() * 2).min(TCP_MAX_BUF_LEN);
+                info!("Growing TCP buffer from {} to {new_len} bytes", buf.len());
+                buf.resize(new_len, 0);
+            }
+
+            let n = match stream.read(&mut buf[count..]) {
+                Ok(n) => n,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) if is_disconnect(&e) => {
+                    debug!("Client reset the connection: {e}");
+                    break;
+                }
+                Err(e) => {
+                    error!("Read error: {e}");
+                    break;
+                }
+            };
             if n == 0 {
+                if count > 0 {
+                    debug!("Client closed with {count} bytes of an incomplete command");
+                }
                 break;
             }
             count += n;
@@ -410,7 +2328,39 @@ fn tcp_server(i2c: Arc<Mutex<I2cDriver<'static>>>) -> Result<(), io::Error> {
             while processed_bytes < count {
                 //info!("Processing bytes: {:?}", &buf[processed_bytes..count]);
                 let bytes = &buf[processed_bytes..count];
-                let result = process_command(bytes, &i2c);
+
+                // il prelude con il token precede il primo comando
+                if !authenticated {
+                    match auth::parse_prelude(bytes) {
+                        Ok(None) => break,
+                        Ok(Some((given, len)))
+                            if token.as_ref().is_some_and(|t| t.matches(given)) =>
+                        {
+                            authenticated = true;
+                            processed_bytes += len;
+                            continue;
+                        }
+                        Ok(Some(_)) => {
+                            warn!("Wrong auth token, closing connection");
+                            return;
+                        }
+                        Err(e) => {
+                            warn!("{e}, closing connection");
+                            return;
+                        }
+                    }
+                }
+
+                // la mappa può cambiare da /config, vale dal comando successivo
+                let chip_map = lock(&chip_map).clone();
+                let result = process_command(
+                    bytes,
+                    &i2c,
+                    &chip_map,
+                    token.is_some(),
+                    &mut resync,
+                    &mut framing,
+                );
                 match result {
                     Ok((response, bytes_read)) => {
                         if bytes_read == 0 {
@@ -419,17 +2369,34 @@ fn tcp_server(i2c: Arc<Mutex<I2cDriver<'static>>>) -> Result<(), io::Error> {
                         }
 
                         processed_bytes += bytes_read;
-                        let response_bytes = response.to_bytes();
-
-                        stream.write_all(&response_bytes).unwrap();
-                        stream.flush().unwrap();
+                        let response_bytes = response.to_bytes_with(framing.checksum());
+
+                        // un client sparito o che non legge chiude solo la sua connessione
+                        if let Err(e) = stream
+                            .write_all(&response_bytes)
+                            .and_then(|_| stream.flush())
+                        {
+                            if is_disconnect(&e) {
+                                debug!("Client gone before its response: {e}");
+                            } else {
+                                warn!("Write error, closing connection: {e}");
+                            }
+                            return;
+                        }
                     }
-                    Err(e) => {
-                        error!("Process command error: {e}");
-                        //error!("{bytes:?}");
-                        // In caso di errore, interrompiamo l'elaborazione
+                    Err(e) if e.is::<IncompleteCommand>() => {
+                        // il resto del comando arriva con la prossima lettura
                         break;
                     }
+                    Err(e) if e.is::<ResyncError>() => {
+                        error!("{e}, closing connection");
+                        return;
+                    }
+                    Err(e) => {
+                        // un header incoerente resterebbe in testa al buffer per sempre
+                        error!("Process command error: {e:#}, closing connection");
+                        return;
+                    }
                 }
             }
 
@@ -440,18 +2407,44 @@ fn tcp_server(i2c: Arc<Mutex<I2cDriver<'static>>>) -> Result<(), io::Error> {
                 }
                 count -= processed_bytes;
             }
+
+            // a large transfer is done, give the memory back
+            if buf.len() > TCP_INITIAL_BUF_LEN && count <= TCP_INITIAL_BUF_LEN {
+                info!(
+                    "Shrinking TCP buffer from {} to {TCP_INITIAL_BUF_LEN} bytes",
+                    buf.len()
+                );
+                buf.truncate(TCP_INITIAL_BUF_LEN);
+                buf.shrink_to_fit();
+            }
         }
     }
 
-    accept(i2c)
+    accept(i2c, chip_map, auth_token)
+}
+
+/// Errori di un client che chiude o resetta la connessione, normali con SigmaStudio
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+    )
 }
 
 fn process_command(
     buf: &[u8],
-    i2c: &Arc<Mutex<I2cDriver<'static>>>,
+    i2c: &I2cBus,
+    chip_map: &ChipMap,
+    auth: bool,
+    resync: &mut Resync,
+    framing: &mut ConnectionFraming,
 ) -> Result<(ProtocolResponse, usize)> {
-    let (command, bytes_read) =
-        ProtocolHandler::parse_command(buf).context("Failed to parse command")?;
+    let (command, bytes_read) = framing.parse(buf).context("Failed to parse command")?;
+
+    resync.check(&command)?;
 
     //info!("Parsed command: {:?}", command);
 
@@ -462,8 +2455,33 @@ fn process_command(
                 header.param_addr, header.data_len
             );
 
+            if let Err(e) = ProtocolHandler::check_read_len(&header, TCP_MAX_READ_LEN) {
+                error!("{e}");
+                return Ok((
+                    ProtocolHandler::create_rejected_read_response(
+                        header.chip_addr,
+                        header.param_addr,
+                        STATUS_READ_TOO_LONG,
+                    ),
+                    bytes_read,
+                ));
+            }
+
+            let Some(dev) = chip_map.i2c_addr(header.chip_addr) else {
+                error!("No I2C address for IC {}", header.chip_addr);
+                return Ok((
+                    ProtocolHandler::create_error_read_response(
+                        header.chip_addr,
+                        header.data_len,
+                        header.param_addr,
+                        STATUS_BACKEND_ERROR,
+                    ),
+                    bytes_read,
+                ));
+            };
+
             // Use the abstracted I2C read function
-            match read_i2c_register(i2c, header.param_addr, header.data_len as u16) {
+            match read_i2c_register(i2c, dev, header.param_addr, header.data_len as usize) {
                 Ok(data) => Ok((
                     ProtocolHandler::create_read_response(
                         header.chip_addr,
@@ -475,8 +2493,17 @@ fn process_command(
                 )),
                 Err(e) => {
                     error!("I2C read failed: {e:?}");
+                    let code = match e.downcast_ref::<I2cError>() {
+                        Some(I2cError::Timeout) => STATUS_TIMEOUT,
+                        _ => STATUS_BACKEND_ERROR,
+                    };
                     Ok((
-                        ProtocolHandler::create_error_response(format!("I2C read error: {e:?}")),
+                        ProtocolHandler::create_error_read_response(
+                            header.chip_addr,
+                            header.data_len,
+                            header.param_addr,
+                            code,
+                        ),
                         bytes_read,
                     ))
                 }
@@ -488,8 +2515,35 @@ fn process_command(
                 header.param_addr, header.data_len
             );
 
+            if FROZEN.load(Ordering::Relaxed) {
+                warn!(
+                    "Device frozen, write at 0x{:04x} refused",
+                    header.param_addr
+                );
+                return Ok((
+                    ProtocolHandler::create_error_response("Device frozen".to_string()),
+                    bytes_read,
+                ));
+            }
+
+            let Some(dev) = chip_map.i2c_addr(header.chip_addr) else {
+                error!("No I2C address for IC {}", header.chip_addr);
+                return Ok((
+                    ProtocolHandler::create_error_response(format!(
+                        "No I2C address for IC {}",
+                        header.chip_addr
+                    )),
+                    bytes_read,
+                ));
+            };
+
             // Use the abstracted I2C write function
-            match write_i2c_register(i2c, header.param_addr, &data) {
+            let result = if header.safeload != 0 {
+                safeload_i2c_write(i2c, dev, header.param_addr, &data)
+            } else {
+                write_i2c_register(i2c, dev, header.param_addr, &data)
+            };
+            match result {
                 Ok(_) => Ok((ProtocolResponse::Write, bytes_read)),
                 Err(e) => {
                     error!("I2C write failed: {e:?}");
@@ -500,8 +2554,33 @@ fn process_command(
                 }
             }
         }
+        ProtocolCommand::Handshake { .. } => {
+            // ConnectionFraming ha già registrato il formato, niente da rispondere
+            Ok((ProtocolResponse::Write, bytes_read))
+        }
+        ProtocolCommand::Identify => {
+            // IC 1, come le richieste HTTP, se la mappa non lo conosce si usa l'indirizzo di default
+            let dev = chip_map.i2c_addr(1).unwrap_or(DSP_I2C_ADDR);
+            let info = identify_dsp(i2c, dev).unwrap_or_else(|e| {
+                error!("Identify failed: {e:?}");
+                DeviceInfo::Unknown
+            });
+            Ok((ProtocolResponse::Identify(info), bytes_read))
+        }
+        ProtocolCommand::Ping => {
+            // nessun accesso all'I2C, serve solo a sapere che il bridge risponde
+            Ok((ProtocolResponse::Pong, bytes_read))
+        }
+        ProtocolCommand::Capabilities => Ok((
+            ProtocolResponse::Capabilities(device_capabilities(chip_map, auth)),
+            bytes_read,
+        )),
+        ProtocolCommand::ChecksumMismatch(command) => Ok((
+            ProtocolHandler::checksum_error_response(*command, TCP_MAX_READ_LEN),
+            bytes_read,
+        )),
         ProtocolCommand::Unknown(cmd) => {
-            error!("Unknown command: 0x{cmd:02x}");
+            // already logged by resync, the next byte is tried as a command
             Ok((
                 ProtocolHandler::create_error_response(format!("Unknown command: 0x{cmd:02x}")),
                 bytes_read,
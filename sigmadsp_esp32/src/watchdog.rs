@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use esp_idf_svc::sys::{
+    esp, esp_err_t, esp_task_wdt_add, esp_task_wdt_config_t, esp_task_wdt_delete,
+    esp_task_wdt_init, esp_task_wdt_reconfigure, esp_task_wdt_reset, ESP_ERR_INVALID_STATE,
+};
+use log::warn;
+
+/// Configures the esp-idf task watchdog, every subscribed task that doesn't
+/// call `feed` within `timeout` makes the chip panic and reboot
+pub fn init(timeout: Duration) -> Result<()> {
+    let config = esp_task_wdt_config_t {
+        timeout_ms: timeout.as_millis() as u32,
+        idle_core_mask: 0,
+        trigger_panic: true,
+    };
+
+    // the TWDT is usually already started by the bootloader config, if not we start it here
+    let err = unsafe { esp_task_wdt_reconfigure(&config) };
+    if err == ESP_ERR_INVALID_STATE as esp_err_t {
+        esp!(unsafe { esp_task_wdt_init(&config) })?;
+    } else {
+        esp!(err)?;
+    }
+
+    warn!(
+        "Task watchdog active, a thread stuck for more than {} ms will reboot the device",
+        timeout.as_millis()
+    );
+
+    Ok(())
+}
+
+/// Subscribes the calling thread to the watchdog
+pub fn subscribe() -> Result<()> {
+    esp!(unsafe { esp_task_wdt_add(std::ptr::null_mut()) })?;
+    Ok(())
+}
+
+/// Unsubscribes the calling thread, must be called before a subscribed thread exits
+pub fn unsubscribe() {
+    if let Err(e) = esp!(unsafe { esp_task_wdt_delete(std::ptr::null_mut()) }) {
+        warn!("Failed to unsubscribe from watchdog: {e}");
+    }
+}
+
+/// Feeds the watchdog for the calling thread
+pub fn feed() {
+    unsafe {
+        esp_task_wdt_reset();
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+/// Bytes a client sends first when the server requires a token, followed
+/// by the token length (one byte) and the token
+pub const AUTH_MAGIC: &[u8; 8] = b"SIGMAKEY";
+
+/// Shared secret a server can require from its clients
+///
+/// Disabled unless configured, SigmaStudio can't send it. TCP clients send
+/// [`AuthToken::prelude`] before any command, HTTP clients an
+/// `Authorization: Bearer <token>` or `X-Token: <token>` header.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthToken(String);
+
+impl AuthToken {
+    /// Fails for an empty token or one longer than 255 bytes
+    pub fn new(token: impl Into<String>) -> Result<Self> {
+        let token = token.into();
+        if token.is_empty() {
+            bail!("Empty auth token");
+        }
+        if token.len() > u8::MAX as usize {
+            bail!("Auth token longer than {} bytes", u8::MAX);
+        }
+        Ok(Self(token))
+    }
+
+    /// Token from the `SIGMA_TCP_TOKEN` environment variable, None if unset or empty
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var("SIGMA_TCP_TOKEN") {
+            Ok(token) if !token.is_empty() => Self::new(token).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Frame a TCP client sends first to authenticate
+    pub fn prelude(&self) -> Vec<u8> {
+        let mut bytes = AUTH_MAGIC.to_vec();
+        bytes.push(self.0.len() as u8);
+        bytes.extend_from_slice(self.0.as_bytes());
+        bytes
+    }
+
+    /// Compares in constant time, so the token can't be guessed byte by byte
+    pub fn matches(&self, given: &[u8]) -> bool {
+        let expected = self.0.as_bytes();
+        if given.len() != expected.len() {
+            return false;
+        }
+        expected
+            .iter()
+            .zip(given)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+
+    /// Checks the `Authorization` and `X-Token` headers of an HTTP request
+    pub fn matches_headers(&self, authorization: Option<&str>, x_token: Option<&str>) -> bool {
+        let bearer = authorization.and_then(|value| value.trim().strip_prefix("Bearer "));
+        [bearer, x_token]
+            .into_iter()
+            .flatten()
+            .any(|token| self.matches(token.trim().as_bytes()))
+    }
+}
+
+// il token non deve finire nei log
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuthToken(..)")
+    }
+}
+
+/// Parses the auth prelude at the start of `buf`
+///
+/// Returns the token and the bytes consumed, None if more data is needed.
+/// Anything else than a prelude is an error.
+pub fn parse_prelude(buf: &[u8]) -> Result<Option<(&[u8], usize)>> {
+    if buf.len() <= AUTH_MAGIC.len() {
+        if AUTH_MAGIC.starts_with(buf) {
+            return Ok(None);
+        }
+        bail!("Expected an auth prelude");
+    }
+    let Some(rest) = buf.strip_prefix(AUTH_MAGIC) else {
+        bail!("Expected an auth prelude");
+    };
+
+    let len = rest[0] as usize;
+    let prelude_len = AUTH_MAGIC.len() + 1 + len;
+    if buf.len() < prelude_len {
+        return Ok(None);
+    }
+    Ok(Some((&rest[1..1 + len], prelude_len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_token() {
+        let token = AuthToken::new("s3cret").unwrap();
+        let prelude = token.prelude();
+
+        // finché il prelude è incompleto serve altro
+        for end in 0..prelude.len() {
+            assert_eq!(parse_prelude(&prelude[..end]).unwrap(), None);
+        }
+
+        let mut buf = prelude.clone();
+        buf.push(0x0c);
+        let (given, len) = parse_prelude(&buf).unwrap().unwrap();
+        assert!(token.matches(given));
+        assert_eq!(len, prelude.len());
+
+        assert!(!token.matches(b"s3cre"));
+        assert!(!token.matches(b"s3creT"));
+        assert!(parse_prelude(&[0x0a, 0x00]).is_err());
+
+        assert!(token.matches_headers(Some("Bearer s3cret"), None));
+        assert!(token.matches_headers(None, Some("s3cret")));
+        assert!(!token.matches_headers(Some("Basic s3cret"), Some("wrong")));
+        assert!(!token.matches_headers(None, None));
+
+        assert!(AuthToken::new("").is_err());
+        assert!(AuthToken::new("x".repeat(256)).is_err());
+        assert_eq!(format!("{:?}", token), "AuthToken(..)");
+    }
+}
@@ -0,0 +1,225 @@
+use std::ops::RangeInclusive;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::warn;
+
+use super::{Backend, WriteSource};
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+
+/// Decorator that only lets reads and writes within configured address ranges
+/// reach the inner backend.
+///
+/// Lets a bridge on a shared network accept parameter RAM writes while
+/// refusing writes to control registers like the PLL or soft reset. A transfer
+/// is allowed if all the addresses it spans, counting 4-byte words, fall in a
+/// single range. Without any range for a direction, that direction is unrestricted.
+pub struct AllowlistBackend<B> {
+    inner: B,
+    read_ranges: Vec<RangeInclusive<u16>>,
+    write_ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl<B: Backend> AllowlistBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            read_ranges: Vec::new(),
+            write_ranges: Vec::new(),
+        }
+    }
+
+    pub fn allow_read(mut self, range: RangeInclusive<u16>) -> Self {
+        self.read_ranges.push(range);
+        self
+    }
+
+    pub fn allow_write(mut self, range: RangeInclusive<u16>) -> Self {
+        self.write_ranges.push(range);
+        self
+    }
+}
+
+fn check_allowed(ranges: &[RangeInclusive<u16>], op: &str, addr: u16, len: usize) -> Result<()> {
+    if ranges.is_empty() {
+        return Ok(());
+    }
+
+    let last = addr as usize + len.div_ceil(4).max(1) - 1;
+    let allowed = ranges
+        .iter()
+        .any(|range| range.contains(&addr) && last <= *range.end() as usize);
+
+    if !allowed {
+        warn!(
+            "rejected {} of {} bytes at 0x{:04x}, outside the allowed ranges",
+            op, len, addr
+        );
+        return Err(anyhow!(
+            "{} at 0x{:04x} is outside the allowed address ranges",
+            op,
+            addr
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses an inclusive address range like `0x0000-0x1FFF`, hex or decimal
+pub fn parse_address_range(value: &str) -> Result<RangeInclusive<u16>> {
+    let (start, end) = value
+        .split_once('-')
+        .with_context(|| format!("Expected start-end, got {:?}", value))?;
+
+    let parse = |s: &str| -> Result<u16> {
+        let s = s.trim();
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .with_context(|| format!("Invalid address {:?}", s))
+    };
+
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        return Err(anyhow!("Empty address range {:?}", value));
+    }
+    Ok(start..=end)
+}
+
+#[async_trait]
+impl<B: Backend> Backend for AllowlistBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        check_allowed(&self.read_ranges, "read", addr, len as usize)?;
+        self.inner.read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        check_allowed(&self.write_ranges, "write", addr, data.len())?;
+        self.inner.write(addr, data).await
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        // check_allowed conta un indirizzo ogni 4 byte, qui ce n'è uno per parola
+        check_allowed(&self.read_ranges, "read", start, count * 4)?;
+        self.inner.read_sequential(start, count, word_len).await
+    }
+
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        check_allowed(&self.write_ranges, "safeload", addr, data.len())?;
+        self.inner.safeload_write(addr, data).await
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        for (addr, word) in batch.words() {
+            check_allowed(&self.write_ranges, "safeload", *addr, word.len())?;
+        }
+        self.inner.safeload_batch(batch).await
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        check_allowed(&self.write_ranges, "write", addr, source.remaining())?;
+        self.inner.write_stream(addr, source).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FaultInjectingBackend, MemoryBackend};
+
+    #[tokio::test]
+    async fn test_write_ranges() {
+        let mut backend = AllowlistBackend::new(MemoryBackend::new()).allow_write(0x0000..=0x1fff);
+
+        backend.write(0x0043, &[1, 2, 3, 4]).await.unwrap();
+        assert_eq!(backend.read(0x0043, 4).await.unwrap(), vec![1, 2, 3, 4]);
+
+        assert!(backend.write(0xf003, &[0, 1]).await.is_err());
+        // starts inside the range but spills past its end
+        assert!(backend.write(0x1fff, &[0; 8]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_before_inner_backend() {
+        // the inner backend counts every operation that reaches it
+        let inner = FaultInjectingBackend::builder(MemoryBackend::new()).build();
+        let mut backend = AllowlistBackend::new(inner).allow_read(0x0000..=0x00ff);
+
+        assert!(backend.read(0xf000, 2).await.is_err());
+        assert!(backend.read(0x0010, 4).await.is_ok());
+        assert_eq!(backend.inner.ops(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_forwards_safeload_and_identify() {
+        use crate::backend::{IdentifyBackend, SafeloadBackend};
+        use crate::identify::PartId;
+        use crate::safeload::SafeloadConfig;
+
+        const PART_ID: PartId = PartId {
+            addr: 0xf3f0,
+            len: 2,
+            parts: &[(&[0x14, 0x52], "ADAU1452")],
+        };
+        let mut memory = MemoryBackend::new();
+        memory.write(0xf3f0, &[0x14, 0x52]).await.unwrap();
+        let inner = IdentifyBackend::new(
+            SafeloadBackend::new(memory, SafeloadConfig::ADAU1452),
+            PART_ID,
+        );
+        let mut backend = AllowlistBackend::new(inner)
+            .allow_read(0x0000..=0xffff)
+            .allow_write(0x0000..=0x1fff);
+
+        // the safeload goes through the registers, not to the target
+        backend.safeload_write(0x0043, &[0, 0, 0, 1]).await.unwrap();
+        assert_eq!(backend.read(0x0043, 4).await.unwrap(), vec![0; 4]);
+        assert_eq!(backend.read(0x6000, 4).await.unwrap(), vec![0, 0, 0, 1]);
+        assert!(backend.safeload_write(0x2000, &[0; 4]).await.is_err());
+
+        assert_eq!(backend.identify().await.unwrap().part(), Some("ADAU1452"));
+    }
+
+    #[tokio::test]
+    async fn test_write_stream_reaches_inner() {
+        use crate::backend::{ChunkedBackend, CountingBackend};
+
+        // transfers over 256 bytes fail, so the payload can't arrive collected whole
+        let memory = MemoryBackend::with_max_transfer(256);
+        let inner = CountingBackend::new(ChunkedBackend::new(memory, 256));
+        let mut backend = AllowlistBackend::new(inner).allow_write(0x0000..=0x1fff);
+
+        let data: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        backend
+            .write_stream(0x0100, &mut data.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(backend.inner.counts().get(0x0100), (0, 1));
+        assert_eq!(backend.inner.read(0x0100, 1024).await.unwrap(), data);
+
+        let mut tail = &data[..8];
+        assert!(backend.write_stream(0x1fff, &mut tail).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_address_range() {
+        assert_eq!(
+            parse_address_range("0x0000-0x1FFF").unwrap(),
+            0x0000..=0x1fff
+        );
+        assert_eq!(parse_address_range("16 - 32").unwrap(), 16..=32);
+        assert!(parse_address_range("0x2000-0x1000").is_err());
+        assert!(parse_address_range("0x2000").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::Backend;
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+use crate::{ProtocolHandler, ProtocolResponse, STATUS_BACKEND_ERROR};
+
+/// One line of a capture log: a command frame and the response frame it produced
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    /// Command frame, hex encoded
+    pub command: String,
+    /// Response frame as sent back to the client, hex encoded (empty for writes)
+    pub response: String,
+}
+
+/// Decorator that logs every transaction as newline-delimited JSON.
+///
+/// The backend only sees addresses and data, so command frames are rebuilt
+/// with `ProtocolHandler::create_read_request`/`create_write_request` for
+/// `chip_addr` 1, and responses are the frames the server would send back.
+/// The log can be fed to [`replay`] to reproduce the session against any backend.
+///
+/// Streamed writes are collected whole (the `write_stream` default), since
+/// each record holds the full command frame.
+pub struct CaptureBackend<B, W> {
+    inner: B,
+    log: W,
+}
+
+impl<B: Backend, W: Write + Send + Sync> CaptureBackend<B, W> {
+    pub fn new(inner: B, log: W) -> Self {
+        Self { inner, log }
+    }
+
+    pub fn into_parts(self) -> (B, W) {
+        (self.inner, self.log)
+    }
+
+    fn record(&mut self, command: &[u8], response: &ProtocolResponse) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let record = CaptureRecord {
+            timestamp,
+            command: to_hex(command),
+            response: to_hex(&response.to_bytes()),
+        };
+
+        writeln!(self.log, "{}", serde_json::to_string(&record)?)?;
+        self.log.flush()?;
+        Ok(())
+    }
+
+    fn record_read(&mut self, addr: u16, len: u32, result: &Result<Vec<u8>>) -> Result<()> {
+        let response = match result {
+            Ok(data) => ProtocolHandler::create_read_response(1, len, addr, data.clone()),
+            Err(_) => {
+                ProtocolHandler::create_error_read_response(1, len, addr, STATUS_BACKEND_ERROR)
+            }
+        };
+        self.record(
+            &ProtocolHandler::create_read_request(1, addr, len),
+            &response,
+        )
+    }
+
+    fn record_write(
+        &mut self,
+        addr: u16,
+        data: &[u8],
+        safeload: bool,
+        result: &Result<()>,
+    ) -> Result<()> {
+        let response = match result {
+            Ok(()) => ProtocolResponse::Write,
+            Err(e) => ProtocolHandler::create_error_response(format!("Write error: {}", e)),
+        };
+        let mut command = ProtocolHandler::create_write_request(1, addr, data);
+        command[1] = safeload as u8;
+        self.record(&command, &response)
+    }
+}
+
+#[async_trait]
+impl<B: Backend, W: Write + Send + Sync> Backend for CaptureBackend<B, W> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        let result = self.inner.read(addr, len).await;
+        self.record_read(addr, len, &result)?;
+        result
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        let result = self.inner.write(addr, data).await;
+        self.record_write(addr, data, false, &result)?;
+        result
+    }
+
+    /// Recorded as the single read a client would send for the same words
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        let result = self.inner.read_sequential(start, count, word_len).await;
+        self.record_read(start, count as u32 * word_len as u32, &result)?;
+        result
+    }
+
+    /// Recorded with the safeload byte set, so replaying it is a safeload too
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        let result = self.inner.safeload_write(addr, data).await;
+        self.record_write(addr, data, true, &result)?;
+        result
+    }
+
+    /// Recorded as a safeload write per word, the protocol has no batches
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        let result = self.inner.safeload_batch(batch).await;
+        for (addr, word) in batch.words() {
+            self.record_write(*addr, word, true, &result)?;
+        }
+        result
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+/// Reads a capture log written by [`CaptureBackend`]
+pub fn read_capture(reader: impl BufRead) -> Result<Vec<CaptureRecord>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(|(i, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("Invalid record on line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Feeds every captured command through the parser and `backend`, failing on
+/// the first response that doesn't match the captured one
+pub async fn replay<B: Backend + ?Sized>(
+    records: &[CaptureRecord],
+    backend: &mut B,
+    max_read_len: u32,
+) -> Result<()> {
+    for (i, record) in records.iter().enumerate() {
+        let command = from_hex(&record.command)?;
+        let (command, _) = ProtocolHandler::parse_command(&command)
+            .with_context(|| format!("Record {}: failed to parse command", i))?;
+
+        let response = ProtocolHandler::execute(backend, command, max_read_len).await;
+        let response = to_hex(&response.to_bytes());
+
+        if response != record.response {
+            return Err(anyhow!(
+                "Record {}: response mismatch, expected {} got {}",
+                i,
+                record.response,
+                response
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid hex string: {}", hex));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    #[tokio::test]
+    async fn test_capture_and_replay() {
+        let mut backend = CaptureBackend::new(MemoryBackend::new(), Vec::new());
+
+        backend.write(0xf020, &[0x00, 0x08]).await.unwrap();
+        backend.read(0xf020, 2).await.unwrap();
+        backend.read(0x0000, 4).await.unwrap();
+
+        let (_, log) = backend.into_parts();
+        let records = read_capture(log.as_slice()).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0].command,
+            "090000000000100100000002f0200008" // same frame as test_write_command_f020_example
+        );
+        assert_eq!(records[0].response, "");
+
+        // replaying against a fresh memory reproduces the same responses
+        replay(&records, &mut MemoryBackend::new(), 1024)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_capture_safeload() {
+        let mut backend = CaptureBackend::new(MemoryBackend::new(), Vec::new());
+
+        backend.safeload_write(0x0043, &[0, 0, 0, 1]).await.unwrap();
+        backend.read_sequential(0x0043, 1, 4).await.unwrap();
+
+        let (_, log) = backend.into_parts();
+        let records = read_capture(log.as_slice()).unwrap();
+        assert_eq!(records.len(), 2);
+        // the safeload byte is set
+        assert_eq!(records[0].command, "090100000000120100000004004300000001");
+
+        replay(&records, &mut MemoryBackend::new(), 1024)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_detects_mismatch() {
+        let mut backend = CaptureBackend::new(MemoryBackend::new(), Vec::new());
+
+        backend
+            .write(0x0010, &[0x01, 0x02, 0x03, 0x04])
+            .await
+            .unwrap();
+        backend.read(0x0010, 4).await.unwrap();
+
+        let (_, log) = backend.into_parts();
+        let records = read_capture(log.as_slice()).unwrap();
+
+        // without the write, the read returns zeros instead of the captured data
+        assert!(replay(&records[1..], &mut MemoryBackend::new(), 1024)
+            .await
+            .is_err());
+    }
+}
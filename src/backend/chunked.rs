@@ -0,0 +1,160 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::debug;
+
+use super::{Backend, WriteSource};
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+
+/// Splits a read of `len` bytes at `addr` into transfers of at most `max_chunk` bytes.
+///
+/// The ADAU sub-address auto-increments once per `word_len` bytes, so each
+/// chunk starts `offset / word_len` addresses after `addr`. `max_chunk` is
+/// rounded down to whole words, and to at least one word.
+pub fn split_read(addr: u16, len: u32, max_chunk: u32, word_len: u32) -> Vec<(u16, u32)> {
+    let chunk = (max_chunk - max_chunk % word_len).max(word_len);
+
+    (0..len)
+        .step_by(chunk as usize)
+        .map(|offset| {
+            let chunk_addr = addr.wrapping_add((offset / word_len) as u16);
+            (chunk_addr, chunk.min(len - offset))
+        })
+        .collect()
+}
+
+/// Decorator that splits large reads into several smaller ones.
+///
+/// For backends like I2C where a single transaction of several KB can fail,
+/// the chunks are read one after the other and concatenated. Streamed writes
+/// are split the same way, each chunk written as soon as it arrives.
+pub struct ChunkedBackend<B> {
+    inner: B,
+    max_chunk: u32,
+    word_len: u32,
+}
+
+impl<B: Backend> ChunkedBackend<B> {
+    /// Reads at most `max_chunk` bytes per transaction, with 4-byte words
+    pub fn new(inner: B, max_chunk: u32) -> Self {
+        Self {
+            inner,
+            max_chunk,
+            word_len: 4,
+        }
+    }
+}
+
+#[async_trait]
+impl<B: Backend> Backend for ChunkedBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        if len <= self.max_chunk {
+            return self.inner.read(addr, len).await;
+        }
+
+        let chunks = split_read(addr, len, self.max_chunk, self.word_len);
+        debug!(
+            "splitting read of {} bytes at 0x{:04x} in {} chunks",
+            len,
+            addr,
+            chunks.len()
+        );
+
+        let mut data = Vec::with_capacity(len as usize);
+        for (chunk_addr, chunk_len) in chunks {
+            data.extend(self.inner.read(chunk_addr, chunk_len).await?);
+        }
+        Ok(data)
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.inner.write(addr, data).await
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        let len = source.remaining() as u32;
+        if len <= self.max_chunk {
+            return self.inner.write_stream(addr, source).await;
+        }
+
+        let chunks = split_read(addr, len, self.max_chunk, self.word_len);
+        debug!(
+            "streaming write of {} bytes at 0x{:04x} in {} chunks",
+            len,
+            addr,
+            chunks.len()
+        );
+
+        // un solo buffer grande quanto un chunk, riusato per tutti
+        let mut buf = vec![0; chunks[0].1 as usize];
+        for (chunk_addr, chunk_len) in chunks {
+            let chunk = &mut buf[..chunk_len as usize];
+            source.read_exact(chunk).await?;
+            self.inner.write(chunk_addr, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// A safeload carries a handful of words, never split
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.inner.safeload_write(addr, data).await
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        self.inner.safeload_batch(batch).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FaultInjectingBackend, MemoryBackend};
+
+    #[test]
+    fn test_split_read() {
+        assert_eq!(split_read(0x0010, 8, 256, 4), vec![(0x0010, 8)]);
+        assert_eq!(
+            split_read(0x0010, 600, 256, 4),
+            vec![(0x0010, 256), (0x0050, 256), (0x0090, 88)]
+        );
+        // chunks are whole words
+        assert_eq!(
+            split_read(0x0000, 12, 6, 4),
+            vec![(0x0000, 4), (0x0001, 4), (0x0002, 4)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked_read_matches_single_read() {
+        let data: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+
+        let mut memory = MemoryBackend::new();
+        memory.write(0x0100, &data).await.unwrap();
+
+        let inner = FaultInjectingBackend::builder(memory).build();
+        let mut backend = ChunkedBackend::new(inner, 256);
+
+        assert_eq!(backend.read(0x0100, 1024).await.unwrap(), data);
+        assert_eq!(backend.inner.ops(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_write_stream() {
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+
+        let inner = FaultInjectingBackend::builder(MemoryBackend::new()).build();
+        let mut backend = ChunkedBackend::new(inner, 256);
+
+        backend
+            .write_stream(0x0010, &mut data.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(backend.inner.ops(), 3);
+
+        assert_eq!(backend.read(0x0010, 600).await.unwrap(), data);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{Backend, WriteSource};
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+
+/// Reads and writes of each address, shared between a [`CountingBackend`]
+/// and whoever dumps the tally
+#[derive(Clone, Default)]
+pub struct AccessCounts(Arc<Mutex<HashMap<u16, (u64, u64)>>>);
+
+impl AccessCounts {
+    fn lock(&self) -> MutexGuard<'_, HashMap<u16, (u64, u64)>> {
+        // un panic altrove non rende inutilizzabili i contatori
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_read(&self, addr: u16) {
+        self.lock().entry(addr).or_default().0 += 1;
+    }
+
+    fn record_write(&self, addr: u16) {
+        self.lock().entry(addr).or_default().1 += 1;
+    }
+
+    /// Reads and writes of `addr`
+    pub fn get(&self, addr: u16) -> (u64, u64) {
+        self.lock().get(&addr).copied().unwrap_or_default()
+    }
+
+    /// Every accessed address with its reads and writes, by address
+    pub fn tally(&self) -> Vec<(u16, u64, u64)> {
+        let mut tally: Vec<_> = self
+            .lock()
+            .iter()
+            .map(|(&addr, &(reads, writes))| (addr, reads, writes))
+            .collect();
+        tally.sort_unstable();
+        tally
+    }
+
+    /// The tally as a table, one address per line
+    pub fn dump(&self) -> String {
+        let mut table = String::from("addr    reads  writes\n");
+        for (addr, reads, writes) in self.tally() {
+            let _ = writeln!(table, "0x{:04x} {:>6} {:>7}", addr, reads, writes);
+        }
+        table
+    }
+}
+
+/// Decorator that counts the reads and writes of each address
+///
+/// Shows which registers SigmaStudio polls while refreshing and which it
+/// writes once at download. Counts are per transfer and by start address,
+/// sequential reads count once per address. Get the tally from
+/// [`CountingBackend::counts`] before boxing the backend.
+pub struct CountingBackend<B> {
+    inner: B,
+    counts: AccessCounts,
+}
+
+impl<B: Backend> CountingBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            counts: AccessCounts::default(),
+        }
+    }
+
+    pub fn counts(&self) -> AccessCounts {
+        self.counts.clone()
+    }
+}
+
+#[async_trait]
+impl<B: Backend> Backend for CountingBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        self.counts.record_read(addr);
+        self.inner.read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.counts.record_write(addr);
+        self.inner.write(addr, data).await
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        for i in 0..count {
+            self.counts.record_read(start.wrapping_add(i as u16));
+        }
+        self.inner.read_sequential(start, count, word_len).await
+    }
+
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.counts.record_write(addr);
+        self.inner.safeload_write(addr, data).await
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        for (addr, _) in batch.words() {
+            self.counts.record_write(*addr);
+        }
+        self.inner.safeload_batch(batch).await
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        self.counts.record_write(addr);
+        self.inner.write_stream(addr, source).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DebugBackend, MemoryBackend};
+
+    #[tokio::test]
+    async fn test_counts_reads_and_writes() {
+        let mut backend = CountingBackend::new(MemoryBackend::new());
+        let counts = backend.counts();
+
+        backend.write(0x0043, &[0, 0, 0, 1]).await.unwrap();
+        for _ in 0..3 {
+            backend.read(0x0043, 4).await.unwrap();
+        }
+        backend.safeload_write(0x0044, &[0, 0, 0, 2]).await.unwrap();
+        backend.read_sequential(0x0044, 2, 4).await.unwrap();
+
+        assert_eq!(counts.get(0x0043), (3, 1));
+        assert_eq!(counts.get(0x0044), (1, 1));
+        assert_eq!(counts.get(0x0046), (0, 0));
+        assert_eq!(
+            counts.tally(),
+            vec![(0x0043, 3, 1), (0x0044, 1, 1), (0x0045, 1, 0)]
+        );
+        assert_eq!(counts.dump().lines().nth(1), Some("0x0043      3       1"));
+
+        // same tally over the debug backend
+        let mut backend = CountingBackend::new(DebugBackend::new());
+        backend.read(0xf000, 2).await.unwrap();
+        assert_eq!(backend.counts().tally(), vec![(0xf000, 1, 0)]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use log::info;
+
+use super::{Backend, TransferTooLarge};
+
+/// Backend that logs every transfer, reads return a fill byte and writes are dropped.
+///
+/// The fill byte is 0 by default, so unwritten memory reads as silence; set
+/// another one with [`DebugBackend::with_fill`] or the [`fill`](Self::fill)
+/// builder, and a different one for reads at a given address with
+/// [`fill_at`](Self::fill_at), e.g.
+/// `DebugBackend::new().fill(0xff).fill_at(0x0043, 0x40)`.
+///
+/// With [`max_transfer`](Self::max_transfer) it stands in for a device that
+/// only moves small chunks.
+#[derive(Default)]
+pub struct DebugBackend {
+    fill: u8,
+    fill_at: HashMap<u16, u8>,
+    max_transfer: Option<usize>,
+}
+
+impl DebugBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads return `fill` everywhere
+    pub fn with_fill(fill: u8) -> Self {
+        Self::new().fill(fill)
+    }
+
+    /// Byte returned by reads at addresses without their own
+    pub fn fill(mut self, fill: u8) -> Self {
+        self.fill = fill;
+        self
+    }
+
+    /// Byte returned by reads starting at `addr`
+    pub fn fill_at(mut self, addr: u16, fill: u8) -> Self {
+        self.fill_at.insert(addr, fill);
+        self
+    }
+
+    /// Reads and writes longer than `max` bytes fail with [`TransferTooLarge`]
+    pub fn max_transfer(mut self, max: usize) -> Self {
+        self.max_transfer = Some(max);
+        self
+    }
+}
+
+#[async_trait]
+impl Backend for DebugBackend {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        info!("read: 0x{:04x} {}", addr, len);
+        TransferTooLarge::check(len as usize, self.max_transfer)?;
+
+        let fill = self.fill_at.get(&addr).copied().unwrap_or(self.fill);
+        Ok(vec![fill; len as usize])
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        info!("write: 0x{:04x} {}", addr, data.len());
+        TransferTooLarge::check(data.len(), self.max_transfer)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fill_byte() {
+        assert_eq!(
+            DebugBackend::new().read(0x0043, 4).await.unwrap(),
+            vec![0; 4]
+        );
+        assert_eq!(
+            DebugBackend::with_fill(0x12).read(0x0043, 2).await.unwrap(),
+            vec![0x12; 2]
+        );
+
+        let mut backend = DebugBackend::new().fill(0xff).fill_at(0x0043, 0x40);
+        assert_eq!(backend.read(0x0043, 4).await.unwrap(), vec![0x40; 4]);
+        assert_eq!(backend.read(0x0044, 4).await.unwrap(), vec![0xff; 4]);
+
+        // le scritture non cambiano le letture
+        backend.write(0x0044, &[1, 2, 3, 4]).await.unwrap();
+        assert_eq!(backend.read(0x0044, 4).await.unwrap(), vec![0xff; 4]);
+
+        let mut backend = backend.max_transfer(4);
+        assert!(backend.read(0x0043, 4).await.is_ok());
+        assert!(backend.read(0x0043, 8).await.is_err());
+        assert!(backend.write(0x0043, &[0; 8]).await.is_err());
+    }
+}
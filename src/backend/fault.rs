@@ -0,0 +1,270 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::warn;
+
+use super::{Backend, WriteSource};
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+
+/// Decorator for tests that makes the inner backend fail on demand.
+///
+/// Operations (reads and writes) are counted from 1, an operation fails if any
+/// of the configured faults matches. Build it with
+/// [`FaultInjectingBackend::builder`].
+pub struct FaultInjectingBackend<B> {
+    inner: B,
+    fail_nth: Vec<u64>,
+    fail_addrs: Vec<u16>,
+    fail_probability: f64,
+    latency: Duration,
+    rng_state: u64,
+    ops: u64,
+}
+
+/// Fault schedule for a [`FaultInjectingBackend`]
+pub struct FaultInjectingBuilder<B> {
+    backend: FaultInjectingBackend<B>,
+}
+
+impl<B: Backend> FaultInjectingBackend<B> {
+    pub fn builder(inner: B) -> FaultInjectingBuilder<B> {
+        FaultInjectingBuilder {
+            backend: Self {
+                inner,
+                fail_nth: Vec::new(),
+                fail_addrs: Vec::new(),
+                fail_probability: 0.0,
+                latency: Duration::ZERO,
+                rng_state: 0x2545_f491_4f6c_dd1d,
+                ops: 0,
+            },
+        }
+    }
+
+    /// Number of operations seen so far, failed ones included
+    pub fn ops(&self) -> u64 {
+        self.ops
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    fn check_fault(&mut self, op: &str, addr: u16) -> Result<()> {
+        self.ops += 1;
+
+        if !self.latency.is_zero() {
+            // blocca il thread, va bene per i test ma non per un server vero
+            std::thread::sleep(self.latency);
+        }
+
+        let reason = if self.fail_nth.contains(&self.ops) {
+            Some(format!("operation #{}", self.ops))
+        } else if self.fail_addrs.contains(&addr) {
+            Some(format!("address 0x{:04x}", addr))
+        } else if self.fail_probability > 0.0 && self.next_random() < self.fail_probability {
+            Some(format!("random fault, p={}", self.fail_probability))
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => {
+                warn!("injected {} fault at 0x{:04x} ({})", op, addr, reason);
+                Err(anyhow!(
+                    "Injected {} fault at 0x{:04x} ({})",
+                    op,
+                    addr,
+                    reason
+                ))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// xorshift64, uniforme in [0, 1), deterministico per un dato seed
+    fn next_random(&mut self) -> f64 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl<B: Backend> FaultInjectingBuilder<B> {
+    /// Fails the `n`th operation, counting from 1. Can be called more than once
+    pub fn fail_nth(mut self, n: u64) -> Self {
+        self.backend.fail_nth.push(n);
+        self
+    }
+
+    /// Fails every operation at `addr`. Can be called more than once
+    pub fn fail_addr(mut self, addr: u16) -> Self {
+        self.backend.fail_addrs.push(addr);
+        self
+    }
+
+    /// Fails each operation with the given probability, `seed` makes runs reproducible
+    pub fn fail_probability(mut self, probability: f64, seed: u64) -> Self {
+        self.backend.fail_probability = probability.clamp(0.0, 1.0);
+        // xorshift non funziona con stato 0
+        self.backend.rng_state = seed.max(1);
+        self
+    }
+
+    /// Delays every operation, blocking the calling thread
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.backend.latency = latency;
+        self
+    }
+
+    pub fn build(self) -> FaultInjectingBackend<B> {
+        self.backend
+    }
+}
+
+#[async_trait]
+impl<B: Backend> Backend for FaultInjectingBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        self.check_fault("read", addr)?;
+        self.inner.read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.check_fault("write", addr)?;
+        self.inner.write(addr, data).await
+    }
+
+    /// A single operation, with a single latency, like the long transfer it models
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        self.check_fault("read", start)?;
+        self.inner.read_sequential(start, count, word_len).await
+    }
+
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.check_fault("safeload", addr)?;
+        self.inner.safeload_write(addr, data).await
+    }
+
+    /// A single operation, checked against the first address of the batch
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        self.check_fault("safeload", batch.words()[0].0)?;
+        self.inner.safeload_batch(batch).await
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        self.check_fault("write", addr)?;
+        self.inner.write_stream(addr, source).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MemoryBackend, VerifyingBackend};
+    use crate::{ProtocolHandler, ProtocolResponse, CMD_RESP, STATUS_BACKEND_ERROR, STATUS_OK};
+
+    async fn run(backend: &mut impl Backend, request: &[u8]) -> ProtocolResponse {
+        let (command, _) = ProtocolHandler::parse_command(request).unwrap();
+        ProtocolHandler::execute(backend, command, 1024).await
+    }
+
+    #[tokio::test]
+    async fn test_nth_operation_fails() {
+        let mut backend = FaultInjectingBackend::builder(MemoryBackend::new())
+            .fail_nth(2)
+            .build();
+
+        assert!(backend.read(0x0010, 4).await.is_ok());
+        assert!(backend.read(0x0010, 4).await.is_err());
+        assert!(backend.write(0x0010, &[0; 4]).await.is_ok());
+        assert_eq!(backend.ops(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_probability_is_reproducible() {
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let mut backend = FaultInjectingBackend::builder(MemoryBackend::new())
+                .fail_probability(0.5, 42)
+                .build();
+            let mut run = Vec::new();
+            for _ in 0..32 {
+                run.push(backend.read(0x0000, 4).await.is_ok());
+            }
+            results.push(run);
+        }
+
+        assert_eq!(results[0], results[1]);
+        assert!(results[0].contains(&true) && results[0].contains(&false));
+    }
+
+    #[tokio::test]
+    async fn test_failed_read_response_is_well_formed() {
+        let mut backend = FaultInjectingBackend::builder(MemoryBackend::new())
+            .fail_addr(0x0043)
+            .build();
+
+        let ok = run(
+            &mut backend,
+            &ProtocolHandler::create_read_request(1, 0x0042, 2),
+        )
+        .await
+        .to_bytes();
+        assert_eq!(ok[0], CMD_RESP);
+        assert_eq!(ok[12], STATUS_OK);
+
+        let failed = run(
+            &mut backend,
+            &ProtocolHandler::create_read_request(1, 0x0043, 2),
+        )
+        .await
+        .to_bytes();
+        assert_eq!(failed.len(), 14 + 2);
+        assert_eq!(failed[0], CMD_RESP);
+        assert_eq!(u32::from_be_bytes(failed[1..5].try_into().unwrap()), 15);
+        assert_eq!(u32::from_be_bytes(failed[6..10].try_into().unwrap()), 2);
+        assert_eq!(u16::from_be_bytes([failed[10], failed[11]]), 0x0043);
+        assert_eq!(failed[12], STATUS_BACKEND_ERROR);
+        assert_eq!(&failed[14..], &[0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_returns_error_response() {
+        let mut backend = FaultInjectingBackend::builder(MemoryBackend::new())
+            .fail_nth(1)
+            .build();
+
+        let response = run(
+            &mut backend,
+            &ProtocolHandler::create_write_request(1, 0x0043, &[1, 2, 3, 4]),
+        )
+        .await;
+        assert!(matches!(response, ProtocolResponse::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn test_verifying_backend_propagates_read_back_fault() {
+        // la write passa, la rilettura della verifica fallisce
+        let mut backend = VerifyingBackend::new(
+            FaultInjectingBackend::builder(MemoryBackend::new())
+                .fail_nth(2)
+                .build(),
+        );
+
+        assert!(backend.write(0x0043, &[1, 2, 3, 4]).await.is_err());
+    }
+}
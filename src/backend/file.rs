@@ -0,0 +1,86 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+
+use super::Backend;
+
+/// Backend that keeps the register file in a binary file on disk.
+///
+/// Same layout as [`MemoryBackend`](super::MemoryBackend): byte `i` of a
+/// transfer at `addr` lives at offset `addr * word_len + i`, and anything past
+/// the end of the file reads as zeros. Writes go straight to the file, so the
+/// state survives restarts and can be inspected with a hex editor.
+pub struct FileBackend {
+    word_len: usize,
+    file: File,
+}
+
+impl FileBackend {
+    /// Opens or creates `path`, with 4-byte words as in the parameter RAM
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open register file {}", path.display()))?;
+
+        Ok(Self { word_len: 4, file })
+    }
+
+    fn offset(&self, addr: u16) -> u64 {
+        (addr as usize * self.word_len) as u64
+    }
+}
+
+#[async_trait]
+impl Backend for FileBackend {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        let mut result = vec![0; len as usize];
+
+        self.file.seek(SeekFrom::Start(self.offset(addr)))?;
+        let mut filled = 0;
+        while filled < result.len() {
+            match self.file.read(&mut result[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(self.offset(addr)))?;
+        self.file.write_all(data)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_state_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("sigma_tcp_file_{}.bin", std::process::id()));
+
+        {
+            let mut backend = FileBackend::open(&path).unwrap();
+            backend.write(0x0010, &[1, 2, 3, 4]).await.unwrap();
+        }
+
+        let mut backend = FileBackend::open(&path).unwrap();
+        assert_eq!(backend.read(0x0010, 4).await.unwrap(), vec![1, 2, 3, 4]);
+        // past the end of the file
+        assert_eq!(backend.read(0x0100, 2).await.unwrap(), vec![0, 0]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
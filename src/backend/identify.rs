@@ -0,0 +1,99 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::debug;
+
+use super::{Backend, WriteSource};
+use crate::identify::{DeviceInfo, PartId};
+use crate::safeload::SafeloadBatch;
+
+/// Decorator that identifies the part by reading its ID register.
+///
+/// Reads and writes go through unchanged, `identify` reads `part_id.len`
+/// bytes at `part_id.addr` and looks them up in the ID table.
+pub struct IdentifyBackend<B> {
+    inner: B,
+    part_id: PartId,
+}
+
+impl<B: Backend> IdentifyBackend<B> {
+    pub fn new(inner: B, part_id: PartId) -> Self {
+        Self { inner, part_id }
+    }
+}
+
+#[async_trait]
+impl<B: Backend> Backend for IdentifyBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        self.inner.read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.inner.write(addr, data).await
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        self.inner.read_sequential(start, count, word_len).await
+    }
+
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.inner.safeload_write(addr, data).await
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        self.inner.safeload_batch(batch).await
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        self.inner.write_stream(addr, source).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        let id = self.inner.read(self.part_id.addr, self.part_id.len).await?;
+        debug!("id register 0x{:04x}: {:02x?}", self.part_id.addr, id);
+        Ok(self.part_id.device_info(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    const PART_ID: PartId = PartId {
+        addr: 0xf3f0,
+        len: 2,
+        parts: &[(&[0x14, 0x52], "ADAU1452"), (&[0x14, 0x66], "ADAU1466")],
+    };
+
+    #[tokio::test]
+    async fn test_identify_reads_id_register() {
+        let mut memory = MemoryBackend::new();
+        memory.write(0xf3f0, &[0x14, 0x66]).await.unwrap();
+        let mut backend = IdentifyBackend::new(memory, PART_ID);
+
+        let info = backend.identify().await.unwrap();
+        assert_eq!(info.part(), Some("ADAU1466"));
+        assert_eq!(
+            info,
+            DeviceInfo::Detected {
+                id: vec![0x14, 0x66],
+                part: Some("ADAU1466".to_string())
+            }
+        );
+
+        // un ID fuori tabella viene riportato senza nome
+        backend.write(0xf3f0, &[0x00, 0x07]).await.unwrap();
+        assert_eq!(backend.identify().await.unwrap().part(), None);
+
+        // without the decorator nothing is known
+        assert_eq!(
+            MemoryBackend::new().identify().await.unwrap(),
+            DeviceInfo::Unknown
+        );
+    }
+}
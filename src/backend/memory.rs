@@ -0,0 +1,206 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{Backend, TransferTooLarge, WriteSource};
+
+/// In-memory backend that behaves like a SigmaDSP register file.
+///
+/// Like the ADAU parts, the sub-address auto-increments once per word during
+/// a transfer, so byte `i` of a transfer at `addr` lives at word
+/// `addr + i / word_len`. Memory that was never written reads back as zeros.
+///
+/// Transfers of any length are accepted unless a limit is set with
+/// [`max_transfer`](Self::max_transfer).
+pub struct MemoryBackend {
+    word_len: usize,
+    max_transfer: Option<usize>,
+    memory: Vec<u8>,
+}
+
+impl MemoryBackend {
+    /// Creates an empty memory with 4-byte words, as in the parameter RAM
+    pub fn new() -> Self {
+        Self::with_word_len(4)
+    }
+
+    pub fn with_word_len(word_len: usize) -> Self {
+        Self {
+            word_len,
+            max_transfer: None,
+            memory: Vec::new(),
+        }
+    }
+
+    /// 4-byte words, transfers longer than `max` bytes fail
+    pub fn with_max_transfer(max: usize) -> Self {
+        Self::new().max_transfer(max)
+    }
+
+    /// Reads and writes longer than `max` bytes fail with [`TransferTooLarge`]
+    pub fn max_transfer(mut self, max: usize) -> Self {
+        self.max_transfer = Some(max);
+        self
+    }
+
+    fn offset(&self, addr: u16) -> usize {
+        addr as usize * self.word_len
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Backend for MemoryBackend {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        TransferTooLarge::check(len as usize, self.max_transfer)?;
+        let start = self.offset(addr);
+        let end = start + len as usize;
+
+        let mut result = vec![0; len as usize];
+        if start < self.memory.len() {
+            let available = end.min(self.memory.len());
+            result[..available - start].copy_from_slice(&self.memory[start..available]);
+        }
+        Ok(result)
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        TransferTooLarge::check(data.len(), self.max_transfer)?;
+        let start = self.offset(addr);
+        let end = start + data.len();
+
+        if self.memory.len() < end {
+            self.memory.resize(end, 0);
+        }
+        self.memory[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        TransferTooLarge::check(source.remaining(), self.max_transfer)?;
+        let start = self.offset(addr);
+        let end = start + source.remaining();
+
+        if self.memory.len() < end {
+            self.memory.resize(end, 0);
+        }
+        // il payload finisce direttamente in memoria, senza copie intermedie
+        source.read_exact(&mut self.memory[start..end]).await
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        if word_len as usize != self.word_len {
+            // parole di dimensione diversa, una read per indirizzo
+            let mut data = Vec::with_capacity(count * word_len as usize);
+            for i in 0..count {
+                data.extend(
+                    self.read(start.wrapping_add(i as u16), word_len as u32)
+                        .await?,
+                );
+            }
+            return Ok(data);
+        }
+
+        // l'indirizzo si auto-incrementa, basta una sola read
+        self.read(start, (count * self.word_len) as u32).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProtocolHandler, ProtocolResponse, STATUS_BACKEND_ERROR};
+
+    #[tokio::test]
+    async fn test_read_back_written_data() {
+        let mut backend = MemoryBackend::new();
+
+        backend
+            .write(0x0010, &[1, 2, 3, 4, 5, 6, 7, 8])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            backend.read(0x0010, 8).await.unwrap(),
+            vec![1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        // the second word of the transfer lives at the next address
+        assert_eq!(backend.read(0x0011, 4).await.unwrap(), vec![5, 6, 7, 8]);
+        // never written memory reads as zeros
+        assert_eq!(backend.read(0xf000, 2).await.unwrap(), vec![0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_write_stream() {
+        let mut backend = MemoryBackend::new();
+        let program: Vec<u8> = (0..64 * 1024).map(|i| (i * 7) as u8).collect();
+
+        let mut source = program.as_slice();
+        backend.write_stream(0x0400, &mut source).await.unwrap();
+
+        assert_eq!(source.remaining(), 0);
+        assert_eq!(
+            backend.read(0x0400, program.len() as u32).await.unwrap(),
+            program
+        );
+        // same layout as a single write
+        assert_eq!(backend.read(0x0401, 4).await.unwrap(), &program[4..8]);
+    }
+
+    #[tokio::test]
+    async fn test_max_transfer() {
+        let mut backend = MemoryBackend::with_max_transfer(8);
+
+        backend.write(0x0010, &[1; 8]).await.unwrap();
+        assert_eq!(backend.read(0x0010, 8).await.unwrap(), vec![1; 8]);
+
+        let err = backend.read(0x0010, 12).await.unwrap_err();
+        let err = err.downcast_ref::<TransferTooLarge>().unwrap();
+        assert_eq!((err.len, err.max), (12, 8));
+        assert!(backend.write(0x0010, &[2; 9]).await.is_err());
+        // la write rifiutata non ha toccato la memoria
+        assert_eq!(backend.read(0x0010, 8).await.unwrap(), vec![1; 8]);
+
+        // the server answers with a failed read response
+        let request = ProtocolHandler::create_read_request(1, 0x0010, 12);
+        let (command, _) = ProtocolHandler::parse_command(&request).unwrap();
+        match ProtocolHandler::execute(&mut backend, command, 1024).await {
+            ProtocolResponse::Read { header, data } => {
+                assert_eq!(header.success, STATUS_BACKEND_ERROR);
+                assert_eq!(data, vec![0; 12]);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_sequential_matches_single_reads() {
+        let mut backend = MemoryBackend::new();
+        let data: Vec<u8> = (0..28).collect();
+        backend.write(0xf6f5, &data).await.unwrap();
+
+        let mut expected = Vec::new();
+        for addr in 0xf6f5..=0xf6fb {
+            expected.extend(backend.read(addr, 4).await.unwrap());
+        }
+
+        assert_eq!(
+            backend.read_sequential(0xf6f5, 7, 4).await.unwrap(),
+            expected
+        );
+        // 2-byte words fall back to one read per address
+        assert_eq!(
+            backend.read_sequential(0xf6f5, 2, 2).await.unwrap(),
+            vec![0, 1, 4, 5]
+        );
+    }
+}
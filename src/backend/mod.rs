@@ -0,0 +1,225 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+
+mod allowlist;
+mod capture;
+mod chunked;
+mod counting;
+mod debug;
+mod fault;
+mod file;
+mod identify;
+mod memory;
+mod pattern;
+#[cfg(feature = "server")]
+mod proxy;
+mod read_only;
+mod safeload;
+mod verifying;
+
+pub use allowlist::{parse_address_range, AllowlistBackend};
+pub use capture::{read_capture, replay, CaptureBackend, CaptureRecord};
+pub use chunked::{split_read, ChunkedBackend};
+pub use counting::{AccessCounts, CountingBackend};
+pub use debug::DebugBackend;
+pub use fault::{FaultInjectingBackend, FaultInjectingBuilder};
+pub use file::FileBackend;
+pub use identify::IdentifyBackend;
+pub use memory::MemoryBackend;
+pub use pattern::{address_pattern, PatternBackend};
+#[cfg(feature = "server")]
+pub use proxy::ProxyBackend;
+pub use read_only::ReadOnlyBackend;
+pub use safeload::SafeloadBackend;
+pub use verifying::VerifyingBackend;
+
+/// A transfer longer than a backend accepts in one go
+///
+/// Returned by [`MemoryBackend`] and [`DebugBackend`] configured with a
+/// `max_transfer`, to behave like a device that only moves small chunks.
+#[derive(Debug, thiserror::Error)]
+#[error("Transfer of {len} bytes exceeds maximum of {max} bytes")]
+pub struct TransferTooLarge {
+    pub len: usize,
+    pub max: usize,
+}
+
+impl TransferTooLarge {
+    /// Fails if `len` exceeds `max`, `None` is unlimited
+    pub fn check(len: usize, max: Option<usize>) -> Result<(), Self> {
+        match max {
+            Some(max) if len > max => Err(Self { len, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Payload of a write handed to [`Backend::write_stream`] as it arrives
+#[async_trait]
+pub trait WriteSource: Send {
+    /// Bytes of the payload not read yet
+    fn remaining(&self) -> usize;
+
+    /// Reads the next bytes of the payload into `buf`, returns how many.
+    /// 0 once the payload is over.
+    async fn read_chunk(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Fills `buf` whole, failing if the payload ends first
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = self.read_chunk(&mut buf[filled..]).await?;
+            if n == 0 {
+                bail!(
+                    "Write payload ended after {} of {} bytes",
+                    filled,
+                    buf.len()
+                );
+            }
+            filled += n;
+        }
+        Ok(())
+    }
+}
+
+/// A payload already in memory
+#[async_trait]
+impl WriteSource for &[u8] {
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+
+    async fn read_chunk(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = buf.len().min(self.len());
+        buf[..n].copy_from_slice(&self[..n]);
+        *self = &self[n..];
+        Ok(n)
+    }
+}
+
+/// Access to the memory of a DSP
+///
+/// # Cancellation
+///
+/// The futures of these methods aren't cancellation safe: a multi-word write
+/// dropped halfway leaves on the device the words sent until then, and a
+/// decorator like [`VerifyingBackend`] can be stopped between its steps.
+/// The server never drops them, each command runs on a task of its own and
+/// completes even if the connection that sent it is gone. Code calling a
+/// backend directly, e.g. under a `select!` or a timeout, has to do the same
+/// or accept partial writes.
+#[async_trait]
+pub trait Backend: Send + Sync {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>>;
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()>;
+
+    /// Reads `count` consecutive addresses of `word_len` bytes each, starting
+    /// at `start`, and returns the words concatenated.
+    ///
+    /// The default issues one `read` per address. Backends where the device
+    /// auto-increments the sub-address can override it with a single long
+    /// transfer: with I2C that's one address phase instead of `count`, and
+    /// with a per-transaction latency of 1 ms, 64 words take ~1 ms instead of
+    /// ~64 ms (see `examples/sequential_read.rs`).
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(count * word_len as usize);
+        for i in 0..count {
+            let addr = start.wrapping_add(i as u16);
+            data.extend(self.read(addr, word_len as u32).await?);
+        }
+        Ok(data)
+    }
+
+    /// Writes `data` at `addr` with the DSP safeload mechanism, so that all
+    /// the words take effect in the same audio frame.
+    ///
+    /// The default is a plain `write`, [`SafeloadBackend`] performs the
+    /// register sequence of a given part.
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.write(addr, data).await
+    }
+
+    /// Updates every word of `batch` in the same audio frame
+    ///
+    /// The default is a `safeload_write` of each run of consecutive
+    /// addresses, [`SafeloadBackend`] loads them all with a single trigger
+    /// where the part allows it, see [`SafeloadConfig::batch_sequence`].
+    ///
+    /// [`SafeloadConfig::batch_sequence`]: crate::safeload::SafeloadConfig::batch_sequence
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        for (addr, data) in batch.runs() {
+            self.safeload_write(addr, &data).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the `source.remaining()` bytes of `source` at `addr`, as a
+    /// single `write` would
+    ///
+    /// A source that fails halfway, like a client disconnecting in the middle
+    /// of the payload, leaves the bytes before it written.
+    ///
+    /// The default collects the payload and issues one `write`. Backends
+    /// that can take it a piece at a time override it, so that a program
+    /// of tens of KB never sits in memory whole: [`MemoryBackend`] and
+    /// [`ChunkedBackend`] do, and the decorators pass it on to the backend
+    /// they wrap.
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        let mut data = vec![0; source.remaining()];
+        source.read_exact(&mut data).await?;
+        self.write(addr, &data).await
+    }
+
+    /// Reports which part is connected
+    ///
+    /// The default knows nothing, [`IdentifyBackend`] reads the ID register
+    /// of a given part.
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        Ok(DeviceInfo::Unknown)
+    }
+}
+
+/// Lets decorators wrap a backend picked at runtime
+#[async_trait]
+impl<B: Backend + ?Sized> Backend for Box<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        (**self).read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        (**self).write(addr, data).await
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        (**self).read_sequential(start, count, word_len).await
+    }
+
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        (**self).safeload_write(addr, data).await
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        (**self).safeload_batch(batch).await
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        (**self).write_stream(addr, source).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        (**self).identify().await
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::info;
+
+use super::Backend;
+
+/// Bytes returned for a read of `len` bytes at `addr`: the address big-endian,
+/// then a ramp 0, 1, 2... wrapping at 256
+pub fn address_pattern(addr: u16, len: u32) -> Vec<u8> {
+    addr.to_be_bytes()
+        .into_iter()
+        .chain((0..=255u8).cycle())
+        .take(len as usize)
+        .collect()
+}
+
+/// Backend whose reads are derived from the requested address, see [`address_pattern`].
+///
+/// Unlike [`DebugBackend`](super::DebugBackend), which returns the same bytes
+/// everywhere, a client reading the wrong address or length sees it right
+/// away. Writes are logged and dropped.
+pub struct PatternBackend {}
+
+impl PatternBackend {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Default for PatternBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Backend for PatternBackend {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        info!("read: 0x{:04x} {}", addr, len);
+
+        Ok(address_pattern(addr, len))
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        info!("write: 0x{:04x} {}", addr, data.len());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_encodes_address() {
+        let mut backend = PatternBackend::new();
+
+        assert_eq!(
+            backend.read(0xf6fb, 6).await.unwrap(),
+            vec![0xf6, 0xfb, 0, 1, 2, 3]
+        );
+        assert_eq!(backend.read(0x0043, 1).await.unwrap(), vec![0x00]);
+
+        let long = backend.read(0x1234, 300).await.unwrap();
+        assert_eq!(long.len(), 300);
+        assert_eq!(&long[..2], &[0x12, 0x34]);
+        // the ramp wraps after 256 bytes
+        assert_eq!(long[258], 0);
+    }
+}
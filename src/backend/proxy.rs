@@ -0,0 +1,297 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::{info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Backend, WriteSource};
+use crate::auth::AuthToken;
+use crate::capabilities::Capabilities;
+use crate::checksum::Checksum;
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+use crate::{
+    ProtocolHandler, ResponseHeader, WriteFraming, CMD_CAPABILITIES, CMD_IDENTIFY, CMD_RESP,
+    STATUS_OK,
+};
+
+/// Backend that forwards every transfer to another sigma-tcp server.
+///
+/// Lets this bridge run in front of a remote one, e.g. the real hardware on
+/// another subnet. The upstream connection is opened on first use; if it
+/// breaks, the transfer is retried once on a fresh connection.
+///
+/// With a [`Checksum`] the connection starts with a handshake enabling it,
+/// so the upstream must be another sigma-tcp server. A response failing
+/// the checksum is treated like a broken connection.
+///
+/// With an [`AuthToken`] the connection starts with its prelude, before
+/// the handshake.
+pub struct ProxyBackend {
+    upstream: String,
+    chip_addr: u8,
+    checksum: Checksum,
+    token: Option<AuthToken>,
+    capabilities: Option<Capabilities>,
+    stream: Option<TcpStream>,
+}
+
+impl ProxyBackend {
+    /// Forwards to `upstream`, a `host:port` address, with chip address 1
+    pub fn new(upstream: impl Into<String>) -> Self {
+        Self {
+            upstream: upstream.into(),
+            chip_addr: 1,
+            checksum: Checksum::None,
+            token: None,
+            capabilities: None,
+            stream: None,
+        }
+    }
+
+    /// Chip address put in forwarded requests
+    pub fn chip_addr(mut self, chip_addr: u8) -> Self {
+        self.chip_addr = chip_addr;
+        self
+    }
+
+    /// Checksum added to requests and expected on read responses
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Token sent to an upstream server that requires one
+    pub fn token(mut self, token: AuthToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    async fn connection(&mut self) -> Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            let mut stream = TcpStream::connect(&self.upstream)
+                .await
+                .with_context(|| format!("Failed to connect to upstream {}", self.upstream))?;
+            info!("Connected to upstream {}", self.upstream);
+            if let Some(token) = &self.token {
+                stream.write_all(&token.prelude()).await?;
+            }
+            if self.checksum != Checksum::None {
+                stream
+                    .write_all(&WriteFraming::Standard.handshake_with(self.checksum))
+                    .await?;
+            }
+            self.stream = Some(stream);
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    async fn try_read(
+        &mut self,
+        addr: u16,
+        len: u32,
+    ) -> std::io::Result<(ResponseHeader, Vec<u8>)> {
+        let checksum = self.checksum;
+        let mut request = ProtocolHandler::create_read_request(self.chip_addr, addr, len);
+        checksum.append(&mut request);
+        let stream = self.connection().await.map_err(std::io::Error::other)?;
+        stream.write_all(&request).await?;
+
+        let mut frame = vec![0u8; 14];
+        stream.read_exact(&mut frame).await?;
+        let header = ResponseHeader::from_bytes(&frame).map_err(std::io::Error::other)?;
+
+        frame.resize(14 + header.data_len as usize, 0);
+        stream.read_exact(&mut frame[14..]).await?;
+
+        let mut trailer = vec![0; checksum.trailer_len()];
+        stream.read_exact(&mut trailer).await?;
+        if !checksum.verify(&frame, &trailer) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{:?} mismatch in read response", checksum),
+            ));
+        }
+
+        Ok((header, frame.split_off(14)))
+    }
+
+    async fn try_write(&mut self, addr: u16, data: &[u8], safeload: bool) -> std::io::Result<()> {
+        let mut request = ProtocolHandler::create_write_request(self.chip_addr, addr, data);
+        request[1] = safeload as u8;
+        self.checksum.append(&mut request);
+        let stream = self.connection().await.map_err(std::io::Error::other)?;
+        stream.write_all(&request).await
+    }
+
+    /// Sends the header, then the payload a piece at a time as it comes
+    async fn try_write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        let header =
+            ProtocolHandler::create_write_header(self.chip_addr, addr, source.remaining() as u32);
+        let stream = self.connection().await?;
+        stream.write_all(&header).await?;
+        let mut buf = [0; 1024];
+        while source.remaining() > 0 {
+            let n = buf.len().min(source.remaining());
+            source.read_exact(&mut buf[..n]).await?;
+            stream.write_all(&buf[..n]).await?;
+        }
+        Ok(())
+    }
+
+    async fn try_identify(&mut self) -> std::io::Result<DeviceInfo> {
+        let stream = self.connection().await.map_err(std::io::Error::other)?;
+        stream.write_all(&[CMD_IDENTIFY]).await?;
+
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await?;
+        if header[0] != CMD_IDENTIFY {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unexpected response to identify",
+            ));
+        }
+        // lunghezza dell'ID, poi l'ID e il nome della parte con la sua lunghezza
+        let mut payload = vec![header[1]];
+        payload.resize(1 + header[1] as usize + 1, 0);
+        stream.read_exact(&mut payload[1..]).await?;
+        let part_len = *payload.last().unwrap() as usize;
+        payload.resize(payload.len() + part_len, 0);
+        let part_start = payload.len() - part_len;
+        stream.read_exact(&mut payload[part_start..]).await?;
+
+        let (info, _) = DeviceInfo::from_bytes(&payload).map_err(std::io::Error::other)?;
+        Ok(info)
+    }
+
+    async fn try_capabilities(&mut self) -> std::io::Result<Capabilities> {
+        let stream = self.connection().await.map_err(std::io::Error::other)?;
+        stream.write_all(&[CMD_CAPABILITIES]).await?;
+
+        let mut response = [0u8; 5];
+        stream.read_exact(&mut response).await?;
+        if response[0] != CMD_CAPABILITIES {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unexpected response to capabilities",
+            ));
+        }
+        Capabilities::from_bytes(&response[1..]).map_err(std::io::Error::other)
+    }
+
+    /// Features of the upstream server, which must be a sigma-tcp one
+    ///
+    /// Asked once, later calls return the cached answer.
+    pub async fn capabilities(&mut self) -> Result<Capabilities> {
+        if let Some(capabilities) = self.capabilities {
+            return Ok(capabilities);
+        }
+        let capabilities = match self.try_capabilities().await {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                self.reset("capabilities", &e);
+                self.try_capabilities().await?
+            }
+        };
+        info!("Upstream {} supports {:?}", self.upstream, capabilities);
+        self.capabilities = Some(capabilities);
+        Ok(capabilities)
+    }
+
+    /// Drops the broken connection so the next attempt reconnects
+    fn reset(&mut self, op: &str, e: &std::io::Error) {
+        warn!(
+            "upstream {} to {} failed: {}, reconnecting",
+            op, self.upstream, e
+        );
+        self.stream = None;
+    }
+}
+
+#[async_trait]
+impl Backend for ProxyBackend {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        let (header, data) = match self.try_read(addr, len).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.reset("read", &e);
+                self.try_read(addr, len).await?
+            }
+        };
+
+        if header.control_bit != CMD_RESP || header.param_addr != addr {
+            // la connessione non è più allineata ai comandi, meglio ripartire da capo
+            self.stream = None;
+            return Err(anyhow!(
+                "Unexpected response from upstream for read at 0x{:04x}",
+                addr
+            ));
+        }
+        if header.success != STATUS_OK {
+            return Err(anyhow!(
+                "Upstream read at 0x{:04x} failed with status {}",
+                addr,
+                header.success
+            ));
+        }
+
+        Ok(data)
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        // le write non hanno risposta, un errore si vede solo lato invio
+        if let Err(e) = self.try_write(addr, data, false).await {
+            self.reset("write", &e);
+            self.try_write(addr, data, false).await?;
+        }
+        Ok(())
+    }
+
+    /// Sent with the safeload byte set, the upstream runs the register sequence
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        if let Err(e) = self.try_write(addr, data, true).await {
+            self.reset("safeload", &e);
+            self.try_write(addr, data, true).await?;
+        }
+        Ok(())
+    }
+
+    /// A safeload write per run of consecutive addresses, a frame carries
+    /// a single address
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        for (addr, data) in batch.runs() {
+            self.safeload_write(addr, &data).await?;
+        }
+        Ok(())
+    }
+
+    /// Streamed to the upstream without a checksum, which covers the whole
+    /// frame and so needs the payload collected first.
+    ///
+    /// Not retried, the payload is gone once sent: on a failure the upstream
+    /// has a partial frame and its connection is dropped.
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        if self.checksum != Checksum::None {
+            let mut data = vec![0; source.remaining()];
+            source.read_exact(&mut data).await?;
+            return self.write(addr, &data).await;
+        }
+        let result = self.try_write_stream(addr, source).await;
+        if let Err(e) = &result {
+            warn!("upstream streamed write to {} failed: {}", self.upstream, e);
+            self.stream = None;
+        }
+        result
+    }
+
+    /// Asks the upstream server, which must be a sigma-tcp one
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        match self.try_identify().await {
+            Ok(info) => Ok(info),
+            Err(e) => {
+                self.reset("identify", &e);
+                Ok(self.try_identify().await?)
+            }
+        }
+    }
+}
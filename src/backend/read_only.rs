@@ -0,0 +1,102 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::info;
+
+use super::{Backend, WriteSource};
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+
+/// Decorator that turns writes into logged no-ops while reads go through.
+///
+/// Writes still succeed, so SigmaStudio keeps working while you observe what
+/// it would write without touching a live DSP.
+pub struct ReadOnlyBackend<B> {
+    inner: B,
+}
+
+impl<B: Backend> ReadOnlyBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl<B: Backend> Backend for ReadOnlyBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        self.inner.read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        info!("read-only: skipped write at 0x{:04x}: {:02x?}", addr, data);
+        Ok(())
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        self.inner.read_sequential(start, count, word_len).await
+    }
+
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        info!(
+            "read-only: skipped safeload at 0x{:04x}: {:02x?}",
+            addr, data
+        );
+        Ok(())
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        info!("read-only: skipped safeload of {:04x?}", batch.words());
+        Ok(())
+    }
+
+    /// Drains the payload without keeping it, only its length is logged
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        let len = source.remaining();
+        let mut buf = [0; 256];
+        while source.remaining() > 0 {
+            let n = buf.len().min(source.remaining());
+            source.read_exact(&mut buf[..n]).await?;
+        }
+        info!(
+            "read-only: skipped write of {} bytes at 0x{:04x}",
+            len, addr
+        );
+        Ok(())
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    #[tokio::test]
+    async fn test_write_does_not_reach_inner() {
+        let mut inner = MemoryBackend::new();
+        inner
+            .write(0x0043, &[0x01, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+
+        let mut backend = ReadOnlyBackend::new(inner);
+
+        // the write reports success, as SigmaStudio expects
+        backend
+            .write(0x0043, &[0xff, 0xff, 0xff, 0xff])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            backend.read(0x0043, 4).await.unwrap(),
+            vec![0x01, 0x00, 0x00, 0x00]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use log::debug;
+
+use super::{Backend, WriteSource};
+use crate::identify::DeviceInfo;
+use crate::safeload::{SafeloadBatch, SafeloadConfig};
+
+/// Decorator that performs safeload writes through the DSP safeload registers.
+///
+/// Plain writes go through unchanged. A safeload write of up to
+/// `data_slots` words is turned into the ADAU sequence of `config`: data
+/// registers, target addresses, then the write that triggers the transfer.
+pub struct SafeloadBackend<B> {
+    inner: B,
+    config: SafeloadConfig,
+}
+
+impl<B: Backend> SafeloadBackend<B> {
+    pub fn new(inner: B, config: SafeloadConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+#[async_trait]
+impl<B: Backend> Backend for SafeloadBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        self.inner.read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.inner.write(addr, data).await
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        self.inner.read_sequential(start, count, word_len).await
+    }
+
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        debug!("safeload of {} bytes at 0x{:04x}", data.len(), addr);
+        for (reg, bytes) in self.config.sequence(addr, data)? {
+            self.inner.write(reg, &bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        debug!("safeload batch of {:04x?}", batch.words());
+        for (reg, bytes) in self.config.batch_sequence(batch)? {
+            self.inner.write(reg, &bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_stream(&mut self, addr: u16, source: &mut dyn WriteSource) -> Result<()> {
+        self.inner.write_stream(addr, source).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    /// Records the writes reaching the memory, in order
+    struct RecordingBackend {
+        memory: MemoryBackend,
+        writes: Vec<(u16, Vec<u8>)>,
+    }
+
+    #[async_trait]
+    impl Backend for RecordingBackend {
+        async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+            self.memory.read(addr, len).await
+        }
+
+        async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+            self.writes.push((addr, data.to_vec()));
+            self.memory.write(addr, data).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_safeload_write_sequence() {
+        let inner = RecordingBackend {
+            memory: MemoryBackend::new(),
+            writes: Vec::new(),
+        };
+        let mut backend = SafeloadBackend::new(inner, SafeloadConfig::ADAU1452);
+
+        backend
+            .safeload_write(0x0043, &[0x01, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            backend.inner.writes,
+            vec![
+                (0x6000, vec![0x01, 0, 0, 0]),
+                (0x6005, vec![0, 0, 0, 0x43]),
+                (0x6006, vec![0, 0, 0, 1]),
+            ]
+        );
+        // the registers hold the loaded data, target address and count
+        assert_eq!(backend.read(0x6000, 4).await.unwrap(), vec![0x01, 0, 0, 0]);
+        assert_eq!(backend.read(0x6006, 4).await.unwrap(), vec![0, 0, 0, 1]);
+
+        // plain writes are untouched
+        backend.write(0x0044, &[0, 0, 0, 2]).await.unwrap();
+        assert_eq!(backend.inner.writes.last().unwrap().0, 0x0044);
+
+        // a batch in any order is one safeload from its lowest address
+        backend.inner.writes.clear();
+        let batch =
+            SafeloadBatch::new(vec![(0x0051, [0, 0, 0, 2]), (0x0050, [0, 0, 0, 1])]).unwrap();
+        backend.safeload_batch(&batch).await.unwrap();
+        assert_eq!(
+            backend.inner.writes,
+            vec![
+                (0x6000, vec![0, 0, 0, 1, 0, 0, 0, 2]),
+                (0x6005, vec![0, 0, 0, 0x50]),
+                (0x6006, vec![0, 0, 0, 2]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_safeload_batch_at_any_addresses() {
+        let inner = RecordingBackend {
+            memory: MemoryBackend::new(),
+            writes: Vec::new(),
+        };
+        let mut backend = SafeloadBackend::new(inner, SafeloadConfig::ADAU1701);
+
+        let batch =
+            SafeloadBatch::new(vec![(0x0200, [0, 0, 0, 2]), (0x0010, [0, 0, 0, 1])]).unwrap();
+        backend.safeload_batch(&batch).await.unwrap();
+        assert_eq!(
+            backend.inner.writes,
+            vec![
+                (0x0810, vec![0, 0, 0, 0, 2]),
+                (0x0815, vec![0x02, 0x00]),
+                (0x0811, vec![0, 0, 0, 0, 1]),
+                (0x0816, vec![0x00, 0x10]),
+                (0x081c, vec![0x00, 0x3c]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::error;
+
+use super::Backend;
+use crate::identify::DeviceInfo;
+use crate::safeload::SafeloadBatch;
+
+/// Decorator that reads back every write and fails if the data doesn't match.
+///
+/// Useful for critical coefficient writes, where a write silently ignored or
+/// corrupted by the device would otherwise go unnoticed.
+///
+/// Streamed writes are collected whole (the `write_stream` default), since
+/// the whole payload is needed to compare it with the read back.
+pub struct VerifyingBackend<B> {
+    inner: B,
+}
+
+impl<B: Backend> VerifyingBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl<B: Backend> Backend for VerifyingBackend<B> {
+    async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+        self.inner.read(addr, len).await
+    }
+
+    async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.inner.write(addr, data).await?;
+
+        let read_back = self.inner.read(addr, data.len() as u32).await?;
+        if read_back != data {
+            error!(
+                "write verification failed at 0x{:04x}: wrote {:02x?}, read back {:02x?}",
+                addr, data, read_back
+            );
+            return Err(anyhow!("Write verification failed at 0x{:04x}", addr));
+        }
+
+        Ok(())
+    }
+
+    async fn read_sequential(
+        &mut self,
+        start: u16,
+        count: usize,
+        word_len: u16,
+    ) -> Result<Vec<u8>> {
+        self.inner.read_sequential(start, count, word_len).await
+    }
+
+    /// Not read back: the target words change at the next audio frame
+    async fn safeload_write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+        self.inner.safeload_write(addr, data).await
+    }
+
+    async fn safeload_batch(&mut self, batch: &SafeloadBatch) -> Result<()> {
+        self.inner.safeload_batch(batch).await
+    }
+
+    async fn identify(&mut self) -> Result<DeviceInfo> {
+        self.inner.identify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    /// Backend that flips a bit in everything it stores
+    struct LossyBackend(MemoryBackend);
+
+    #[async_trait]
+    impl Backend for LossyBackend {
+        async fn read(&mut self, addr: u16, len: u32) -> Result<Vec<u8>> {
+            self.0.read(addr, len).await
+        }
+
+        async fn write(&mut self, addr: u16, data: &[u8]) -> Result<()> {
+            let corrupted: Vec<u8> = data.iter().map(|b| b ^ 0x01).collect();
+            self.0.write(addr, &corrupted).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verified_write_succeeds() {
+        let mut backend = VerifyingBackend::new(MemoryBackend::new());
+
+        backend
+            .write(0x0043, &[0x01, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.read(0x0043, 4).await.unwrap(),
+            vec![0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lossy_write_fails_verification() {
+        let mut backend = VerifyingBackend::new(LossyBackend(MemoryBackend::new()));
+
+        assert!(backend
+            .write(0x0043, &[0x01, 0x00, 0x00, 0x00])
+            .await
+            .is_err());
+    }
+}
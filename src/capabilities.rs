@@ -0,0 +1,150 @@
+use std::fmt;
+use std::ops::BitOr;
+
+use anyhow::{bail, Result};
+
+/// Optional features of a server, answer to [`crate::CMD_CAPABILITIES`]
+///
+/// A set of flags, sent as a big-endian `u32`. Bits a client doesn't know
+/// are kept, so a newer server doesn't break an older client.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Safeload writes are applied atomically instead of as plain writes
+    pub const SAFELOAD: Self = Self(1 << 0);
+    /// The IC index of a command selects one of several DSPs
+    pub const MULTI_CHIP: Self = Self(1 << 1);
+    /// HTTP `/read_multi` and `/write_multi`
+    pub const BATCH: Self = Self(1 << 2);
+    /// CRC trailers, see [`crate::checksum::Checksum`]
+    pub const CHECKSUM: Self = Self(1 << 3);
+    /// [`crate::CMD_IDENTIFY`]
+    pub const IDENTIFY: Self = Self(1 << 4);
+    /// [`crate::CMD_PING`]
+    pub const PING: Self = Self(1 << 5);
+    /// Clients must authenticate, see [`crate::auth`]
+    pub const AUTH: Self = Self(1 << 6);
+    /// HTTP WebSocket `/ws` register stream
+    pub const STREAM: Self = Self(1 << 7);
+    /// Prometheus `/metrics` endpoint
+    pub const METRICS: Self = Self(1 << 8);
+
+    /// Everything the protocol code of this crate implements, whatever the server
+    pub const PROTOCOL: Self =
+        Self(Self::SAFELOAD.0 | Self::CHECKSUM.0 | Self::IDENTIFY.0 | Self::PING.0);
+
+    const NAMES: [(Self, &'static str); 9] = [
+        (Self::SAFELOAD, "safeload"),
+        (Self::MULTI_CHIP, "multi_chip"),
+        (Self::BATCH, "batch"),
+        (Self::CHECKSUM, "checksum"),
+        (Self::IDENTIFY, "identify"),
+        (Self::PING, "ping"),
+        (Self::AUTH, "auth"),
+        (Self::STREAM, "stream"),
+        (Self::METRICS, "metrics"),
+    ];
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds `other` if `enabled`, for flags that depend on the configuration
+    pub const fn with_if(self, other: Self, enabled: bool) -> Self {
+        if enabled {
+            Self(self.0 | other.0)
+        } else {
+            self
+        }
+    }
+
+    /// Names of the known flags that are set, for JSON APIs
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, known)| *known == name)
+            .map(|(flag, _)| *flag)
+    }
+
+    /// Payload of a capabilities response
+    pub fn to_bytes(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let Some(bytes) = buf.get(..4) else {
+            bail!("Buffer too short for capabilities");
+        };
+        Ok(Self(u32::from_be_bytes(bytes.try_into()?)))
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Capabilities({:#x}: {})",
+            self.0,
+            self.names().join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_round_trip() {
+        let caps = Capabilities::PROTOCOL.with_if(Capabilities::AUTH, true);
+        assert!(caps.contains(Capabilities::PING | Capabilities::AUTH));
+        assert!(!caps.contains(Capabilities::BATCH));
+        assert_eq!(caps.with_if(Capabilities::BATCH, false), caps);
+
+        assert_eq!(Capabilities::from_bytes(&caps.to_bytes()).unwrap(), caps);
+        assert_eq!(
+            caps.names(),
+            vec!["safeload", "checksum", "identify", "ping", "auth"]
+        );
+        assert_eq!(
+            Capabilities::from_name("checksum"),
+            Some(Capabilities::CHECKSUM)
+        );
+
+        // i bit sconosciuti restano, un server più nuovo non rompe il client
+        let newer = Capabilities::from_bits(1 << 31 | Capabilities::PING.bits());
+        assert_eq!(Capabilities::from_bytes(&newer.to_bytes()).unwrap(), newer);
+        assert_eq!(newer.names(), vec!["ping"]);
+
+        assert!(Capabilities::from_bytes(&[0, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+/// Optional checksum trailing every command and read response frame
+///
+/// SigmaStudio doesn't know about it, so it's only enabled by a handshake
+/// (see [`crate::WriteFraming::handshake_with`]) between this crate's own
+/// client and server, e.g. a [`crate::backend::ProxyBackend`] over Wi-Fi.
+/// The trailer is big-endian and covers the whole frame, `total_len`
+/// doesn't include it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Checksum {
+    #[default]
+    None,
+    /// CRC-16/CCITT-FALSE, 2 bytes
+    Crc16,
+    /// CRC-32 (IEEE 802.3), 4 bytes
+    Crc32,
+}
+
+impl Checksum {
+    /// Bytes appended to each frame
+    pub fn trailer_len(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc16 => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    /// Value carried in the high nibble of the handshake id byte
+    pub fn id(self) -> u8 {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc16 => 1,
+            Checksum::Crc32 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Checksum::None),
+            1 => Some(Checksum::Crc16),
+            2 => Some(Checksum::Crc32),
+            _ => None,
+        }
+    }
+
+    /// Trailer for `frame`
+    pub fn compute(self, frame: &[u8]) -> Vec<u8> {
+        match self {
+            Checksum::None => Vec::new(),
+            Checksum::Crc16 => crc16(frame).to_be_bytes().to_vec(),
+            Checksum::Crc32 => crc32(frame).to_be_bytes().to_vec(),
+        }
+    }
+
+    /// Appends the trailer of `frame` to it
+    pub fn append(self, frame: &mut Vec<u8>) {
+        let trailer = self.compute(frame);
+        frame.extend_from_slice(&trailer);
+    }
+
+    pub fn verify(self, frame: &[u8], trailer: &[u8]) -> bool {
+        self.compute(frame) == trailer
+    }
+}
+
+/// CRC-16/CCITT-FALSE: poly 0x1021, init 0xffff, no reflection
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-32 as in zlib/Ethernet: reflected poly 0xedb88320
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_values() {
+        // valori di controllo standard dei due CRC
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        assert_eq!(Checksum::Crc16.compute(b"123456789"), vec![0x29, 0xb1]);
+        assert!(Checksum::Crc32.verify(b"123456789", &[0xcb, 0xf4, 0x39, 0x26]));
+        assert!(!Checksum::Crc32.verify(b"123456780", &[0xcb, 0xf4, 0x39, 0x26]));
+        assert!(Checksum::None.verify(b"anything", &[]));
+    }
+}
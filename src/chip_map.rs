@@ -0,0 +1,110 @@
+use std::fmt;
+
+use anyhow::{bail, Context, Result};
+
+/// Maps the SigmaStudio IC index sent in `chip_addr` to the I2C address of that DSP.
+///
+/// SigmaStudio numbers the ICs of a project from 1, a command for an IC with
+/// no entry must be refused rather than sent to another DSP. The textual
+/// form is `ic:i2c_addr` pairs, e.g. `1:0x3b,2:0x3a`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChipMap {
+    entries: Vec<(u8, u8)>,
+}
+
+impl ChipMap {
+    /// Only IC 1, at `i2c_addr`
+    pub fn single(i2c_addr: u8) -> Self {
+        Self::default().with(1, i2c_addr)
+    }
+
+    /// Maps `ic` to `i2c_addr`, replacing a previous entry for `ic`
+    pub fn with(mut self, ic: u8, i2c_addr: u8) -> Self {
+        self.entries.retain(|(existing, _)| *existing != ic);
+        self.entries.push((ic, i2c_addr));
+        self.entries.sort_unstable();
+        self
+    }
+
+    pub fn i2c_addr(&self, ic: u8) -> Option<u8> {
+        self.entries
+            .iter()
+            .find(|(existing, _)| *existing == ic)
+            .map(|(_, i2c_addr)| *i2c_addr)
+    }
+
+    pub fn entries(&self) -> &[(u8, u8)] {
+        &self.entries
+    }
+
+    /// Parses `1:0x3b,2:0x3a`, numbers in hex or decimal
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut map = Self::default();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (ic, i2c_addr) = entry
+                .split_once(':')
+                .with_context(|| format!("Expected ic:i2c_addr, got {:?}", entry))?;
+            let (ic, i2c_addr) = (parse_u8(ic)?, parse_u8(i2c_addr)?);
+            if i2c_addr > 0x7f {
+                bail!("I2C address 0x{:02x} is not a 7-bit address", i2c_addr);
+            }
+            map = map.with(ic, i2c_addr);
+        }
+
+        if map.entries.is_empty() {
+            bail!("Empty chip map {:?}", value);
+        }
+        Ok(map)
+    }
+}
+
+impl fmt::Display for ChipMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (ic, i2c_addr)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:0x{:02x}", ic, i2c_addr)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_u8(value: &str) -> Result<u8> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .with_context(|| format!("Invalid number {:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chip_map_lookup() {
+        let map = ChipMap::parse("2:0x3a, 1:0x3b").unwrap();
+
+        assert_eq!(map.i2c_addr(1), Some(0x3b));
+        assert_eq!(map.i2c_addr(2), Some(0x3a));
+        // IC 3 is not part of the project, it must not fall back to IC 1
+        assert_eq!(map.i2c_addr(3), None);
+
+        assert_eq!(map.to_string(), "1:0x3b,2:0x3a");
+        assert_eq!(ChipMap::parse(&map.to_string()).unwrap(), map);
+        assert_eq!(ChipMap::single(0x3b).with(1, 0x38).i2c_addr(1), Some(0x38));
+    }
+
+    #[test]
+    fn test_chip_map_parse_errors() {
+        assert!(ChipMap::parse("").is_err());
+        assert!(ChipMap::parse("1").is_err());
+        assert!(ChipMap::parse("1:0x80").is_err());
+        assert!(ChipMap::parse("x:0x3b").is_err());
+    }
+}
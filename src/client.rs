@@ -0,0 +1,121 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{ProtocolHandler, ResponseHeader, CMD_RESP, STATUS_OK};
+
+/// Blocking client of a sigma-tcp server, to read and write DSP registers
+/// from a Rust program
+///
+/// Requests are sent one at a time: a read waits for its response before
+/// returning, a write has no response and returns once it's sent.
+pub struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr).context("Failed to connect to sigma-tcp server")?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Wraps an already connected stream
+    pub fn from_stream(stream: TcpStream) -> Self {
+        Self { stream }
+    }
+
+    /// Reads `len` bytes at `addr` of the chip at `chip`
+    pub fn read(&mut self, chip: u8, addr: u16, len: u32) -> Result<Vec<u8>> {
+        let request = ProtocolHandler::create_read_request(chip, addr, len);
+        self.stream.write_all(&request)?;
+
+        let mut frame = [0u8; 14];
+        self.stream.read_exact(&mut frame)?;
+        let header = ResponseHeader::from_bytes(&frame)?;
+        check_read_header(&header, addr)?;
+
+        let mut data = vec![0; header.data_len as usize];
+        self.stream.read_exact(&mut data)?;
+        read_result(&header, data)
+    }
+
+    /// Writes `data` at `addr` of the chip at `chip`
+    pub fn write(&mut self, chip: u8, addr: u16, data: &[u8]) -> Result<()> {
+        let request = ProtocolHandler::create_write_request(chip, addr, data);
+        self.stream.write_all(&request)?;
+        Ok(())
+    }
+}
+
+/// Like [`Client`], on a tokio [`tokio::net::TcpStream`]
+#[cfg(feature = "server")]
+pub struct AsyncClient {
+    stream: tokio::net::TcpStream,
+}
+
+#[cfg(feature = "server")]
+impl AsyncClient {
+    pub async fn connect(addr: impl tokio::net::ToSocketAddrs) -> Result<Self> {
+        let stream = tokio::net::TcpStream::connect(addr)
+            .await
+            .context("Failed to connect to sigma-tcp server")?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Wraps an already connected stream
+    pub fn from_stream(stream: tokio::net::TcpStream) -> Self {
+        Self { stream }
+    }
+
+    /// Reads `len` bytes at `addr` of the chip at `chip`
+    pub async fn read(&mut self, chip: u8, addr: u16, len: u32) -> Result<Vec<u8>> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let request = ProtocolHandler::create_read_request(chip, addr, len);
+        self.stream.write_all(&request).await?;
+
+        let mut frame = [0u8; 14];
+        self.stream.read_exact(&mut frame).await?;
+        let header = ResponseHeader::from_bytes(&frame)?;
+        check_read_header(&header, addr)?;
+
+        let mut data = vec![0; header.data_len as usize];
+        self.stream.read_exact(&mut data).await?;
+        read_result(&header, data)
+    }
+
+    /// Writes `data` at `addr` of the chip at `chip`
+    pub async fn write(&mut self, chip: u8, addr: u16, data: &[u8]) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let request = ProtocolHandler::create_write_request(chip, addr, data);
+        self.stream.write_all(&request).await?;
+        Ok(())
+    }
+}
+
+/// Checks that `header` answers a read at `addr`, before its payload is read
+fn check_read_header(header: &ResponseHeader, addr: u16) -> Result<()> {
+    if header.control_bit != CMD_RESP || header.param_addr != addr {
+        return Err(anyhow!(
+            "Unexpected response for read at 0x{:04x}: {:?}",
+            addr,
+            header
+        ));
+    }
+    Ok(())
+}
+
+/// A failed read still carries `data_len` zero bytes, the error is only
+/// returned once they're consumed so the next request lines up
+fn read_result(header: &ResponseHeader, data: Vec<u8>) -> Result<Vec<u8>> {
+    if header.success != STATUS_OK {
+        return Err(anyhow!(
+            "Read at 0x{:04x} failed with status {}",
+            header.param_addr,
+            header.success
+        ));
+    }
+    Ok(data)
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+/// What a backend knows about the connected part
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceInfo {
+    /// The backend has no way to tell
+    #[default]
+    Unknown,
+    /// ID read from the device, with the part name if it's in the table
+    Detected { id: Vec<u8>, part: Option<String> },
+}
+
+impl DeviceInfo {
+    pub fn part(&self) -> Option<&str> {
+        match self {
+            DeviceInfo::Detected { part, .. } => part.as_deref(),
+            DeviceInfo::Unknown => None,
+        }
+    }
+
+    /// Payload of an identify response: ID length and bytes, then part
+    /// name length and UTF-8 bytes. Unknown is two zero lengths.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (id, part) = match self {
+            DeviceInfo::Unknown => (&[][..], ""),
+            DeviceInfo::Detected { id, part } => (&id[..], part.as_deref().unwrap_or("")),
+        };
+        // entrambe le lunghezze stanno in un byte
+        let id = &id[..id.len().min(255)];
+        let part = &part.as_bytes()[..part.len().min(255)];
+
+        let mut bytes = Vec::with_capacity(2 + id.len() + part.len());
+        bytes.push(id.len() as u8);
+        bytes.extend_from_slice(id);
+        bytes.push(part.len() as u8);
+        bytes.extend_from_slice(part);
+        bytes
+    }
+
+    /// Parses the payload written by `to_bytes`, returns it with the bytes consumed
+    pub fn from_bytes(buf: &[u8]) -> Result<(Self, usize)> {
+        let Some(&id_len) = buf.first() else {
+            bail!("Buffer too short for device info");
+        };
+        let id_end = 1 + id_len as usize;
+        let Some(&part_len) = buf.get(id_end) else {
+            bail!("Buffer too short for device info");
+        };
+        let part_end = id_end + 1 + part_len as usize;
+        if buf.len() < part_end {
+            bail!("Buffer too short for device info");
+        }
+
+        let id = buf[1..id_end].to_vec();
+        let part = String::from_utf8(buf[id_end + 1..part_end].to_vec())?;
+        let info = if id.is_empty() && part.is_empty() {
+            DeviceInfo::Unknown
+        } else {
+            DeviceInfo::Detected {
+                id,
+                part: (!part.is_empty()).then_some(part),
+            }
+        };
+        Ok((info, part_end))
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceInfo::Unknown => write!(f, "unknown"),
+            DeviceInfo::Detected { id, part } => {
+                write!(f, "{} (id", part.as_deref().unwrap_or("unknown part"))?;
+                for byte in id {
+                    write!(f, " {:02x}", byte)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Register holding a part ID, and the IDs it can hold
+///
+/// The ADAU1452 family has no documented ID register, so there is no
+/// built-in table: set it for the parts on the board.
+#[derive(Clone, Copy, Debug)]
+pub struct PartId {
+    pub addr: u16,
+    pub len: u32,
+    /// Known IDs, with the name of their part
+    pub parts: &'static [(&'static [u8], &'static str)],
+}
+
+impl PartId {
+    /// Device info for the `id` read from `addr`
+    pub fn device_info(&self, id: Vec<u8>) -> DeviceInfo {
+        let part = self
+            .parts
+            .iter()
+            .find(|(known, _)| *known == id.as_slice())
+            .map(|(_, name)| name.to_string());
+        DeviceInfo::Detected { id, part }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_info_round_trip() {
+        let id_table = PartId {
+            addr: 0xf000,
+            len: 2,
+            parts: &[(&[0x14, 0x52], "ADAU1452")],
+        };
+
+        for info in [
+            DeviceInfo::Unknown,
+            id_table.device_info(vec![0x14, 0x52]),
+            id_table.device_info(vec![0x00, 0x01]),
+        ] {
+            let bytes = info.to_bytes();
+            assert_eq!(DeviceInfo::from_bytes(&bytes).unwrap(), (info, bytes.len()));
+        }
+
+        assert_eq!(
+            id_table.device_info(vec![0x14, 0x52]).to_string(),
+            "ADAU1452 (id 14 52)"
+        );
+        assert_eq!(id_table.device_info(vec![0x00, 0x01]).part(), None);
+        assert!(DeviceInfo::from_bytes(&[2, 0x14]).is_err());
+    }
+}
@@ -1,9 +1,122 @@
 use anyhow::Result;
-use log::{error, info};
+use log::{error, info, warn};
+
+pub mod auth;
+pub mod backend;
+pub mod capabilities;
+pub mod checksum;
+pub mod chip_map;
+pub mod client;
+pub mod identify;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod query;
+pub mod registers;
+pub mod safeload;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod sigmastudio;
+pub mod ticker;
+
+use backend::Backend;
+use capabilities::Capabilities;
+use checksum::Checksum;
+use identify::DeviceInfo;
 
 pub const CMD_READ: u8 = 0x0a;
 pub const CMD_WRITE: u8 = 0x09;
 pub const CMD_RESP: u8 = 0x0b;
+/// Liveness probe, not part of SigmaStudio's protocol: the server answers
+/// with this same single byte without touching the backend
+pub const CMD_PING: u8 = 0x0c;
+/// Asks which part is connected, not part of SigmaStudio's protocol: the
+/// answer is this byte followed by [`DeviceInfo::to_bytes`]
+pub const CMD_IDENTIFY: u8 = 0x0d;
+/// Asks which optional features the server supports, not part of
+/// SigmaStudio's protocol: the answer is this byte followed by
+/// [`Capabilities::to_bytes`]
+pub const CMD_CAPABILITIES: u8 = 0x0e;
+
+/// Largest `data_len` accepted in a write, the size of the ADAU1452 memory partition
+pub const MAX_DATA_LEN: u32 = 20480 * 4;
+
+// Values of the `success` byte in a read response header, SigmaStudio shows
+// any non-zero value as a failed transaction
+/// The read completed and the payload holds the device data
+pub const STATUS_OK: u8 = 0;
+/// The backend failed to complete the read (e.g. I2C NACK), the payload is zero-filled
+pub const STATUS_BACKEND_ERROR: u8 = 1;
+/// The backend didn't complete the read in time, the payload is zero-filled
+pub const STATUS_TIMEOUT: u8 = 2;
+/// The request failed its checksum and wasn't executed, the payload is zero-filled
+pub const STATUS_CHECKSUM_ERROR: u8 = 3;
+/// The read is longer than the server accepts and wasn't executed, the payload is empty
+pub const STATUS_READ_TOO_LONG: u8 = 4;
+
+/// Bytes a client can send first to pick the write framing of its
+/// connection, followed by the framing id (see [`WriteFraming::id`]) with
+/// the [`Checksum::id`] in the high nibble
+pub const HANDSHAKE_MAGIC: &[u8; 8] = b"SIGMATCP";
+
+/// Layout of a write header. SigmaStudio sends the safeload and channel bytes,
+/// some third-party tools leave them out and frame writes like reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteFraming {
+    /// control, safeload, channel, total_len, chip, data_len, param: 14 bytes
+    Standard,
+    /// control, total_len, chip, data_len, param: 12 bytes, no safeload
+    Compact,
+}
+
+impl WriteFraming {
+    pub fn header_len(self) -> usize {
+        match self {
+            WriteFraming::Standard => 14,
+            WriteFraming::Compact => 12,
+        }
+    }
+
+    /// Byte that follows [`HANDSHAKE_MAGIC`] to select this framing
+    pub fn id(self) -> u8 {
+        match self {
+            WriteFraming::Standard => 0,
+            WriteFraming::Compact => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(WriteFraming::Standard),
+            1 => Some(WriteFraming::Compact),
+            _ => None,
+        }
+    }
+
+    /// Framing under which the write header at the start of `buf` has
+    /// `total_len == header_len + data_len`, Standard first
+    pub fn detect(buf: &[u8]) -> Option<Self> {
+        [WriteFraming::Standard, WriteFraming::Compact]
+            .into_iter()
+            .find(|framing| {
+                WriteHeader::from_bytes_with(buf, *framing).is_ok_and(|header| {
+                    header.total_len as u64 == framing.header_len() as u64 + header.data_len as u64
+                })
+            })
+    }
+
+    /// Handshake frame a client sends to select this framing
+    pub fn handshake(self) -> Vec<u8> {
+        self.handshake_with(Checksum::None)
+    }
+
+    /// Handshake frame selecting this framing and `checksum` on every
+    /// following command and read response
+    pub fn handshake_with(self, checksum: Checksum) -> Vec<u8> {
+        let mut bytes = HANDSHAKE_MAGIC.to_vec();
+        bytes.push(checksum.id() << 4 | self.id());
+        bytes
+    }
+}
 
 #[derive(Debug)]
 pub struct RequestHeader {
@@ -50,22 +163,48 @@ impl RequestHeader {
 
 impl WriteHeader {
     pub fn from_bytes(buf: &[u8]) -> Result<Self> {
-        if buf.len() < 14 {
+        Self::from_bytes_with(buf, WriteFraming::Standard)
+    }
+
+    /// Parses a write header laid out as `framing`, without the safeload
+    /// and channel bytes they read as 0
+    pub fn from_bytes_with(buf: &[u8], framing: WriteFraming) -> Result<Self> {
+        if buf.len() < framing.header_len() {
             return Err(anyhow::anyhow!("Buffer too short for write header"));
         }
+        let (safeload, channel_num, rest) = match framing {
+            WriteFraming::Standard => (buf[1], buf[2], &buf[3..]),
+            WriteFraming::Compact => (0, 0, &buf[1..]),
+        };
         Ok(Self {
             control_bit: buf[0],
-            safeload: buf[1],
-            channel_num: buf[2],
-            total_len: u32::from_be_bytes([buf[3], buf[4], buf[5], buf[6]]),
-            chip_addr: buf[7],
-            data_len: u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]),
-            param_addr: u16::from_be_bytes([buf[12], buf[13]]),
+            safeload,
+            channel_num,
+            total_len: u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]),
+            chip_addr: rest[4],
+            data_len: u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]),
+            param_addr: u16::from_be_bytes([rest[9], rest[10]]),
         })
     }
 }
 
 impl ResponseHeader {
+    /// Parses the 14 byte header of a read response, as sent by another sigma-tcp server
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 14 {
+            return Err(anyhow::anyhow!("Buffer too short for response header"));
+        }
+        Ok(Self {
+            control_bit: buf[0],
+            total_len: u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]),
+            chip_addr: buf[5],
+            data_len: u32::from_be_bytes([buf[6], buf[7], buf[8], buf[9]]),
+            param_addr: u16::from_be_bytes([buf[10], buf[11]]),
+            success: buf[12],
+            reserved: [buf[13]],
+        })
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(13);
         bytes.push(self.control_bit);
@@ -81,8 +220,26 @@ impl ResponseHeader {
 
 #[derive(Debug)]
 pub enum ProtocolCommand {
-    Read { header: RequestHeader },
-    Write { header: WriteHeader, data: Vec<u8> },
+    Read {
+        header: RequestHeader,
+    },
+    Write {
+        header: WriteHeader,
+        data: Vec<u8>,
+    },
+    /// The client picked the write framing and checksum of the connection
+    Handshake {
+        framing: WriteFraming,
+        checksum: Checksum,
+    },
+    /// A read or write whose checksum doesn't match, not to be executed
+    ChecksumMismatch(Box<ProtocolCommand>),
+    /// Liveness probe, see [`CMD_PING`]
+    Ping,
+    /// See [`CMD_IDENTIFY`]
+    Identify,
+    /// See [`CMD_CAPABILITIES`]
+    Capabilities,
     Unknown(u8),
 }
 
@@ -93,6 +250,10 @@ pub enum ProtocolResponse {
         data: Vec<u8>,
     },
     Write,
+    /// Answer to [`ProtocolCommand::Ping`]
+    Pong,
+    Identify(DeviceInfo),
+    Capabilities(Capabilities),
     Error(String),
 }
 
@@ -127,8 +288,8 @@ impl ProtocolResponse {
                 // 5. Param addr (2 byte, big-endian)
                 bytes.extend_from_slice(&header.param_addr.to_be_bytes());
 
-                // 6. Success (1 byte): sempre 0 per risposte normali
-                bytes.push(0);
+                // 6. Success (1 byte): 0 per risposte normali, vedi STATUS_*
+                bytes.push(header.success);
 
                 // 7. Reserved (1 byte): sempre 0
                 bytes.push(0);
@@ -141,20 +302,182 @@ impl ProtocolResponse {
             ProtocolResponse::Write => {
                 vec![]
             }
+            ProtocolResponse::Pong => vec![CMD_PING],
+            ProtocolResponse::Identify(info) => {
+                let mut bytes = vec![CMD_IDENTIFY];
+                bytes.extend_from_slice(&info.to_bytes());
+                bytes
+            }
+            ProtocolResponse::Capabilities(capabilities) => {
+                let mut bytes = vec![CMD_CAPABILITIES];
+                bytes.extend_from_slice(&capabilities.to_bytes());
+                bytes
+            }
             ProtocolResponse::Error(_) => {
                 // Per gli errori, inviamo una risposta vuota
                 vec![]
             }
         }
     }
+
+    /// Like `to_bytes`, with the `checksum` trailer after read responses
+    pub fn to_bytes_with(&self, checksum: Checksum) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        if let ProtocolResponse::Read { .. } = self {
+            checksum.append(&mut bytes);
+        }
+        bytes
+    }
+}
+
+/// Consecutive unknown command bytes skipped before a connection is dropped
+pub const MAX_RESYNC_SKIP: usize = 64;
+
+/// Too many consecutive unknown bytes, the stream is garbage or out of sync
+#[derive(Debug, thiserror::Error)]
+#[error("{0} consecutive unknown command bytes, giving up on the stream")]
+pub struct ResyncError(pub usize);
+
+/// The buffer ends in the middle of a command, more bytes are needed to parse it
+///
+/// Not a protocol error: a large write spans several reads, and SigmaStudio
+/// can close the connection with a partial command still buffered.
+#[derive(Debug, thiserror::Error)]
+#[error("Incomplete command, need more bytes")]
+pub struct IncompleteCommand;
+
+/// Resynchronizes a stream after unknown command bytes
+///
+/// `parse_command` consumes a single byte for an unknown command, so a junk
+/// prefix is skipped one byte at a time until a valid command header lines up.
+/// This counts the consecutive skips and fails once they exceed `max_skip`.
+pub struct Resync {
+    skipped: usize,
+    max_skip: usize,
+}
+
+impl Resync {
+    pub fn new(max_skip: usize) -> Self {
+        Self {
+            skipped: 0,
+            max_skip,
+        }
+    }
+
+    /// To be called on every parsed command before executing it
+    pub fn check(&mut self, command: &ProtocolCommand) -> Result<(), ResyncError> {
+        match command {
+            ProtocolCommand::Unknown(byte) => {
+                self.skipped += 1;
+                warn!("Skipping unknown command byte 0x{:02x}", byte);
+                if self.skipped > self.max_skip {
+                    return Err(ResyncError(self.skipped));
+                }
+            }
+            _ => {
+                if self.skipped > 0 {
+                    info!("Resynchronized after {} unknown bytes", self.skipped);
+                }
+                self.skipped = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Resync {
+    fn default() -> Self {
+        Self::new(MAX_RESYNC_SKIP)
+    }
+}
+
+/// Write framing of a connection
+///
+/// Set by a handshake if the client sends one, otherwise detected on the
+/// first write whose lengths are consistent under one of the framings.
+/// Until then writes are parsed as [`WriteFraming::Standard`]. Checksums
+/// are only enabled by a handshake.
+#[derive(Debug, Default)]
+pub struct ConnectionFraming {
+    framing: Option<WriteFraming>,
+    checksum: Checksum,
+}
+
+impl ConnectionFraming {
+    pub fn framing(&self) -> WriteFraming {
+        self.framing.unwrap_or(WriteFraming::Standard)
+    }
+
+    /// Checksum trailing commands and read responses, to be passed to
+    /// [`ProtocolResponse::to_bytes_with`]
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    /// Parses the next command of the connection with its framing
+    pub fn parse(&mut self, buf: &[u8]) -> Result<(ProtocolCommand, usize)> {
+        self.detect(buf);
+
+        let result = ProtocolHandler::parse_command_checked(buf, self.framing(), self.checksum);
+        if let Ok((ProtocolCommand::Handshake { framing, checksum }, _)) = &result {
+            info!(
+                "Client selected {:?} write framing, checksum {:?}",
+                framing, checksum
+            );
+            self.framing = Some(*framing);
+            self.checksum = *checksum;
+        }
+        result
+    }
+
+    /// Header of the write at the start of `buf`, laid out with the framing
+    /// of the connection, for a write whose payload is streamed instead of
+    /// buffered
+    pub fn parse_write_header(&mut self, buf: &[u8]) -> Result<WriteHeader> {
+        self.detect(buf);
+        ProtocolHandler::parse_write_header(buf, self.framing())
+    }
+
+    fn detect(&mut self, buf: &[u8]) {
+        if self.framing.is_none() && buf.first() == Some(&CMD_WRITE) {
+            if let Some(framing) = WriteFraming::detect(buf) {
+                info!("Detected {:?} write framing", framing);
+                self.framing = Some(framing);
+            }
+        }
+    }
 }
 
 pub struct ProtocolHandler;
 
 impl ProtocolHandler {
     pub fn parse_command(buf: &[u8]) -> Result<(ProtocolCommand, usize)> {
+        Self::parse_command_with(buf, WriteFraming::Standard)
+    }
+
+    /// Like `parse_command`, with writes laid out as `framing`
+    pub fn parse_command_with(
+        buf: &[u8],
+        framing: WriteFraming,
+    ) -> Result<(ProtocolCommand, usize)> {
         if buf.is_empty() {
-            return Err(anyhow::anyhow!("Empty buffer"));
+            return Err(IncompleteCommand.into());
+        }
+
+        let handshake_len = HANDSHAKE_MAGIC.len() + 1;
+        if buf.len() < handshake_len && HANDSHAKE_MAGIC.starts_with(buf) {
+            return Err(IncompleteCommand.into());
+        }
+        if let Some(rest) = buf.strip_prefix(HANDSHAKE_MAGIC) {
+            let id = rest[0];
+            let framing = WriteFraming::from_id(id & 0x0f)
+                .ok_or_else(|| anyhow::anyhow!("Unknown framing 0x{:02x} in handshake", id))?;
+            let checksum = Checksum::from_id(id >> 4)
+                .ok_or_else(|| anyhow::anyhow!("Unknown checksum 0x{:02x} in handshake", id))?;
+            return Ok((
+                ProtocolCommand::Handshake { framing, checksum },
+                handshake_len,
+            ));
         }
 
         match buf[0] {
@@ -162,6 +485,11 @@ impl ProtocolHandler {
                 if buf.len() >= 12 {
                     let header = RequestHeader::from_bytes(buf)?;
                     let packet_len = header.total_len as usize;
+                    if packet_len < 12 {
+                        // consumeremmo meno dell'header, o niente del tutto
+                        error!("Invalid read total_len {}", packet_len);
+                        return Err(anyhow::anyhow!("Invalid read total_len {}", packet_len));
+                    }
                     if buf.len() >= packet_len {
                         Ok((ProtocolCommand::Read { header }, packet_len))
                     } else {
@@ -170,66 +498,350 @@ impl ProtocolHandler {
                         Ok((ProtocolCommand::Read { header }, 12))
                     }
                 } else {
-                    error!(
-                        "Buffer too short for read command, expected at least 12 bytes, got {}",
-                        buf.len()
-                    );
-                    Err(anyhow::anyhow!("Buffer too short for read command"))
+                    Err(IncompleteCommand.into())
                 }
             }
             CMD_WRITE => {
-                if buf.len() >= 14 {
-                    let header = WriteHeader::from_bytes(buf)?;
-                    let required_len = header.total_len as usize;
-                    if buf.len() >= required_len {
-                        let data = buf[14..14 + header.data_len as usize].to_vec();
-                        Ok((ProtocolCommand::Write { header, data }, required_len))
-                    } else {
-                        error!(
-                            "Buffer too short for write data, expected {} bytes, got {}",
-                            header.data_len,
-                            buf.len() - 14
-                        );
-                        Err(anyhow::anyhow!("Buffer too short for write data"))
-                    }
+                let header = Self::parse_write_header(buf, framing)?;
+                let header_len = framing.header_len();
+                let required_len = header.total_len as usize;
+                if buf.len() >= required_len {
+                    let data = buf[header_len..header_len + header.data_len as usize].to_vec();
+                    Ok((ProtocolCommand::Write { header, data }, required_len))
                 } else {
-                    error!(
-                        "Buffer too short for write command, expected at least 14 bytes, got {}",
-                        buf.len()
-                    );
-                    Err(anyhow::anyhow!("Buffer too short for write command"))
+                    Err(IncompleteCommand.into())
                 }
             }
+            CMD_PING => Ok((ProtocolCommand::Ping, 1)),
+            CMD_IDENTIFY => Ok((ProtocolCommand::Identify, 1)),
+            CMD_CAPABILITIES => Ok((ProtocolCommand::Capabilities, 1)),
             cmd => Ok((ProtocolCommand::Unknown(cmd), 1)),
         }
     }
 
+    /// Parses and validates the header of the write at the start of `buf`,
+    /// without waiting for its payload
+    ///
+    /// Once this succeeds `header_len + data_len <= total_len`, the payload
+    /// follows the header and `total_len` ends the frame.
+    pub fn parse_write_header(buf: &[u8], framing: WriteFraming) -> Result<WriteHeader> {
+        let header_len = framing.header_len();
+        if buf.len() < header_len {
+            return Err(IncompleteCommand.into());
+        }
+
+        let header = WriteHeader::from_bytes_with(buf, framing)?;
+        let required_len = header.total_len as usize;
+        if required_len < header_len {
+            error!("Invalid write total_len {}", required_len);
+            return Err(anyhow::anyhow!("Invalid write total_len {}", required_len));
+        }
+        if header.data_len > MAX_DATA_LEN {
+            error!(
+                "Write data_len {} exceeds maximum of {} bytes",
+                header.data_len, MAX_DATA_LEN
+            );
+            return Err(anyhow::anyhow!(
+                "Write data_len {} exceeds maximum of {} bytes",
+                header.data_len,
+                MAX_DATA_LEN
+            ));
+        }
+        // data_len viene dal client e può non essere coerente con total_len,
+        // su target a 32 bit (ESP32) la somma potrebbe anche andare in overflow
+        header_len
+            .checked_add(header.data_len as usize)
+            .filter(|&end| end <= required_len)
+            .ok_or_else(|| {
+                error!(
+                    "Write data_len {} exceeds total_len {}",
+                    header.data_len, required_len
+                );
+                anyhow::anyhow!("Write data_len exceeds total_len")
+            })?;
+        Ok(header)
+    }
+
+    /// Like `parse_command_with`, with reads and writes followed by a
+    /// `checksum` trailer
+    ///
+    /// A frame failing the checksum is consumed, trailer included, and
+    /// returned as [`ProtocolCommand::ChecksumMismatch`].
+    pub fn parse_command_checked(
+        buf: &[u8],
+        framing: WriteFraming,
+        checksum: Checksum,
+    ) -> Result<(ProtocolCommand, usize)> {
+        let (command, frame_len) = Self::parse_command_with(buf, framing)?;
+        let trailer_len = checksum.trailer_len();
+        if trailer_len == 0
+            || !matches!(
+                command,
+                ProtocolCommand::Read { .. } | ProtocolCommand::Write { .. }
+            )
+        {
+            return Ok((command, frame_len));
+        }
+
+        let Some(trailer) = buf.get(frame_len..frame_len + trailer_len) else {
+            return Err(IncompleteCommand.into());
+        };
+        if !checksum.verify(&buf[..frame_len], trailer) {
+            warn!("{:?} mismatch, dropping {:?}", checksum, command);
+            return Ok((
+                ProtocolCommand::ChecksumMismatch(Box::new(command)),
+                frame_len + trailer_len,
+            ));
+        }
+        Ok((command, frame_len + trailer_len))
+    }
+
+    /// Read response carrying `data` as payload
+    ///
+    /// SigmaStudio trusts the `data_len` of its request, so a backend returning
+    /// a different amount of bytes is padded with zeros or truncated to
+    /// `data_len`, with a warning.
     pub fn create_read_response(
         chip_addr: u8,
         data_len: u32,
         param_addr: u16,
-        data: Vec<u8>,
+        mut data: Vec<u8>,
+    ) -> ProtocolResponse {
+        if data.len() != data_len as usize {
+            warn!(
+                "read at addr 0x{:04x} returned {} bytes instead of {}, resizing",
+                param_addr,
+                data.len(),
+                data_len
+            );
+            data.resize(data_len as usize, 0);
+        }
+
+        let header = ResponseHeader {
+            control_bit: CMD_RESP,
+            total_len: 13 + data.len() as u32,
+            chip_addr,
+            data_len,
+            param_addr,
+            success: STATUS_OK,
+            reserved: [0],
+        };
+        ProtocolResponse::Read { header, data }
+    }
+
+    /// Read response for a failed transaction: same framing as a successful
+    /// read so SigmaStudio can match it, with `success` set to one of the
+    /// `STATUS_*` codes and `data_len` zero bytes as payload
+    pub fn create_error_read_response(
+        chip_addr: u8,
+        data_len: u32,
+        param_addr: u16,
+        code: u8,
     ) -> ProtocolResponse {
+        let data = vec![0; data_len as usize];
         let header = ResponseHeader {
             control_bit: CMD_RESP,
             total_len: 13 + data.len() as u32,
             chip_addr,
             data_len,
             param_addr,
-            success: 0,
+            success: code,
             reserved: [0],
         };
         ProtocolResponse::Read { header, data }
     }
 
+    /// Read response with an empty payload, for a read rejected before
+    /// anything is allocated for its client supplied length
+    pub fn create_rejected_read_response(
+        chip_addr: u8,
+        param_addr: u16,
+        code: u8,
+    ) -> ProtocolResponse {
+        Self::create_error_read_response(chip_addr, 0, param_addr, code)
+    }
+
     pub fn create_error_response(error: String) -> ProtocolResponse {
         ProtocolResponse::Error(error)
     }
+
+    /// Frames a read request the way SigmaStudio sends it
+    pub fn create_read_request(chip_addr: u8, param_addr: u16, data_len: u32) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(14);
+        bytes.push(CMD_READ);
+        bytes.extend_from_slice(&14u32.to_be_bytes());
+        bytes.push(chip_addr);
+        bytes.extend_from_slice(&data_len.to_be_bytes());
+        bytes.extend_from_slice(&param_addr.to_be_bytes());
+        // SigmaStudio pads read requests to 14 bytes
+        bytes.extend_from_slice(&[0, 0]);
+        bytes
+    }
+
+    /// Frames a block write request, without safeload
+    pub fn create_write_request(chip_addr: u8, param_addr: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Self::create_write_header(chip_addr, param_addr, data.len() as u32);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Header of a block write request of `data_len` bytes, for a payload
+    /// sent after it
+    pub fn create_write_header(chip_addr: u8, param_addr: u16, data_len: u32) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(14 + data_len as usize);
+        bytes.push(CMD_WRITE);
+        bytes.push(0); // safeload
+        bytes.push(0); // channel_num
+        bytes.extend_from_slice(&(14 + data_len).to_be_bytes());
+        bytes.push(chip_addr);
+        bytes.extend_from_slice(&data_len.to_be_bytes());
+        bytes.extend_from_slice(&param_addr.to_be_bytes());
+        bytes
+    }
+
+    /// Runs a parsed command against a backend and builds the response to send back
+    ///
+    /// Reads longer than `max_read_len` are rejected before reaching the backend,
+    /// backend failures are turned into error responses.
+    pub async fn execute<B: Backend + ?Sized>(
+        backend: &mut B,
+        command: ProtocolCommand,
+        max_read_len: u32,
+    ) -> ProtocolResponse {
+        match command {
+            ProtocolCommand::Read { header } => {
+                if let Err(e) = Self::check_read_len(&header, max_read_len) {
+                    error!("{}", e);
+                    return Self::create_rejected_read_response(
+                        header.chip_addr,
+                        header.param_addr,
+                        STATUS_READ_TOO_LONG,
+                    );
+                }
+
+                match backend.read(header.param_addr, header.data_len).await {
+                    Ok(data) => {
+                        info!(
+                            "read at addr 0x{:04x} size {:?} resp {:02x?}",
+                            header.param_addr, header.data_len, data
+                        );
+
+                        Self::create_read_response(
+                            header.chip_addr,
+                            header.data_len,
+                            header.param_addr,
+                            data,
+                        )
+                    }
+                    Err(e) => {
+                        error!("read at addr 0x{:04x} failed: {}", header.param_addr, e);
+                        Self::create_error_read_response(
+                            header.chip_addr,
+                            header.data_len,
+                            header.param_addr,
+                            STATUS_BACKEND_ERROR,
+                        )
+                    }
+                }
+            }
+            ProtocolCommand::Write { header, data } => {
+                let result = if header.safeload != 0 {
+                    backend.safeload_write(header.param_addr, &data).await
+                } else {
+                    backend.write(header.param_addr, &data).await
+                };
+                match result {
+                    Ok(()) => {
+                        info!(
+                            "write at addr 0x{:04x} size {:?}",
+                            header.param_addr, header.data_len
+                        );
+
+                        ProtocolResponse::Write
+                    }
+                    Err(e) => {
+                        error!("write at addr 0x{:04x} failed: {}", header.param_addr, e);
+                        Self::create_error_response(format!("Write error: {}", e))
+                    }
+                }
+            }
+            ProtocolCommand::Handshake { framing, checksum } => {
+                // the framing is per connection, there is nothing to execute or send back
+                info!(
+                    "handshake for {:?} write framing, checksum {:?}",
+                    framing, checksum
+                );
+                ProtocolResponse::Write
+            }
+            ProtocolCommand::Ping => ProtocolResponse::Pong,
+            ProtocolCommand::Identify => match backend.identify().await {
+                Ok(info) => {
+                    info!("identify: {}", info);
+                    ProtocolResponse::Identify(info)
+                }
+                Err(e) => {
+                    // si risponde comunque, il client aspetta una risposta
+                    error!("identify failed: {}", e);
+                    ProtocolResponse::Identify(DeviceInfo::Unknown)
+                }
+            },
+            // a server adds the features of its configuration, see server::capabilities
+            ProtocolCommand::Capabilities => ProtocolResponse::Capabilities(Capabilities::PROTOCOL),
+            ProtocolCommand::ChecksumMismatch(command) => {
+                Self::checksum_error_response(*command, max_read_len)
+            }
+            ProtocolCommand::Unknown(cmd) => {
+                error!("Unknown command: 0x{:02x}", cmd);
+                Self::create_error_response(format!("Unknown command: 0x{:02x}", cmd))
+            }
+        }
+    }
+
+    /// Response to a command that failed its checksum: a read still gets a
+    /// read response, flagged with [`STATUS_CHECKSUM_ERROR`], so the client
+    /// isn't left waiting. Its payload is empty if the read is too long.
+    pub fn checksum_error_response(
+        command: ProtocolCommand,
+        max_read_len: u32,
+    ) -> ProtocolResponse {
+        match command {
+            ProtocolCommand::Read { header }
+                if Self::check_read_len(&header, max_read_len).is_ok() =>
+            {
+                Self::create_error_read_response(
+                    header.chip_addr,
+                    header.data_len,
+                    header.param_addr,
+                    STATUS_CHECKSUM_ERROR,
+                )
+            }
+            ProtocolCommand::Read { header } => Self::create_rejected_read_response(
+                header.chip_addr,
+                header.param_addr,
+                STATUS_CHECKSUM_ERROR,
+            ),
+            _ => Self::create_error_response("Checksum mismatch".to_string()),
+        }
+    }
+
+    /// Rejects reads longer than `max_len`, to be called before the backend
+    /// allocates or reads anything for a client supplied length
+    pub fn check_read_len(header: &RequestHeader, max_len: u32) -> Result<()> {
+        if header.data_len > max_len {
+            return Err(anyhow::anyhow!(
+                "Read length {} exceeds maximum of {} bytes",
+                header.data_len,
+                max_len
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_read_command_f6fb_example() {
@@ -344,7 +956,7 @@ mod tests {
         // Add 80 bytes of zeros for the data payload
         buf.extend(vec![0x00; 80]);
 
-        let (cmd, bytes_read) = ProtocolHandler::parse_command(&buf.as_slice()).unwrap();
+        let (cmd, bytes_read) = ProtocolHandler::parse_command(buf.as_slice()).unwrap();
 
         assert_eq!(bytes_read, 94);
         match cmd {
@@ -410,4 +1022,389 @@ mod tests {
             _ => panic!("Expected Read response"),
         }
     }
+
+    #[test]
+    fn test_read_len_above_cap_rejected() {
+        // Read Request for IC 1, Param Address: 0x0000, Bytes: 0x00010000
+        let buf = [
+            0x0a, // CMD_READ
+            0x00, 0x00, 0x00, 0x0e, // total_len = 14
+            0x01, // chip_addr = 1
+            0x00, 0x01, 0x00, 0x00, // data_len = 65536
+            0x00, 0x00, // param_addr = 0x0000
+            0x00, 0x00, // trailing bytes
+        ];
+        let (cmd, _) = ProtocolHandler::parse_command(&buf).unwrap();
+
+        match cmd {
+            ProtocolCommand::Read { header } => {
+                assert!(ProtocolHandler::check_read_len(&header, 1024).is_err());
+                assert!(ProtocolHandler::check_read_len(&header, 65536).is_ok());
+            }
+            _ => panic!("Expected Read command"),
+        }
+    }
+
+    #[test]
+    fn test_error_read_response() {
+        let response =
+            ProtocolHandler::create_error_read_response(0x01, 2, 0xf6f5, STATUS_BACKEND_ERROR);
+        let bytes = response.to_bytes();
+
+        assert_eq!(
+            bytes,
+            vec![
+                CMD_RESP, // control bit
+                0x00,
+                0x00,
+                0x00,
+                0x0f, // total_len = 15
+                0x01, // chip_addr
+                0x00,
+                0x00,
+                0x00,
+                0x02, // data_len = 2
+                0xf6,
+                0xf5,                 // param_addr
+                STATUS_BACKEND_ERROR, // success
+                0x00,                 // reserved
+                0x00,
+                0x00, // zero-filled data
+            ]
+        );
+    }
+
+    /// Backend whose reads always come back two bytes short
+    struct ShortReadBackend;
+
+    #[async_trait::async_trait]
+    impl Backend for ShortReadBackend {
+        async fn read(&mut self, _addr: u16, len: u32) -> Result<Vec<u8>> {
+            Ok(vec![0xaa; len.saturating_sub(2) as usize])
+        }
+
+        async fn write(&mut self, _addr: u16, _data: &[u8]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_short_read_is_padded() {
+        let request = ProtocolHandler::create_read_request(1, 0x0010, 4);
+        let (command, _) = ProtocolHandler::parse_command(&request).unwrap();
+        let bytes = ProtocolHandler::execute(&mut ShortReadBackend, command, 1024)
+            .await
+            .to_bytes();
+
+        let data_len = u32::from_be_bytes(bytes[6..10].try_into().unwrap()) as usize;
+        assert_eq!(data_len, 4);
+        assert_eq!(&bytes[14..], &[0xaa, 0xaa, 0x00, 0x00]);
+
+        let response = ProtocolHandler::create_read_response(1, 2, 0x0010, vec![1, 2, 3]);
+        assert_eq!(&response.to_bytes()[14..], &[1, 2]);
+    }
+
+    #[test]
+    fn test_resync_after_junk_prefix() {
+        let mut buf = vec![0xde, 0xad, 0xbe, 0xef];
+        buf.extend_from_slice(&ProtocolHandler::create_read_request(1, 0x0043, 4));
+
+        let mut resync = Resync::default();
+        let mut offset = 0;
+        let header = loop {
+            let (command, bytes_read) = ProtocolHandler::parse_command(&buf[offset..]).unwrap();
+            resync.check(&command).unwrap();
+            offset += bytes_read;
+            if let ProtocolCommand::Read { header } = command {
+                break header;
+            }
+        };
+
+        assert_eq!(header.param_addr, 0x0043);
+        assert_eq!(header.data_len, 4);
+        assert_eq!(offset, buf.len());
+    }
+
+    #[test]
+    fn test_resync_gives_up_on_garbage() {
+        let mut resync = Resync::new(3);
+        for _ in 0..3 {
+            resync.check(&ProtocolCommand::Unknown(0xff)).unwrap();
+        }
+        assert!(resync.check(&ProtocolCommand::Unknown(0xff)).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_command_never_panics(
+            cmd in prop_oneof![Just(CMD_READ), Just(CMD_WRITE), any::<u8>()],
+            rest in proptest::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let mut buf = vec![cmd];
+            buf.extend_from_slice(&rest);
+            check_parse_progress(&buf)?;
+        }
+
+        // random bytes almost never have coherent lengths, here they are small
+        // enough to fall inside the buffer
+        #[test]
+        fn test_parse_framed_command_never_panics(
+            is_write in any::<bool>(),
+            total_len in 0u32..80,
+            data_len in prop_oneof![0u32..80, any::<u32>()],
+            param_addr in any::<u16>(),
+            payload in proptest::collection::vec(any::<u8>(), 0..80),
+        ) {
+            let mut buf = if is_write { vec![CMD_WRITE, 0, 0] } else { vec![CMD_READ] };
+            buf.extend_from_slice(&total_len.to_be_bytes());
+            buf.push(1);
+            buf.extend_from_slice(&data_len.to_be_bytes());
+            buf.extend_from_slice(&param_addr.to_be_bytes());
+            buf.extend_from_slice(&payload);
+            check_parse_progress(&buf)?;
+        }
+    }
+
+    /// Either an error, or a command that consumes at least one byte of the buffer
+    fn check_parse_progress(buf: &[u8]) -> Result<(), TestCaseError> {
+        if let Ok((_, bytes_read)) = ProtocolHandler::parse_command(buf) {
+            prop_assert!(bytes_read >= 1 && bytes_read <= buf.len());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_data_len_overflow() {
+        let mut buf = ProtocolHandler::create_write_request(1, 0x0010, &[1, 2, 3, 4]);
+        buf[8..12].copy_from_slice(&0xFFFF_FFFFu32.to_be_bytes());
+
+        let err = ProtocolHandler::parse_command(&buf).unwrap_err();
+        assert!(err.to_string().contains("exceeds maximum"));
+    }
+
+    #[test]
+    fn test_write_data_len_past_total_len() {
+        let mut buf = ProtocolHandler::create_write_request(1, 0x0010, &[1, 2, 3, 4]);
+        // data_len = 8, but only 4 bytes follow the header
+        buf[8..12].copy_from_slice(&8u32.to_be_bytes());
+        buf.extend_from_slice(&[0; 8]);
+
+        let err = ProtocolHandler::parse_command(&buf).unwrap_err();
+        assert!(!err.is::<IncompleteCommand>());
+    }
+
+    #[test]
+    fn test_response_header_round_trip() {
+        let response = ProtocolHandler::create_error_read_response(1, 8, 0x0043, STATUS_TIMEOUT);
+        let bytes = response.to_bytes();
+
+        let header = ResponseHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(header.control_bit, CMD_RESP);
+        assert_eq!(header.total_len, 13 + 8);
+        assert_eq!(header.chip_addr, 1);
+        assert_eq!(header.data_len, 8);
+        assert_eq!(header.param_addr, 0x0043);
+        assert_eq!(header.success, STATUS_TIMEOUT);
+
+        assert!(ResponseHeader::from_bytes(&bytes[..13]).is_err());
+    }
+
+    #[test]
+    fn test_compact_write_framing() {
+        // same write as test_write_command_f020_example, without safeload and channel
+        let buf = [
+            0x09, // CMD_WRITE
+            0x00, 0x00, 0x00, 0x0e, // total_len = 14 (12 + 2)
+            0x01, // chip_addr = 1
+            0x00, 0x00, 0x00, 0x02, // data_len = 2
+            0xf0, 0x20, // param_addr = 0xf020
+            0x00, 0x08, // data payload
+        ];
+        assert_eq!(WriteFraming::detect(&buf), Some(WriteFraming::Compact));
+
+        let mut framing = ConnectionFraming::default();
+        let (cmd, bytes_read) = framing.parse(&buf).unwrap();
+        assert_eq!(bytes_read, 14);
+        assert_eq!(framing.framing(), WriteFraming::Compact);
+        match cmd {
+            ProtocolCommand::Write { header, data } => {
+                assert_eq!(header.safeload, 0);
+                assert_eq!(header.chip_addr, 0x01);
+                assert_eq!(header.param_addr, 0xf020);
+                assert_eq!(data, vec![0x00, 0x08]);
+            }
+            _ => panic!("Expected Write command"),
+        }
+    }
+
+    #[test]
+    fn test_standard_write_framing_detected() {
+        let buf = ProtocolHandler::create_write_request(1, 0xf020, &[0x00, 0x08]);
+        assert_eq!(WriteFraming::detect(&buf), Some(WriteFraming::Standard));
+
+        let mut framing = ConnectionFraming::default();
+        let (cmd, bytes_read) = framing.parse(&buf).unwrap();
+        assert_eq!(bytes_read, 16);
+        assert_eq!(framing.framing(), WriteFraming::Standard);
+        assert!(
+            matches!(cmd, ProtocolCommand::Write { header, .. } if header.param_addr == 0xf020)
+        );
+    }
+
+    #[test]
+    fn test_incomplete_command() {
+        let read = ProtocolHandler::create_read_request(1, 0x0010, 4);
+        let write = ProtocolHandler::create_write_request(1, 0x0010, &[1, 2, 3, 4]);
+
+        // quello che resta nel buffer quando SigmaStudio chiude a metà comando
+        for partial in [
+            &[][..],
+            &read[..1],
+            &read[..11],
+            &write[..3],
+            &write[..write.len() - 1],
+            &HANDSHAKE_MAGIC[..2],
+        ] {
+            let err = ProtocolHandler::parse_command(partial).unwrap_err();
+            assert!(err.is::<IncompleteCommand>(), "{:x?}: {}", partial, err);
+        }
+
+        // un header incoerente non si completa con altri bytes
+        let mut bad = read.clone();
+        bad[1..5].copy_from_slice(&4u32.to_be_bytes());
+        let err = ProtocolHandler::parse_command(&bad).unwrap_err();
+        assert!(!err.is::<IncompleteCommand>());
+    }
+
+    #[test]
+    fn test_handshake_selects_framing() {
+        let mut buf = WriteFraming::Compact.handshake();
+        // under Standard these lengths don't add up, the handshake decides
+        buf.extend_from_slice(&[0x09, 0, 0, 0, 0x10, 1, 0, 0, 0, 4, 0, 0x43, 1, 2, 3, 4]);
+
+        let mut framing = ConnectionFraming::default();
+        assert!(framing.parse(&buf[..4]).is_err());
+
+        let (cmd, bytes_read) = framing.parse(&buf).unwrap();
+        assert!(matches!(
+            cmd,
+            ProtocolCommand::Handshake {
+                framing: WriteFraming::Compact,
+                checksum: Checksum::None
+            }
+        ));
+        assert_eq!(bytes_read, HANDSHAKE_MAGIC.len() + 1);
+
+        let (cmd, _) = framing.parse(&buf[bytes_read..]).unwrap();
+        match cmd {
+            ProtocolCommand::Write { header, data } => {
+                assert_eq!(header.param_addr, 0x0043);
+                assert_eq!(data, vec![1, 2, 3, 4]);
+            }
+            _ => panic!("Expected Write command"),
+        }
+
+        let mut bad = HANDSHAKE_MAGIC.to_vec();
+        bad.push(0x7f);
+        assert!(ProtocolHandler::parse_command(&bad).is_err());
+    }
+
+    #[test]
+    fn test_ping() {
+        let mut buf = vec![CMD_PING];
+        buf.extend_from_slice(&ProtocolHandler::create_read_request(1, 0x0043, 4));
+
+        let (cmd, bytes_read) = ProtocolHandler::parse_command(&buf).unwrap();
+        assert!(matches!(cmd, ProtocolCommand::Ping));
+        assert_eq!(bytes_read, 1);
+        assert_eq!(ProtocolResponse::Pong.to_bytes(), vec![CMD_PING]);
+
+        // il comando successivo è intatto
+        let (cmd, _) = ProtocolHandler::parse_command(&buf[bytes_read..]).unwrap();
+        assert!(matches!(cmd, ProtocolCommand::Read { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_identify_command() {
+        let (cmd, bytes_read) = ProtocolHandler::parse_command(&[CMD_IDENTIFY]).unwrap();
+        assert!(matches!(cmd, ProtocolCommand::Identify));
+        assert_eq!(bytes_read, 1);
+
+        let mut backend = crate::backend::MemoryBackend::new();
+        let response = ProtocolHandler::execute(&mut backend, cmd, 4096).await;
+        assert_eq!(response.to_bytes(), vec![CMD_IDENTIFY, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_command() {
+        let (cmd, bytes_read) = ProtocolHandler::parse_command(&[CMD_CAPABILITIES]).unwrap();
+        assert!(matches!(cmd, ProtocolCommand::Capabilities));
+        assert_eq!(bytes_read, 1);
+
+        let mut backend = crate::backend::MemoryBackend::new();
+        let response = ProtocolHandler::execute(&mut backend, cmd, 4096).await;
+        let bytes = response.to_bytes();
+        assert_eq!(bytes[0], CMD_CAPABILITIES);
+        assert_eq!(
+            Capabilities::from_bytes(&bytes[1..]).unwrap(),
+            Capabilities::PROTOCOL
+        );
+    }
+
+    #[test]
+    fn test_checksummed_frames() {
+        let mut framing = ConnectionFraming::default();
+        let handshake = WriteFraming::Standard.handshake_with(Checksum::Crc16);
+        framing.parse(&handshake).unwrap();
+        assert_eq!(framing.checksum(), Checksum::Crc16);
+
+        let mut write = ProtocolHandler::create_write_request(1, 0x0043, &[0, 0x80, 0, 0]);
+        Checksum::Crc16.append(&mut write);
+        let (cmd, bytes_read) = framing.parse(&write).unwrap();
+        assert!(matches!(cmd, ProtocolCommand::Write { ref data, .. } if data == &[0, 0x80, 0, 0]));
+        assert_eq!(bytes_read, write.len());
+
+        // the trailer hasn't arrived yet
+        assert!(framing.parse(&write[..write.len() - 1]).is_err());
+
+        // un bit sbagliato nel coefficiente
+        let mut corrupted = write.clone();
+        corrupted[15] ^= 0x01;
+        let (cmd, bytes_read) = framing.parse(&corrupted).unwrap();
+        let ProtocolCommand::ChecksumMismatch(cmd) = cmd else {
+            panic!("Expected a checksum mismatch, got {:?}", cmd);
+        };
+        assert_eq!(bytes_read, write.len());
+        assert!(matches!(
+            ProtocolHandler::checksum_error_response(*cmd, 4096),
+            ProtocolResponse::Error(_)
+        ));
+
+        let mut read = ProtocolHandler::create_read_request(1, 0x0043, 4);
+        Checksum::Crc16.append(&mut read);
+        read[11] ^= 0x01;
+        let (ProtocolCommand::ChecksumMismatch(cmd), _) = framing.parse(&read).unwrap() else {
+            panic!("Expected a checksum mismatch");
+        };
+        match ProtocolHandler::checksum_error_response(*cmd, 4096) {
+            ProtocolResponse::Read { header, .. } => {
+                assert_eq!(header.success, STATUS_CHECKSUM_ERROR)
+            }
+            other => panic!("Expected an error read response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_response_checksum() {
+        let response = ProtocolHandler::create_read_response(1, 4, 0x0043, vec![1, 2, 3, 4]);
+        let plain = response.to_bytes();
+        let bytes = response.to_bytes_with(Checksum::Crc32);
+
+        assert_eq!(bytes.len(), plain.len() + 4);
+        assert!(Checksum::Crc32.verify(&plain, &bytes[plain.len()..]));
+        // le write non hanno risposta, nemmeno il checksum
+        assert!(ProtocolResponse::Write
+            .to_bytes_with(Checksum::Crc32)
+            .is_empty());
+    }
 }
@@ -0,0 +1,323 @@
+//! Counters and latency histogram of the desktop server, exported in the
+//! Prometheus text format on a small `/metrics` HTTP endpoint. The same
+//! endpoint can also take raw protocol frames on `POST /raw`.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::{debug, error, info};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+use crate::backend::Backend;
+use crate::server::{run_raw_frame, ServerOptions};
+
+/// Largest request accepted by the HTTP endpoint, headers and hex body
+const MAX_HTTP_REQUEST_LEN: usize = 16 * 1024;
+
+/// Upper bounds of the latency buckets, in microseconds
+const LATENCY_BUCKETS_US: [u64; 9] = [
+    100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000,
+];
+
+/// Metrics of the whole process, updated by the server
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    /// Commands parsed, unknown command bytes included
+    pub commands: AtomicU64,
+    pub reads: AtomicU64,
+    pub writes: AtomicU64,
+    /// Unknown command bytes, rejected commands and failed backend calls
+    pub errors: AtomicU64,
+    /// Bytes received from clients
+    pub bytes_in: AtomicU64,
+    /// Bytes sent to clients
+    pub bytes_out: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS_US.len()],
+    latency_sum_us: AtomicU64,
+    latency_count: AtomicU64,
+}
+
+impl Metrics {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+
+    pub const fn new() -> Self {
+        Self {
+            commands: AtomicU64::new(0),
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            latency_buckets: [Self::ZERO; LATENCY_BUCKETS_US.len()],
+            latency_sum_us: AtomicU64::new(0),
+            latency_count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Adds the duration of a backend call to the latency histogram
+    pub fn observe_latency(&self, elapsed: Duration) {
+        let us = elapsed.as_micros() as u64;
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS_US) {
+            if us <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_sum_us.fetch_add(us, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "sigma_tcp_commands_total",
+                "Commands parsed",
+                &self.commands,
+            ),
+            ("sigma_tcp_reads_total", "Read commands", &self.reads),
+            ("sigma_tcp_writes_total", "Write commands", &self.writes),
+            (
+                "sigma_tcp_errors_total",
+                "Unknown commands and failed commands",
+                &self.errors,
+            ),
+            (
+                "sigma_tcp_bytes_in_total",
+                "Bytes received from clients",
+                &self.bytes_in,
+            ),
+            (
+                "sigma_tcp_bytes_out_total",
+                "Bytes sent to clients",
+                &self.bytes_out,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+
+        let name = "sigma_tcp_backend_latency_seconds";
+        let _ = writeln!(out, "# HELP {} Duration of backend reads and writes", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS_US) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                bound as f64 / 1e6,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.latency_sum_us.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serves `GET /metrics` on `addr`, anything else gets a 404
+pub async fn serve_metrics(addr: SocketAddr) -> Result<()> {
+    serve_metrics_with(addr, None).await
+}
+
+/// Backend and server options of `POST /raw`
+#[derive(Clone)]
+pub struct RawEndpoint {
+    pub backend: Arc<Mutex<dyn Backend>>,
+    /// Read limit and token of the TCP server, the token is required as
+    /// `Authorization: Bearer` or `X-Token` when set
+    pub options: ServerOptions,
+}
+
+/// Like `serve_metrics`, with `POST /raw` answered by `raw` if given
+///
+/// The body of `POST /raw` is a hex-encoded command frame, whitespace is
+/// ignored. The commands run as on a new TCP connection and the response
+/// is their hex-encoded responses, empty if they have none (writes).
+pub async fn serve_metrics_with(addr: SocketAddr, raw: Option<RawEndpoint>) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics endpoint to {}", addr))?;
+    info!("Serving metrics on http://{}/metrics", addr);
+    if raw.is_some() {
+        info!("Accepting raw protocol frames on http://{}/raw", addr);
+    }
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let raw = raw.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_metrics_request(stream, raw.as_ref()).await {
+                        debug!("metrics request from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => error!("Failed to accept metrics connection: {}", e),
+        }
+    }
+}
+
+async fn handle_metrics_request(mut stream: TcpStream, raw: Option<&RawEndpoint>) -> Result<()> {
+    let request = read_request(&mut stream).await?;
+
+    let (status, content_type, body) = if request.starts_with(b"GET /metrics ") {
+        ("200 OK", "text/plain; version=0.0.4", METRICS.render())
+    } else if let (true, Some(raw)) = (request.starts_with(b"POST /raw "), raw) {
+        let (status, body) = raw_request(raw, &request).await;
+        (status, "text/plain", body)
+    } else {
+        ("404 Not Found", "text/plain", "Not found\n".to_string())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads the headers and, if there is a `Content-Length`, the body of a request
+async fn read_request(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; MAX_HTTP_REQUEST_LEN];
+    let mut len = 0;
+    let mut request_len = None;
+    while request_len.is_none_or(|request_len| len < request_len) {
+        if len == buf.len() {
+            bail!("Request larger than {} bytes", MAX_HTTP_REQUEST_LEN);
+        }
+        let n = stream.read(&mut buf[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        if request_len.is_none() {
+            if let Some(end) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+                let headers = String::from_utf8_lossy(&buf[..end]);
+                request_len = Some(
+                    end + 4
+                        + header(&headers, "content-length").map_or(0, |v| v.parse().unwrap_or(0)),
+                );
+            }
+        }
+    }
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Value of the header `name` (lowercase) in `headers`
+fn header<'a>(headers: &'a str, name: &str) -> Option<&'a str> {
+    headers.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+async fn raw_request(raw: &RawEndpoint, request: &[u8]) -> (&'static str, String) {
+    let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return ("400 Bad Request", "Incomplete request\n".to_string());
+    };
+    let headers = String::from_utf8_lossy(&request[..end]);
+
+    if let Some(token) = raw.options.auth_token() {
+        if !token.matches_headers(
+            header(&headers, "authorization"),
+            header(&headers, "x-token"),
+        ) {
+            return (
+                "401 Unauthorized",
+                "Missing or wrong auth token\n".to_string(),
+            );
+        }
+    }
+
+    let frame = match parse_hex_frame(&String::from_utf8_lossy(&request[end + 4..])) {
+        Ok(frame) => frame,
+        Err(e) => return ("400 Bad Request", format!("{}\n", e)),
+    };
+
+    match run_raw_frame(&raw.backend, &frame, &raw.options).await {
+        Ok(responses) => {
+            let hex: String = responses.iter().map(|b| format!("{:02x}", b)).collect();
+            ("200 OK", format!("{}\n", hex))
+        }
+        Err(e) => ("400 Bad Request", format!("{}\n", e)),
+    }
+}
+
+/// Bytes of a hex string, whitespace between the digits is ignored
+fn parse_hex_frame(hex: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.is_empty() {
+        bail!("Empty frame");
+    }
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+        bail!("Frame must be hex digits, two per byte");
+    }
+    digits
+        .chunks(2)
+        .map(|pair| Ok(u8::from_str_radix(std::str::from_utf8(pair)?, 16)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram() {
+        let metrics = Metrics::new();
+        metrics.observe_latency(Duration::from_micros(300));
+        metrics.observe_latency(Duration::from_millis(2));
+
+        let text = metrics.render();
+        assert!(text.contains("sigma_tcp_backend_latency_seconds_bucket{le=\"0.0001\"} 0\n"));
+        assert!(text.contains("sigma_tcp_backend_latency_seconds_bucket{le=\"0.0005\"} 1\n"));
+        assert!(text.contains("sigma_tcp_backend_latency_seconds_bucket{le=\"0.005\"} 2\n"));
+        assert!(text.contains("sigma_tcp_backend_latency_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn test_parse_hex_frame() {
+        assert_eq!(
+            parse_hex_frame("0a 00 00 00\n0E01").unwrap(),
+            vec![0x0a, 0, 0, 0, 0x0e, 0x01]
+        );
+        assert!(parse_hex_frame("").is_err());
+        assert!(parse_hex_frame("0a0").is_err());
+        assert!(parse_hex_frame("0x0a").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+/// Parameters of a URL query string, `application/x-www-form-urlencoded`
+///
+/// Keys and values are percent-decoded and `+` becomes a space. A key can
+/// appear more than once: [`QueryParams::get`] returns the first value,
+/// [`QueryParams::get_all`] every value in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    /// Parses the query of `uri`, everything after the first `?` and before a `#`
+    ///
+    /// A parameter without `=` has an empty value.
+    pub fn from_uri(uri: &str) -> Self {
+        match uri.split_once('?') {
+            Some((_, query)) => Self::parse(query.split('#').next().unwrap_or_default()),
+            None => Self::default(),
+        }
+    }
+
+    /// Parses a bare query string, without the leading `?`
+    pub fn parse(query: &str) -> Self {
+        let pairs = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                (decode(key), decode(value))
+            })
+            .collect();
+        Self { pairs }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Percent-decodes a query component, `+` is a space
+///
+/// A `%` not followed by two hex digits is kept as is, like browsers do;
+/// bytes that aren't UTF-8 become U+FFFD.
+pub fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match bytes.get(i + 1..i + 3).and_then(hex_byte) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    let digits = std::str::from_utf8(digits).ok()?;
+    if !digits.bytes().all(|d| d.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_params() {
+        let params = QueryParams::from_uri("/write?addr=%30x0043&data=0x%30%31%30%32");
+        assert_eq!(params.get("addr"), Some("0x0043"));
+        assert_eq!(params.get("data"), Some("0x0102"));
+        assert_eq!(params.get("len"), None);
+
+        // token e SSID possono contenere qualunque carattere
+        let params = QueryParams::from_uri("/config?token=a%2Bb+c%26d%3De&ssid=Caff%C3%A8#top");
+        assert_eq!(params.get("token"), Some("a+b c&d=e"));
+        assert_eq!(params.get("ssid"), Some("Caffè"));
+
+        // chiavi ripetute: get dà la prima, get_all tutte
+        let params = QueryParams::from_uri("/read_multi?regs=0x10:4&regs=0x20:2&freeze");
+        assert_eq!(params.get("regs"), Some("0x10:4"));
+        assert_eq!(
+            params.get_all("regs").collect::<Vec<_>>(),
+            vec!["0x10:4", "0x20:2"]
+        );
+        assert_eq!(params.get("freeze"), Some(""));
+
+        assert_eq!(QueryParams::from_uri("/info"), QueryParams::default());
+        assert_eq!(QueryParams::from_uri("/info?&&"), QueryParams::default());
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("0x%41b"), "0xAb");
+        assert_eq!(decode("%2b+%2B"), "+ +");
+        // escape non validi restano come sono
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%4"), "%4");
+        assert_eq!(decode("%zz%"), "%zz%");
+        assert_eq!(decode("%+1"), "% 1");
+        assert_eq!(decode("%FF"), "\u{FFFD}");
+    }
+}
@@ -0,0 +1,772 @@
+//! Registri DSP e formati dei loro valori, condivisi con la web UI
+//!
+//! The web UI (`webui/dsp-control-wasm`) re-exports these types, so a
+//! register list saved as JSON means the same thing to the servers, e.g.
+//! for [`crate::sigmastudio`].
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// https://ez.analog.com/dsp/sigmadsp/w/documents/5169/what-are-the-number-formats-for-sigmadsp
+// pag 81 of the datasheet
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DataType {
+    Int5_23, // 5.23 integer fixed point decimal format, this is used for audio samples, 4 bytes
+    Int8_24,
+    Int28_0, // 28.0 bit integer for dsp, 4 bytes
+    Int32_0,
+    UInt32_0, // 32 bit unsigned, for counters and status words where the MSB isn't a sign
+    Int64_0,  // 64 bit integer, two 32 bit words MSB-first (high word at the lower address)
+    //Int5_19, // 5.19 hardware readback format, 3 bytes
+    Double,           // IEEE-754 double, 8 bytes big-endian
+    Float,            // IEEE-754 single, 4 bytes big-endian, coefficients of the SIMD cores
+    Raw { len: u16 }, // opaque bytes, shown only as hex
+}
+
+impl DataType {
+    /// Natural width of the data type in bytes
+    pub fn size(&self) -> u16 {
+        match self {
+            DataType::Int5_23 => 4,
+            DataType::Int8_24 => 4,
+            DataType::Int28_0 => 4,
+            DataType::Int32_0 => 4,
+            DataType::UInt32_0 => 4,
+            DataType::Int64_0 => 8,
+            //DataType::Int5_19 => 3,
+            DataType::Double => 8,
+            DataType::Float => 4,
+            DataType::Raw { len } => *len,
+        }
+    }
+
+    /// Raw registers have no numeric interpretation, only their bytes are shown
+    pub fn is_numeric(&self) -> bool {
+        !matches!(self, DataType::Raw { .. })
+    }
+
+    /// Big-endian, as SigmaDSP parts expect
+    pub fn value_to_bytes(&self, value: f64) -> Vec<u8> {
+        match self {
+            DataType::Int5_23 => {
+                let int_value = fixed_point_to_i32(value, 8388608.0); // 2^23
+
+                int_value.to_be_bytes().to_vec()
+            }
+            DataType::Int8_24 => {
+                // For 8.24 format, multiply by 2^24 to get the fixed point representation
+                let int_value = fixed_point_to_i32(value, 16777216.0); // 2^24
+
+                int_value.to_be_bytes().to_vec()
+            }
+            DataType::Int32_0 => {
+                let int_value = value as i32;
+
+                int_value.to_be_bytes().to_vec()
+            }
+            DataType::Int28_0 => {
+                let int_value = value as i32;
+
+                int_value.to_be_bytes().to_vec()
+            }
+            DataType::UInt32_0 => {
+                // i negativi diventano 0 invece di girare a 0xFFFFFFFF
+                let int_value = value.clamp(0.0, u32::MAX as f64) as u32;
+
+                int_value.to_be_bytes().to_vec()
+            }
+            DataType::Int64_0 => {
+                let int_value = value as i64;
+
+                int_value.to_be_bytes().to_vec()
+            }
+            DataType::Double => value.to_be_bytes().to_vec(),
+            DataType::Float => (value as f32).to_be_bytes().to_vec(),
+            DataType::Raw { len } => vec![0; *len as usize],
+        }
+    }
+
+    /// Accepts slices shorter than 4 bytes (e.g. 2-byte control registers),
+    /// they are sign-extended before being interpreted. Floats and unsigned
+    /// integers shorter than their size are zero-extended instead.
+    pub fn bytes_to_value(&self, bytes: &[u8]) -> f64 {
+        match self {
+            DataType::Int5_23 => {
+                let int_value = be_bytes_to_i32(bytes);
+                int_value as f64 / 8388608.0
+            }
+            DataType::Int8_24 => {
+                let int_value = be_bytes_to_i32(bytes);
+                int_value as f64 / 16777216.0
+            }
+            DataType::Int32_0 => {
+                let int_value = be_bytes_to_i32(bytes);
+                int_value as f64
+            }
+            DataType::Int28_0 => {
+                let int_value = be_bytes_to_i32(bytes);
+                int_value as f64
+            }
+            DataType::UInt32_0 => {
+                let int_value = u32::from_be_bytes(be_bytes_padded(bytes));
+                int_value as f64
+            }
+            DataType::Int64_0 => {
+                // oltre 2^53 il valore perde precisione, basta per un contatore a schermo
+                let int_value = be_bytes_to_i64(bytes);
+                int_value as f64
+            }
+            DataType::Double => f64::from_be_bytes(be_bytes_padded(bytes)),
+            DataType::Float => f32::from_be_bytes(be_bytes_padded(bytes)) as f64,
+            DataType::Raw { .. } => f64::NAN,
+        }
+    }
+
+    pub fn value_to_bytes_with(&self, value: f64, endianness: Endianness) -> Vec<u8> {
+        endianness.reorder(&self.value_to_bytes(value))
+    }
+
+    pub fn bytes_to_value_with(&self, bytes: &[u8], endianness: Endianness) -> f64 {
+        self.bytes_to_value(&endianness.reorder(bytes))
+    }
+}
+
+/// Ordine dei byte di un registro sul device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Endianness {
+    /// MSB first, SigmaDSP
+    #[default]
+    Big,
+    /// LSB first, for other peripherals behind the same bridge
+    Little,
+}
+
+impl Endianness {
+    /// Big-endian bytes in this order, or back: reversing is its own inverse
+    pub fn reorder(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Endianness::Big => bytes.to_vec(),
+            Endianness::Little => bytes.iter().rev().copied().collect(),
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Int5_23 => f.write_str("Int5.23"),
+            DataType::Int8_24 => f.write_str("Int8.24"),
+            DataType::Int28_0 => f.write_str("Int28.0"),
+            DataType::Int32_0 => f.write_str("Int32.0"),
+            DataType::UInt32_0 => f.write_str("UInt32.0"),
+            DataType::Int64_0 => f.write_str("Int64.0"),
+            DataType::Double => f.write_str("Double"),
+            DataType::Float => f.write_str("Float"),
+            DataType::Raw { len } => write!(f, "Raw ({} bytes)", len),
+        }
+    }
+}
+
+impl fmt::Display for MeasurementUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MeasurementUnit::Decibel => "dB",
+            MeasurementUnit::Hertz => "Hz",
+            MeasurementUnit::None => "",
+        })
+    }
+}
+
+/// Sign-extends up to 4 big-endian bytes into an i32, longer slices keep the last 4 bytes
+fn be_bytes_to_i32(bytes: &[u8]) -> i32 {
+    let bytes = &bytes[bytes.len().saturating_sub(4)..];
+    let fill = if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+        0xFF
+    } else {
+        0x00
+    };
+
+    let mut buf = [fill; 4];
+    buf[4 - bytes.len()..].copy_from_slice(bytes);
+    i32::from_be_bytes(buf)
+}
+
+/// Scala `value` in virgola fissa, saturando al fondo scala invece di girare
+///
+/// Values beyond the representable range, about ±128 for 8.24 and ±256 for
+/// 5.23, pin to `i32::MAX`/`i32::MIN`, the most positive and most negative
+/// words the DSP accepts. NaN becomes 0.
+fn fixed_point_to_i32(value: f64, scale: f64) -> i32 {
+    let scaled = value * scale;
+    if scaled.is_nan() {
+        return 0;
+    }
+    scaled.clamp(i32::MIN as f64, i32::MAX as f64) as i32
+}
+
+/// Sign-extends up to 8 big-endian bytes into an i64, longer slices keep the last 8 bytes
+pub fn be_bytes_to_i64(bytes: &[u8]) -> i64 {
+    let bytes = &bytes[bytes.len().saturating_sub(8)..];
+    let fill = if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+        0xFF
+    } else {
+        0x00
+    };
+
+    let mut buf = [fill; 8];
+    buf[8 - bytes.len()..].copy_from_slice(bytes);
+    i64::from_be_bytes(buf)
+}
+
+/// Last `N` big-endian bytes, zero-extended on the left if the slice is shorter
+fn be_bytes_padded<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let bytes = &bytes[bytes.len().saturating_sub(N)..];
+    let mut buf = [0; N];
+    buf[N - bytes.len()..].copy_from_slice(bytes);
+    buf
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum MeasurementUnit {
+    Decibel,
+    Hertz,
+    #[default]
+    None,
+}
+
+impl MeasurementUnit {
+    /// Decimali di default: uno per i dB, nessuno per le frequenze e i conteggi
+    pub fn precision(&self) -> usize {
+        match self {
+            MeasurementUnit::Decibel => 1,
+            MeasurementUnit::Hertz => 0,
+            MeasurementUnit::None => 0,
+        }
+    }
+
+    /// Formatta un valore nell'unità con `precision` decimali
+    ///
+    /// Levels keep their trailing zeros, "-6.0" dB next to "-6.5" dB reads
+    /// as a level; other units drop them.
+    pub fn format(&self, value: f64, precision: usize) -> String {
+        match self {
+            MeasurementUnit::Decibel => format!("{:.*}", precision, value),
+            MeasurementUnit::Hertz | MeasurementUnit::None => format_value(value, precision),
+        }
+    }
+}
+
+/// Livello a cui corrispondono i dB di un registro
+///
+/// A linear `value` reads as `20 * log10(value / reference) + offset_db`.
+/// The default, reference 1.0 and no offset, is dBFS; a meter in dBu whose
+/// full scale is +24 dBu has reference 1.0 and offset 24.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DecibelReference {
+    pub reference: f64,
+    #[serde(default)]
+    pub offset_db: f64,
+}
+
+impl Default for DecibelReference {
+    fn default() -> Self {
+        Self {
+            reference: 1.0,
+            offset_db: 0.0,
+        }
+    }
+}
+
+/// Rappresenta un registro DSP
+///
+/// In JSON only `name`, `address`, `data_type`, `min` and `max` are
+/// required, the other fields have the defaults of a plain read-write
+/// SigmaDSP register.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DspRegister {
+    pub name: String,
+    pub address: u16,
+    pub data_type: DataType,
+    /// Byte length on the device, if different from the natural width of the data type
+    #[serde(default)]
+    pub len: Option<u16>,
+    /// Consecutive 32 bit words the value spans, e.g. 2 for an Int64.0 accumulator
+    #[serde(default = "one_word")]
+    pub words: u8,
+    pub min: i32,
+    pub max: i32,
+    #[serde(default)]
+    pub read_only: bool,
+    /// Asks for confirmation before writing, for registers that can take the DSP offline
+    #[serde(default)]
+    pub confirm: bool,
+    #[serde(default)]
+    pub unit: MeasurementUnit,
+    /// Slider step, in the register unit
+    #[serde(default = "unit_step")]
+    pub step: f64,
+    /// Decimal places shown for the value, None for the default of the unit
+    #[serde(default)]
+    pub precision: Option<usize>,
+    /// Byte order on the device, big-endian for every SigmaDSP register
+    #[serde(default)]
+    pub endianness: Endianness,
+    /// Changes smaller than this, in display units, don't move a read-only
+    /// meter, so a noisy level doesn't flicker
+    #[serde(default)]
+    pub deadband: Option<f64>,
+    /// Sezione richiudibile in cui mostrare il registro, senza gruppo va in quella predefinita
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Reference level of a dB register, None for dBFS
+    #[serde(default)]
+    pub decibel: Option<DecibelReference>,
+}
+
+fn one_word() -> u8 {
+    1
+}
+
+fn unit_step() -> f64 {
+    1.0
+}
+
+impl DspRegister {
+    /// Number of bytes to read/write for this register
+    ///
+    /// A multi-word register is read in a single transfer of `words * 4`
+    /// bytes, the device auto-increments the address after each word.
+    pub fn byte_len(&self) -> u16 {
+        self.len.unwrap_or_else(|| {
+            if self.words > 1 {
+                self.words as u16 * 4
+            } else {
+                self.data_type.size()
+            }
+        })
+    }
+
+    /// Encodes a raw value with the register's data type, truncated or sign-extended to
+    /// `byte_len`, in the register's byte order
+    pub fn value_to_bytes(&self, raw_value: f64) -> Vec<u8> {
+        let bytes = self.data_type.value_to_bytes(raw_value);
+        let len = self.byte_len() as usize;
+
+        let bytes = if len <= bytes.len() {
+            bytes[bytes.len() - len..].to_vec()
+        } else {
+            let fill = if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+                0xFF
+            } else {
+                0x00
+            };
+            let mut extended = vec![fill; len - bytes.len()];
+            extended.extend_from_slice(&bytes);
+            extended
+        };
+        self.endianness.reorder(&bytes)
+    }
+
+    /// Decodes bytes read from the device, in the register's byte order
+    pub fn bytes_to_value(&self, bytes: &[u8]) -> f64 {
+        self.data_type.bytes_to_value_with(bytes, self.endianness)
+    }
+
+    /// Limits a value in the register unit to min/max before it is written,
+    /// NaN becomes min
+    pub fn clamp(&self, value: f64) -> f64 {
+        if value.is_nan() {
+            return self.min as f64;
+        }
+        value.clamp(self.min as f64, self.max as f64)
+    }
+
+    pub fn unit_to_raw_value(&self, value: f64) -> f64 {
+        match self.unit {
+            // Convert decibels to linear scale
+            MeasurementUnit::Decibel => {
+                let db = self.decibel.unwrap_or_default();
+                db.reference * 10.0f64.powf((value - db.offset_db) / 20.0)
+            }
+            MeasurementUnit::Hertz | MeasurementUnit::None => value,
+        }
+    }
+
+    /// Inverse of [`unit_to_raw_value`](Self::unit_to_raw_value), both use amplitude dB
+    pub fn raw_value_to_unit(&self, value: f64) -> f64 {
+        match self.unit {
+            // Convert linear scale to decibels
+            MeasurementUnit::Decibel => {
+                let db = self.decibel.unwrap_or_default();
+                20.0 * (value / db.reference).log10() + db.offset_db
+            }
+            MeasurementUnit::Hertz | MeasurementUnit::None => value,
+        }
+    }
+
+    pub fn precision(&self) -> usize {
+        self.precision.unwrap_or_else(|| self.unit.precision())
+    }
+
+    /// Valore nell'unità del registro, formattato per la visualizzazione
+    pub fn format_value(&self, value: f64) -> String {
+        self.unit.format(value, self.precision())
+    }
+}
+
+/// Format a float value with up to `precision` decimal places, removing trailing zeros
+pub fn format_value(value: f64, precision: usize) -> String {
+    // First format with fixed precision
+    let formatted = format!("{:.*}", precision, value);
+
+    // Remove trailing zeros after decimal point
+    if formatted.contains('.') {
+        let trimmed = formatted.trim_end_matches('0');
+        // If we trimmed all the way to the decimal point, remove it too
+        if trimmed.ends_with('.') {
+            return trimmed.trim_end_matches('.').to_string();
+        }
+        return trimmed.to_string();
+    }
+
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int32_endianness() {
+        let dtype = DataType::Int32_0;
+
+        assert_eq!(
+            dtype.value_to_bytes_with(305419896.0, Endianness::Big),
+            vec![0x12, 0x34, 0x56, 0x78]
+        );
+        assert_eq!(
+            dtype.value_to_bytes_with(305419896.0, Endianness::Little),
+            vec![0x78, 0x56, 0x34, 0x12]
+        );
+        assert_eq!(
+            dtype.bytes_to_value_with(&[0x78, 0x56, 0x34, 0x12], Endianness::Little),
+            305419896.0
+        );
+        assert_eq!(
+            dtype.bytes_to_value_with(&[0xFE, 0xFF, 0xFF, 0xFF], Endianness::Little),
+            -2.0
+        );
+    }
+
+    #[test]
+    fn test_fixed_point_saturation() {
+        let full_scale = vec![0x7F, 0xFF, 0xFF, 0xFF];
+        let negative_full_scale = vec![0x80, 0x00, 0x00, 0x00];
+
+        // oltre il fondo scala si resta agli estremi, senza girare di segno
+        assert_eq!(DataType::Int8_24.value_to_bytes(200.0), full_scale);
+        assert_eq!(DataType::Int8_24.value_to_bytes(1e12), full_scale);
+        assert_eq!(DataType::Int8_24.value_to_bytes(f64::INFINITY), full_scale);
+        assert_eq!(
+            DataType::Int8_24.value_to_bytes(-200.0),
+            negative_full_scale
+        );
+        assert_eq!(DataType::Int5_23.value_to_bytes(300.0), full_scale);
+        assert_eq!(
+            DataType::Int5_23.value_to_bytes(-300.0),
+            negative_full_scale
+        );
+        assert_eq!(DataType::Int8_24.value_to_bytes(f64::NAN), vec![0x00; 4]);
+
+        // dentro l'intervallo non cambia nulla
+        assert_eq!(
+            DataType::Int8_24.value_to_bytes(-128.0),
+            negative_full_scale
+        );
+        assert_eq!(
+            DataType::Int8_24.value_to_bytes(1.0),
+            vec![0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_uint32_format() {
+        let signed = DataType::Int32_0;
+        let unsigned = DataType::UInt32_0;
+        let msb = [0x80, 0x00, 0x00, 0x00];
+
+        assert_eq!(signed.bytes_to_value(&msb), -2147483648.0);
+        assert_eq!(unsigned.bytes_to_value(&msb), 2147483648.0);
+        assert_eq!(unsigned.value_to_bytes(2147483648.0), msb.to_vec());
+        assert_eq!(unsigned.bytes_to_value(&[0xFF; 4]), 4294967295.0);
+
+        // fuori dall'intervallo senza segno si satura
+        assert_eq!(unsigned.value_to_bytes(-1.0), vec![0x00; 4]);
+        assert_eq!(unsigned.value_to_bytes(1e12), vec![0xFF; 4]);
+        // i registri corti non vengono estesi col segno
+        assert_eq!(unsigned.bytes_to_value(&[0xFF, 0xFE]), 65534.0);
+    }
+
+    #[test]
+    fn test_int5_23_format() {
+        let dtype = DataType::Int5_23;
+
+        assert_eq!(dtype.value_to_bytes(1.0), vec![0x00, 0x80, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(-0.5), vec![0xFF, 0xC0, 0x00, 0x00]);
+        assert_eq!(dtype.bytes_to_value(&[0xF8, 0x00, 0x00, 0x00]), -16.0);
+    }
+
+    #[test]
+    fn test_int8_24_format() {
+        let dtype = DataType::Int8_24;
+
+        assert_eq!(dtype.value_to_bytes(-128.0), vec![0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(-32.0), vec![0xE0, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(-8.0), vec![0xF8, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(-2.0), vec![0xFE, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(-1.0), vec![0xFF, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(-0.5), vec![0xFF, 0x80, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(0.0), vec![0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(0.25), vec![0x00, 0x40, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(0.5), vec![0x00, 0x80, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(1.0), vec![0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(2.0), vec![0x02, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_int32_0_format() {
+        let dtype = DataType::Int32_0;
+
+        assert_eq!(
+            dtype.value_to_bytes(-2147483648.0),
+            vec![0x80, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            dtype.value_to_bytes(-2147483647.0),
+            vec![0x80, 0x00, 0x00, 0x01]
+        );
+        assert_eq!(
+            dtype.value_to_bytes(-2147483646.0),
+            vec![0x80, 0x00, 0x00, 0x02]
+        );
+        assert_eq!(
+            dtype.value_to_bytes(-1073741824.0),
+            vec![0xC0, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            dtype.value_to_bytes(-536870912.0),
+            vec![0xE0, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(dtype.value_to_bytes(-4.0), vec![0xFF, 0xFF, 0xFF, 0xFC]);
+        assert_eq!(dtype.value_to_bytes(-2.0), vec![0xFF, 0xFF, 0xFF, 0xFE]);
+        assert_eq!(dtype.value_to_bytes(-1.0), vec![0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(dtype.value_to_bytes(0.0), vec![0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(dtype.value_to_bytes(1.0), vec![0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(dtype.value_to_bytes(2.0), vec![0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(dtype.value_to_bytes(3.0), vec![0x00, 0x00, 0x00, 0x03]);
+        assert_eq!(dtype.value_to_bytes(4.0), vec![0x00, 0x00, 0x00, 0x04]);
+        assert_eq!(
+            dtype.value_to_bytes(2147483646.0),
+            vec![0x7F, 0xFF, 0xFF, 0xFE]
+        );
+        assert_eq!(
+            dtype.value_to_bytes(2147483647.0),
+            vec![0x7F, 0xFF, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_short_register_decoding() {
+        // 2-byte control register, like 0xF020 in the protocol tests
+        let dtype = DataType::Int32_0;
+
+        assert_eq!(dtype.bytes_to_value(&[0x00, 0x08]), 8.0);
+        assert_eq!(dtype.bytes_to_value(&[0xFF, 0xFE]), -2.0);
+        assert_eq!(
+            dtype.bytes_to_value(&[0x80, 0x00, 0x00, 0x00]),
+            -2147483648.0
+        );
+    }
+
+    #[test]
+    fn test_register_byte_len() {
+        let register = DspRegister {
+            name: "Control".to_string(),
+            address: 0xF020,
+            data_type: DataType::Int32_0,
+            len: Some(2),
+            words: 1,
+            min: 0,
+            max: 65535,
+            read_only: false,
+            confirm: false,
+            unit: MeasurementUnit::None,
+            step: 1.0,
+            precision: Some(3),
+            endianness: Endianness::Big,
+            deadband: None,
+            group: None,
+            decibel: None,
+        };
+
+        assert_eq!(register.data_type.size(), 4);
+        assert_eq!(register.byte_len(), 2);
+        assert_eq!(register.value_to_bytes(8.0), vec![0x00, 0x08]);
+        assert_eq!(register.value_to_bytes(-2.0), vec![0xFF, 0xFE]);
+
+        let little = DspRegister {
+            endianness: Endianness::Little,
+            ..register.clone()
+        };
+        assert_eq!(little.value_to_bytes(8.0), vec![0x08, 0x00]);
+        assert_eq!(little.bytes_to_value(&[0xFE, 0xFF]), -2.0);
+
+        let raw = DspRegister {
+            data_type: DataType::Raw { len: 6 },
+            len: None,
+            ..register
+        };
+
+        assert_eq!(raw.byte_len(), 6);
+        assert!(!raw.data_type.is_numeric());
+        assert!(raw.data_type.bytes_to_value(&[0x01; 6]).is_nan());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let register = DspRegister {
+            name: "Gain".to_string(),
+            address: 0x007E,
+            data_type: DataType::Int8_24,
+            len: None,
+            words: 1,
+            min: -80,
+            max: 10,
+            read_only: false,
+            confirm: false,
+            unit: MeasurementUnit::Decibel,
+            step: 0.5,
+            precision: Some(1),
+            endianness: Endianness::Big,
+            deadband: None,
+            group: None,
+            decibel: None,
+        };
+
+        assert_eq!(register.clamp(25.0), 10.0);
+        assert_eq!(register.clamp(-120.0), -80.0);
+        assert_eq!(register.clamp(-6.5), -6.5);
+        assert_eq!(register.clamp(f64::NAN), -80.0);
+        assert_eq!(register.clamp(f64::INFINITY), 10.0);
+    }
+
+    #[test]
+    fn test_decibel_reference() {
+        let mut register = DspRegister {
+            name: "Level".to_string(),
+            address: 0x003D,
+            data_type: DataType::Int8_24,
+            len: None,
+            words: 1,
+            min: -96,
+            max: 24,
+            read_only: true,
+            confirm: false,
+            unit: MeasurementUnit::Decibel,
+            step: 1.0,
+            precision: None,
+            endianness: Endianness::Big,
+            deadband: None,
+            group: None,
+            decibel: None,
+        };
+
+        // dBFS: il fondo scala è 0 dB
+        assert_eq!(register.raw_value_to_unit(1.0), 0.0);
+        assert!((register.raw_value_to_unit(0.5) + 6.0206).abs() < 1e-4);
+        assert!((register.unit_to_raw_value(-6.0206) - 0.5).abs() < 1e-5);
+        assert_eq!(register.raw_value_to_unit(0.0), f64::NEG_INFINITY);
+
+        // dBu con il fondo scala a +24 dBu
+        register.decibel = Some(DecibelReference {
+            reference: 1.0,
+            offset_db: 24.0,
+        });
+        assert_eq!(register.raw_value_to_unit(1.0), 24.0);
+        assert!((register.unit_to_raw_value(4.0) - 0.1).abs() < 1e-12);
+
+        // riferimento diverso da 1, andata e ritorno
+        register.decibel = Some(DecibelReference {
+            reference: 0.25,
+            offset_db: -3.0,
+        });
+        assert!((register.raw_value_to_unit(0.25) + 3.0).abs() < 1e-12);
+        for db in [-60.0, -12.5, 0.0, 6.0] {
+            let raw = register.unit_to_raw_value(db);
+            assert!((register.raw_value_to_unit(raw) - db).abs() < 1e-9);
+        }
+
+        let parsed: DecibelReference = serde_json::from_str(r#"{"reference": 0.775}"#).unwrap();
+        assert_eq!(parsed.offset_db, 0.0);
+    }
+
+    #[test]
+    fn test_float_formats() {
+        let float = DataType::Float;
+        assert_eq!(float.size(), 4);
+        assert_eq!(float.value_to_bytes(1.0), vec![0x3F, 0x80, 0x00, 0x00]);
+        assert_eq!(float.value_to_bytes(-2.0), vec![0xC0, 0x00, 0x00, 0x00]);
+        assert_eq!(float.bytes_to_value(&[0x3F, 0x00, 0x00, 0x00]), 0.5);
+
+        let double = DataType::Double;
+        assert_eq!(double.size(), 8);
+        assert_eq!(
+            double.value_to_bytes(1.0),
+            vec![0x3F, 0xF0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            double.bytes_to_value(&[0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            -2.0
+        );
+        // una lettura corta non deve andare in panic
+        assert_eq!(double.bytes_to_value(&[0x00, 0x00]), 0.0);
+    }
+
+    #[test]
+    fn test_two_word_value() {
+        let register = DspRegister {
+            name: "Accumulator".to_string(),
+            address: 0x0100,
+            data_type: DataType::Int64_0,
+            len: None,
+            words: 2,
+            min: 0,
+            max: i32::MAX,
+            read_only: true,
+            confirm: false,
+            unit: MeasurementUnit::None,
+            step: 1.0,
+            precision: Some(0),
+            endianness: Endianness::Big,
+            deadband: None,
+            group: None,
+            decibel: None,
+        };
+        assert_eq!(register.byte_len(), 8);
+
+        // parola alta all'indirizzo 0x0100, parola bassa a 0x0101
+        let bytes = [0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02];
+        assert_eq!(register.bytes_to_value(&bytes), 4294967298.0);
+        assert_eq!(register.value_to_bytes(4294967298.0), bytes.to_vec());
+        assert_eq!(register.data_type.bytes_to_value(&[0xFF; 8]), -1.0);
+    }
+
+    #[test]
+    fn test_format_value_precision() {
+        assert_eq!(format_value(-6.02, 3), "-6.02");
+        assert_eq!(format_value(-6.02, 1), "-6");
+        assert_eq!(format_value(-6.27, 1), "-6.3");
+        assert_eq!(format_value(12.5, 0), "12");
+    }
+}
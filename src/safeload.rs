@@ -0,0 +1,420 @@
+use anyhow::{bail, Result};
+
+/// Registers of the safeload mechanism, which updates up to `data_slots`
+/// parameter words at once, at the start of an audio frame, so a filter
+/// never runs with half of its new coefficients.
+///
+/// The ADAU sequence is: load the new words in the data registers, set the
+/// target addresses, then trigger the transfer, which happens at the next
+/// frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SafeloadConfig {
+    /// First of the consecutive data registers, one word each
+    pub data_reg: u16,
+    /// Number of data registers, the most words a single safeload can carry
+    pub data_slots: usize,
+    /// Bytes per data register, a parameter word shorter than this is
+    /// padded with zeros in front
+    pub data_len: usize,
+    /// Registers taking the target parameter addresses
+    pub address: SafeloadAddress,
+    /// Bytes per address register, and per count with [`SafeloadTrigger::Count`]
+    pub address_len: usize,
+    /// What starts the transfer once data and addresses are loaded
+    pub trigger: SafeloadTrigger,
+    /// Bytes per parameter word
+    pub word_len: usize,
+}
+
+/// Where the words of a safeload go
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SafeloadAddress {
+    /// A single register takes the address of the first word, the others
+    /// go to the following addresses
+    First(u16),
+    /// Every data register has its own address register, consecutive from
+    /// this one
+    PerSlot(u16),
+}
+
+/// How a loaded safeload is started
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SafeloadTrigger {
+    /// Writing the number of words to this register
+    Count(u16),
+    /// Writing `value` to the 2-byte control register `reg`
+    Control { reg: u16, value: u16 },
+}
+
+impl SafeloadConfig {
+    /// ADAU1452/1466: data at 0x6000-0x6004, address of the first word at
+    /// 0x6005, count at 0x6006 (`num_SafeLoad_Lower`), 4-byte registers
+    pub const ADAU1452: SafeloadConfig = SafeloadConfig {
+        data_reg: 0x6000,
+        data_slots: 5,
+        data_len: 4,
+        address: SafeloadAddress::First(0x6005),
+        address_len: 4,
+        trigger: SafeloadTrigger::Count(0x6006),
+        word_len: 4,
+    };
+
+    /// ADAU1701/1401: 5-byte data registers at 0x0810-0x0814, each with its
+    /// 2-byte address register at 0x0815-0x0819. The transfer starts when
+    /// the IST bit (0x0020) of the core control register 0x081c is set,
+    /// written along with the bits of a running DSP (0x001c)
+    pub const ADAU1701: SafeloadConfig = SafeloadConfig {
+        data_reg: 0x0810,
+        data_slots: 5,
+        data_len: 5,
+        address: SafeloadAddress::PerSlot(0x0815),
+        address_len: 2,
+        trigger: SafeloadTrigger::Control {
+            reg: 0x081c,
+            value: 0x003c,
+        },
+        word_len: 4,
+    };
+
+    /// Writes that perform a safeload of `data` at `addr`, in order
+    ///
+    /// `data` must be whole words and fit in the data registers. Addresses,
+    /// count and control value are written big-endian.
+    pub fn sequence(&self, addr: u16, data: &[u8]) -> Result<Vec<(u16, Vec<u8>)>> {
+        if data.is_empty() || !data.len().is_multiple_of(self.word_len) {
+            bail!(
+                "Safeload of {} bytes is not a whole number of {}-byte words",
+                data.len(),
+                self.word_len
+            );
+        }
+        let words = data.len() / self.word_len;
+        if words > self.data_slots {
+            bail!(
+                "Safeload of {} words exceeds the {} data registers",
+                words,
+                self.data_slots
+            );
+        }
+
+        let mut sequence = Vec::new();
+        match self.address {
+            // i registri dati sono consecutivi, basta una scrittura
+            SafeloadAddress::First(address_reg) if self.data_len == self.word_len => {
+                sequence.push((self.data_reg, data.to_vec()));
+                sequence.push((address_reg, be_bytes(addr as u32, self.address_len)));
+            }
+            SafeloadAddress::First(address_reg) => {
+                for (slot, word) in data.chunks(self.word_len).enumerate() {
+                    sequence.push(self.data_write(slot, word));
+                }
+                sequence.push((address_reg, be_bytes(addr as u32, self.address_len)));
+            }
+            SafeloadAddress::PerSlot(address_reg) => {
+                for (slot, word) in data.chunks(self.word_len).enumerate() {
+                    let target = addr.wrapping_add(slot as u16);
+                    sequence.push(self.data_write(slot, word));
+                    sequence.push((
+                        address_reg + slot as u16,
+                        be_bytes(target as u32, self.address_len),
+                    ));
+                }
+            }
+        }
+        sequence.push(self.trigger_write(words));
+        Ok(sequence)
+    }
+
+    /// Writes that load the words of `batch`, at any addresses, in order
+    ///
+    /// With [`SafeloadAddress::PerSlot`] each word gets its data and its
+    /// address register, then a single trigger moves all of them in the
+    /// same audio frame.
+    ///
+    /// With [`SafeloadAddress::First`] the words of a safeload go to
+    /// consecutive addresses, so each run of consecutive addresses is a
+    /// safeload of its own: atomic within the run, not across runs. The
+    /// five coefficients of a biquad are a single run.
+    pub fn batch_sequence(&self, batch: &SafeloadBatch) -> Result<Vec<(u16, Vec<u8>)>> {
+        let words = batch.words();
+        if words.len() > self.data_slots {
+            bail!(
+                "Safeload batch of {} words exceeds the {} data registers",
+                words.len(),
+                self.data_slots
+            );
+        }
+
+        let mut sequence = Vec::new();
+        match self.address {
+            SafeloadAddress::First(_) => {
+                for (addr, data) in batch.runs() {
+                    sequence.extend(self.sequence(addr, &data)?);
+                }
+            }
+            SafeloadAddress::PerSlot(address_reg) => {
+                for (slot, (addr, word)) in words.iter().enumerate() {
+                    sequence.push(self.data_write(slot, word));
+                    sequence.push((
+                        address_reg + slot as u16,
+                        be_bytes(*addr as u32, self.address_len),
+                    ));
+                }
+                sequence.push(self.trigger_write(words.len()));
+            }
+        }
+        Ok(sequence)
+    }
+
+    /// Write of `word` to the data register of `slot`
+    fn data_write(&self, slot: usize, word: &[u8]) -> (u16, Vec<u8>) {
+        let mut bytes = vec![0; self.data_len.saturating_sub(word.len())];
+        bytes.extend_from_slice(word);
+        (self.data_reg + slot as u16, bytes)
+    }
+
+    /// Write that starts a safeload of `words` words
+    fn trigger_write(&self, words: usize) -> (u16, Vec<u8>) {
+        match self.trigger {
+            SafeloadTrigger::Count(reg) => (reg, be_bytes(words as u32, self.address_len)),
+            SafeloadTrigger::Control { reg, value } => (reg, value.to_be_bytes().to_vec()),
+        }
+    }
+}
+
+/// `value` big-endian, padded or truncated to `len` bytes
+fn be_bytes(value: u32, len: usize) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut word = vec![0; len.saturating_sub(bytes.len())];
+    word.extend_from_slice(&bytes[bytes.len().saturating_sub(len)..]);
+    word
+}
+
+impl Default for SafeloadConfig {
+    fn default() -> Self {
+        Self::ADAU1452
+    }
+}
+
+/// Parameter words, each with its own address, to update in the same audio
+/// frame: e.g. the coefficients of a filter, sent in any order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SafeloadBatch {
+    words: Vec<(u16, [u8; 4])>,
+}
+
+impl SafeloadBatch {
+    /// Safeload data registers of the ADAU parts
+    pub const MAX_WORDS: usize = 5;
+
+    /// Fails on an empty batch, more than [`Self::MAX_WORDS`] words or an
+    /// address given twice
+    pub fn new(words: Vec<(u16, [u8; 4])>) -> Result<Self> {
+        if words.is_empty() || words.len() > Self::MAX_WORDS {
+            bail!(
+                "Safeload batch of {} words, must be 1 to {}",
+                words.len(),
+                Self::MAX_WORDS
+            );
+        }
+        for (i, (addr, _)) in words.iter().enumerate() {
+            if words[..i].iter().any(|(other, _)| other == addr) {
+                bail!("Address 0x{:04x} appears twice in the safeload batch", addr);
+            }
+        }
+        Ok(Self { words })
+    }
+
+    /// Batch of writes of one 4-byte word each
+    pub fn from_writes(writes: &[(u16, Vec<u8>)]) -> Result<Self> {
+        let words = writes
+            .iter()
+            .map(|(addr, data)| match <[u8; 4]>::try_from(data.as_slice()) {
+                Ok(word) => Ok((*addr, word)),
+                Err(_) => bail!(
+                    "Safeload write at 0x{:04x} is {} bytes, not one 4-byte word",
+                    addr,
+                    data.len()
+                ),
+            })
+            .collect::<Result<_>>()?;
+        Self::new(words)
+    }
+
+    pub fn words(&self) -> &[(u16, [u8; 4])] {
+        &self.words
+    }
+
+    /// The words in address order, grouped in runs of consecutive
+    /// addresses: first address and words of each run
+    pub fn runs(&self) -> Vec<(u16, Vec<u8>)> {
+        let mut words = self.words.clone();
+        words.sort_by_key(|(addr, _)| *addr);
+
+        let mut runs: Vec<(u16, Vec<u8>)> = Vec::new();
+        for (addr, word) in words {
+            match runs.last_mut() {
+                Some((start, data)) if start.checked_add((data.len() / 4) as u16) == Some(addr) => {
+                    data.extend_from_slice(&word)
+                }
+                _ => runs.push((addr, word.to_vec())),
+            }
+        }
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adau1452_sequence() {
+        let sequence = SafeloadConfig::default()
+            .sequence(0x0043, &[0, 0x80, 0, 0, 0, 0, 0, 1])
+            .unwrap();
+
+        assert_eq!(
+            sequence,
+            vec![
+                (0x6000, vec![0, 0x80, 0, 0, 0, 0, 0, 1]),
+                (0x6005, vec![0, 0, 0x00, 0x43]),
+                (0x6006, vec![0, 0, 0, 2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adau1701_sequence() {
+        let sequence = SafeloadConfig::ADAU1701
+            .sequence(0x0043, &[0, 0x80, 0, 0, 0, 0, 0, 1])
+            .unwrap();
+
+        assert_eq!(
+            sequence,
+            vec![
+                (0x0810, vec![0, 0, 0x80, 0, 0]),
+                (0x0815, vec![0x00, 0x43]),
+                (0x0811, vec![0, 0, 0, 0, 1]),
+                (0x0816, vec![0x00, 0x44]),
+                (0x081c, vec![0x00, 0x3c]),
+            ]
+        );
+        assert!(SafeloadConfig::ADAU1701.sequence(0x0043, &[0; 5]).is_err());
+    }
+
+    #[test]
+    fn test_sequence_rejects_bad_lengths() {
+        let config = SafeloadConfig::ADAU1452;
+        assert!(config.sequence(0x0043, &[]).is_err());
+        assert!(config.sequence(0x0043, &[0; 6]).is_err());
+        assert!(config.sequence(0x0043, &[0; 24]).is_err());
+        assert!(config.sequence(0x0043, &[0; 20]).is_ok());
+    }
+
+    #[test]
+    fn test_safeload_batch() {
+        let word = |n: u8| [0, 0, 0, n];
+
+        // biquad coefficients in any order
+        let batch =
+            SafeloadBatch::new(vec![(0x22, word(3)), (0x20, word(1)), (0x21, word(2))]).unwrap();
+        assert_eq!(
+            batch.runs(),
+            vec![(0x20, vec![0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3])]
+        );
+        let gap =
+            SafeloadBatch::new(vec![(0x23, word(4)), (0x20, word(1)), (0x21, word(2))]).unwrap();
+        assert_eq!(
+            gap.runs(),
+            vec![
+                (0x20, vec![0, 0, 0, 1, 0, 0, 0, 2]),
+                (0x23, vec![0, 0, 0, 4])
+            ]
+        );
+        let wrap = SafeloadBatch::new(vec![(0xffff, word(1)), (0x0000, word(2))]).unwrap();
+        assert_eq!(
+            wrap.runs(),
+            vec![(0x0000, vec![0, 0, 0, 2]), (0xffff, vec![0, 0, 0, 1])]
+        );
+
+        assert!(SafeloadBatch::new(vec![]).is_err());
+        assert!(SafeloadBatch::new((0..6).map(|i| (i, word(0))).collect()).is_err());
+        assert!(SafeloadBatch::new(vec![(0x20, word(1)), (0x20, word(2))]).is_err());
+
+        assert!(SafeloadBatch::from_writes(&[(0x20, vec![0, 0, 0, 1])]).is_ok());
+        assert!(SafeloadBatch::from_writes(&[(0x20, vec![0, 1])]).is_err());
+    }
+
+    #[test]
+    fn test_batch_sequence() {
+        let batch = SafeloadBatch::new(
+            (0..5)
+                .rev()
+                .map(|i| (0x0100 + i, [0, 0, 0, i as u8]))
+                .collect(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            SafeloadConfig::ADAU1452.batch_sequence(&batch).unwrap(),
+            vec![
+                (
+                    0x6000,
+                    vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4]
+                ),
+                (0x6005, vec![0, 0, 0x01, 0x00]),
+                (0x6006, vec![0, 0, 0, 5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_sequence_arbitrary_addresses() {
+        let batch = SafeloadBatch::new(vec![
+            (0x0043, [0, 0, 0, 1]),
+            (0x0200, [0, 0, 0, 2]),
+            (0x0010, [0, 0, 0, 3]),
+            (0x0044, [0, 0, 0, 4]),
+            (0x03ff, [0, 0, 0, 5]),
+        ])
+        .unwrap();
+
+        // un registro indirizzo per parola, un solo trigger
+        assert_eq!(
+            SafeloadConfig::ADAU1701.batch_sequence(&batch).unwrap(),
+            vec![
+                (0x0810, vec![0, 0, 0, 0, 1]),
+                (0x0815, vec![0x00, 0x43]),
+                (0x0811, vec![0, 0, 0, 0, 2]),
+                (0x0816, vec![0x02, 0x00]),
+                (0x0812, vec![0, 0, 0, 0, 3]),
+                (0x0817, vec![0x00, 0x10]),
+                (0x0813, vec![0, 0, 0, 0, 4]),
+                (0x0818, vec![0x00, 0x44]),
+                (0x0814, vec![0, 0, 0, 0, 5]),
+                (0x0819, vec![0x03, 0xff]),
+                (0x081c, vec![0x00, 0x3c]),
+            ]
+        );
+
+        // un solo indirizzo di partenza, una safeload per gruppo consecutivo
+        assert_eq!(
+            SafeloadConfig::ADAU1452.batch_sequence(&batch).unwrap(),
+            vec![
+                (0x6000, vec![0, 0, 0, 3]),
+                (0x6005, vec![0, 0, 0x00, 0x10]),
+                (0x6006, vec![0, 0, 0, 1]),
+                (0x6000, vec![0, 0, 0, 1, 0, 0, 0, 4]),
+                (0x6005, vec![0, 0, 0x00, 0x43]),
+                (0x6006, vec![0, 0, 0, 2]),
+                (0x6000, vec![0, 0, 0, 2]),
+                (0x6005, vec![0, 0, 0x02, 0x00]),
+                (0x6006, vec![0, 0, 0, 1]),
+                (0x6000, vec![0, 0, 0, 5]),
+                (0x6005, vec![0, 0, 0x03, 0xff]),
+                (0x6006, vec![0, 0, 0, 1]),
+            ]
+        );
+    }
+}
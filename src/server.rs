@@ -0,0 +1,815 @@
+use std::io;
+use std::net::SocketAddr;
+#[cfg(feature = "tls")]
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Mutex};
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+use tracing::{field, info_span, Instrument, Span};
+
+use crate::auth::{self, AuthToken};
+use crate::backend::{Backend, WriteSource};
+use crate::capabilities::Capabilities;
+use crate::checksum::Checksum;
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, METRICS};
+use crate::{
+    ConnectionFraming, IncompleteCommand, ProtocolCommand, ProtocolHandler, ProtocolResponse,
+    Resync, WriteHeader, CMD_WRITE, MAX_DATA_LEN, STATUS_READ_TOO_LONG,
+};
+
+/// Connection buffer, writes with a larger frame are streamed to the backend,
+/// or buffered whole when checksummed
+const MAX_BUF_SIZE: usize = 2048;
+/// Responses queued for a slow client before the connection stops reading commands
+const RESPONSE_QUEUE_LEN: usize = 32;
+/// Size of the ADAU1452 memory partition, no legitimate read is larger
+pub const MAX_READ_LEN: u32 = 20480 * 4;
+/// Longest wait for the next bytes of a streamed write, which holds the backend
+pub const PAYLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings shared by every connection of a server
+#[derive(Clone)]
+pub struct ServerOptions {
+    token: Option<AuthToken>,
+    max_read_len: u32,
+    payload_timeout: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+}
+
+impl ServerOptions {
+    /// No authentication, reads up to [`MAX_READ_LEN`], payloads stalled
+    /// for [`PAYLOAD_TIMEOUT`], plaintext
+    pub fn new() -> Self {
+        Self {
+            token: None,
+            max_read_len: MAX_READ_LEN,
+            payload_timeout: PAYLOAD_TIMEOUT,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Requires `token` from every client
+    ///
+    /// A client must send [`AuthToken::prelude`] before its first command,
+    /// the connection is closed on a wrong token or any other first frame.
+    pub fn token(mut self, token: AuthToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Rejects reads longer than `max_read_len` bytes with an error
+    /// response, before the backend is locked or any buffer is allocated
+    pub fn max_read_len(mut self, max_read_len: u32) -> Self {
+        self.max_read_len = max_read_len;
+        self
+    }
+
+    /// Token clients must send, if any
+    pub fn auth_token(&self) -> Option<&AuthToken> {
+        self.token.as_ref()
+    }
+
+    /// Closes a connection whose streamed write gets no bytes for `timeout`,
+    /// so a stalled client doesn't keep the backend locked
+    pub fn payload_timeout(mut self, timeout: Duration) -> Self {
+        self.payload_timeout = timeout;
+        self
+    }
+
+    /// Runs every accepted connection through a TLS handshake with
+    /// `acceptor` before serving it, see [`load_tls_acceptor`]
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+        self
+    }
+}
+
+/// TLS acceptor with the certificate chain in the PEM file `cert` and its
+/// private key in the PEM file `key`
+#[cfg(feature = "tls")]
+pub fn load_tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    use rustls_pki_types::pem::PemObject;
+    use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+    use tokio_rustls::rustls::ServerConfig;
+
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read private key from {}", key.display()))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate or key")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Binds `addr` and serves SigmaStudio clients with `backend`
+///
+/// Only returns if binding fails, connection errors are logged and only
+/// close the connection they happened on.
+pub async fn serve(backend: Arc<Mutex<dyn Backend>>, addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind to {}", addr))?;
+    info!("Waiting for connections on {}...", addr);
+
+    serve_listener(backend, listener).await
+}
+
+/// Binds every address that can be bound, failing only if none can
+///
+/// On Linux `[::]` is dual-stack by default, so binding `0.0.0.0` on the same
+/// port after it fails and is only logged.
+pub async fn bind_all(addrs: &[SocketAddr]) -> Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                info!("Waiting for connections on {}...", addr);
+                listeners.push(listener);
+            }
+            Err(e) => error!("Failed to bind to {}: {}", addr, e),
+        }
+    }
+
+    if listeners.is_empty() {
+        bail!("Failed to bind to any address");
+    }
+
+    Ok(listeners)
+}
+
+/// Accepts connections on an already bound listener, one task per client
+pub async fn serve_listener(backend: Arc<Mutex<dyn Backend>>, listener: TcpListener) -> Result<()> {
+    serve_listener_with(backend, listener, ServerOptions::default()).await
+}
+
+/// Like `serve_listener`, with the authentication and limits of `options`
+pub async fn serve_listener_with(
+    backend: Arc<Mutex<dyn Backend>>,
+    listener: TcpListener,
+    options: ServerOptions,
+) -> Result<()> {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                info!("New connection from {}", addr);
+                let backend = backend.clone();
+                let options = options.clone();
+                // tutti i messaggi della connessione, comandi inclusi, stanno in questo span
+                let span = info_span!("connection", peer = %addr);
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = accept_connection(stream, backend, options).await {
+                            error!("Error handling connection: {}", e);
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+            }
+        }
+    }
+}
+
+/// Serves an accepted connection, after the TLS handshake if `options` has
+/// an acceptor
+async fn accept_connection(
+    stream: tokio::net::TcpStream,
+    backend: Arc<Mutex<dyn Backend>>,
+    options: ServerOptions,
+) -> Result<()> {
+    #[cfg(feature = "tls")]
+    if let Some(acceptor) = options.tls.clone() {
+        let stream = acceptor
+            .accept(stream)
+            .await
+            .context("TLS handshake failed")?;
+        debug!("TLS handshake completed");
+        return serve_connection(stream, backend, options).await;
+    }
+
+    serve_connection(stream, backend, options).await
+}
+
+/// Features of this server, answer to the capabilities command
+///
+/// The protocol ones, plus authentication when a token is required and
+/// metrics when built with them. Chip addresses are ignored, there is a
+/// single backend.
+pub fn capabilities(auth: bool) -> Capabilities {
+    Capabilities::PROTOCOL
+        .with_if(Capabilities::AUTH, auth)
+        .with_if(Capabilities::METRICS, cfg!(feature = "metrics"))
+}
+
+/// Serves a single client until it disconnects
+///
+/// Works on any byte stream, not only a [`tokio::net::TcpStream`]: an
+/// accepted connection can be wrapped first, e.g. by a TLS acceptor.
+///
+/// Responses are written by a separate task through a bounded queue, so
+/// commands keep executing while a slow client reads; only once the queue
+/// is full the connection waits for it. A client that disconnects before
+/// reading its responses just ends the connection.
+pub async fn serve_connection<S>(
+    stream: S,
+    backend: Arc<Mutex<dyn Backend>>,
+    options: ServerOptions,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let (responses, queue) = mpsc::channel(RESPONSE_QUEUE_LEN);
+
+    let writer = tokio::spawn(write_responses(writer, queue).in_current_span());
+    let result = read_commands(reader, responses, backend, options).await;
+    // the sender is gone, the writer flushes the queue and ends
+    let write_result = writer.await?;
+
+    result.and(write_result)
+}
+
+async fn write_responses<W>(mut writer: W, mut queue: mpsc::Receiver<Vec<u8>>) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(response_bytes) = queue.recv().await {
+        debug!("tx {:x?}", &response_bytes);
+        match writer.write_all(&response_bytes).await {
+            Ok(()) => {
+                #[cfg(feature = "metrics")]
+                Metrics::inc(&METRICS.bytes_out, response_bytes.len() as u64);
+            }
+            Err(e) if is_disconnect(&e) => {
+                info!("Client disconnected before reading its responses");
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+async fn read_commands<R>(
+    mut reader: R,
+    responses: mpsc::Sender<Vec<u8>>,
+    backend: Arc<Mutex<dyn Backend>>,
+    options: ServerOptions,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let ServerOptions {
+        token,
+        max_read_len,
+        payload_timeout,
+        ..
+    } = options;
+    let mut buf = vec![0u8; MAX_BUF_SIZE];
+    let mut count = 0;
+    let mut resync = Resync::default();
+    let mut framing = ConnectionFraming::default();
+    let mut authenticated = token.is_none();
+    let capabilities = capabilities(token.is_some());
+
+    loop {
+        if count == buf.len() {
+            // solo una write con checksum può non stare nel buffer
+            match checksummed_write_len(&buf[..count], &mut framing) {
+                Some(frame_len) if frame_len > count => buf.resize(frame_len, 0),
+                _ => {
+                    warn!(
+                        "Command larger than the {} byte buffer, closing connection",
+                        count
+                    );
+                    return Ok(());
+                }
+            }
+        }
+
+        let n = match reader.read(&mut buf[count..]).await {
+            Ok(n) => n,
+            Err(e) if is_disconnect(&e) => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if n == 0 {
+            break;
+        }
+        count += n;
+        #[cfg(feature = "metrics")]
+        Metrics::inc(&METRICS.bytes_in, n as u64);
+
+        debug!("rx {:x?}", &buf[..count]);
+
+        let mut processed_bytes = 0;
+        while processed_bytes < count {
+            if !authenticated {
+                match auth::parse_prelude(&buf[processed_bytes..count]) {
+                    Ok(None) => break,
+                    Ok(Some((given, len))) if token.as_ref().is_some_and(|t| t.matches(given)) => {
+                        debug!("Client authenticated");
+                        authenticated = true;
+                        processed_bytes += len;
+                        continue;
+                    }
+                    Ok(Some(_)) => {
+                        warn!("Wrong auth token, closing connection");
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        warn!("{}, closing connection", e);
+                        return Ok(());
+                    }
+                }
+            }
+
+            if let Some(header) = streamed_write(&buf[processed_bytes..count], &mut framing) {
+                // tutto quello che resta nel buffer fa parte di questa write
+                let header_len = framing.framing().header_len();
+                let payload = &buf[processed_bytes + header_len..count];
+                let Some(response) = stream_write(
+                    header,
+                    header_len,
+                    payload,
+                    &mut reader,
+                    payload_timeout,
+                    &backend,
+                    &mut resync,
+                )
+                .await?
+                else {
+                    debug!("Streamed write cut short, closing connection");
+                    return Ok(());
+                };
+                processed_bytes = count;
+
+                let response_bytes = response.to_bytes_with(framing.checksum());
+                if responses.send(response_bytes).await.is_err() {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            let (response, bytes_read) = process_command(
+                &buf[processed_bytes..count],
+                &backend,
+                &mut resync,
+                &mut framing,
+                capabilities,
+                max_read_len,
+            )
+            .await?;
+            if bytes_read == 0 {
+                // Non ci sono abbastanza dati per un comando completo
+                break;
+            }
+
+            processed_bytes += bytes_read;
+
+            let response_bytes = response.to_bytes_with(framing.checksum());
+            if !response_bytes.is_empty() && responses.send(response_bytes).await.is_err() {
+                // il writer ha già chiuso, il client se n'è andato
+                return Ok(());
+            }
+        }
+
+        // Sposta i dati non processati all'inizio del buffer
+        if processed_bytes > 0 {
+            if processed_bytes < count {
+                buf.copy_within(processed_bytes..count, 0);
+            }
+            count -= processed_bytes;
+        }
+        if buf.len() > MAX_BUF_SIZE && count <= MAX_BUF_SIZE {
+            buf.truncate(MAX_BUF_SIZE);
+            buf.shrink_to_fit();
+        }
+    }
+
+    if count > 0 {
+        debug!(
+            "Client closed the connection with {} bytes of an incomplete command",
+            count
+        );
+    }
+
+    Ok(())
+}
+
+/// Header of the write at the start of `buf` if its frame can't fit in the
+/// connection buffer, so its payload has to be streamed
+///
+/// Only without a checksum: the trailer covers the whole frame, which is
+/// buffered before being executed (see `checksummed_write_len`). A
+/// malformed header is left to `process_command` to report.
+fn streamed_write(buf: &[u8], framing: &mut ConnectionFraming) -> Option<WriteHeader> {
+    if buf.first() != Some(&CMD_WRITE) || framing.checksum() != Checksum::None {
+        return None;
+    }
+    framing
+        .parse_write_header(buf)
+        .ok()
+        .filter(|header| header.total_len as usize > MAX_BUF_SIZE)
+}
+
+/// Length of the checksummed write at the start of `buf`, trailer included,
+/// for a frame that has to be buffered whole before the trailer can be
+/// checked
+///
+/// Bounded by `MAX_DATA_LEN` plus header and trailer: a frame padded past
+/// that isn't buffered.
+fn checksummed_write_len(buf: &[u8], framing: &mut ConnectionFraming) -> Option<usize> {
+    let checksum = framing.checksum();
+    if buf.first() != Some(&CMD_WRITE) || checksum == Checksum::None {
+        return None;
+    }
+    let header = framing.parse_write_header(buf).ok()?;
+    let header_len = framing.framing().header_len();
+    let frame_len = header.total_len as usize + checksum.trailer_len();
+    (frame_len <= header_len + MAX_DATA_LEN as usize + checksum.trailer_len()).then_some(frame_len)
+}
+
+/// Executes a write whose payload starts with the `buffered` bytes after its
+/// header and continues on `reader`, handing it to [`Backend::write_stream`]
+/// as it arrives
+///
+/// The backend stays locked until the whole payload is read, like it would
+/// for the single write of a buffered frame, but no longer than `timeout`
+/// between two reads. Returns `None` if the client closes the connection or
+/// stalls before the end of the frame: unlike a buffered write, what
+/// arrived until then has already reached the backend.
+async fn stream_write<R>(
+    header: WriteHeader,
+    header_len: usize,
+    buffered: &[u8],
+    reader: &mut R,
+    timeout: Duration,
+    backend: &Arc<Mutex<dyn Backend>>,
+    resync: &mut Resync,
+) -> Result<Option<ProtocolResponse>>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let (addr, data_len, safeload) = (header.param_addr, header.data_len, header.safeload);
+    // bytes after the payload, up to total_len, are skipped like parse_command does
+    let padding = header.total_len as usize - header_len - data_len as usize;
+
+    let command = ProtocolCommand::Write {
+        header,
+        data: Vec::new(),
+    };
+    debug!("Streaming command: {:?}", command);
+    #[cfg(feature = "metrics")]
+    count_command(&command);
+    resync.check(&command)?;
+
+    let mut payload = ConnectionPayload {
+        buffered,
+        reader,
+        timeout,
+        remaining: data_len as usize,
+        closed: false,
+    };
+
+    let span = command_span(&command);
+    let start = Instant::now();
+    let result = async {
+        let mut backend = backend.lock().await;
+        #[cfg(feature = "metrics")]
+        let backend_start = Instant::now();
+        let result = if safeload != 0 {
+            // the safeload registers take a handful of words, nothing to stream
+            let mut data = vec![0; payload.remaining()];
+            match payload.read_exact(&mut data).await {
+                Ok(()) => backend.safeload_write(addr, &data).await,
+                Err(e) => Err(e),
+            }
+        } else {
+            backend.write_stream(addr, &mut payload).await
+        };
+        #[cfg(feature = "metrics")]
+        METRICS.observe_latency(backend_start.elapsed());
+        result
+    }
+    .instrument(span.clone())
+    .await;
+    span.record("elapsed_us", start.elapsed().as_micros() as u64);
+
+    // quello che il backend non ha letto va comunque tolto dalla connessione
+    if !payload.closed {
+        payload.remaining += padding;
+        if let Err(e) = payload.discard().await {
+            if !payload.closed {
+                return Err(e);
+            }
+        }
+    }
+    if payload.closed {
+        return Ok(None);
+    }
+
+    let response = match result {
+        Ok(()) => {
+            info!("write at addr 0x{:04x} size {:?} streamed", addr, data_len);
+            ProtocolResponse::Write
+        }
+        Err(e) => {
+            error!("write at addr 0x{:04x} failed: {}", addr, e);
+            #[cfg(feature = "metrics")]
+            Metrics::inc(&METRICS.errors, 1);
+            ProtocolHandler::create_error_response(format!("Write error: {}", e))
+        }
+    };
+    Ok(Some(response))
+}
+
+/// Payload of a streamed write: the bytes already in the connection buffer,
+/// then the rest read from the connection
+///
+/// `closed` once the connection ended or went `timeout` without a byte.
+struct ConnectionPayload<'a, R> {
+    buffered: &'a [u8],
+    reader: &'a mut R,
+    timeout: Duration,
+    remaining: usize,
+    closed: bool,
+}
+
+impl<R: AsyncRead + Unpin + Send> ConnectionPayload<'_, R> {
+    async fn discard(&mut self) -> Result<()> {
+        let mut scratch = [0u8; 256];
+        while self.remaining > 0 {
+            self.read_chunk(&mut scratch).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<R: AsyncRead + Unpin + Send> WriteSource for ConnectionPayload<'_, R> {
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    async fn read_chunk(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(self.remaining);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let n = if !self.buffered.is_empty() {
+            let n = len.min(self.buffered.len());
+            buf[..n].copy_from_slice(&self.buffered[..n]);
+            self.buffered = &self.buffered[n..];
+            n
+        } else {
+            let read = tokio::time::timeout(self.timeout, self.reader.read(&mut buf[..len]));
+            let n = match read.await {
+                Ok(Ok(n)) => n,
+                Ok(Err(e)) if is_disconnect(&e) => 0,
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => {
+                    // il backend è bloccato finché la write non finisce
+                    self.closed = true;
+                    warn!(
+                        "No bytes of the write for {:?}, {} missing",
+                        self.timeout, self.remaining
+                    );
+                    bail!(
+                        "Write payload stalled with {} bytes missing",
+                        self.remaining
+                    );
+                }
+            };
+            if n == 0 {
+                self.closed = true;
+                bail!(
+                    "Connection closed with {} bytes of the write missing",
+                    self.remaining
+                );
+            }
+            #[cfg(feature = "metrics")]
+            Metrics::inc(&METRICS.bytes_in, n as u64);
+            n
+        };
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+/// Runs the commands of `frame` as a client would on a new connection,
+/// returns the bytes of their responses
+///
+/// Backs `POST /raw`, to reproduce an exact byte sequence without a TCP
+/// client. Reads are capped and capabilities reported as by `options`, the
+/// token itself is checked by the caller. A frame ending in a partial or
+/// malformed command is an error.
+pub async fn run_raw_frame(
+    backend: &Arc<Mutex<dyn Backend>>,
+    frame: &[u8],
+    options: &ServerOptions,
+) -> Result<Vec<u8>> {
+    let mut resync = Resync::default();
+    let mut framing = ConnectionFraming::default();
+    let mut responses = Vec::new();
+
+    let mut offset = 0;
+    while offset < frame.len() {
+        let (response, bytes_read) = process_command(
+            &frame[offset..],
+            backend,
+            &mut resync,
+            &mut framing,
+            capabilities(options.token.is_some()),
+            options.max_read_len,
+        )
+        .await?;
+        if bytes_read == 0 {
+            bail!("Incomplete command at byte {}", offset);
+        }
+        offset += bytes_read;
+        responses.extend(response.to_bytes_with(framing.checksum()));
+    }
+
+    Ok(responses)
+}
+
+async fn process_command(
+    buf: &[u8],
+    backend: &Arc<Mutex<dyn Backend>>,
+    resync: &mut Resync,
+    framing: &mut ConnectionFraming,
+    capabilities: Capabilities,
+    max_read_len: u32,
+) -> Result<(ProtocolResponse, usize)> {
+    let parse_result = framing.parse(buf);
+
+    match parse_result {
+        Ok((command, bytes_read)) => {
+            debug!("Parsed command: {:?}", command);
+            #[cfg(feature = "metrics")]
+            count_command(&command);
+
+            // un byte sconosciuto viene saltato, si riprova dal successivo
+            resync.check(&command)?;
+            if let ProtocolCommand::Unknown(byte) = command {
+                return Ok((
+                    ProtocolHandler::create_error_response(format!(
+                        "Unknown command: 0x{:02x}",
+                        byte
+                    )),
+                    bytes_read,
+                ));
+            }
+            if let ProtocolCommand::Handshake { .. } = command {
+                // already applied by ConnectionFraming
+                return Ok((ProtocolResponse::Write, bytes_read));
+            }
+            if let ProtocolCommand::Ping = command {
+                // answered without waiting for the backend lock
+                return Ok((ProtocolResponse::Pong, bytes_read));
+            }
+            if let ProtocolCommand::Capabilities = command {
+                return Ok((ProtocolResponse::Capabilities(capabilities), bytes_read));
+            }
+            if let ProtocolCommand::Read { header } = &command {
+                // data_len viene dal client, va controllato prima di allocare la risposta
+                if let Err(e) = ProtocolHandler::check_read_len(header, max_read_len) {
+                    warn!("{}", e);
+                    #[cfg(feature = "metrics")]
+                    Metrics::inc(&METRICS.errors, 1);
+                    return Ok((
+                        ProtocolHandler::create_rejected_read_response(
+                            header.chip_addr,
+                            header.param_addr,
+                            STATUS_READ_TOO_LONG,
+                        ),
+                        bytes_read,
+                    ));
+                }
+            }
+
+            let span = command_span(&command);
+            let start = Instant::now();
+            // on its own task, so that dropping the connection can't stop a
+            // write halfway: see the cancellation notes of Backend
+            let backend = backend.clone();
+            let response = tokio::spawn(
+                async move {
+                    let mut backend = backend.lock_owned().await;
+                    #[cfg(feature = "metrics")]
+                    let backend_start = Instant::now();
+                    let response =
+                        ProtocolHandler::execute(&mut *backend, command, max_read_len).await;
+                    #[cfg(feature = "metrics")]
+                    METRICS.observe_latency(backend_start.elapsed());
+                    response
+                }
+                .instrument(span.clone()),
+            )
+            .await?;
+            span.record("elapsed_us", start.elapsed().as_micros() as u64);
+
+            #[cfg(feature = "metrics")]
+            if is_failure(&response) {
+                Metrics::inc(&METRICS.errors, 1);
+            }
+
+            Ok((response, bytes_read))
+        }
+        Err(e) if e.is::<IncompleteCommand>() => {
+            // Non ci sono abbastanza dati per un comando completo
+            Ok((ProtocolResponse::Error("Incomplete command".to_string()), 0))
+        }
+        Err(e) => {
+            // nessun modo di sapere dove inizia il comando successivo: chiudere
+            // invece di riprovare all'infinito sugli stessi byte
+            error!("Failed to parse command: {}, closing connection", e);
+            Err(e.context("Failed to parse command"))
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn count_command(command: &ProtocolCommand) {
+    Metrics::inc(&METRICS.commands, 1);
+    match command {
+        ProtocolCommand::Read { .. } => Metrics::inc(&METRICS.reads, 1),
+        ProtocolCommand::Write { .. } => Metrics::inc(&METRICS.writes, 1),
+        // the error response of a mismatch is counted once executed
+        ProtocolCommand::Handshake { .. }
+        | ProtocolCommand::ChecksumMismatch(_)
+        | ProtocolCommand::Ping
+        | ProtocolCommand::Identify
+        | ProtocolCommand::Capabilities => {}
+        ProtocolCommand::Unknown(_) => Metrics::inc(&METRICS.errors, 1),
+    }
+}
+
+/// Error responses and reads answered with a non-OK status
+#[cfg(feature = "metrics")]
+fn is_failure(response: &ProtocolResponse) -> bool {
+    match response {
+        ProtocolResponse::Read { header, .. } => header.success != crate::STATUS_OK,
+        ProtocolResponse::Write
+        | ProtocolResponse::Pong
+        | ProtocolResponse::Identify(_)
+        | ProtocolResponse::Capabilities(_) => false,
+        ProtocolResponse::Error(_) => true,
+    }
+}
+
+/// Span of a single command, child of the connection span. `elapsed_us` is
+/// recorded once the backend call completes.
+fn command_span(command: &ProtocolCommand) -> Span {
+    let (kind, addr, len) = match command {
+        ProtocolCommand::Read { header } => ("read", header.param_addr, header.data_len),
+        ProtocolCommand::Write { header, .. } => ("write", header.param_addr, header.data_len),
+        ProtocolCommand::Handshake { .. } => ("handshake", 0, 0),
+        ProtocolCommand::ChecksumMismatch(_) => ("checksum_mismatch", 0, 0),
+        ProtocolCommand::Ping => ("ping", 0, 0),
+        ProtocolCommand::Identify => ("identify", 0, 0),
+        ProtocolCommand::Capabilities => ("capabilities", 0, 0),
+        ProtocolCommand::Unknown(_) => ("unknown", 0, 0),
+    };
+    info_span!(
+        "command",
+        kind,
+        addr = format_args!("0x{:04x}", addr),
+        len,
+        elapsed_us = field::Empty
+    )
+}
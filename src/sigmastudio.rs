@@ -0,0 +1,114 @@
+use std::fmt::Write as _;
+
+use crate::backend::Backend;
+use crate::registers::{DataType, DspRegister};
+use anyhow::{Context, Result};
+
+/// Parses a JSON array of [`DspRegister`], as the web UI describes them
+pub fn parse_registers(json: &str) -> Result<Vec<DspRegister>> {
+    serde_json::from_str(json).context("Invalid register list")
+}
+
+/// Reads every register from `backend` and formats them with [`format_params`]
+pub async fn export_params<B: Backend + ?Sized>(
+    backend: &mut B,
+    registers: &[DspRegister],
+) -> Result<String> {
+    let mut values = Vec::with_capacity(registers.len());
+    for register in registers {
+        let data = backend
+            .read(register.address, register.byte_len() as u32)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to read {} at 0x{:04x}",
+                    register.name, register.address
+                )
+            })?;
+        values.push((register.clone(), data));
+    }
+    Ok(format_params(&values))
+}
+
+/// Registers and their bytes in SigmaStudio's `.params` layout
+///
+/// One block per register, separated by a blank line, as SigmaStudio writes
+/// it with "Export System Files" and reads it back:
+///
+/// ```text
+/// Cell Name         = Gain
+/// Parameter Name    = Gain
+/// Parameter Address = 67
+/// Parameter Value   = 1.000000
+/// Parameter Data    :
+/// 0x01, 0x00, 0x00, 0x00,
+/// ```
+///
+/// The address is decimal. The value is decoded with the data type of the
+/// register, raw registers as 8.24 like parameter RAM; the data line has
+/// every byte as read.
+pub fn format_params(values: &[(DspRegister, Vec<u8>)]) -> String {
+    let mut params = String::new();
+    for (register, data) in values {
+        let _ = writeln!(params, "Cell Name         = {}", register.name);
+        let _ = writeln!(params, "Parameter Name    = {}", register.name);
+        let _ = writeln!(params, "Parameter Address = {}", register.address);
+        let value = match register.data_type {
+            DataType::Raw { .. } => DataType::Int8_24.bytes_to_value(data),
+            _ => register.bytes_to_value(data),
+        };
+        let _ = writeln!(params, "Parameter Value   = {:.6}", value);
+        let _ = writeln!(params, "Parameter Data    :");
+        let bytes: Vec<_> = data.iter().map(|byte| format!("0x{:02X},", byte)).collect();
+        let _ = writeln!(params, "{}", bytes.join(" "));
+        params.push('\n');
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    #[tokio::test]
+    async fn test_export_params() {
+        // la lista della web UI, con campi che l'export non usa
+        let registers = parse_registers(
+            r#"[
+                {"name": "Gain", "address": 67, "data_type": "Int8_24", "min": -80, "max": 0,
+                 "unit": "Decibel", "group": "Output"},
+                {"name": "Delay", "address": 68, "data_type": {"Raw": {"len": 2}}, "min": 0, "max": 0},
+                {"name": "Acc", "address": 80, "data_type": "Int64_0", "words": 2, "min": 0, "max": 0}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(registers[0].byte_len(), 4);
+        assert_eq!(registers[1].byte_len(), 2);
+        assert_eq!(registers[2].byte_len(), 8);
+
+        let mut backend = MemoryBackend::new();
+        backend.write(67, &[0x00, 0x80, 0x00, 0x00]).await.unwrap();
+        backend.write(68, &[0xff, 0x00]).await.unwrap();
+
+        let params = export_params(&mut backend, &registers).await.unwrap();
+        let blocks: Vec<_> = params.split("\n\n").collect();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[3], "");
+        assert_eq!(
+            blocks[0],
+            "Cell Name         = Gain\n\
+             Parameter Name    = Gain\n\
+             Parameter Address = 67\n\
+             Parameter Value   = 0.500000\n\
+             Parameter Data    :\n\
+             0x00, 0x80, 0x00, 0x00,"
+        );
+        // un registro corto viene esteso col segno
+        assert!(blocks[1].contains("Parameter Value   = -0.000015\n"));
+        assert!(blocks[1].ends_with("0xFF, 0x00,"));
+        assert!(blocks[2].ends_with(&["0x00,"; 8].join(" ")));
+
+        assert!(parse_registers(r#"[{"name": "Gain", "address": 67}]"#).is_err());
+    }
+}
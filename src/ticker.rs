@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+/// Fixed-rate schedule with absolute deadlines
+///
+/// Tick `n` is due at `start + n * interval`, so the time spent on a tick
+/// doesn't push the following ones back and the ticks stay evenly spaced.
+/// Ticks whose deadline passed while the previous one was running are
+/// skipped, not run late in a burst: a gap in the tick numbers tells that
+/// the work doesn't fit in `interval`.
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    start: Instant,
+    interval: Duration,
+    next: u64,
+}
+
+impl Ticker {
+    /// Tick 0 is due at `start`. Panics if `interval` is zero.
+    pub fn new(start: Instant, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "Ticker interval must not be zero");
+        Self {
+            start,
+            interval,
+            next: 0,
+        }
+    }
+
+    /// Number of the next tick not yet past at `now`, and how long to wait
+    /// for its deadline
+    pub fn next_tick(&mut self, now: Instant) -> (u64, Duration) {
+        let elapsed = now.saturating_duration_since(self.start);
+        // il primo tick la cui scadenza non è ancora passata
+        let due = elapsed.as_nanos().div_ceil(self.interval.as_nanos()) as u64;
+        let tick = self.next.max(due);
+        self.next = tick + 1;
+
+        (tick, self.deadline(tick).saturating_duration_since(now))
+    }
+
+    pub fn deadline(&self, tick: u64) -> Instant {
+        self.start + self.offset(tick)
+    }
+
+    /// Time of `tick` from the start, the timestamp of its samples
+    pub fn offset(&self, tick: u64) -> Duration {
+        self.interval
+            .saturating_mul(u32::try_from(tick).unwrap_or(u32::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticker() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut ticker = Ticker::new(start, ms(100));
+
+        assert_eq!(ticker.next_tick(start), (0, ms(0)));
+        // a read of 30 ms doesn't delay the next tick
+        assert_eq!(ticker.next_tick(start + ms(30)), (1, ms(70)));
+        // woken up a bit late, the following deadline doesn't move
+        assert_eq!(ticker.next_tick(start + ms(112)), (2, ms(88)));
+        // exactly on the deadline
+        assert_eq!(ticker.next_tick(start + ms(300)), (3, ms(0)));
+        // a read of 250 ms skips the ticks that went by
+        assert_eq!(ticker.next_tick(start + ms(550)), (6, ms(50)));
+        // a clock read before the deadline of the last tick still moves on
+        assert_eq!(ticker.next_tick(start + ms(590)), (7, ms(110)));
+
+        assert_eq!(ticker.offset(7), ms(700));
+        assert_eq!(ticker.deadline(7), start + ms(700));
+    }
+}
@@ -1,10 +1,23 @@
 use log::{error, info};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
-use web_sys::{Document, Element, HtmlElement, HtmlInputElement, Window};
+use web_sys::{Document, Element, HtmlElement, HtmlInputElement, WebSocket, Window};
 
-use crate::reg_io::{read_registers, write_registers};
+use crate::reg_io::{
+    connection_state, identify, is_frozen_error, open_register_stream, parse_hex_data,
+    probe_device, read_config, read_registers, read_registers_batch, set_connection_listener,
+    set_frozen, set_latency_listener, write_registers, write_registers_batch, ConnectionState,
+    LatencyStats,
+};
 
 mod reg_io;
+mod scan;
+mod state;
+
+// i registri e i loro formati sono nella libreria, condivisi con i server
+use sigma_tcp_rs::registers::format_value;
+pub use sigma_tcp_rs::registers::{DataType, DspRegister, Endianness, MeasurementUnit};
 
 #[wasm_bindgen(start)]
 pub fn start() {
@@ -12,404 +25,1672 @@ pub fn start() {
     info!("DSP Control WASM module initialized");
 }
 
-// https://ez.analog.com/dsp/sigmadsp/w/documents/5169/what-are-the-number-formats-for-sigmadsp
-// pag 81 of the datasheet
-#[derive(Clone, Debug)]
-pub enum DataType {
-    //Int5_23, // 5.23 integer fixed point decimal format, this is used for audio samples, 4 bytes
-    Int8_24,
-    Int28_0, // 28.0 bit integer for dsp, 4 bytes
-    Int32_0,
-    //Int5_19, // 5.19 hardware readback format, 3 bytes
-    //Double,
-    //Float,
-}
-
-impl DataType {
-    #[deprecated]
-    pub fn get_size(&self) -> u16 {
-        match self {
-            //DataType::Int5_23 => 4,
-            DataType::Int8_24 => 4,
-            DataType::Int28_0 => 4,
-            DataType::Int32_0 => 4,
-            //DataType::Int5_19 => 3,
-            //DataType::Double => 8,
-            //DataType::Float => 4,
-        }
-    }
-
-    pub fn to_string(&self) -> String {
-        match self {
-            DataType::Int8_24 => "Int8.24".to_string(),
-            DataType::Int28_0 => "Int28.0".to_string(),
-            DataType::Int32_0 => "Int32.0".to_string(),
-        }
-    }
-
-    pub fn value_to_bytes(&self, value: f64) -> Vec<u8> {
-        match self {
-            DataType::Int8_24 => {
-                // For 8.24 format, multiply by 2^24 to get the fixed point representation
-                let scaled_value = value * 16777216.0; // 2^24
-                let int_value = scaled_value as i32;
-
-                int_value.to_be_bytes().to_vec()
-            }
-            DataType::Int32_0 => {
-                let int_value = value as i32;
-
-                int_value.to_be_bytes().to_vec()
-            }
-            DataType::Int28_0 => {
-                let int_value = value as i32;
-
-                int_value.to_be_bytes().to_vec()
-            }
-        }
-    }
-
-    pub fn bytes_to_value(&self, bytes: &[u8]) -> f64 {
-        match self {
-            DataType::Int8_24 => {
-                let int_value = i32::from_be_bytes(bytes.try_into().unwrap());
-                int_value as f64 / 16777216.0
-            }
-            DataType::Int32_0 => {
-                let int_value = i32::from_be_bytes(bytes.try_into().unwrap());
-                int_value as f64
-            }
-            DataType::Int28_0 => {
-                let int_value = i32::from_be_bytes(bytes.try_into().unwrap());
-                int_value as f64
-            }
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub enum MeasurementUnit {
-    Decibel,
-    None,
-}
-
-impl MeasurementUnit {
-    pub fn to_string(&self) -> String {
-        match self {
-            MeasurementUnit::Decibel => "dB".to_string(),
-            MeasurementUnit::None => "".to_string(),
-        }
-    }
-}
-
-/// Rappresenta un registro DSP
-#[derive(Clone)]
-pub struct DspRegister {
-    pub name: String,
-    pub address: u16,
-    pub data_type: DataType,
-    pub min: i32,
-    pub max: i32,
-    pub read_only: bool,
-    pub unit: MeasurementUnit,
+/// Intervallo di indirizzi (estremi inclusi) con il formato dei suoi valori
+struct MemoryRegion {
+    start: u16,
+    end: u16,
+    data_type: DataType,
 }
 
-impl DspRegister {
-    pub fn unit_to_raw_value(&self, value: f64) -> f64 {
-        match self.unit {
-            // this is not consistent? from the gain slider vs the level meter
-            MeasurementUnit::Decibel => {
-                // Convert decibels to linear scale
-                let linear_value = 10.0f64.powf(value / 20.0);
-                linear_value
-            }
-            MeasurementUnit::None => value,
-        }
-    }
-
-    pub fn raw_value_to_unit(&self, value: f64) -> f64 {
-        match self.unit {
-            MeasurementUnit::Decibel => {
-                // Convert linear scale to decibels
-                let decibel_value = 10.0 * value.log10();
-                decibel_value
-            }
-            MeasurementUnit::None => value,
-        }
-    }
+/// Mappa di memoria dell'ADAU1452/1466, da adattare per altre parti
+const ADAU1452_REGIONS: &[MemoryRegion] = &[
+    // parameter RAM (DM0), coefficienti 8.24
+    MemoryRegion {
+        start: 0x0000,
+        end: 0x5FFF,
+        data_type: DataType::Int8_24,
+    },
+    // safeload: 5 parole di dati, poi indirizzo e numero di parole
+    MemoryRegion {
+        start: 0x6000,
+        end: 0x6004,
+        data_type: DataType::Int8_24,
+    },
+    MemoryRegion {
+        start: 0x6005,
+        end: 0x6007,
+        data_type: DataType::Int32_0,
+    },
+    // data RAM (DM1), campioni audio 5.23
+    MemoryRegion {
+        start: 0x6008,
+        end: 0xBFFF,
+        data_type: DataType::Int5_23,
+    },
+    // program RAM, istruzioni da 32 bit
+    MemoryRegion {
+        start: 0xC000,
+        end: 0xDFFF,
+        data_type: DataType::Raw { len: 4 },
+    },
+    // control registers, 16 bit
+    MemoryRegion {
+        start: 0xF000,
+        end: 0xFFFF,
+        data_type: DataType::Raw { len: 2 },
+    },
+];
+
+/// Formato predefinito per un registro aggiunto a mano all'indirizzo `addr`
+///
+/// Addresses outside the known regions are shown as raw 4-byte words.
+pub fn address_to_default_datatype(addr: u16) -> DataType {
+    ADAU1452_REGIONS
+        .iter()
+        .find(|region| (region.start..=region.end).contains(&addr))
+        .map(|region| region.data_type.clone())
+        .unwrap_or(DataType::Raw { len: 4 })
 }
 
 /// Configurazione dei registri DSP
-
+///
+/// Builds the list from scratch, use [`registers`] for the shared copy.
 fn get_dsp_registers() -> Vec<DspRegister> {
-if false {
     vec![
-        DspRegister {
-            name: "Gain".to_string(),
-            address: 0x007E,
-            data_type: DataType::Int8_24,
-            //min: 0,
-            //max: 16777216,
-            min: -80,
-            max: 10,
-            read_only: false,
-            unit: MeasurementUnit::Decibel,
-        },
-        DspRegister {
-            name: "Signal Level - Input".to_string(),
-            address: 115,
-            data_type: DataType::Int8_24,
-            //min: 0,
-            //max: 1 << 30,
-            min: 0,
-            max: 100,
-            read_only: true,
-            unit: MeasurementUnit::Decibel,
-        },
-        DspRegister {
-            name: "Signal Level - Aux ADC".to_string(),
-            address: 87,
-            data_type: DataType::Int32_0,
-            min: 0,
-            max: 268435456,
-            read_only: true,
-            unit: MeasurementUnit::None,
-        },
-    ];
-}
-
-
-    vec![
-
         DspRegister {
             name: "Signal Level - Source".to_string(),
             address: 61,
             data_type: DataType::Int8_24,
+            len: None,
+            words: 1,
             //min: 0,
             //max: 1 << 30,
             min: -96,
             max: 0,
             read_only: true,
+            confirm: false,
             unit: MeasurementUnit::Decibel,
+            step: 1.0,
+            precision: None,
+            endianness: Endianness::Big,
+            deadband: Some(0.5),
+            group: None,
+            decibel: None,
         },
         DspRegister {
             name: "Gain".to_string(),
             address: 0x0043,
             data_type: DataType::Int8_24,
+            len: None,
+            words: 1,
             //min: 0,
             //max: 16777216,
             min: -80,
             max: 0,
             read_only: false,
+            confirm: false,
             unit: MeasurementUnit::Decibel,
+            step: 0.1,
+            precision: None,
+            endianness: Endianness::Big,
+            deadband: None,
+            group: None,
+            decibel: None,
         },
         DspRegister {
             name: "Signal Level - Dest".to_string(),
             address: 79,
             data_type: DataType::Int8_24,
+            len: None,
+            words: 1,
             //min: 0,
             //max: 1 << 30,
             min: -96,
             max: 0,
             read_only: true,
+            confirm: false,
             unit: MeasurementUnit::Decibel,
+            step: 1.0,
+            precision: None,
+            endianness: Endianness::Big,
+            deadband: Some(0.5),
+            group: None,
+            decibel: None,
         },
         DspRegister {
             name: "Signal Level - Aux ADC".to_string(),
             address: 41,
             data_type: DataType::Int32_0,
+            len: None,
+            words: 1,
             min: 0,
             max: 268435456,
             read_only: true,
+            confirm: false,
             unit: MeasurementUnit::None,
+            step: 1.0,
+            precision: None,
+            endianness: Endianness::Big,
+            deadband: None,
+            group: None,
+            decibel: None,
         },
         DspRegister {
             name: "Signal Level - MP7".to_string(),
             address: 65,
             data_type: DataType::Int32_0,
+            len: None,
+            words: 1,
             min: 0,
             max: 268435456,
             read_only: true,
+            confirm: false,
             unit: MeasurementUnit::None,
+            step: 1.0,
+            precision: None,
+            endianness: Endianness::Big,
+            deadband: None,
+            group: None,
+            decibel: None,
         },
     ]
 }
 
+/// Registri configurati, costruiti una volta sola e condivisi
+fn registers() -> Rc<[DspRegister]> {
+    state::with_state(|state| state.registers())
+}
+
 fn get_dsp_register_by_address(address: u16) -> Option<DspRegister> {
-    get_dsp_registers()
+    registers().iter().find(|r| r.address == address).cloned()
+}
+
+/// Formatta un valore come stringa esadecimale
+pub fn format_hex_bytes(bytes: &[u8]) -> String {
+    let mut hex_string = String::new();
+
+    // Ensure we display the correct number of bytes based on the size
+    for (i, byte) in bytes.iter().enumerate() {
+        hex_string.push_str(&format!("{:02X}", byte));
+        if i < bytes.len() - 1 {
+            hex_string.push(' ');
+        }
+    }
+
+    hex_string
+}
+
+/// Nome del registro con l'unità, per aria-label
+fn aria_label(register: &DspRegister) -> String {
+    match register.unit.to_string().as_str() {
+        "" => register.name.clone(),
+        unit => format!("{} ({})", register.name, unit),
+    }
+}
+
+/// Valore formattato con l'unità, per aria-valuetext
+fn aria_value_text(register: &DspRegister, value: f64) -> String {
+    let formatted = register.format_value(value);
+    match register.unit.to_string().as_str() {
+        "" => formatted,
+        unit => format!("{} {}", formatted, unit),
+    }
+}
+
+/// Aggiorna l'interfaccia utente per un registro
+pub fn update_ui_for_register(register: &DspRegister, value: f64) -> Result<(), JsValue> {
+    let document = get_document()?;
+
+    // Gli indicatori in sola lettura si muovono solo oltre la deadband,
+    // i registri di controllo mostrano sempre il valore scritto
+    if register.read_only {
+        let now = js_sys::Date::now();
+        let range = (register.max - register.min) as f64;
+        let (hold_ms, decay) = unsafe { (PEAK_HOLD_MS, PEAK_DECAY) };
+        let deadband = register.deadband.unwrap_or(0.0);
+        let (moved, peak) = state::update_state(|state| {
+            let meter = state.meter(register.address);
+            let moved = meter.update(value, now, deadband, hold_ms, decay, range);
+            (moved, meter.peak)
+        });
+        set_slider_percentage(&document, register, "--slider-peak", peak)?;
+        if matches!(register.unit, MeasurementUnit::Decibel) {
+            show_level_stats(&document, register, value, peak)?;
+        }
+        if !moved {
+            show_staleness(&document, register.address, now)?;
+            return Ok(());
+        }
+    }
+
+    // Aggiorna il valore decimale
+    let value_text = aria_value_text(register, value);
+
+    if let Some(value_box) = document.get_element_by_id(&format!("value-{}", register.address)) {
+        value_box.set_text_content(Some(&register.format_value(value)));
+        value_box.set_attribute("aria-valuenow", &value.to_string())?;
+        value_box.set_attribute("aria-valuetext", &value_text)?;
+    }
+
+    // Aggiorna il valore esadecimale, a meno che l'utente ci stia scrivendo
+    if let Some(hex_value) = document.get_element_by_id(&format!("hex-value-{}", register.address))
+    {
+        if !is_focused(&document, &hex_value) {
+            let raw_value = register.unit_to_raw_value(value);
+            let hex_string = format_hex_bytes(&register.value_to_bytes(raw_value));
+            hex_value.set_text_content(Some(&hex_string));
+        }
+    }
+
+    // Aggiorna il campo numerico, a meno che l'utente ci stia scrivendo
+    if let Some(number_element) =
+        document.get_element_by_id(&format!("number-{}", register.address))
+    {
+        if !is_focused(&document, &number_element) {
+            let number_input = number_element.dyn_into::<HtmlInputElement>()?;
+            number_input.set_value(&register.format_value(value));
+        }
+    }
+
+    // Aggiorna il valore dello slider
+    if let Some(slider_element) =
+        document.get_element_by_id(&format!("slider-{}", register.address))
+    {
+        let slider = slider_element.dyn_into::<HtmlInputElement>()?;
+        slider.set_value(&value.to_string());
+        slider.set_attribute("aria-valuetext", &value_text)?;
+
+        // For readonly sliders, set a CSS custom property to visualize the value
+        if slider.disabled() {
+            set_slider_percentage(&document, register, "--slider-value", value)?;
+        }
+    }
+
+    show_staleness(&document, register.address, js_sys::Date::now())?;
+
+    Ok(())
+}
+
+// Tempo per cui il picco degli indicatori resta fermo prima di scendere, in ms
+static mut PEAK_HOLD_MS: f64 = 1500.0;
+
+/// Cambia il tempo di hold del picco degli indicatori (default 1500 ms), chiamabile da JS
+#[wasm_bindgen]
+pub fn set_peak_hold(hold_ms: f64) {
+    unsafe {
+        PEAK_HOLD_MS = hold_ms.max(0.0);
+    }
+}
+
+// Frazione della scala di cui il picco scende al secondo, finito il tempo di hold
+static mut PEAK_DECAY: f64 = 0.25;
+
+/// Cambia la velocità di discesa del picco (default 0.25 della scala al secondo), chiamabile da JS
+#[wasm_bindgen]
+pub fn set_peak_decay(fraction_per_second: f64) {
+    unsafe {
+        PEAK_DECAY = fraction_per_second.max(0.0);
+    }
+}
+
+// Letture su cui si calcolano minimo e RMS degli indicatori in dB
+static mut LEVEL_STATS_WINDOW: usize = 20;
+
+/// Cambia il numero di letture della media RMS (default 20), chiamabile da JS
+#[wasm_bindgen]
+pub fn set_level_stats_window(samples: usize) {
+    unsafe {
+        LEVEL_STATS_WINDOW = samples.max(1);
+    }
+}
+
+/// Aggiunge una lettura alle statistiche di un indicatore in dB e le mostra
+/// accanto al valore
+fn show_level_stats(
+    document: &Document,
+    register: &DspRegister,
+    value: f64,
+    peak: f64,
+) -> Result<(), JsValue> {
+    let floor = register.min as f64;
+    let window = unsafe { LEVEL_STATS_WINDOW };
+    let (min, rms) = state::update_state(|state| {
+        let stats = state.level_stats(register.address);
+        stats.push(value, floor, window);
+        (stats.min(), stats.rms())
+    });
+
+    let Some(element) = document.get_element_by_id(&format!("stats-{}", register.address)) else {
+        return Ok(());
+    };
+    // sotto il fondo scala c'è solo silenzio, anche il picco si ferma lì
+    let format = |value: f64| register.format_value(value.max(floor));
+    element.set_text_content(Some(&format!(
+        "min {} · pk {} · rms {}",
+        min.map_or("-".to_string(), format),
+        format(peak),
+        rms.map_or("-".to_string(), format)
+    )));
+    Ok(())
+}
+
+/// Imposta `property` dello slider del registro alla posizione di `value` sulla scala
+fn set_slider_percentage(
+    document: &Document,
+    register: &DspRegister,
+    property: &str,
+    value: f64,
+) -> Result<(), JsValue> {
+    let Some(slider) = document.get_element_by_id(&format!("slider-{}", register.address)) else {
+        return Ok(());
+    };
+    let min = register.min as f64;
+    let max = register.max as f64;
+
+    // Calculate percentage (clamped between 0-100%)
+    let percentage = if max > min {
+        ((value - min) / (max - min) * 100.0).clamp(0.0, 100.0)
+    } else {
+        0.0
+    };
+
+    slider
+        .dyn_into::<HtmlElement>()?
+        .style()
+        .set_property(property, &format!("{}%", percentage))
+}
+
+/// Aggiorna l'interfaccia utente per un registro Raw, mostrando solo i bytes
+pub fn update_ui_for_raw_register(register: &DspRegister, bytes: &[u8]) -> Result<(), JsValue> {
+    let document = get_document()?;
+
+    if let Some(value_box) = document.get_element_by_id(&format!("value-{}", register.address)) {
+        value_box.set_text_content(Some("-"));
+    }
+
+    if let Some(hex_value) = document.get_element_by_id(&format!("hex-value-{}", register.address))
+    {
+        if !is_focused(&document, &hex_value) {
+            hex_value.set_text_content(Some(&format_hex_bytes(bytes)));
+        }
+    }
+
+    show_staleness(&document, register.address, js_sys::Date::now())?;
+
+    Ok(())
+}
+
+fn is_focused(document: &Document, element: &Element) -> bool {
+    document
+        .active_element()
+        .is_some_and(|active| active == *element)
+}
+
+/// Bytes scritti a mano nel box esadecimale, es. "00 80 00 00" o "00800000"
+///
+/// Whitespace is ignored; the byte count must match the register length.
+fn parse_hex_input(text: &str, len: usize) -> Result<Vec<u8>, String> {
+    let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = parse_hex_data(&compact)?;
+    if bytes.len() != len {
+        return Err(format!("Expected {} bytes, got {}", len, bytes.len()));
+    }
+    Ok(bytes)
+}
+
+/// Inizializza l'interfaccia utente
+pub fn init_ui() -> Result<(), JsValue> {
+    let document = get_document()?;
+
+    // Ottieni il contenitore dei controlli
+    let controls_container = document
+        .get_element_by_id("controlsContainer")
+        .ok_or_else(|| JsValue::from_str("Controls container not found"))?;
+
+    // Crea elementi di controllo per ogni registro, in sezioni se ci sono gruppi
+    let registers = registers();
+    let groups = group_registers(&registers);
+    if groups.iter().all(|(group, _)| group.is_none()) {
+        for register in registers.iter() {
+            let control_item = create_control_item(&document, register)?;
+            controls_container.append_child(&control_item)?;
+        }
+    } else {
+        let collapsed = collapsed_groups();
+        for (index, (group, members)) in groups.into_iter().enumerate() {
+            let name = group.unwrap_or(DEFAULT_GROUP);
+            let (section, body) = create_group_section(&document, name, !collapsed.contains(name))?;
+            section.set_id(&format!("group-{}", index));
+            for register in members {
+                let control_item = create_control_item(&document, register)?;
+                body.append_child(&control_item)?;
+            }
+            controls_container.append_child(&section)?;
+        }
+    }
+
+    init_search_box(&document)?;
+    init_theme_toggle(&document)?;
+    init_auth_token(&document)?;
+    init_connection_ui(&document)?;
+    init_write_all_button(&document)?;
+    init_sync_now_button(&document)?;
+    init_freeze_toggle(&document)?;
+    init_verify_toggle(&document)?;
+    scan::init_scan_tool(&document)?;
+    init_staleness_timer()?;
+    check_device_part();
+    check_capabilities();
+
+    set_latency_listener(|stats| {
+        if let Ok(document) = get_document() {
+            if let Some(latency) = document.get_element_by_id("latency") {
+                latency.set_text_content(Some(&format_latency(stats)));
+            }
+        }
+    });
+
+    // Configura il toggle di auto-refresh
+    if let Some(auto_refresh_toggle) = document.get_element_by_id("autoRefreshToggle") {
+        let toggle = auto_refresh_toggle.dyn_into::<HtmlInputElement>()?;
+
+        let on_change = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            if let Ok(document) = get_document() {
+                if let Some(toggle_element) = document.get_element_by_id("autoRefreshToggle") {
+                    if let Ok(toggle) = toggle_element.dyn_into::<HtmlInputElement>() {
+                        if toggle.checked() {
+                            let _ = start_auto_refresh();
+                            let _ = set_status("Auto refresh enabled", false);
+                        } else {
+                            let _ = stop_auto_refresh();
+                            let _ = set_status("Auto refresh disabled", false);
+                        }
+                    }
+                }
+            }
+        }) as Box<dyn FnMut(_)>);
+
+        toggle.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+        on_change.forget();
+    }
+
+    Ok(())
+}
+
+// Dopo una scrittura il refresh non aggiorna il registro per questo tempo, in ms
+const WRITE_SUPPRESS_MS: f64 = 300.0;
+
+/// Ricorda il valore scritto, per annullare una modifica; il refresh salta il registro
+/// per WRITE_SUPPRESS_MS
+fn remember_write(address: u16, value: f64) {
+    let now = js_sys::Date::now();
+    state::update_state(|state| state.record_write(address, value, now));
+}
+
+/// True se una lettura del registro potrebbe precedere l'ultima scrittura
+fn write_pending(address: u16) -> bool {
+    let now = js_sys::Date::now();
+    state::with_state(|state| state.recently_written(address, now, WRITE_SUPPRESS_MS))
+}
+
+/// Ultimo valore noto di un registro, 0 (il valore iniziale degli slider) se mai letto
+fn last_value(address: u16) -> f64 {
+    state::with_state(|state| state.register_state(address).value.unwrap_or(0.0))
+}
+
+// Età oltre la quale il valore letto di un registro viene segnato come vecchio, in ms
+const STALE_AFTER_MS: f64 = 2000.0;
+
+/// Età di un valore letto `age_ms` fa, None finché non è vecchio
+fn staleness_text(age_ms: f64) -> Option<String> {
+    if age_ms < STALE_AFTER_MS {
+        return None;
+    }
+    let seconds = (age_ms / 1000.0).floor() as u64;
+    Some(if seconds < 60 {
+        format!("{}s ago", seconds)
+    } else {
+        format!("{}m ago", seconds / 60)
+    })
+}
+
+/// Attenua il valore di un registro e ne mostra l'età se l'ultima lettura è vecchia
+///
+/// Only registers with an age element (the read-only ones) are marked.
+fn show_staleness(document: &Document, address: u16, now: f64) -> Result<(), JsValue> {
+    let Some(age) = document.get_element_by_id(&format!("age-{}", address)) else {
+        return Ok(());
+    };
+    let text = state::with_state(|state| {
+        let read_at = state.register_state(address).read_at?;
+        staleness_text(now - read_at)
+    });
+
+    age.set_text_content(Some(text.as_deref().unwrap_or("")));
+    if let Some(value_box) = document.get_element_by_id(&format!("value-{}", address)) {
+        value_box
+            .class_list()
+            .toggle_with_force("dsp-control__value-box--stale", text.is_some())?;
+    }
+    Ok(())
+}
+
+/// Aggiorna ogni secondo l'età dei valori, anche quando le letture falliscono
+fn init_staleness_timer() -> Result<(), JsValue> {
+    let window = get_window()?;
+
+    let callback = Closure::wrap(Box::new(move || {
+        let Ok(document) = get_document() else {
+            return;
+        };
+        let now = js_sys::Date::now();
+        let addresses = state::with_state(|state| state.read_addresses());
+        for address in addresses {
+            let _ = show_staleness(&document, address, now);
+        }
+    }) as Box<dyn FnMut()>);
+
+    window.set_interval_with_callback_and_timeout_and_arguments(
+        callback.as_ref().unchecked_ref(),
+        1000,
+        &js_sys::Array::new(),
+    )?;
+    callback.forget();
+
+    Ok(())
+}
+
+/// Chiede conferma prima di scrivere un registro marcato `confirm`.
+/// Se l'utente annulla, l'UI torna all'ultimo valore noto e viene restituito false.
+fn confirm_write(register: &DspRegister, value: f64) -> bool {
+    confirm_write_text(register, &aria_value_text(register, value))
+}
+
+/// Come [`confirm_write`], con il valore già formattato (es. bytes esadecimali)
+fn confirm_write_text(register: &DspRegister, value_text: &str) -> bool {
+    if !register.confirm {
+        return true;
+    }
+
+    let message = format!(
+        "Write {} to {} (0x{:02X})?",
+        value_text, register.name, register.address
+    );
+    let confirmed = web_sys::window()
+        .and_then(|window| window.confirm_with_message(&message).ok())
+        .unwrap_or(false);
+
+    if !confirmed {
+        if register.data_type.is_numeric() {
+            let _ = update_ui_for_register(register, last_value(register.address));
+        }
+        let _ = set_status(&format!("Write to {} cancelled", register.name), false);
+    }
+    confirmed
+}
+
+// Le scritture sono bloccate sul device, vedi /config?freeze=1
+static mut FROZEN: bool = false;
+
+/// Collega il pulsante "Freeze" del template e legge lo stato iniziale dal device
+fn init_freeze_toggle(document: &Document) -> Result<(), JsValue> {
+    let Some(toggle) = document.get_element_by_id("freezeToggle") else {
+        return Ok(());
+    };
+    let toggle = toggle.dyn_into::<HtmlElement>()?;
+
+    let on_click = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        let frozen = unsafe { !FROZEN };
+        wasm_bindgen_futures::spawn_local(async move {
+            match set_frozen(frozen).await {
+                Ok(config) => {
+                    let _ = apply_frozen(config.frozen);
+                    let message = if config.frozen {
+                        "Device frozen, writes are disabled"
+                    } else {
+                        "Device unfrozen, writes are enabled"
+                    };
+                    let _ = set_status(message, false);
+                }
+                Err(e) => {
+                    let error_msg = e.as_string().unwrap_or_else(|| "Unknown error".to_string());
+                    let _ = set_status(&format!("Failed to change freeze: {}", error_msg), true);
+                }
+            }
+        });
+    }) as Box<dyn FnMut(_)>);
+
+    toggle.set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+
+    wasm_bindgen_futures::spawn_local(async {
+        match read_config().await {
+            Ok(config) => {
+                let _ = apply_frozen(config.frozen);
+            }
+            Err(e) => error!("Failed to read the device config: {:?}", e),
+        }
+    });
+
+    Ok(())
+}
+
+// Parti per cui sono scritti i formati dei registri configurati
+const SUPPORTED_PARTS: &[&str] = &["ADAU1452", "ADAU1466"];
+
+/// Avviso se la parte rilevata non è una di quelle dei registri configurati
+fn part_mismatch_warning(part: &str) -> Option<String> {
+    if SUPPORTED_PARTS
         .iter()
-        .find(|r| r.address == address)
-        .cloned()
+        .any(|supported| supported.eq_ignore_ascii_case(part))
+    {
+        return None;
+    }
+    Some(format!(
+        "Connected DSP is {}, the register formats are for {}: values may be wrong",
+        part,
+        SUPPORTED_PARTS.join("/")
+    ))
+}
+
+/// Controlla che il DSP collegato sia quello per cui sono configurati i registri
+fn check_device_part() {
+    wasm_bindgen_futures::spawn_local(async {
+        match identify().await {
+            Ok(identity) => match identity.part {
+                Some(part) => match part_mismatch_warning(&part) {
+                    Some(warning) => {
+                        let _ = set_status(&warning, true);
+                    }
+                    None => info!("Connected to a {}", part),
+                },
+                None => info!("Device part unknown, id {:?}", identity.id),
+            },
+            Err(e) => error!("Failed to identify the device: {:?}", e),
+        }
+    });
 }
 
-/// Converte un valore in una rappresentazione di byte
-#[deprecated]
-pub fn value_to_bytes(value: i32, size: u16) -> Vec<u8> {
-    let mut raw_value = value;
+/// Chiede le capabilities al device e adatta il client
+///
+/// Firmware without /capabilities is left alone, batch writes and the
+/// stream fall back when they fail.
+fn check_capabilities() {
+    wasm_bindgen_futures::spawn_local(async {
+        let capabilities = match reg_io::capabilities().await {
+            Ok(Some(capabilities)) => capabilities,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to get the device capabilities: {:?}", e);
+                return;
+            }
+        };
+        info!("Device capabilities: {:?}", capabilities.capabilities);
 
-    // Gestisci i numeri negativi (converti in complemento a due)
-    if raw_value < 0 {
-        // Calcola il valore massimo per la larghezza di bit data (size * 8 bit)
-        let max_val = 1 << (size * 8);
-        raw_value = max_val + raw_value;
+        reg_io::set_batch_write_available(capabilities.has("batch"));
+        unsafe {
+            STREAM_AVAILABLE = capabilities.has("stream");
+        }
+        if capabilities.has("auth") && !reg_io::has_auth_token() {
+            let _ = set_status("The device requires an auth token", true);
+        }
+    });
+}
+
+/// Mostra lo stato di blocco e abilita o disabilita i controlli dei registri scrivibili
+fn apply_frozen(frozen: bool) -> Result<(), JsValue> {
+    unsafe {
+        FROZEN = frozen;
     }
+    let document = get_document()?;
 
-    // Formatta come array di byte
-    let mut bytes = Vec::with_capacity(size as usize);
-    for i in (0..size).rev() {
-        let byte = ((raw_value >> (i * 8)) & 0xFF) as u8;
-        bytes.push(byte);
+    if let Some(toggle) = document.get_element_by_id("freezeToggle") {
+        toggle.set_attribute("aria-pressed", &frozen.to_string())?;
+        toggle.set_text_content(Some(if frozen { "Frozen" } else { "Freeze" }));
+    }
+    if let Some(container) = document.get_element_by_id("controlsContainer") {
+        container
+            .class_list()
+            .toggle_with_force("dsp-control__controls--frozen", frozen)?;
+    }
+    if let Some(button) = document.get_element_by_id("writeAllButton") {
+        button.toggle_attribute_with_force("disabled", frozen)?;
     }
 
-    bytes
+    for register in registers().iter().filter(|r| !r.read_only) {
+        for id in [
+            format!("slider-{}", register.address),
+            format!("number-{}", register.address),
+        ] {
+            if let Some(input) = document.get_element_by_id(&id) {
+                input.dyn_into::<HtmlInputElement>()?.set_disabled(frozen);
+            }
+        }
+        if let Some(hex_value) =
+            document.get_element_by_id(&format!("hex-value-{}", register.address))
+        {
+            hex_value.set_attribute("contenteditable", &(!frozen).to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Se una scrittura è stata rifiutata perché il device è bloccato, aggiorna l'UI
+fn check_frozen_error(message: &str) {
+    if is_frozen_error(message) {
+        let _ = apply_frozen(true);
+    }
+}
+
+/// Collega il pulsante "Write All" del template
+fn init_write_all_button(document: &Document) -> Result<(), JsValue> {
+    let Some(button) = document.get_element_by_id("writeAllButton") else {
+        return Ok(());
+    };
+    let button = button.dyn_into::<HtmlElement>()?;
+
+    let on_click = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        if let Err(e) = write_all() {
+            let _ = set_status(&format!("Write all failed: {:?}", e), true);
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    button.set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+
+    Ok(())
 }
 
-/// Converte un array di byte in un valore
-#[deprecated]
-pub fn bytes_to_value(bytes: &[u8], size: u16) -> i32 {
-    let mut value: i32 = 0;
+/// Collega il pulsante "Sync Now" del template: rilegge tutti i registri dal device
+fn init_sync_now_button(document: &Document) -> Result<(), JsValue> {
+    let Some(button) = document.get_element_by_id("syncNowButton") else {
+        return Ok(());
+    };
+    let button = button.dyn_into::<HtmlElement>()?;
 
-    for &byte in bytes {
-        value = (value << 8) | (byte as i32 & 0xFF);
+    let on_click = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        let _ = set_status("Syncing registers...", false);
+        wasm_bindgen_futures::spawn_local(async {
+            // l'errore è già nello status
+            if read_all_registers_and_update_ui(false).await.is_ok() {
+                let _ = set_status(&format!("Synced {} registers", registers().len()), false);
+            }
+        });
+    }) as Box<dyn FnMut(_)>);
+
+    button.set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+
+    Ok(())
+}
+
+/// Scrive sul device il valore corrente dello slider di ogni registro scrivibile
+fn write_all() -> Result<(), JsValue> {
+    let document = get_document()?;
+
+    let mut values = Vec::new();
+    for register in registers().iter() {
+        if register.read_only || !register.data_type.is_numeric() {
+            continue;
+        }
+        let Some(slider) = document.get_element_by_id(&format!("slider-{}", register.address))
+        else {
+            continue;
+        };
+        let value = slider.dyn_into::<HtmlInputElement>()?.value_as_number();
+        values.push((register.clone(), register.clamp(value)));
     }
 
-    // Controlla se questo è un valore negativo (complemento a due)
-    // Se il bit più significativo è impostato
-    if size > 0 && !bytes.is_empty() {
-        if (bytes[0] & 0x80) != 0 {
-            // Calcola il valore massimo per questa larghezza di bit
-            let max_val = 1 << (size * 8);
-            // Converti dal complemento a due
-            value = value - max_val;
+    if values.is_empty() {
+        return set_status("No writable registers", false);
+    }
+
+    // una sola conferma per tutti i registri marcati confirm
+    let confirm_names: Vec<&str> = values
+        .iter()
+        .filter(|(register, _)| register.confirm)
+        .map(|(register, _)| register.name.as_str())
+        .collect();
+    if !confirm_names.is_empty() {
+        let message = format!(
+            "Write all {} registers, including {}?",
+            values.len(),
+            confirm_names.join(", ")
+        );
+        let confirmed = get_window()?.confirm_with_message(&message)?;
+        if !confirmed {
+            return set_status("Write all cancelled", false);
         }
     }
 
-    info!("Converted bytes to value: {}", value);
-    value
+    let writes: Vec<(u16, Vec<u8>)> = values
+        .iter()
+        .map(|(register, value)| {
+            let raw_value = register.unit_to_raw_value(*value);
+            (register.address, register.value_to_bytes(raw_value))
+        })
+        .collect();
+
+    set_status(&format!("Writing {} registers...", writes.len()), false)?;
+
+    wasm_bindgen_futures::spawn_local(async move {
+        match write_registers_batch(&writes).await {
+            Ok(results) => {
+                for ((register, value), result) in values.iter().zip(&results) {
+                    if result.is_ok() {
+                        remember_write(register.address, *value);
+                    }
+                }
+                let names: Vec<&str> = values.iter().map(|(r, _)| r.name.as_str()).collect();
+                let (message, is_error) = write_all_summary(&names, &results);
+                let _ = set_status(&message, is_error);
+            }
+            Err(e) => {
+                let error_msg = e.as_string().unwrap_or_else(|| "Unknown error".to_string());
+                check_frozen_error(&error_msg);
+                let _ = set_status(&format!("Write all failed: {}", error_msg), true);
+            }
+        }
+    });
+
+    Ok(())
 }
 
-/// Formatta un valore come stringa esadecimale
-pub fn format_hex_bytes(bytes: &[u8]) -> String {
-    let mut hex_string = String::new();
+/// Riepilogo di "Write All": quante scritture sono riuscite e quali no
+fn write_all_summary(names: &[&str], results: &[Result<(), String>]) -> (String, bool) {
+    let failed: Vec<String> = names
+        .iter()
+        .zip(results)
+        .filter_map(|(name, result)| result.as_ref().err().map(|e| format!("{}: {}", name, e)))
+        .collect();
 
-    // Ensure we display the correct number of bytes based on the size
-    for (i, byte) in bytes.iter().enumerate() {
-        hex_string.push_str(&format!("{:02X}", byte));
-        if i < bytes.len() - 1 {
-            hex_string.push(' ');
+    if failed.is_empty() {
+        return (format!("Wrote all {} registers", results.len()), false);
+    }
+    (
+        format!(
+            "Wrote {} of {} registers, failed {}",
+            results.len() - failed.len(),
+            results.len(),
+            failed.join("; ")
+        ),
+        true,
+    )
+}
+
+// Intervallo tra due tentativi di riconnessione, in ms
+const RECONNECT_INTERVAL: i32 = 2000;
+
+thread_local! {
+    static RECONNECT_HANDLE: Cell<Option<i32>> = const { Cell::new(None) };
+}
+
+/// Crea l'indicatore di connessione nell'header e il banner di riconnessione
+fn init_connection_ui(document: &Document) -> Result<(), JsValue> {
+    let indicator = document.create_element("div")?;
+    indicator.set_id("connectionStatus");
+    indicator.set_class_name("dsp-control__connection");
+    indicator.set_attribute("role", "status")?;
+
+    let actions = document
+        .get_element_by_id("headerActions")
+        .ok_or_else(|| JsValue::from_str("Header actions not found"))?;
+    actions.insert_before(&indicator, actions.first_child().as_ref())?;
+
+    let banner = document.create_element("div")?;
+    banner.set_id("connectionBanner");
+    banner.set_class_name("dsp-control__banner hidden");
+    banner.set_attribute("role", "alert")?;
+
+    let banner_text = document.create_element("span")?;
+    banner_text.set_text_content(Some("Device offline, reconnecting..."));
+
+    let reconnect_button = document
+        .create_element("button")?
+        .dyn_into::<HtmlElement>()?;
+    reconnect_button.set_attribute("type", "button")?;
+    reconnect_button.set_class_name("dsp-control__banner-button");
+    reconnect_button.set_text_content(Some("Reconnect"));
+
+    let on_click = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        wasm_bindgen_futures::spawn_local(async {
+            let _ = set_status("Reconnecting...", false);
+            if !probe_device().await {
+                let _ = set_status("Device still offline", true);
+            }
+        });
+    }) as Box<dyn FnMut(_)>);
+    reconnect_button.set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+
+    banner.append_child(&banner_text)?;
+    banner.append_child(&reconnect_button)?;
+
+    let controls_container = document
+        .get_element_by_id("controlsContainer")
+        .ok_or_else(|| JsValue::from_str("Controls container not found"))?;
+    controls_container
+        .parent_node()
+        .ok_or_else(|| JsValue::from_str("Controls container has no parent"))?
+        .insert_before(&banner, Some(&controls_container))?;
+
+    set_connection_listener(|state| {
+        if let Err(e) = on_connection_change(state) {
+            error!("Failed to handle connection change: {:?}", e);
         }
+    });
+    show_connection_state(document, connection_state())?;
+
+    Ok(())
+}
+
+/// Aggiorna indicatore e banner con lo stato della connessione
+fn show_connection_state(document: &Document, state: ConnectionState) -> Result<(), JsValue> {
+    let online = state == ConnectionState::Online;
+
+    if let Some(indicator) = document.get_element_by_id("connectionStatus") {
+        indicator.set_text_content(Some(if online { "Online" } else { "Offline" }));
+        indicator
+            .class_list()
+            .toggle_with_force("dsp-control__connection--offline", !online)?;
+    }
+    if let Some(banner) = document.get_element_by_id("connectionBanner") {
+        banner.class_list().toggle_with_force("hidden", online)?;
     }
 
-    hex_string
+    Ok(())
 }
 
-/// Format a float value with up to 3 decimal places, removing trailing zeros
-fn format_value(value: f64) -> String {
-    // First format with fixed precision
-    let formatted = format!("{:.3}", value);
-    
-    // Remove trailing zeros after decimal point
-    if formatted.contains('.') {
-        let trimmed = formatted.trim_end_matches('0');
-        // If we trimmed all the way to the decimal point, remove it too
-        if trimmed.ends_with('.') {
-            return trimmed.trim_end_matches('.').to_string();
+/// Offline: ferma l'auto-refresh e prova a riconnettersi periodicamente.
+/// Online: smette di provare e riprende l'auto-refresh se era attivo.
+fn on_connection_change(state: ConnectionState) -> Result<(), JsValue> {
+    let document = get_document()?;
+    let window = get_window()?;
+    show_connection_state(&document, state)?;
+
+    match state {
+        ConnectionState::Offline => {
+            stop_auto_refresh()?;
+            set_status("Device offline, reconnecting...", true)?;
+
+            if RECONNECT_HANDLE.get().is_none() {
+                let callback = Closure::wrap(Box::new(move || {
+                    wasm_bindgen_futures::spawn_local(async {
+                        probe_device().await;
+                    });
+                }) as Box<dyn FnMut()>);
+
+                let handle = window.set_interval_with_callback_and_timeout_and_arguments(
+                    callback.as_ref().unchecked_ref(),
+                    RECONNECT_INTERVAL,
+                    &js_sys::Array::new(),
+                )?;
+                RECONNECT_HANDLE.set(Some(handle));
+                callback.forget();
+            }
+        }
+        ConnectionState::Online => {
+            if let Some(handle) = RECONNECT_HANDLE.take() {
+                window.clear_interval_with_handle(handle);
+            }
+            set_status("Device back online", false)?;
+
+            let auto_refresh = document
+                .get_element_by_id("autoRefreshToggle")
+                .and_then(|toggle| toggle.dyn_into::<HtmlInputElement>().ok())
+                .is_some_and(|toggle| toggle.checked());
+            if auto_refresh {
+                start_auto_refresh()?;
+            }
         }
-        return trimmed.to_string();
     }
-    
-    formatted
+
+    Ok(())
 }
 
-/// Aggiorna l'interfaccia utente per un registro
-pub fn update_ui_for_register(register: &DspRegister, value: f64) -> Result<(), JsValue> {
+const THEME_STORAGE_KEY: &str = "dsp-control-theme";
+
+/// Tema salvato in localStorage, altrimenti quello preferito dal sistema
+fn initial_theme(stored: Option<&str>, prefers_dark: bool) -> &'static str {
+    match stored {
+        Some("dark") => "dark",
+        Some("light") => "light",
+        _ if prefers_dark => "dark",
+        _ => "light",
+    }
+}
+
+/// Imposta data-theme sulla radice del documento, i colori sono nel CSS
+fn apply_theme(document: &Document, theme: &str) -> Result<(), JsValue> {
+    if let Some(root) = document.document_element() {
+        root.set_attribute("data-theme", theme)?;
+    }
+
+    if let Some(toggle) = document.get_element_by_id("themeToggle") {
+        let dark = theme == "dark";
+        toggle.set_text_content(Some(if dark { "Light theme" } else { "Dark theme" }));
+        toggle.set_attribute("aria-pressed", if dark { "true" } else { "false" })?;
+    }
+
+    Ok(())
+}
+
+/// Crea il pulsante del tema nell'header e applica il tema iniziale
+fn init_theme_toggle(document: &Document) -> Result<(), JsValue> {
+    let window = get_window()?;
+    let storage = window.local_storage().ok().flatten();
+
+    let stored = storage
+        .as_ref()
+        .and_then(|storage| storage.get_item(THEME_STORAGE_KEY).ok().flatten());
+    let prefers_dark = window
+        .match_media("(prefers-color-scheme: dark)")
+        .ok()
+        .flatten()
+        .is_some_and(|query| query.matches());
+
+    let toggle = document
+        .create_element("button")?
+        .dyn_into::<HtmlElement>()?;
+    toggle.set_id("themeToggle");
+    toggle.set_class_name("dsp-control__theme-toggle");
+    toggle.set_attribute("type", "button")?;
+
+    match document.get_element_by_id("headerActions") {
+        Some(actions) => {
+            actions.insert_before(&toggle, actions.first_child().as_ref())?;
+        }
+        None => return Err(JsValue::from_str("Header actions not found")),
+    }
+
+    apply_theme(document, initial_theme(stored.as_deref(), prefers_dark))?;
+
+    let on_click = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        let Ok(document) = get_document() else {
+            return;
+        };
+        let current = document
+            .document_element()
+            .and_then(|root| root.get_attribute("data-theme"));
+        let theme = if current.as_deref() == Some("dark") {
+            "light"
+        } else {
+            "dark"
+        };
+
+        let _ = apply_theme(&document, theme);
+        if let Some(storage) = &storage {
+            let _ = storage.set_item(THEME_STORAGE_KEY, theme);
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    toggle.set_onclick(Some(on_click.as_ref().unchecked_ref()));
+    on_click.forget();
+
+    Ok(())
+}
+
+const TOKEN_STORAGE_KEY: &str = "dsp-control-token";
+
+/// Collega il campo del token di autenticazione, salvato in localStorage
+fn init_auth_token(document: &Document) -> Result<(), JsValue> {
+    let Some(input) = document
+        .get_element_by_id("authToken")
+        .and_then(|element| element.dyn_into::<HtmlInputElement>().ok())
+    else {
+        return Ok(());
+    };
+
+    let storage = get_window()?.local_storage().ok().flatten();
+    let stored = storage
+        .as_ref()
+        .and_then(|storage| storage.get_item(TOKEN_STORAGE_KEY).ok().flatten());
+    if let Some(token) = &stored {
+        input.set_value(token);
+    }
+    reg_io::set_auth_token(stored);
+
+    let on_change = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let Some(input) = event
+            .target()
+            .and_then(|target| target.dyn_into::<HtmlInputElement>().ok())
+        else {
+            return;
+        };
+        let token = input.value().trim().to_string();
+
+        if let Some(storage) = &storage {
+            let _ = if token.is_empty() {
+                storage.remove_item(TOKEN_STORAGE_KEY)
+            } else {
+                storage.set_item(TOKEN_STORAGE_KEY, &token)
+            };
+        }
+        reg_io::set_auth_token(Some(token));
+        let _ = set_status("Auth token updated", false);
+    }) as Box<dyn FnMut(_)>);
+
+    input.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+    on_change.forget();
+
+    Ok(())
+}
+
+const GROUPS_STORAGE_KEY: &str = "dsp-control-collapsed-groups";
+// Sezione dei registri senza gruppo
+const DEFAULT_GROUP: &str = "Other";
+
+/// Registri divisi per gruppo, nell'ordine in cui i gruppi compaiono
+///
+/// Registers without a group end up together in a last section.
+fn group_registers(registers: &[DspRegister]) -> Vec<(Option<&str>, Vec<&DspRegister>)> {
+    let mut groups: Vec<(Option<&str>, Vec<&DspRegister>)> = Vec::new();
+    for register in registers {
+        let group = register.group.as_deref();
+        match groups.iter_mut().find(|(known, _)| *known == group) {
+            Some((_, members)) => members.push(register),
+            None => groups.push((group, vec![register])),
+        }
+    }
+    // la sezione predefinita va in fondo
+    groups.sort_by_key(|(group, _)| group.is_none());
+    groups
+}
+
+/// Gruppi chiusi dall'utente, salvati in localStorage come lista JSON
+fn collapsed_groups() -> std::collections::HashSet<String> {
+    get_window()
+        .ok()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .and_then(|storage| storage.get_item(GROUPS_STORAGE_KEY).ok().flatten())
+        .and_then(|stored| serde_json::from_str(&stored).ok())
+        .unwrap_or_default()
+}
+
+/// Ricorda se il gruppo `name` è aperto o chiuso
+fn remember_group(name: &str, open: bool) {
+    let Some(storage) = get_window()
+        .ok()
+        .and_then(|window| window.local_storage().ok().flatten())
+    else {
+        return;
+    };
+    let mut collapsed = collapsed_groups();
+    if open {
+        collapsed.remove(name);
+    } else {
+        collapsed.insert(name.to_string());
+    }
+    if let Ok(json) = serde_json::to_string(&collapsed) {
+        let _ = storage.set_item(GROUPS_STORAGE_KEY, &json);
+    }
+}
+
+/// Crea la sezione `<details>` di un gruppo e il contenitore dei suoi controlli
+fn create_group_section(
+    document: &Document,
+    name: &str,
+    open: bool,
+) -> Result<(Element, Element), JsValue> {
+    let details = document.create_element("details")?;
+    details.set_class_name("dsp-control__group");
+    details.toggle_attribute_with_force("open", open)?;
+
+    let summary = document.create_element("summary")?;
+    summary.set_class_name("dsp-control__group-title");
+    summary.set_text_content(Some(name));
+    details.append_child(&summary)?;
+
+    let body = document.create_element("div")?;
+    body.set_class_name("dsp-control__group-body");
+    details.append_child(&body)?;
+
+    let name = name.to_string();
+    let on_toggle = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        if let Some(details) = event
+            .target()
+            .and_then(|target| target.dyn_into::<Element>().ok())
+        {
+            remember_group(&name, details.has_attribute("open"));
+        }
+    }) as Box<dyn FnMut(_)>);
+    details.add_event_listener_with_callback("toggle", on_toggle.as_ref().unchecked_ref())?;
+    on_toggle.forget();
+
+    Ok((details, body))
+}
+
+// Attesa dopo l'ultimo tasto prima di filtrare, in ms
+const SEARCH_DEBOUNCE_MS: i32 = 150;
+static mut SEARCH_HANDLE: Option<i32> = None;
+
+/// Il registro corrisponde alla ricerca per nome o per indirizzo esadecimale
+///
+/// Case-insensitive substring match; the address matches as `0x0043`, so
+/// both `43` and `0x004` find it. An empty query matches everything.
+fn register_matches(register: &DspRegister, query: &str) -> bool {
+    let query = query.trim().to_lowercase();
+    query.is_empty()
+        || register.name.to_lowercase().contains(&query)
+        || format!("0x{:04x}", register.address).contains(&query)
+}
+
+/// Mostra solo i controlli che corrispondono a `query`, e i gruppi che ne hanno
+fn apply_search(query: &str) -> Result<(), JsValue> {
     let document = get_document()?;
+    let registers = registers();
 
-    // Aggiorna il valore decimale
-    if let Some(value_box) = document.get_element_by_id(&format!("value-{}", register.address)) {
-        value_box.set_text_content(Some(&format_value(value)));
+    for register in registers.iter() {
+        if let Some(control) = document.get_element_by_id(&format!("control-{}", register.address))
+        {
+            control.class_list().toggle_with_force(
+                "dsp-control__control-item--hidden",
+                !register_matches(register, query),
+            )?;
+        }
     }
 
-    // Aggiorna il valore esadecimale
-    if let Some(hex_value) = document.get_element_by_id(&format!("hex-value-{}", register.address))
+    // un gruppo senza risultati sparisce del tutto
+    for (index, (_, members)) in group_registers(&registers).iter().enumerate() {
+        if let Some(section) = document.get_element_by_id(&format!("group-{}", index)) {
+            let empty = !members
+                .iter()
+                .any(|register| register_matches(register, query));
+            section
+                .class_list()
+                .toggle_with_force("dsp-control__group--hidden", empty)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Crea il campo di ricerca sopra i controlli, filtra mentre si scrive
+fn init_search_box(document: &Document) -> Result<(), JsValue> {
+    let input = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    input.set_type("search");
+    input.set_id("registerSearch");
+    input.set_class_name("dsp-control__search");
+    input.set_placeholder("Search registers by name or address");
+    input.set_attribute("aria-label", "Search registers")?;
+    input.set_attribute("aria-controls", "controlsContainer")?;
+
+    let controls_container = document
+        .get_element_by_id("controlsContainer")
+        .ok_or_else(|| JsValue::from_str("Controls container not found"))?;
+    controls_container
+        .parent_node()
+        .ok_or_else(|| JsValue::from_str("Controls container has no parent"))?
+        .insert_before(&input, Some(&controls_container))?;
+
+    let on_input = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let Some(input) = event
+            .target()
+            .and_then(|target| target.dyn_into::<HtmlInputElement>().ok())
+        else {
+            return;
+        };
+        let Ok(window) = get_window() else {
+            return;
+        };
+
+        if let Some(handle) = unsafe { SEARCH_HANDLE } {
+            window.clear_timeout_with_handle(handle);
+        }
+        let callback = Closure::once_into_js(move || {
+            unsafe {
+                SEARCH_HANDLE = None;
+            }
+            if let Err(e) = apply_search(&input.value()) {
+                error!("Failed to filter registers: {:?}", e);
+            }
+        });
+        if let Ok(handle) = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+            callback.unchecked_ref(),
+            SEARCH_DEBOUNCE_MS,
+        ) {
+            unsafe {
+                SEARCH_HANDLE = Some(handle);
+            }
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    input.add_event_listener_with_callback("input", on_input.as_ref().unchecked_ref())?;
+    on_input.forget();
+
+    Ok(())
+}
+
+const VERIFY_STORAGE_KEY: &str = "dsp-control-verify-writes";
+
+// Dopo ogni scrittura il registro viene riletto e confrontato
+static mut VERIFY_WRITES: bool = false;
+
+/// Collega il toggle "Verify writes", salvato in localStorage
+fn init_verify_toggle(document: &Document) -> Result<(), JsValue> {
+    let Some(toggle) = document
+        .get_element_by_id("verifyWritesToggle")
+        .and_then(|element| element.dyn_into::<HtmlInputElement>().ok())
+    else {
+        return Ok(());
+    };
+
+    let storage = get_window()?.local_storage().ok().flatten();
+    let enabled = storage
+        .as_ref()
+        .and_then(|storage| storage.get_item(VERIFY_STORAGE_KEY).ok().flatten())
+        .is_some_and(|stored| stored == "1");
+    toggle.set_checked(enabled);
+    unsafe {
+        VERIFY_WRITES = enabled;
+    }
+
+    let on_change = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let Some(toggle) = event
+            .target()
+            .and_then(|target| target.dyn_into::<HtmlInputElement>().ok())
+        else {
+            return;
+        };
+        let enabled = toggle.checked();
+        unsafe {
+            VERIFY_WRITES = enabled;
+        }
+        if let Some(storage) = &storage {
+            let _ = storage.set_item(VERIFY_STORAGE_KEY, if enabled { "1" } else { "0" });
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    toggle.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+    on_change.forget();
+
+    Ok(())
+}
+
+/// Differenza tra i bytes scritti e quelli riletti, None se coincidono
+fn write_mismatch(written: &[u8], read_back: &[u8]) -> Option<String> {
+    (written != read_back).then(|| {
+        format!(
+            "wrote {} but read back {}",
+            format_hex_bytes(written),
+            format_hex_bytes(read_back)
+        )
+    })
+}
+
+/// Rilegge `address` dopo una scrittura riuscita, se la verifica è attiva
+///
+/// Catches writes the DSP ignored or changed, e.g. a value outside a range
+/// the hardware enforces, even with servers that don't verify on their own.
+/// A mismatch goes to the status bar and marks the control until a later
+/// write of the same register verifies.
+async fn verify_write(address: u16, written: &[u8]) {
+    if !unsafe { VERIFY_WRITES } {
+        return;
+    }
+
+    let mismatch = match read_registers(address, written.len() as u16).await {
+        Ok(read_back) => write_mismatch(written, &read_back),
+        Err(e) => Some(format!(
+            "read back failed: {}",
+            e.as_string().unwrap_or_else(|| "Unknown error".to_string())
+        )),
+    };
+
+    if let Some(control) = get_document()
+        .ok()
+        .and_then(|document| document.get_element_by_id(&format!("control-{}", address)))
     {
+        let _ = control
+            .class_list()
+            .toggle_with_force("dsp-control__control-item--mismatch", mismatch.is_some());
+    }
+    if let Some(mismatch) = mismatch {
+        set_status(
+            &format!("Verify failed for register 0x{:02X}: {}", address, mismatch),
+            true,
+        )
+        .ok();
+    }
+}
+
+/// Scrive un valore (nell'unità del registro) sul device, in background
+///
+/// Il valore viene prima limitato a min/max del registro.
+fn write_register_value(address: u16, value: f64) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let register = get_dsp_register_by_address(address).unwrap();
+        let clamped = register.clamp(value);
+        if clamped != value {
+            set_status(
+                &format!(
+                    "Clamped {} to {} for register 0x{:02X}",
+                    value, clamped, address
+                ),
+                false,
+            )
+            .ok();
+            let _ = update_ui_for_register(&register, clamped);
+        }
+        let value = clamped;
         let raw_value = register.unit_to_raw_value(value);
-        let hex_string = format_hex_bytes(&register.data_type.value_to_bytes(raw_value));
-        hex_value.set_text_content(Some(&hex_string));
-    }
+        let bytes = register.value_to_bytes(raw_value);
+
+        match write_registers(address, &bytes).await {
+            Ok(success) => {
+                if success {
+                    remember_write(address, value);
+                    let bytes_str = format_hex_bytes(&bytes);
+                    set_status(
+                        &format!(
+                            "Wrote {} ({}) to register 0x{:02X}",
+                            value, bytes_str, address
+                        ),
+                        false,
+                    )
+                    .ok();
+                    verify_write(address, &bytes).await;
+                } else {
+                    set_status(
+                        &format!("Failed to write {} to register 0x{:02X}", value, address),
+                        true,
+                    )
+                    .ok();
+                }
+            }
+            Err(e) => {
+                let error_msg = format!(
+                    "Error: {}",
+                    e.as_string().unwrap_or_else(|| "Unknown error".to_string())
+                );
+                check_frozen_error(&error_msg);
+                set_status(&error_msg, true).ok();
+            }
+        }
+    });
+}
 
-    // Aggiorna il valore dello slider
-    if let Some(slider_element) =
-        document.get_element_by_id(&format!("slider-{}", register.address))
-    {
-        let slider = slider_element.dyn_into::<HtmlInputElement>()?;
-        slider.set_value(&value.to_string());
+// Finestra di coalescenza delle scritture dagli slider, in ms
+const WRITE_DEBOUNCE_MS: i32 = 50;
 
-        // For readonly sliders, set a CSS custom property to visualize the value
-        if slider.disabled() {
-            let min = register.min as f64;
-            let max = register.max as f64;
-            let val = value as f64;
+/// Scritture in attesa, per indirizzo: timer del setTimeout e ultimi bytes
+#[derive(Default)]
+struct PendingWrites {
+    pending: std::collections::HashMap<u16, (i32, Vec<u8>)>,
+}
 
-            // Calculate percentage (clamped between 0-100%)
-            let percentage = if max > min {
-                ((val - min) / (max - min) * 100.0).max(0.0).min(100.0)
-            } else {
-                0.0
-            };
+impl PendingWrites {
+    /// Sostituisce la scrittura in attesa su `address`, ritorna il timer da annullare
+    fn replace(&mut self, address: u16, handle: i32, bytes: Vec<u8>) -> Option<i32> {
+        self.pending
+            .insert(address, (handle, bytes))
+            .map(|(handle, _)| handle)
+    }
 
-            // Set the custom property for the gradient
-            let html_slider = slider.dyn_into::<web_sys::HtmlElement>()?;
-            html_slider
-                .style()
-                .set_property("--slider-value", &format!("{}%", percentage))?;
+    /// Bytes da scrivere allo scadere del timer `handle`
+    ///
+    /// None se nel frattempo è arrivato un valore più recente con un altro timer.
+    fn take(&mut self, address: u16, handle: i32) -> Option<Vec<u8>> {
+        match self.pending.get(&address) {
+            Some((pending, _)) if *pending == handle => {
+                self.pending.remove(&address).map(|(_, bytes)| bytes)
+            }
+            _ => None,
         }
     }
+}
 
-    Ok(())
+thread_local! {
+    static PENDING_WRITES: RefCell<PendingWrites> = RefCell::new(PendingWrites::default());
 }
 
-/// Inizializza l'interfaccia utente
-pub fn init_ui() -> Result<(), JsValue> {
-    let document = get_document()?;
+/// Scrive `bytes` su `address` dopo `delay_ms`, se nel frattempo non arriva un altro valore
+///
+/// Più scritture sullo stesso indirizzo entro la finestra diventano una sola,
+/// con l'ultimo valore: ogni chiamata annulla il setTimeout precedente.
+fn schedule_write(address: u16, bytes: Vec<u8>, delay_ms: i32) -> Result<(), JsValue> {
+    let window = get_window()?;
 
-    // Ottieni il contenitore dei controlli
-    let controls_container = document
-        .get_element_by_id("controlsContainer")
-        .ok_or_else(|| JsValue::from_str("Controls container not found"))?;
+    // il timer non è ancora noto quando si crea la callback
+    let handle_cell = Rc::new(std::cell::Cell::new(0));
+    let handle_clone = handle_cell.clone();
+    let callback = Closure::once_into_js(move || {
+        let handle = handle_clone.get();
+        let Some(bytes) = PENDING_WRITES.with(|pending| pending.borrow_mut().take(address, handle))
+        else {
+            return;
+        };
+        match get_dsp_register_by_address(address) {
+            Some(register) => write_register_bytes(register, bytes),
+            None => error!("No register at 0x{:04X}", address),
+        }
+    });
 
-    // Ottieni i registri
-    let registers = get_dsp_registers();
+    let handle = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        callback.unchecked_ref(),
+        delay_ms,
+    )?;
+    handle_cell.set(handle);
 
-    // Crea elementi di controllo per ogni registro
-    for register in registers.iter() {
-        let control_item = create_control_item(&document, register)?;
-        controls_container.append_child(&control_item)?;
+    let previous =
+        PENDING_WRITES.with(|pending| pending.borrow_mut().replace(address, handle, bytes));
+    if let Some(previous) = previous {
+        window.clear_timeout_with_handle(previous);
     }
+    Ok(())
+}
 
-    // Configura il toggle di auto-refresh
-    if let Some(auto_refresh_toggle) = document.get_element_by_id("autoRefreshToggle") {
-        let toggle = auto_refresh_toggle.dyn_into::<HtmlInputElement>()?;
-
-        let on_change = Closure::wrap(Box::new(move |_event: web_sys::Event| {
-            if let Ok(document) = get_document() {
-                if let Some(toggle_element) = document.get_element_by_id("autoRefreshToggle") {
-                    if let Ok(toggle) = toggle_element.dyn_into::<HtmlInputElement>() {
-                        if toggle.checked() {
-                            let _ = start_auto_refresh();
-                            let _ = set_status("Auto refresh enabled", false);
-                        } else {
-                            let _ = stop_auto_refresh();
-                            let _ = set_status("Auto refresh disabled", false);
-                        }
-                    }
+/// Scrive i bytes esatti inseriti nel box esadecimale, senza conversioni di unità
+fn write_register_bytes(register: DspRegister, bytes: Vec<u8>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let address = register.address;
+        let bytes_str = format_hex_bytes(&bytes);
+
+        match write_registers(address, &bytes).await {
+            Ok(true) => {
+                if register.data_type.is_numeric() {
+                    let raw_value = register.bytes_to_value(&bytes);
+                    let value = register.raw_value_to_unit(raw_value);
+                    remember_write(address, value);
+                    let _ = update_ui_for_register(&register, value);
+                } else {
+                    remember_write(address, f64::NAN);
+                    let _ = update_ui_for_raw_register(&register, &bytes);
                 }
+                set_status(
+                    &format!("Wrote {} to register 0x{:02X}", bytes_str, address),
+                    false,
+                )
+                .ok();
+                verify_write(address, &bytes).await;
             }
-        }) as Box<dyn FnMut(_)>);
+            Ok(false) => {
+                set_status(
+                    &format!(
+                        "Failed to write {} to register 0x{:02X}",
+                        bytes_str, address
+                    ),
+                    true,
+                )
+                .ok();
+            }
+            Err(e) => {
+                let error_msg = format!(
+                    "Error: {}",
+                    e.as_string().unwrap_or_else(|| "Unknown error".to_string())
+                );
+                check_frozen_error(&error_msg);
+                set_status(&error_msg, true).ok();
+            }
+        }
+    });
+}
 
-        toggle.set_onchange(Some(on_change.as_ref().unchecked_ref()));
-        on_change.forget();
-    }
+/// Rende modificabile il box esadecimale di un registro scrivibile
+///
+/// The bytes are written as typed when the box loses focus or on Enter;
+/// while the text isn't valid hex of the right length the box is marked invalid.
+fn make_hex_box_editable(hex_value: &Element, register: &DspRegister) -> Result<(), JsValue> {
+    hex_value.set_attribute("contenteditable", "true")?;
+    hex_value.set_attribute("role", "textbox")?;
+    hex_value.set_attribute("aria-live", "off")?;
+    hex_value.set_attribute("spellcheck", "false")?;
+    hex_value
+        .class_list()
+        .add_1("dsp-control__hex-box--editable")?;
+
+    let len = register.byte_len() as usize;
+
+    let on_input = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let element = event.target().unwrap().dyn_into::<Element>().unwrap();
+        let text = element.text_content().unwrap_or_default();
+        let valid = parse_hex_input(&text, len).is_ok();
+        let _ = element
+            .class_list()
+            .toggle_with_force("dsp-control__hex-box--invalid", !valid);
+    }) as Box<dyn FnMut(_)>);
+
+    // invio conferma come l'uscita dal campo
+    let on_keydown = Closure::wrap(Box::new(move |event: web_sys::KeyboardEvent| {
+        if event.key() == "Enter" {
+            event.prevent_default();
+            if let Some(element) = event
+                .target()
+                .and_then(|target| target.dyn_into::<HtmlElement>().ok())
+            {
+                let _ = element.blur();
+            }
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    let register_clone = register.clone();
+    let on_blur = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let element = event.target().unwrap().dyn_into::<Element>().unwrap();
+        let text = element.text_content().unwrap_or_default();
+
+        let bytes = match parse_hex_input(&text, len) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let _ = set_status(
+                    &format!(
+                        "Invalid bytes for register 0x{:02X}: {}",
+                        register_clone.address, e
+                    ),
+                    true,
+                );
+                return;
+            }
+        };
+
+        let _ = element
+            .class_list()
+            .remove_1("dsp-control__hex-box--invalid");
+        if !confirm_write_text(&register_clone, &format_hex_bytes(&bytes)) {
+            return;
+        }
+        write_register_bytes(register_clone.clone(), bytes);
+    }) as Box<dyn FnMut(_)>);
+
+    hex_value.add_event_listener_with_callback("input", on_input.as_ref().unchecked_ref())?;
+    hex_value.add_event_listener_with_callback("keydown", on_keydown.as_ref().unchecked_ref())?;
+    hex_value.add_event_listener_with_callback("blur", on_blur.as_ref().unchecked_ref())?;
+
+    on_input.forget();
+    on_keydown.forget();
+    on_blur.forget();
 
     Ok(())
 }
@@ -465,12 +1746,17 @@ pub fn create_control_item(
     slider_input.set_class_name("dsp-control__range");
     slider_input.set_min(&register.min.to_string());
     slider_input.set_max(&register.max.to_string());
+    slider_input.set_step(&register.step.to_string());
     slider_input.set_value("0");
     slider_input.set_id(&format!("slider-{}", register.address));
 
     slider_input.set_attribute("data-address", &register.address.to_string())?;
+    slider_input.set_attribute("role", "slider")?;
+    slider_input.set_attribute("aria-label", &aria_label(register))?;
+    slider_input.set_attribute("aria-valuetext", &aria_value_text(register, 0.0))?;
 
-    if register.read_only {
+    // Raw registers have no value to drive a slider, they are display-only
+    if register.read_only || !register.data_type.is_numeric() {
         slider_input.set_disabled(true);
         slider_input
             .set_class_name(format!("{} {}", slider_input.class_name(), "readonly").as_str());
@@ -487,46 +1773,20 @@ pub fn create_control_item(
             let _ = update_ui_for_register(&register_clone, value);
         }) as Box<dyn FnMut(_)>);
 
-
+        let register_clone = register.clone();
         let on_change = Closure::wrap(Box::new(move |event: web_sys::Event| {
             let target = event.target().unwrap();
             let input = target.dyn_into::<HtmlInputElement>().unwrap();
             let value = input.value().parse::<f64>().unwrap_or(0.0);
 
-            wasm_bindgen_futures::spawn_local(async move {
-                let register = get_dsp_register_by_address(address).unwrap();
-                let raw_value = register.unit_to_raw_value(value);
-                let bytes = register.data_type.value_to_bytes(raw_value);
-
-                match write_registers(address, &bytes).await {
-                    Ok(success) => {
-                        if success {
-                            let bytes_str = format_hex_bytes(&bytes);
-                            set_status(
-                                &format!(
-                                    "Wrote {} ({}) to register 0x{:02X}",
-                                    value, bytes_str, address
-                                ),
-                                false,
-                            )
-                            .ok();
-                        } else {
-                            set_status(
-                                &format!("Failed to write {} to register 0x{:02X}", value, address),
-                                true,
-                            )
-                            .ok();
-                        }
-                    }
-                    Err(e) => {
-                        let error_msg = format!(
-                            "Error: {}",
-                            e.as_string().unwrap_or_else(|| "Unknown error".to_string())
-                        );
-                        set_status(&error_msg, true).ok();
-                    }
-                }
-            });
+            if !confirm_write(&register_clone, value) {
+                return;
+            }
+            let value = register_clone.clamp(value);
+            let bytes = register_clone.value_to_bytes(register_clone.unit_to_raw_value(value));
+            if let Err(e) = schedule_write(address, bytes, WRITE_DEBOUNCE_MS) {
+                error!("Failed to schedule write to 0x{:04X}: {:?}", address, e);
+            }
         }) as Box<dyn FnMut(_)>);
 
         slider_input.set_oninput(Some(on_input.as_ref().unchecked_ref()));
@@ -542,7 +1802,11 @@ pub fn create_control_item(
 
     let min = document.create_element("span")?;
     let unit_text = register.unit.to_string();
-    let unit_suffix = if !unit_text.is_empty() { format!(" {}", unit_text) } else { "".to_string() };
+    let unit_suffix = if !unit_text.is_empty() {
+        format!(" {}", unit_text)
+    } else {
+        "".to_string()
+    };
     min.set_text_content(Some(&format!("{}{}", register.min, unit_suffix)));
 
     let max = document.create_element("span")?;
@@ -552,10 +1816,17 @@ pub fn create_control_item(
     range_min_max.append_child(&max)?;
 
     // Add data type information
-    
+
     range_container.append_child(&slider_input)?;
     range_container.append_child(&range_min_max)?;
 
+    // Campo numerico per inserire un valore esatto, sincronizzato con lo slider
+    let number_input = if register.data_type.is_numeric() {
+        Some(create_number_input(document, register)?)
+    } else {
+        None
+    };
+
     // Display del valore
     let value_display = document.create_element("div")?;
     value_display.set_class_name("dsp-control__value-display");
@@ -568,6 +1839,13 @@ pub fn create_control_item(
     value_box.set_class_name("dsp-control__value-box");
     value_box.set_id(&format!("value-{}", register.address));
     value_box.set_text_content(Some("0"));
+    // lettura del valore per gli screen reader, senza annunciare ogni refresh
+    value_box.set_attribute("role", "meter")?;
+    value_box.set_attribute("aria-label", &format!("{} value", aria_label(register)))?;
+    value_box.set_attribute("aria-valuemin", &register.min.to_string())?;
+    value_box.set_attribute("aria-valuemax", &register.max.to_string())?;
+    value_box.set_attribute("aria-valuenow", "0")?;
+    value_box.set_attribute("aria-valuetext", &aria_value_text(register, 0.0))?;
 
     let dec_label = document.create_element("div")?;
     dec_label.set_class_name("dsp-control__value-label");
@@ -576,6 +1854,22 @@ pub fn create_control_item(
     dec_column.append_child(&value_box)?;
     dec_column.append_child(&dec_label)?;
 
+    // età dell'ultima lettura, solo per i registri aggiornati dall'auto-refresh
+    if register.read_only {
+        let age = document.create_element("div")?;
+        age.set_class_name("dsp-control__age");
+        age.set_id(&format!("age-{}", register.address));
+        dec_column.append_child(&age)?;
+    }
+
+    // minimo, picco e RMS recenti degli indicatori in dB
+    if register.read_only && matches!(register.unit, MeasurementUnit::Decibel) {
+        let stats = document.create_element("div")?;
+        stats.set_class_name("dsp-control__stats");
+        stats.set_id(&format!("stats-{}", register.address));
+        dec_column.append_child(&stats)?;
+    }
+
     // Colonna valore esadecimale
     let hex_column = document.create_element("div")?;
     hex_column.set_class_name("dsp-control__value-column");
@@ -583,6 +1877,12 @@ pub fn create_control_item(
     let hex_value = document.create_element("div")?;
     hex_value.set_class_name("dsp-control__hex-box");
     hex_value.set_id(&format!("hex-value-{}", register.address));
+    hex_value.set_attribute("role", "status")?;
+    hex_value.set_attribute("aria-live", "off")?;
+    hex_value.set_attribute("aria-label", &format!("{} raw bytes", register.name))?;
+    if !register.read_only {
+        make_hex_box_editable(&hex_value, register)?;
+    }
 
     let hex_label = document.create_element("div")?;
     hex_label.set_class_name("dsp-control__value-label");
@@ -596,6 +1896,9 @@ pub fn create_control_item(
     value_display.append_child(&hex_column)?;
 
     body.append_child(&range_container)?;
+    if let Some(number_input) = &number_input {
+        body.append_child(number_input)?;
+    }
     body.append_child(&value_display)?;
 
     // Assemblaggio dell'elemento di controllo
@@ -605,6 +1908,82 @@ pub fn create_control_item(
     Ok(control_item)
 }
 
+/// Crea il campo numerico di un registro
+///
+/// Typed values are clamped to min/max and written like a slider change; while
+/// the text is out of range the field is marked invalid.
+fn create_number_input(
+    document: &Document,
+    register: &DspRegister,
+) -> Result<HtmlInputElement, JsValue> {
+    let input = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    input.set_type("number");
+    input.set_class_name("dsp-control__number-input");
+    input.set_id(&format!("number-{}", register.address));
+    input.set_attribute("aria-label", &aria_label(register))?;
+    input.set_min(&register.min.to_string());
+    input.set_max(&register.max.to_string());
+    input.set_step(&register.step.to_string());
+    input.set_value("0");
+
+    if register.read_only {
+        input.set_disabled(true);
+        return Ok(input);
+    }
+
+    let (min, max) = (register.min as f64, register.max as f64);
+
+    let on_input = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let input = event
+            .target()
+            .unwrap()
+            .dyn_into::<HtmlInputElement>()
+            .unwrap();
+        let valid = input
+            .value()
+            .parse::<f64>()
+            .is_ok_and(|value| value >= min && value <= max);
+        let _ = input
+            .class_list()
+            .toggle_with_force("dsp-control__number-input--invalid", !valid);
+    }) as Box<dyn FnMut(_)>);
+
+    // change arriva su invio o quando il campo perde il focus
+    let register_clone = register.clone();
+    let on_change = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let input = event
+            .target()
+            .unwrap()
+            .dyn_into::<HtmlInputElement>()
+            .unwrap();
+        let Ok(value) = input.value().parse::<f64>() else {
+            return;
+        };
+
+        let value = register_clone.clamp(value);
+        let _ = input
+            .class_list()
+            .remove_1("dsp-control__number-input--invalid");
+        input.set_value(&register_clone.format_value(value));
+
+        if !confirm_write(&register_clone, value) {
+            return;
+        }
+        let _ = update_ui_for_register(&register_clone, value);
+        write_register_value(register_clone.address, value);
+    }) as Box<dyn FnMut(_)>);
+
+    input.set_oninput(Some(on_input.as_ref().unchecked_ref()));
+    input.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+
+    on_input.forget();
+    on_change.forget();
+
+    Ok(input)
+}
+
 /// Imposta il messaggio di stato
 pub fn set_status(message: &str, is_error: bool) -> Result<(), JsValue> {
     let document = get_document()?;
@@ -648,47 +2027,18 @@ pub fn hide_loading() -> Result<(), JsValue> {
 }
 
 /// Legge il valore di un registro e aggiorna l'UI
+///
+/// Raw registers have no numeric value, for those NaN is returned
 pub async fn read_register_and_update_ui(register: &DspRegister) -> Result<f64, JsValue> {
     info!(
         "Reading register at address 0x{:02X} with size {}",
         register.address,
-        register.data_type.get_size()
+        register.byte_len()
     );
     //show_loading()?;
 
-    match read_registers(register.address, register.data_type.get_size()).await {
-        Ok(bytes) => {
-            let raw_value = register.data_type.bytes_to_value(&bytes);
-            let value = register.raw_value_to_unit(raw_value);
-
-            info!(
-                "got value: {:?}, trying to parse a {:?}",
-                raw_value, register.unit
-            );
-
-            if value.is_nan() {
-                error!(
-                    "got nan from value: {:?}, trying to parse a {:?}",
-                    raw_value, register.unit
-                );
-            }
-
-            // Aggiorna l'UI con il corretto tipo di dati
-            let display_size = register.data_type.get_size();
-            info!(
-                "Updating UI with value {} using display size {}",
-                value, display_size
-            );
-            update_ui_for_register(register, value)?;
-
-            //hide_loading()?;
-            set_status(
-                &format!("Read register 0x{:02X}: {}", register.address, value),
-                false,
-            )?;
-
-            Ok(value)
-        }
+    match read_registers(register.address, register.byte_len()).await {
+        Ok(bytes) => apply_register_bytes(register, &bytes),
         Err(error) => {
             //hide_loading()?;
             let error_msg = error
@@ -706,37 +2056,209 @@ pub async fn read_register_and_update_ui(register: &DspRegister) -> Result<f64,
     }
 }
 
+/// Decodifica i bytes letti da un registro e aggiorna l'UI
+///
+/// Raw registers have no numeric value, for those NaN is returned
+pub fn apply_register_bytes(register: &DspRegister, bytes: &[u8]) -> Result<f64, JsValue> {
+    let now = js_sys::Date::now();
+
+    if !register.data_type.is_numeric() {
+        state::update_state(|state| state.record_read(register.address, f64::NAN, now));
+        update_ui_for_raw_register(register, bytes)?;
+
+        set_status(
+            &format!(
+                "Read register 0x{:02X}: {}",
+                register.address,
+                format_hex_bytes(bytes)
+            ),
+            false,
+        )?;
+
+        return Ok(f64::NAN);
+    }
+
+    let raw_value = register.bytes_to_value(bytes);
+    let value = register.raw_value_to_unit(raw_value);
+
+    info!(
+        "got value: {:?}, trying to parse a {:?}",
+        raw_value, register.unit
+    );
+
+    if value.is_nan() {
+        error!(
+            "got nan from value: {:?}, trying to parse a {:?}",
+            raw_value, register.unit
+        );
+    }
+
+    // Aggiorna l'UI con il corretto tipo di dati
+    info!(
+        "Updating UI with value {} using display size {}",
+        value,
+        bytes.len()
+    );
+    state::update_state(|state| state.record_read(register.address, value, now));
+    update_ui_for_register(register, value)?;
+
+    //hide_loading()?;
+    set_status(
+        &format!("Read register 0x{:02X}: {}", register.address, value),
+        false,
+    )?;
+
+    Ok(value)
+}
+
 /// Legge tutti i registri e aggiorna l'UI
+///
+/// All registers are fetched with a single batch request when the device supports it.
+/// Registers written in the last WRITE_SUPPRESS_MS are skipped, the read may predate the write.
 pub async fn read_all_registers_and_update_ui(read_only: bool) -> Result<(), JsValue> {
-    let registers = get_dsp_registers();
-
-    if read_only {
+    let all = registers();
+    let registers: Vec<&DspRegister> = all.iter().filter(|r| !read_only || r.read_only).collect();
 
-        for register in registers.iter().filter(|r| r.read_only) {
-            read_register_and_update_ui(register).await?;
+    let requests: Vec<(u16, u16)> = registers
+        .iter()
+        .map(|r| (r.address, r.byte_len()))
+        .collect();
+
+    match read_registers_batch(&requests).await {
+        Ok(results) => {
+            for (register, bytes) in registers.iter().zip(results.iter()) {
+                if write_pending(register.address) {
+                    continue;
+                }
+                apply_register_bytes(register, bytes)?;
+            }
+            Ok(())
         }
-    } else {
-        for register in registers.iter() {
-            read_register_and_update_ui(register).await?;
+        Err(error) => {
+            let error_msg = error
+                .as_string()
+                .unwrap_or_else(|| "Unknown error".to_string());
+            set_status(&format!("Error reading registers: {}", error_msg), true)?;
+            Err(error)
         }
     }
-
-    Ok(())
 }
 
 // Variabili globali per l'auto-refresh
-static mut AUTO_REFRESH_HANDLE: Option<i32> = None;
 static AUTO_REFRESH_RATE: i32 = 100; // ms
+                                     // Un ciclo di lettura è ancora in corso, i tick successivi vengono saltati
+static mut AUTO_REFRESH_IN_FLIGHT: bool = false;
+// Cicli completati dall'inizio della finestra di misura corrente
+static mut AUTO_REFRESH_CYCLES: u32 = 0;
+static mut AUTO_REFRESH_WINDOW_START: f64 = 0.0;
+// Diventa false se il device non accetta il WebSocket /ws, da lì in poi si usa il polling
+static mut STREAM_AVAILABLE: bool = true;
+
+thread_local! {
+    // Intervallo del polling attivo
+    static AUTO_REFRESH_HANDLE: Cell<Option<i32>> = const { Cell::new(None) };
+    // Stream WebSocket attivo al posto del polling
+    static AUTO_REFRESH_SOCKET: RefCell<Option<WebSocket>> = const { RefCell::new(None) };
+}
 
 /// Avvia l'auto-refresh
+///
+/// Usa lo stream WebSocket del device se disponibile, altrimenti il polling HTTP
 pub fn start_auto_refresh() -> Result<(), JsValue> {
     stop_auto_refresh()?;
 
+    unsafe {
+        AUTO_REFRESH_CYCLES = 0;
+        AUTO_REFRESH_WINDOW_START = js_sys::Date::now();
+    }
+
+    if unsafe { STREAM_AVAILABLE } {
+        match start_register_stream() {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                error!("Failed to open the register stream: {:?}", e);
+                unsafe {
+                    STREAM_AVAILABLE = false;
+                }
+            }
+        }
+    }
+
+    start_polling()
+}
+
+/// Auto-refresh tramite lo stream /ws, se si chiude si passa al polling
+fn start_register_stream() -> Result<(), JsValue> {
+    let all = registers();
+    let registers: Vec<DspRegister> = all.iter().filter(|r| r.read_only).cloned().collect();
+
+    let requests: Vec<(u16, u16)> = registers
+        .iter()
+        .map(|r| (r.address, r.byte_len()))
+        .collect();
+
+    let socket = open_register_stream(
+        &requests,
+        move |values| {
+            for (register, bytes) in registers.iter().zip(values.iter()) {
+                if write_pending(register.address) {
+                    continue;
+                }
+                if let Err(e) = apply_register_bytes(register, bytes) {
+                    error!(
+                        "Failed to update register 0x{:04X}: {:?}",
+                        register.address, e
+                    );
+                }
+            }
+            record_refresh_cycle();
+        },
+        |was_open| {
+            AUTO_REFRESH_SOCKET.with(|socket| socket.borrow_mut().take());
+
+            if !was_open {
+                info!("Register stream not available, falling back to polling");
+                unsafe {
+                    STREAM_AVAILABLE = false;
+                }
+            } else {
+                info!("Register stream closed, falling back to polling");
+            }
+
+            if let Err(e) = start_polling() {
+                error!("Failed to start polling: {:?}", e);
+            }
+        },
+    )?;
+
+    AUTO_REFRESH_SOCKET.with(|s| *s.borrow_mut() = Some(socket));
+
+    Ok(())
+}
+
+/// Auto-refresh tramite /read_multi ogni AUTO_REFRESH_RATE ms
+fn start_polling() -> Result<(), JsValue> {
     let window = get_window()?;
 
     let callback = Closure::wrap(Box::new(move || {
+        // If the device is slower than the refresh rate, don't pile up requests
+        if unsafe { AUTO_REFRESH_IN_FLIGHT } {
+            return;
+        }
+        unsafe {
+            AUTO_REFRESH_IN_FLIGHT = true;
+        }
+
         wasm_bindgen_futures::spawn_local(async {
-            let _ = read_all_registers_and_update_ui(true).await;
+            let result = read_all_registers_and_update_ui(true).await;
+
+            unsafe {
+                AUTO_REFRESH_IN_FLIGHT = false;
+            }
+
+            if result.is_ok() {
+                record_refresh_cycle();
+            }
         });
     }) as Box<dyn FnMut()>);
 
@@ -746,9 +2268,7 @@ pub fn start_auto_refresh() -> Result<(), JsValue> {
         &js_sys::Array::new(),
     )?;
 
-    unsafe {
-        AUTO_REFRESH_HANDLE = Some(handle);
-    }
+    AUTO_REFRESH_HANDLE.set(Some(handle));
 
     callback.forget();
 
@@ -759,16 +2279,86 @@ pub fn start_auto_refresh() -> Result<(), JsValue> {
 pub fn stop_auto_refresh() -> Result<(), JsValue> {
     let window = get_window()?;
 
-    unsafe {
-        if let Some(handle) = AUTO_REFRESH_HANDLE {
-            window.clear_interval_with_handle(handle);
-            AUTO_REFRESH_HANDLE = None;
+    if let Some(handle) = AUTO_REFRESH_HANDLE.take() {
+        window.clear_interval_with_handle(handle);
+    }
+
+    if let Some(socket) = AUTO_REFRESH_SOCKET.with(|s| s.borrow_mut().take()) {
+        // senza onclose la chiusura non fa ripartire il polling
+        socket.set_onclose(None);
+        socket.close()?;
+    }
+
+    set_refresh_rate_text("")?;
+
+    // picchi e medie ripartono da zero al prossimo avvio
+    state::update_state(|state| state.reset_levels());
+    let document = get_document()?;
+    for register in registers().iter() {
+        if let Some(stats) = document.get_element_by_id(&format!("stats-{}", register.address)) {
+            stats.set_text_content(None);
         }
     }
 
     Ok(())
 }
 
+/// Conta un ciclo di auto-refresh completato, e aggiorna la frequenza effettiva ogni secondo
+fn record_refresh_cycle() {
+    let now = js_sys::Date::now();
+
+    let rate = unsafe {
+        AUTO_REFRESH_CYCLES += 1;
+
+        let elapsed = now - AUTO_REFRESH_WINDOW_START;
+        if elapsed < 1000.0 {
+            return;
+        }
+
+        let rate = AUTO_REFRESH_CYCLES as f64 * 1000.0 / elapsed;
+        AUTO_REFRESH_CYCLES = 0;
+        AUTO_REFRESH_WINDOW_START = now;
+        rate
+    };
+
+    // stopped while the last cycle was in flight
+    let streaming = AUTO_REFRESH_SOCKET.with(|s| s.borrow().is_some());
+    if AUTO_REFRESH_HANDLE.get().is_none() && !streaming {
+        return;
+    }
+
+    let target = 1000.0 / AUTO_REFRESH_RATE as f64;
+    let _ = set_refresh_rate_text(&format!(
+        "{} / {} refresh/s",
+        format_value((rate * 10.0).round() / 10.0, 3),
+        format_value(target, 3)
+    ));
+}
+
+/// Mostra la frequenza effettiva di auto-refresh nella barra di stato
+fn set_refresh_rate_text(text: &str) -> Result<(), JsValue> {
+    let document = get_document()?;
+
+    if let Some(refresh_rate) = document.get_element_by_id("refreshRate") {
+        refresh_rate.set_text_content(Some(text));
+    }
+
+    Ok(())
+}
+
+/// Latenza dell'ultima richiesta e media, con il tempo I2C riportato dal device
+fn format_latency(stats: &LatencyStats) -> String {
+    let mut text = format!("Latency {} ms", stats.last_ms.round());
+    if let Some(average) = stats.average_ms() {
+        text.push_str(&format!(" (avg {} ms", average.round()));
+        if let Some(device_ms) = stats.last_device_ms {
+            text.push_str(&format!(", I2C {} ms", format_value(device_ms, 1)));
+        }
+        text.push(')');
+    }
+    text
+}
+
 /// Inizializza l'applicazione
 #[wasm_bindgen]
 pub async fn initialize_app() -> Result<(), JsValue> {
@@ -781,6 +2371,12 @@ pub async fn initialize_app() -> Result<(), JsValue> {
     Ok(())
 }
 
+/// Cambia il timeout delle richieste al device (default 3000 ms), chiamabile da JS
+#[wasm_bindgen]
+pub fn set_request_timeout(timeout_ms: i32) {
+    reg_io::set_request_timeout(timeout_ms);
+}
+
 /// Helper function to get the window
 fn get_window() -> Result<Window, JsValue> {
     web_sys::window().ok_or_else(|| JsValue::from_str("No window found"))
@@ -799,61 +2395,217 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_int8_24_format() {
-        let dtype = DataType::Int8_24;
-
-        assert_eq!(dtype.value_to_bytes(-128.0), vec![0x80, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(-32.0), vec![0xE0, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(-8.0), vec![0xF8, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(-2.0), vec![0xFE, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(-1.0), vec![0xFF, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(-0.5), vec![0xFF, 0x80, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(0.0), vec![0x00, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(0.25), vec![0x00, 0x40, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(0.5), vec![0x00, 0x80, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(1.0), vec![0x01, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(2.0), vec![0x02, 0x00, 0x00, 0x00]);
+    fn test_group_registers() {
+        let registers = get_dsp_registers();
+        // senza gruppi resta una sola lista
+        let groups = group_registers(&registers);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0, None);
+        assert_eq!(groups[0].1.len(), registers.len());
+
+        let mut registers = registers;
+        registers[0].group = Some("Levels".to_string());
+        registers[2].group = Some("Levels".to_string());
+        registers[3].group = Some("ADC".to_string());
+        let groups = group_registers(&registers);
+        let names: Vec<_> = groups.iter().map(|(group, _)| *group).collect();
+        assert_eq!(names, vec![Some("Levels"), Some("ADC"), None]);
+        let addresses: Vec<_> = groups[0].1.iter().map(|r| r.address).collect();
+        assert_eq!(addresses, vec![61, 79]);
+        assert_eq!(groups[2].1.len(), 2);
+    }
+
+    #[test]
+    fn test_register_matches() {
+        let registers = get_dsp_registers();
+        let gain = &registers[1];
+
+        assert!(register_matches(gain, ""));
+        assert!(register_matches(gain, "  "));
+        assert!(register_matches(gain, "gAIN"));
+        assert!(register_matches(gain, "43"));
+        assert!(register_matches(gain, "0x0043"));
+        assert!(register_matches(gain, "0X004"));
+        assert!(!register_matches(gain, "level"));
+        assert!(!register_matches(gain, "0x0044"));
+
+        let found: Vec<_> = registers
+            .iter()
+            .filter(|register| register_matches(register, "signal level"))
+            .map(|register| register.address)
+            .collect();
+        assert_eq!(found, vec![61, 79, 41, 65]);
     }
 
     #[test]
-    fn test_int32_0_format() {
-        let dtype = DataType::Int32_0;
+    fn test_write_mismatch() {
+        assert_eq!(write_mismatch(&[0x01, 0x00], &[0x01, 0x00]), None);
+        assert_eq!(
+            write_mismatch(&[0x7F, 0xFF], &[0x3F, 0xFF]),
+            Some("wrote 7F FF but read back 3F FF".to_string())
+        );
+        // una rilettura più corta è comunque una differenza
+        assert!(write_mismatch(&[0x01, 0x00], &[0x01]).is_some());
+    }
 
+    #[test]
+    fn test_address_to_default_datatype() {
+        assert_eq!(address_to_default_datatype(0x0000), DataType::Int8_24);
+        assert_eq!(address_to_default_datatype(0x5FFF), DataType::Int8_24);
+        assert_eq!(address_to_default_datatype(0x6004), DataType::Int8_24);
+        assert_eq!(address_to_default_datatype(0x6005), DataType::Int32_0);
+        assert_eq!(address_to_default_datatype(0x6007), DataType::Int32_0);
+        assert_eq!(address_to_default_datatype(0x6008), DataType::Int5_23);
+        assert_eq!(address_to_default_datatype(0xBFFF), DataType::Int5_23);
+        assert_eq!(
+            address_to_default_datatype(0xC000),
+            DataType::Raw { len: 4 }
+        );
+        // buco tra program RAM e registri di controllo
         assert_eq!(
-            dtype.value_to_bytes(-2147483648.0),
-            vec![0x80, 0x00, 0x00, 0x00]
+            address_to_default_datatype(0xE000),
+            DataType::Raw { len: 4 }
         );
         assert_eq!(
-            dtype.value_to_bytes(-2147483647.0),
-            vec![0x80, 0x00, 0x00, 0x01]
+            address_to_default_datatype(0xF000),
+            DataType::Raw { len: 2 }
         );
         assert_eq!(
-            dtype.value_to_bytes(-2147483646.0),
-            vec![0x80, 0x00, 0x00, 0x02]
+            address_to_default_datatype(0xFFFF),
+            DataType::Raw { len: 2 }
         );
+    }
+
+    #[test]
+    fn test_write_all_summary() {
+        let names = ["Gain", "Mute", "Volume"];
+
         assert_eq!(
-            dtype.value_to_bytes(-1073741824.0),
-            vec![0xC0, 0x00, 0x00, 0x00]
+            write_all_summary(&names, &[Ok(()), Ok(()), Ok(())]),
+            ("Wrote all 3 registers".to_string(), false)
         );
         assert_eq!(
-            dtype.value_to_bytes(-536870912.0),
-            vec![0xE0, 0x00, 0x00, 0x00]
+            write_all_summary(
+                &names,
+                &[
+                    Ok(()),
+                    Err("I2C write failed (i2c_nack)".to_string()),
+                    Ok(())
+                ]
+            ),
+            (
+                "Wrote 2 of 3 registers, failed Mute: I2C write failed (i2c_nack)".to_string(),
+                true
+            )
         );
-        assert_eq!(dtype.value_to_bytes(-4.0), vec![0xFF, 0xFF, 0xFF, 0xFC]);
-        assert_eq!(dtype.value_to_bytes(-2.0), vec![0xFF, 0xFF, 0xFF, 0xFE]);
-        assert_eq!(dtype.value_to_bytes(-1.0), vec![0xFF, 0xFF, 0xFF, 0xFF]);
-        assert_eq!(dtype.value_to_bytes(0.0), vec![0x00, 0x00, 0x00, 0x00]);
-        assert_eq!(dtype.value_to_bytes(1.0), vec![0x00, 0x00, 0x00, 0x01]);
-        assert_eq!(dtype.value_to_bytes(2.0), vec![0x00, 0x00, 0x00, 0x02]);
-        assert_eq!(dtype.value_to_bytes(3.0), vec![0x00, 0x00, 0x00, 0x03]);
-        assert_eq!(dtype.value_to_bytes(4.0), vec![0x00, 0x00, 0x00, 0x04]);
+    }
+
+    #[test]
+    fn test_initial_theme() {
+        assert_eq!(initial_theme(Some("dark"), false), "dark");
+        assert_eq!(initial_theme(Some("light"), true), "light");
+        // nessuna scelta salvata, o un valore sconosciuto: decide il sistema
+        assert_eq!(initial_theme(None, true), "dark");
+        assert_eq!(initial_theme(Some("sepia"), false), "light");
+    }
+
+    #[test]
+    fn test_parse_hex_input() {
         assert_eq!(
-            dtype.value_to_bytes(2147483646.0),
-            vec![0x7F, 0xFF, 0xFF, 0xFE]
+            parse_hex_input("00 80 00 00", 4),
+            Ok(vec![0x00, 0x80, 0x00, 0x00])
         );
+        assert_eq!(parse_hex_input(" fffe ", 2), Ok(vec![0xFF, 0xFE]));
+        // wrong byte count for the register
+        assert!(parse_hex_input("00 80 00", 4).is_err());
+        assert!(parse_hex_input("00 80 00 00 00", 4).is_err());
+        assert!(parse_hex_input("0 80 00 00", 4).is_err());
+        assert!(parse_hex_input("zz 80 00 00", 4).is_err());
+    }
+
+    #[test]
+    fn test_aria_text() {
+        let register = get_dsp_register_by_address(0x0043).unwrap();
+        assert_eq!(aria_label(&register), "Gain (dB)");
+        assert_eq!(aria_value_text(&register, -6.27), "-6.3 dB");
+
+        let level = get_dsp_register_by_address(41).unwrap();
+        assert_eq!(aria_label(&level), "Signal Level - Aux ADC");
+        assert_eq!(aria_value_text(&level, 1024.0), "1024");
+    }
+
+    #[test]
+    fn test_registers_built_once() {
+        // stessa lista condivisa, non una nuova a ogni chiamata
+        assert!(Rc::ptr_eq(&registers(), &registers()));
+        assert_eq!(registers().len(), get_dsp_registers().len());
+    }
+
+    #[test]
+    fn test_part_mismatch_warning() {
+        assert_eq!(part_mismatch_warning("ADAU1452"), None);
+        assert_eq!(part_mismatch_warning("adau1466"), None);
+        assert!(part_mismatch_warning("ADAU1701")
+            .unwrap()
+            .starts_with("Connected DSP is ADAU1701"));
+    }
+
+    #[test]
+    fn test_staleness_text() {
+        assert_eq!(staleness_text(0.0), None);
+        assert_eq!(staleness_text(STALE_AFTER_MS - 1.0), None);
+        assert_eq!(staleness_text(5400.0), Some("5s ago".to_string()));
+        assert_eq!(staleness_text(125_000.0), Some("2m ago".to_string()));
+    }
+
+    #[test]
+    fn test_format_latency() {
+        let mut stats = LatencyStats::default();
+        assert_eq!(format_latency(&stats), "Latency 0 ms");
+
+        stats.record(10.4, None);
+        assert_eq!(format_latency(&stats), "Latency 10 ms (avg 10 ms)");
+        stats.record(20.0, Some(1.234));
         assert_eq!(
-            dtype.value_to_bytes(2147483647.0),
-            vec![0x7F, 0xFF, 0xFF, 0xFF]
+            format_latency(&stats),
+            "Latency 20 ms (avg 15 ms, I2C 1.2 ms)"
         );
     }
+
+    #[test]
+    fn test_unit_precision() {
+        // i dB tengono il decimale anche se è zero
+        assert_eq!(MeasurementUnit::Decibel.format(-6.0, 1), "-6.0");
+        assert_eq!(MeasurementUnit::Decibel.format(-6.27, 1), "-6.3");
+        assert_eq!(MeasurementUnit::Hertz.format(1000.4, 0), "1000");
+        assert_eq!(MeasurementUnit::Hertz.format(62.5, 1), "62.5");
+        assert_eq!(MeasurementUnit::None.format(268435456.0, 0), "268435456");
+        assert_eq!(MeasurementUnit::None.format(0.5, 3), "0.5");
+
+        let registers = get_dsp_registers();
+        let gain = &registers[1];
+        assert_eq!(gain.precision(), 1);
+        assert_eq!(gain.format_value(-6.0), "-6.0");
+        // il contatore non mostra decimali spuri
+        assert_eq!(registers[3].format_value(1234.0), "1234");
+
+        let mut precise = gain.clone();
+        precise.precision = Some(3);
+        assert_eq!(precise.format_value(-6.0), "-6.000");
+    }
+
+    #[test]
+    fn test_pending_writes_keep_last_value() {
+        let mut pending = PendingWrites::default();
+        assert_eq!(pending.replace(0x0043, 1, vec![0x01]), None);
+        // un nuovo valore annulla il timer precedente
+        assert_eq!(pending.replace(0x0043, 2, vec![0x02]), Some(1));
+        assert_eq!(pending.replace(0x0044, 3, vec![0x03]), None);
+
+        // il timer annullato, se scattasse comunque, non scrive nulla
+        assert_eq!(pending.take(0x0043, 1), None);
+        assert_eq!(pending.take(0x0043, 2), Some(vec![0x02]));
+        assert_eq!(pending.take(0x0043, 2), None);
+        assert_eq!(pending.take(0x0044, 3), Some(vec![0x03]));
+    }
 }
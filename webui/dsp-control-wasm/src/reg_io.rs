@@ -1,11 +1,15 @@
 use js_sys::{Array, Function, Object, Promise, Reflect};
-use log::{error, info};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
-    Document, Element, HtmlElement, HtmlInputElement, Request, RequestInit, RequestMode, Response,
-    Window,
+    AbortController, Document, Element, HtmlElement, HtmlInputElement, MessageEvent, Request,
+    RequestInit, RequestMode, Response, WebSocket, Window,
 };
 
 use crate::get_window;
@@ -19,12 +23,339 @@ fn get_api_base_url() -> &'static str {
     unsafe { API_BASE_URL }
 }
 
-/// Legge un registro DSP
-pub async fn read_registers(address: u16, size: u16) -> Result<Vec<u8>, JsValue> {
+thread_local! {
+    // Token richiesto dal device, None se l'autenticazione è disabilitata
+    static AUTH_TOKEN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Imposta il token mandato al device, None o vuoto per non mandarne
+pub fn set_auth_token(token: Option<String>) {
+    let token = token.filter(|token| !token.is_empty());
+    AUTH_TOKEN.with(|t| *t.borrow_mut() = token);
+}
+
+fn auth_token() -> Option<String> {
+    AUTH_TOKEN.with(|t| t.borrow().clone())
+}
+
+pub fn has_auth_token() -> bool {
+    AUTH_TOKEN.with(|t| t.borrow().is_some())
+}
+
+/// URL base per i WebSocket, lo stesso host dell'API con schema ws:// o wss://
+fn get_ws_base_url() -> Result<String, JsValue> {
+    // "http://host" -> "ws://host", "https://host" -> "wss://host"
+    if let Some(rest) = get_api_base_url().strip_prefix("http") {
+        return Ok(format!("ws{}", rest));
+    }
+
+    // URL relativo, stesso host della pagina
+    let location = get_window()?.location();
+    let scheme = if location.protocol()? == "https:" {
+        "wss"
+    } else {
+        "ws"
+    };
+    Ok(format!("{}://{}", scheme, location.host()?))
+}
+
+/// Stato della connessione con il device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Online,
+    Offline,
+}
+
+// Richieste fallite di fila prima di considerare il device offline
+const OFFLINE_AFTER_FAILURES: u32 = 3;
+
+/// Conta le richieste fallite di fila e decide quando il device è offline
+#[derive(Clone, Copy, Debug)]
+struct ConnectionTracker {
+    failures: u32,
+    state: ConnectionState,
+}
+
+impl ConnectionTracker {
+    const fn new() -> Self {
+        Self {
+            failures: 0,
+            state: ConnectionState::Online,
+        }
+    }
+
+    /// Registra l'esito di una richiesta, restituisce il nuovo stato se è cambiato
+    fn record(&mut self, ok: bool) -> Option<ConnectionState> {
+        let previous = self.state;
+        if ok {
+            self.failures = 0;
+            self.state = ConnectionState::Online;
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            if self.failures >= OFFLINE_AFTER_FAILURES {
+                self.state = ConnectionState::Offline;
+            }
+        }
+        (self.state != previous).then_some(self.state)
+    }
+}
+
+type Listener = Box<dyn Fn(ConnectionState)>;
+
+thread_local! {
+    static CONNECTION: RefCell<ConnectionTracker> =
+        const { RefCell::new(ConnectionTracker::new()) };
+    // Chiamato a ogni cambio di stato della connessione
+    static CONNECTION_LISTENER: RefCell<Option<Listener>> = const { RefCell::new(None) };
+}
+
+/// Stato attuale della connessione con il device
+pub fn connection_state() -> ConnectionState {
+    CONNECTION.with(|c| c.borrow().state)
+}
+
+/// Registra la funzione chiamata quando il device va offline o torna online
+pub fn set_connection_listener(listener: impl Fn(ConnectionState) + 'static) {
+    CONNECTION_LISTENER.with(|l| *l.borrow_mut() = Some(Box::new(listener)));
+}
+
+fn record_request(ok: bool) {
+    let changed = CONNECTION.with(|c| c.borrow_mut().record(ok));
+    if let Some(state) = changed {
+        info!("Device is now {:?}", state);
+        CONNECTION_LISTENER.with(|l| {
+            if let Some(listener) = l.borrow().as_ref() {
+                listener(state);
+            }
+        });
+    }
+}
+
+// Richieste considerate per la latenza media
+const LATENCY_SAMPLES: usize = 20;
+
+/// Latenza delle ultime richieste al device, in ms
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyStats {
+    samples: VecDeque<f64>,
+    /// Durata dell'ultima richiesta, rete e I2C
+    pub last_ms: f64,
+    /// Tempo I2C riportato dal device per l'ultima richiesta, se presente
+    pub last_device_ms: Option<f64>,
+}
+
+impl LatencyStats {
+    const fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            last_ms: 0.0,
+            last_device_ms: None,
+        }
+    }
+
+    pub(crate) fn record(&mut self, elapsed_ms: f64, device_ms: Option<f64>) {
+        if self.samples.len() == LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(elapsed_ms);
+        self.last_ms = elapsed_ms;
+        self.last_device_ms = device_ms;
+    }
+
+    /// Media delle ultime `LATENCY_SAMPLES` richieste
+    pub fn average_ms(&self) -> Option<f64> {
+        (!self.samples.is_empty())
+            .then(|| self.samples.iter().sum::<f64>() / self.samples.len() as f64)
+    }
+}
+
+/// Durata `dur` della metrica `name` in un header Server-Timing, in ms
+///
+/// e.g. `i2c;dur=1.234, total;dur=3`
+fn parse_server_timing(header: &str, name: &str) -> Option<f64> {
+    header.split(',').find_map(|metric| {
+        let mut params = metric.split(';').map(str::trim);
+        if params.next()? != name {
+            return None;
+        }
+        params.find_map(|param| param.strip_prefix("dur=")?.parse().ok())
+    })
+}
+
+type LatencyListener = Box<dyn Fn(&LatencyStats)>;
+
+thread_local! {
+    static LATENCY: RefCell<LatencyStats> = const { RefCell::new(LatencyStats::new()) };
+    // Chiamato dopo ogni richiesta completata
+    static LATENCY_LISTENER: RefCell<Option<LatencyListener>> = const { RefCell::new(None) };
+}
+
+/// Registra la funzione chiamata con la latenza aggiornata dopo ogni richiesta
+pub fn set_latency_listener(listener: impl Fn(&LatencyStats) + 'static) {
+    LATENCY_LISTENER.with(|l| *l.borrow_mut() = Some(Box::new(listener)));
+}
+
+fn record_latency(elapsed_ms: f64, device_ms: Option<f64>) {
+    LATENCY.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.record(elapsed_ms, device_ms);
+        LATENCY_LISTENER.with(|l| {
+            if let Some(listener) = l.borrow().as_ref() {
+                listener(&stats);
+            }
+        });
+    });
+}
+
+thread_local! {
+    // Tempo massimo per una richiesta, risposta completa inclusa, in ms
+    static REQUEST_TIMEOUT_MS: Cell<i32> = const { Cell::new(3000) };
+}
+
+/// Cambia il timeout delle richieste al device
+pub fn set_request_timeout(timeout_ms: i32) {
+    REQUEST_TIMEOUT_MS.set(timeout_ms);
+}
+
+/// Esegue una richiesta e ne legge il corpo, con timeout e tenendo traccia
+/// dello stato della connessione
+///
+/// The timeout covers the whole body, so a device that hangs mid-response is
+/// aborted too. Only network failures and timeouts count against the
+/// connection, an HTTP error status still means the device is reachable.
+/// Completed requests are timed for the latency display, together with the
+/// I2C time from the device's Server-Timing header when there is one.
+/// The auth token, if set, goes in the X-Token header.
+async fn fetch(request: &Request) -> Result<(u16, String), JsValue> {
+    let window = get_window()?;
+    let timeout_ms = REQUEST_TIMEOUT_MS.get();
+
+    if let Some(token) = auth_token() {
+        request.headers().set("X-Token", &token)?;
+    }
+
+    let controller = AbortController::new()?;
+    let init = RequestInit::new();
+    init.set_signal(Some(&controller.signal()));
+
+    let timed_out = Rc::new(Cell::new(false));
+    let on_timeout = {
+        let controller = controller.clone();
+        let timed_out = timed_out.clone();
+        Closure::once(move || {
+            timed_out.set(true);
+            controller.abort();
+        })
+    };
+    let timer = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        on_timeout.as_ref().unchecked_ref(),
+        timeout_ms,
+    )?;
+
+    let performance = window.performance();
+    let start = performance.as_ref().map(|p| p.now());
+
+    let result = async {
+        let resp: Response = JsFuture::from(window.fetch_with_request_and_init(request, &init))
+            .await?
+            .dyn_into()?;
+        let text = JsFuture::from(resp.text()?).await?;
+        let device_ms = resp
+            .headers()
+            .get("Server-Timing")
+            .ok()
+            .flatten()
+            .and_then(|header| parse_server_timing(&header, "i2c"));
+        Ok::<_, JsValue>((
+            resp.status(),
+            text.as_string().unwrap_or_default(),
+            device_ms,
+        ))
+    }
+    .await;
+
+    // il timer va tolto prima di liberare la sua closure
+    window.clear_timeout_with_handle(timer);
+    drop(on_timeout);
+
+    match result {
+        Ok((status, text, device_ms)) => {
+            record_request(true);
+            if let (Some(performance), Some(start)) = (performance, start) {
+                record_latency(performance.now() - start, device_ms);
+            }
+            Ok((status, text))
+        }
+        Err(_) if timed_out.get() => {
+            record_request(false);
+            Err(JsValue::from_str(&format!(
+                "Request to {} timed out after {} ms",
+                request.url(),
+                timeout_ms
+            )))
+        }
+        Err(e) => {
+            record_request(false);
+            Err(e)
+        }
+    }
+}
+
+/// Controlla se il device risponde "ok" su GET /
+pub async fn probe_device() -> bool {
     let mut opts = RequestInit::new();
     opts.method("GET");
     opts.mode(RequestMode::Cors);
 
+    let Ok(request) = Request::new_with_str_and_init(&format!("{}/", get_api_base_url()), &opts)
+    else {
+        return false;
+    };
+
+    fetch(&request)
+        .await
+        .is_ok_and(|(status, text)| status == 200 && text.trim() == "ok")
+}
+
+/// Corpo JSON restituito dal device in caso di errore
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    code: Option<String>,
+}
+
+impl ErrorResponse {
+    fn message(&self) -> String {
+        match &self.code {
+            Some(code) => format!("{} ({})", self.error, code),
+            None => self.error.clone(),
+        }
+    }
+}
+
+/// Interpreta il corpo di una risposta del device
+///
+/// An `{"error": ..., "code": ...}` body is an Err with the device's
+/// message, whatever the status. Anything else must have the success shape,
+/// otherwise the Err tells which status and body came back rather than
+/// only the field that failed to deserialize.
+fn parse_response<T: DeserializeOwned>(status: u16, body: &str) -> Result<T, String> {
+    if let Ok(response) = serde_json::from_str::<ErrorResponse>(body) {
+        return Err(response.message());
+    }
+    serde_json::from_str(body).map_err(|e| {
+        let body: String = body.trim().chars().take(80).collect();
+        format!("Unexpected response (HTTP {}) {:?}: {}", status, body, e)
+    })
+}
+
+/// Legge un registro DSP
+pub async fn read_registers(address: u16, size: u16) -> Result<Vec<u8>, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+
     let url = format!(
         "{}/read?addr=0x{:04x}&len={}",
         get_api_base_url(),
@@ -33,79 +364,735 @@ pub async fn read_registers(address: u16, size: u16) -> Result<Vec<u8>, JsValue>
     );
     let request = Request::new_with_str_and_init(&url, &opts)?;
 
-    let window = get_window()?;
-    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-    let resp: Response = resp_value.dyn_into().unwrap();
+    let (status, body) = fetch(&request).await?;
 
-    let json = JsFuture::from(resp.json()?).await?;
+    let response: ReadRegisterResponse =
+        parse_response(status, &body).map_err(|e| JsValue::from_str(&e))?;
 
-    #[derive(Debug, Serialize, Deserialize)]
-    struct ReadRegisterResponse {
-        addr: String,
-        len: u16,
-        data: String,
+    info!("Received data: {}", response.data);
+
+    parse_hex_data(&response.data).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Risposta di /read, e di ogni elemento di /read_multi
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadRegisterResponse {
+    #[serde(default)]
+    addr: String,
+    #[serde(default)]
+    len: u16,
+    /// Hex contiguo, due cifre per byte, come il parametro data di /write
+    data: String,
+}
+
+/// Bytes in hex contiguo, il formato del parametro data di /write
+fn hex_data(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Converte la stringa hex "data" restituita dal device in bytes
+pub(crate) fn parse_hex_data(data: &str) -> Result<Vec<u8>, String> {
+    if !data.len().is_multiple_of(2) {
+        return Err(format!("Odd length hex data {:?}", data));
     }
 
-    // Convert JsValue to our Rust struct
-    let response: ReadRegisterResponse = serde_wasm_bindgen::from_value(json)?;
+    (0..data.len())
+        .step_by(2)
+        .map(|i| {
+            data.get(i..i + 2)
+                .filter(|pair| pair.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("Invalid hex data {:?}", data))
+        })
+        .collect()
+}
 
-    // Parse the data string into bytes
-    let data_str = &response.data;
-    info!("Received data: {}", data_str);
+/// Lunghezza massima di una lettura HTTP, come HTTP_MAX_READ_LEN nel firmware
+pub const MAX_READ_LEN: u16 = 256;
+/// Registri al massimo in una richiesta /read_multi, come HTTP_MAX_BATCH_READS nel firmware
+pub const MAX_BATCH_READS: usize = 32;
+/// Byte al massimo in una scansione, quanto sta in una sola richiesta /read_multi
+pub const MAX_SCAN_LEN: usize = MAX_READ_LEN as usize * MAX_BATCH_READS;
 
-    // Rimuovi le parentesi quadre e dividi per virgole
-    let brackets = ['[', ']'];
-    let cleaned_str = data_str.replace(&brackets[..], "");
-    let hex_values: Vec<&str> = cleaned_str.split(',').map(|s| s.trim()).collect();
+/// Divide la lettura di `count` parole da `word_len` bytes in letture da al più
+/// MAX_READ_LEN bytes, ognuna all'indirizzo in cui arriva l'auto-incremento
+fn scan_chunks(start: u16, count: u16, word_len: u16) -> Vec<(u16, u16)> {
+    let words_per_chunk = (MAX_READ_LEN / word_len).max(1);
 
-    // Converti le stringhe esadecimali in array di byte
-    let mut byte_array = Vec::new();
-    for hex_str in hex_values {
-        if let Ok(byte) = u8::from_str_radix(&hex_str.replace("0x", ""), 16) {
-            byte_array.push(byte);
+    (0..count)
+        .step_by(words_per_chunk as usize)
+        .map(|offset| {
+            let words = words_per_chunk.min(count - offset);
+            (start.wrapping_add(offset), words * word_len)
+        })
+        .collect()
+}
+
+/// Legge `count` indirizzi consecutivi da `word_len` bytes a partire da `start`
+///
+/// The range is read with a single /read_multi request when the device
+/// supports it, and is limited to MAX_SCAN_LEN bytes.
+pub async fn read_range(start: u16, count: u16, word_len: u16) -> Result<Vec<u8>, JsValue> {
+    let total = count as usize * word_len as usize;
+    if word_len == 0 || total == 0 {
+        return Ok(Vec::new());
+    }
+    if total > MAX_SCAN_LEN {
+        return Err(JsValue::from_str(&format!(
+            "Scan of {} bytes exceeds maximum of {} bytes",
+            total, MAX_SCAN_LEN
+        )));
+    }
+
+    let chunks = scan_chunks(start, count, word_len);
+    let results = read_registers_batch(&chunks).await?;
+    Ok(results.concat())
+}
+
+// Diventa false se il device non supporta /read_multi, da lì in poi si legge un registro alla volta
+static mut BATCH_READ_AVAILABLE: bool = true;
+
+/// Legge più registri DSP, con una sola richiesta se il device supporta /read_multi
+pub async fn read_registers_batch(registers: &[(u16, u16)]) -> Result<Vec<Vec<u8>>, JsValue> {
+    if unsafe { BATCH_READ_AVAILABLE } {
+        match read_registers_multi(registers).await? {
+            Some(results) => return Ok(results),
+            None => {
+                info!("Batch read not supported by the device, falling back to single reads");
+                unsafe {
+                    BATCH_READ_AVAILABLE = false;
+                }
+            }
         }
     }
 
-    Ok(byte_array)
+    let mut results = Vec::with_capacity(registers.len());
+    for &(address, size) in registers {
+        results.push(read_registers(address, size).await?);
+    }
+    Ok(results)
 }
 
-/// Scrive dei bytes in un registro DSP
-pub async fn write_registers(address: u16, bytes: &[u8]) -> Result<bool, JsValue> {
-    // Converti il valore in stringa esadecimale per l'API
-    let mut hex_value = String::new();
-    for byte in bytes {
-        hex_value.push_str(&format!("{:02x}", byte));
+/// Chiama /read_multi, restituisce None se l'endpoint non esiste
+async fn read_registers_multi(registers: &[(u16, u16)]) -> Result<Option<Vec<Vec<u8>>>, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+
+    let url = format!(
+        "{}/read_multi?regs={}",
+        get_api_base_url(),
+        format_read_list(registers)
+    );
+    let request = Request::new_with_str_and_init(&url, &opts)?;
+
+    let (status, body) = fetch(&request).await?;
+
+    if status == 404 {
+        return Ok(None);
     }
 
-    let mut opts = RequestInit::new();
-    opts.method("GET");
-    opts.mode(RequestMode::Cors);
+    let responses: Vec<ReadRegisterResponse> =
+        parse_response(status, &body).map_err(|e| JsValue::from_str(&e))?;
+
+    let results = responses
+        .iter()
+        .map(|response| parse_hex_data(&response.data))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    Ok(Some(results))
+}
+
+/// Lista di registri nel formato "addr:len,..." di /read_multi e /ws
+fn format_read_list(registers: &[(u16, u16)]) -> String {
+    registers
+        .iter()
+        .map(|(address, size)| format!("0x{:04x}:{}", address, size))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Apre lo stream WebSocket /ws e si iscrive a `registers`
+///
+/// Il device manda i valori a intervalli regolari, `on_values` riceve i bytes
+/// di ogni registro nello stesso ordine di `registers`. `on_close` viene
+/// chiamato alla chiusura con `true` se la connessione era stata aperta,
+/// `false` se non si è mai connesso.
+pub fn open_register_stream(
+    registers: &[(u16, u16)],
+    mut on_values: impl FnMut(Vec<Vec<u8>>) + 'static,
+    mut on_close: impl FnMut(bool) + 'static,
+) -> Result<WebSocket, JsValue> {
+    let socket = WebSocket::new(&format!("{}/ws", get_ws_base_url()?))?;
+
+    let subscribe = format_subscribe_message(registers, auth_token().as_deref());
+    let opened = Rc::new(Cell::new(false));
+
+    let onopen = {
+        let socket = socket.clone();
+        let opened = opened.clone();
+        Closure::wrap(Box::new(move || {
+            opened.set(true);
+            if let Err(e) = socket.send_with_str(&subscribe) {
+                error!("Failed to subscribe to the register stream: {:?}", e);
+            }
+        }) as Box<dyn FnMut()>)
+    };
+    socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+
+    let mut last_seq = None;
+    let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let Some(text) = event.data().as_string() else {
+            return;
+        };
+        match parse_stream_message(&text) {
+            Ok(update) => {
+                if let Some(skipped) = update.skipped_since(last_seq) {
+                    warn!(
+                        "Register stream skipped {} updates, the device read took {} us",
+                        skipped,
+                        update.read_us.unwrap_or_default()
+                    );
+                }
+                last_seq = update.seq.or(last_seq);
+                on_values(update.values)
+            }
+            Err(e) => error!("Register stream error: {}", e),
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    // un errore di connessione è sempre seguito da un evento close
+    let onclose = Closure::wrap(Box::new(move || {
+        on_close(opened.get());
+    }) as Box<dyn FnMut()>);
+    socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+
+    Ok(socket)
+}
+
+/// Messaggio di iscrizione allo stream /ws, il token va nel messaggio
+/// perché il browser non può mettere header sul WebSocket
+fn format_subscribe_message(registers: &[(u16, u16)], token: Option<&str>) -> String {
+    let mut message = serde_json::json!({ "regs": format_read_list(registers) });
+    if let Some(token) = token {
+        message["token"] = token.into();
+    }
+    message.to_string()
+}
+
+/// Messaggio dello stream /ws: i firmware più vecchi mandano solo l'array
+/// dei valori, senza numero del tick e durata della lettura
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StreamMessage {
+    Tick {
+        seq: u64,
+        read_us: u64,
+        values: Vec<ReadRegisterResponse>,
+    },
+    Values(Vec<ReadRegisterResponse>),
+}
+
+/// Valori di un messaggio dello stream /ws
+#[derive(Debug, PartialEq)]
+struct StreamUpdate {
+    /// Bytes di ogni registro, nell'ordine dell'iscrizione
+    values: Vec<Vec<u8>>,
+    /// Numero del tick del device
+    seq: Option<u64>,
+    /// Durata della lettura I2C sul device
+    read_us: Option<u64>,
+}
+
+impl StreamUpdate {
+    /// Tick saltati dal device dopo `last_seq`, perché le letture durano più
+    /// dell'intervallo dello stream
+    fn skipped_since(&self, last_seq: Option<u64>) -> Option<u64> {
+        let skipped = self.seq?.checked_sub(last_seq? + 1)?;
+        (skipped > 0).then_some(skipped)
+    }
+}
+
+/// Converte un messaggio dello stream /ws nei bytes di ogni registro
+fn parse_stream_message(text: &str) -> Result<StreamUpdate, String> {
+    if let Ok(response) = serde_json::from_str::<ErrorResponse>(text) {
+        return Err(response.message());
+    }
+
+    let message: StreamMessage = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let (responses, seq, read_us) = match message {
+        StreamMessage::Tick {
+            seq,
+            read_us,
+            values,
+        } => (values, Some(seq), Some(read_us)),
+        StreamMessage::Values(values) => (values, None, None),
+    };
+
+    let values = responses
+        .iter()
+        .map(|response| parse_hex_data(&response.data))
+        .collect::<Result<_, _>>()?;
+
+    Ok(StreamUpdate {
+        values,
+        seq,
+        read_us,
+    })
+}
+
+/// Scrive dei bytes in un registro DSP
+pub async fn write_registers(address: u16, bytes: &[u8]) -> Result<bool, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
 
     let url = format!(
         "{}/write?addr=0x{:04x}&data={}",
         get_api_base_url(),
         address,
-        hex_value
+        hex_data(bytes)
     );
     let request = Request::new_with_str_and_init(&url, &opts)?;
 
-    let window = get_window()?;
-    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-    let resp: Response = resp_value.dyn_into().unwrap();
-
-    let json = JsFuture::from(resp.json()?).await?;
+    let (status, body) = fetch(&request).await?;
 
     #[derive(Debug, Serialize, Deserialize)]
     struct WriteRegisterResponse {
         status: String,
+        #[serde(default)]
         addr: String,
+        #[serde(default)]
         data_written: String,
+        #[serde(default)]
         length: u16,
     }
-    // Convert JsValue to our Rust struct
-    let response: WriteRegisterResponse = serde_wasm_bindgen::from_value(json)?;
+    let response: WriteRegisterResponse =
+        parse_response(status, &body).map_err(|e| JsValue::from_str(&e))?;
 
     // Check if the write was successful
     let success = response.status == "ok";
     Ok(success)
 }
+
+/// Configurazione restituita da /config
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub chip_map: String,
+    /// Le scritture sono bloccate da /config?freeze=1
+    #[serde(default)]
+    pub frozen: bool,
+}
+
+/// Legge la configurazione del device
+pub async fn read_config() -> Result<DeviceConfig, JsValue> {
+    config_request("").await
+}
+
+/// Attiva o disattiva il blocco delle scritture sul device
+pub async fn set_frozen(frozen: bool) -> Result<DeviceConfig, JsValue> {
+    config_request(if frozen { "?freeze=1" } else { "?freeze=0" }).await
+}
+
+async fn config_request(query: &str) -> Result<DeviceConfig, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+
+    let url = format!("{}/config{}", get_api_base_url(), query);
+    let request = Request::new_with_str_and_init(&url, &opts)?;
+
+    let (status, body) = fetch(&request).await?;
+
+    parse_response(status, &body).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Parte restituita da /identify, entrambi i campi mancano se il device non la conosce
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    /// ID letto dal registro di identificazione, in hex
+    pub id: Option<String>,
+    pub part: Option<String>,
+}
+
+/// Chiede al device quale DSP è collegato
+///
+/// Firmware without /identify reports an unknown part.
+pub async fn identify() -> Result<DeviceIdentity, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+
+    let url = format!("{}/identify", get_api_base_url());
+    let request = Request::new_with_str_and_init(&url, &opts)?;
+
+    let (status, body) = fetch(&request).await?;
+    if status == 404 {
+        return Ok(DeviceIdentity::default());
+    }
+
+    parse_response(status, &body).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Funzioni del device restituite da /capabilities
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceCapabilities {
+    pub capabilities: Vec<String>,
+}
+
+impl DeviceCapabilities {
+    pub fn has(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|known| known == name)
+    }
+}
+
+/// Chiede al device quali funzioni supporta
+///
+/// None for firmware without /capabilities, whose features are then found
+/// out by trying them.
+pub async fn capabilities() -> Result<Option<DeviceCapabilities>, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::Cors);
+
+    let url = format!("{}/capabilities", get_api_base_url());
+    let request = Request::new_with_str_and_init(&url, &opts)?;
+
+    let (status, body) = fetch(&request).await?;
+    if status == 404 {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        parse_response(status, &body).map_err(|e| JsValue::from_str(&e))?,
+    ))
+}
+
+/// Errore restituito dal device per una scrittura mentre è bloccato
+pub fn is_frozen_error(message: &str) -> bool {
+    message.ends_with("(frozen)")
+}
+
+/// Scritture al massimo in una richiesta /write_multi, come HTTP_MAX_BATCH_WRITES nel firmware
+pub const MAX_BATCH_WRITES: usize = 32;
+
+// Diventa false se il device non ha /write_multi
+static mut BATCH_WRITE_AVAILABLE: bool = true;
+
+/// Usa /write_multi o no, secondo le capabilities del device
+pub fn set_batch_write_available(available: bool) {
+    unsafe {
+        BATCH_WRITE_AVAILABLE = available;
+    }
+}
+
+/// Elemento del corpo di /write_multi
+#[derive(Debug, Serialize, Deserialize)]
+struct WriteMultiEntry {
+    addr: String,
+    data: String,
+}
+
+/// Esito di un elemento di /write_multi
+#[derive(Debug, Serialize, Deserialize)]
+struct WriteMultiResult {
+    addr: String,
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    code: Option<String>,
+}
+
+/// Scrive più registri, con una /write_multi ogni MAX_BATCH_WRITES se il
+/// device la supporta, altrimenti con una /write per registro
+///
+/// Returns the outcome of every write, in the order of `writes`.
+pub async fn write_registers_batch(
+    writes: &[(u16, Vec<u8>)],
+) -> Result<Vec<Result<(), String>>, JsValue> {
+    let mut results = Vec::with_capacity(writes.len());
+
+    for chunk in writes.chunks(MAX_BATCH_WRITES) {
+        if unsafe { BATCH_WRITE_AVAILABLE } {
+            match write_registers_multi(chunk).await? {
+                Some(chunk_results) => {
+                    results.extend(chunk_results);
+                    continue;
+                }
+                None => {
+                    info!("Batch write not supported by the device, falling back to single writes");
+                    unsafe {
+                        BATCH_WRITE_AVAILABLE = false;
+                    }
+                }
+            }
+        }
+
+        for (address, bytes) in chunk {
+            results.push(match write_registers(*address, bytes).await {
+                Ok(true) => Ok(()),
+                Ok(false) => Err("write failed".to_string()),
+                Err(e) => Err(e.as_string().unwrap_or_else(|| "Unknown error".to_string())),
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+/// Chiama /write_multi, restituisce None se l'endpoint non esiste
+async fn write_registers_multi(
+    writes: &[(u16, Vec<u8>)],
+) -> Result<Option<Vec<Result<(), String>>>, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("POST");
+    opts.set_mode(RequestMode::Cors);
+    opts.set_body(&JsValue::from_str(&format_write_list(writes)));
+
+    let url = format!("{}/write_multi", get_api_base_url());
+    let request = Request::new_with_str_and_init(&url, &opts)?;
+    request.headers().set("Content-Type", "application/json")?;
+
+    let (status, body) = fetch(&request).await?;
+
+    if status == 404 || status == 405 {
+        return Ok(None);
+    }
+
+    let responses: Vec<WriteMultiResult> =
+        parse_response(status, &body).map_err(|e| JsValue::from_str(&e))?;
+    if responses.len() != writes.len() {
+        return Err(JsValue::from_str(&format!(
+            "Expected {} write results, got {}",
+            writes.len(),
+            responses.len()
+        )));
+    }
+
+    Ok(Some(responses.into_iter().map(write_result).collect()))
+}
+
+/// Corpo JSON di /write_multi: [{"addr":"0x0043","data":"01000000"},...]
+fn format_write_list(writes: &[(u16, Vec<u8>)]) -> String {
+    let entries: Vec<WriteMultiEntry> = writes
+        .iter()
+        .map(|(address, bytes)| WriteMultiEntry {
+            addr: format!("0x{:04x}", address),
+            data: hex_data(bytes),
+        })
+        .collect();
+    serde_json::to_string(&entries).unwrap_or_default()
+}
+
+fn write_result(result: WriteMultiResult) -> Result<(), String> {
+    if result.status == "ok" {
+        return Ok(());
+    }
+    Err(format!(
+        "{} ({})",
+        result.error.unwrap_or_else(|| "write failed".to_string()),
+        result.code.unwrap_or_else(|| result.status.clone())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex_data() {
+        assert_eq!(parse_hex_data("01020304"), Ok(vec![1, 2, 3, 4]));
+        assert_eq!(parse_hex_data("00FFab"), Ok(vec![0x00, 0xff, 0xab]));
+        assert_eq!(parse_hex_data(""), Ok(vec![]));
+        assert!(parse_hex_data("010").is_err());
+        assert!(parse_hex_data("0g").is_err());
+        assert!(parse_hex_data("+1").is_err());
+    }
+
+    #[test]
+    fn test_scan_chunks() {
+        assert_eq!(scan_chunks(0x0010, 4, 4), vec![(0x0010, 16)]);
+        // 64 parole da 4 bytes per lettura, gli indirizzi avanzano di 64
+        assert_eq!(
+            scan_chunks(0x0000, 150, 4),
+            vec![(0x0000, 256), (0x0040, 256), (0x0080, 88)]
+        );
+        assert_eq!(scan_chunks(0xf000, 3, 2), vec![(0xf000, 6)]);
+    }
+
+    #[test]
+    fn test_connection_tracker() {
+        let mut tracker = ConnectionTracker::new();
+
+        // un errore isolato non basta
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(true), None);
+
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(false), Some(ConnectionState::Offline));
+        assert_eq!(tracker.record(false), None);
+
+        assert_eq!(tracker.record(true), Some(ConnectionState::Online));
+        assert_eq!(tracker.failures, 0);
+    }
+
+    #[test]
+    fn test_latency() {
+        assert_eq!(parse_server_timing("i2c;dur=1.25", "i2c"), Some(1.25));
+        assert_eq!(
+            parse_server_timing("db;desc=\"x\", i2c;desc=bus;dur=3", "i2c"),
+            Some(3.0)
+        );
+        assert_eq!(parse_server_timing("i2cx;dur=3", "i2c"), None);
+        assert_eq!(parse_server_timing("i2c", "i2c"), None);
+
+        let mut stats = LatencyStats::new();
+        assert_eq!(stats.average_ms(), None);
+        for ms in 0..=LATENCY_SAMPLES {
+            stats.record(ms as f64, None);
+        }
+        stats.record(30.0, Some(2.0));
+        // solo gli ultimi LATENCY_SAMPLES campioni: 2..=20 e 30
+        assert_eq!(stats.average_ms(), Some((209.0 + 30.0) / 20.0));
+        assert_eq!(stats.last_ms, 30.0);
+        assert_eq!(stats.last_device_ms, Some(2.0));
+    }
+
+    #[test]
+    fn test_parse_stream_message() {
+        let update = parse_stream_message(
+            r#"{"seq":12,"t_ms":1200,"read_us":850,"values":[{"addr":"0x003d","len":2,"data":"0102"},{"addr":"0x004f","len":1,"data":"ff"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            update,
+            StreamUpdate {
+                values: vec![vec![0x01, 0x02], vec![0xff]],
+                seq: Some(12),
+                read_us: Some(850),
+            }
+        );
+        assert_eq!(update.skipped_since(None), None);
+        assert_eq!(update.skipped_since(Some(11)), None);
+        assert_eq!(update.skipped_since(Some(9)), Some(2));
+
+        // firmware senza scadenze: solo l'array dei valori
+        let update = parse_stream_message(
+            r#"[{"addr":"0x003d","len":2,"data":"0102"},{"addr":"0x004f","len":1,"data":"ff"}]"#,
+        )
+        .unwrap();
+        assert_eq!(update.values, vec![vec![0x01, 0x02], vec![0xff]]);
+        assert_eq!(update.seq, None);
+        assert_eq!(update.skipped_since(Some(3)), None);
+
+        assert_eq!(
+            parse_stream_message(r#"{"error":"I2C timeout after 100 ms","code":"i2c_timeout"}"#),
+            Err("I2C timeout after 100 ms (i2c_timeout)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response: ReadRegisterResponse =
+            parse_response(200, r#"{"addr":"0x003d","len":2,"data":"0102"}"#).unwrap();
+        assert_eq!(response.data, "0102");
+        // basta il campo data
+        let response: ReadRegisterResponse = parse_response(200, r#"{"data":"ff"}"#).unwrap();
+        assert_eq!(response.len, 0);
+
+        // l'errore del device vince sulla forma attesa, con o senza codice
+        assert_eq!(
+            parse_response::<ReadRegisterResponse>(
+                503,
+                r#"{"error":"DSP writes are frozen","code":"frozen"}"#
+            )
+            .unwrap_err(),
+            "DSP writes are frozen (frozen)"
+        );
+        assert_eq!(
+            parse_response::<Vec<ReadRegisterResponse>>(500, r#"{"error":"I2C busy"}"#)
+                .unwrap_err(),
+            "I2C busy"
+        );
+
+        let err = parse_response::<ReadRegisterResponse>(502, "Bad Gateway").unwrap_err();
+        assert!(err.starts_with(r#"Unexpected response (HTTP 502) "Bad Gateway""#));
+    }
+
+    #[test]
+    fn test_device_capabilities() {
+        let caps: DeviceCapabilities = serde_json::from_str(
+            r#"{"capabilities":["safeload","batch","ping","stream"],"bits":"0x000000a5"}"#,
+        )
+        .unwrap();
+        assert!(caps.has("batch"));
+        assert!(caps.has("stream"));
+        assert!(!caps.has("auth"));
+        assert!(!DeviceCapabilities::default().has("batch"));
+    }
+
+    #[test]
+    fn test_subscribe_message() {
+        assert_eq!(
+            format_subscribe_message(&[(0x003d, 4)], None),
+            r#"{"regs":"0x003d:4"}"#
+        );
+        assert_eq!(
+            format_subscribe_message(&[(0x003d, 4), (0x004f, 4)], Some("s3cret")),
+            r#"{"regs":"0x003d:4,0x004f:4","token":"s3cret"}"#
+        );
+    }
+
+    #[test]
+    fn test_write_multi_format() {
+        assert_eq!(
+            format_write_list(&[(0x0043, vec![1, 0, 0, 0]), (0xf020, vec![0x00, 0x08])]),
+            r#"[{"addr":"0x0043","data":"01000000"},{"addr":"0xf020","data":"0008"}]"#
+        );
+
+        let results: Vec<WriteMultiResult> = serde_json::from_str(
+            r#"[{"addr":"0x0043","status":"ok"},
+                {"addr":"0xf020","status":"error","error":"I2C write failed","code":"i2c_nack"}]"#,
+        )
+        .unwrap();
+        let results: Vec<_> = results.into_iter().map(write_result).collect();
+        assert_eq!(
+            results,
+            vec![Ok(()), Err("I2C write failed (i2c_nack)".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_device_identity() {
+        let identity: DeviceIdentity =
+            serde_json::from_str(r#"{ "id": "1452", "part": "ADAU1452" }"#).unwrap();
+        assert_eq!(identity.part.as_deref(), Some("ADAU1452"));
+
+        let unknown: DeviceIdentity =
+            serde_json::from_str(r#"{ "id": null, "part": null }"#).unwrap();
+        assert_eq!(unknown, DeviceIdentity::default());
+    }
+
+    #[test]
+    fn test_device_config() {
+        let config: DeviceConfig =
+            serde_json::from_str(r#"{"chip_map":"1:0x3b","frozen":true}"#).unwrap();
+        assert!(config.frozen);
+
+        // firmware senza freeze
+        let config: DeviceConfig = serde_json::from_str(r#"{"chip_map":"1:0x3b"}"#).unwrap();
+        assert!(!config.frozen);
+
+        assert!(is_frozen_error(
+            "Device frozen, writes are disabled (frozen)"
+        ));
+        assert!(!is_frozen_error("I2C timeout after 100 ms (i2c_timeout)"));
+    }
+}
@@ -0,0 +1,369 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use log::info;
+use serde::Serialize;
+use sigma_tcp_rs::registers::be_bytes_to_i64;
+use wasm_bindgen::prelude::*;
+use web_sys::{Blob, BlobPropertyBag, Document, Element, HtmlElement, HtmlInputElement, Url};
+
+use crate::reg_io::{read_range, MAX_SCAN_LEN};
+use crate::{format_hex_bytes, get_document, set_status};
+
+/// Una parola letta durante una scansione
+#[derive(Clone, Debug, PartialEq)]
+struct ScanCell {
+    address: u16,
+    bytes: Vec<u8>,
+}
+
+impl ScanCell {
+    /// Valore con segno, big-endian come i registri del DSP
+    fn value(&self) -> i64 {
+        be_bytes_to_i64(&self.bytes)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.bytes.iter().all(|&b| b == 0)
+    }
+}
+
+/// Riga dell'export JSON
+#[derive(Serialize)]
+struct ScanExportRow {
+    addr: String,
+    hex: String,
+    value: i64,
+}
+
+thread_local! {
+    // Ultima scansione, per evidenziare i cambiamenti e per l'export
+    static LAST_SCAN: RefCell<Vec<ScanCell>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Divide i bytes letti in una cella per indirizzo
+fn scan_cells(start: u16, word_len: u16, data: &[u8]) -> Vec<ScanCell> {
+    data.chunks(word_len as usize)
+        .enumerate()
+        .map(|(i, bytes)| ScanCell {
+            address: start.wrapping_add(i as u16),
+            bytes: bytes.to_vec(),
+        })
+        .collect()
+}
+
+/// Indirizzi presenti in entrambe le scansioni con un valore diverso
+fn changed_addresses(previous: &[ScanCell], current: &[ScanCell]) -> HashSet<u16> {
+    current
+        .iter()
+        .filter(|cell| {
+            previous
+                .iter()
+                .any(|old| old.address == cell.address && old.bytes != cell.bytes)
+        })
+        .map(|cell| cell.address)
+        .collect()
+}
+
+fn scan_to_csv(cells: &[ScanCell]) -> String {
+    let mut csv = String::from("address,hex,decimal\n");
+    for cell in cells {
+        csv.push_str(&format!(
+            "0x{:04X},{},{}\n",
+            cell.address,
+            format_hex_bytes(&cell.bytes),
+            cell.value()
+        ));
+    }
+    csv
+}
+
+fn scan_to_json(cells: &[ScanCell]) -> String {
+    let rows: Vec<ScanExportRow> = cells
+        .iter()
+        .map(|cell| ScanExportRow {
+            addr: format!("0x{:04X}", cell.address),
+            hex: format_hex_bytes(&cell.bytes),
+            value: cell.value(),
+        })
+        .collect();
+    serde_json::to_string_pretty(&rows).unwrap_or_default()
+}
+
+/// Indirizzo esadecimale (con 0x) o decimale
+fn parse_address(text: &str) -> Option<u16> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Crea un campo con etichetta nel form della scansione
+fn create_field(
+    document: &Document,
+    form: &Element,
+    id: &str,
+    label: &str,
+    input_type: &str,
+    value: &str,
+) -> Result<HtmlInputElement, JsValue> {
+    let label_element = document.create_element("label")?;
+    label_element.set_class_name("dsp-control__scan-label");
+    label_element.set_attribute("for", id)?;
+    label_element.set_text_content(Some(label));
+
+    let input = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    input.set_id(id);
+    input.set_type(input_type);
+    input.set_class_name("dsp-control__number-input");
+    input.set_value(value);
+
+    form.append_child(&label_element)?;
+    form.append_child(&input)?;
+    Ok(input)
+}
+
+fn create_button(document: &Document, form: &Element, text: &str) -> Result<HtmlElement, JsValue> {
+    let button = document
+        .create_element("button")?
+        .dyn_into::<HtmlElement>()?;
+    button.set_attribute("type", "button")?;
+    button.set_class_name("dsp-control__scan-button");
+    button.set_text_content(Some(text));
+    form.append_child(&button)?;
+    Ok(button)
+}
+
+/// Crea lo strumento di scansione di un intervallo di indirizzi in #scanContainer
+pub fn init_scan_tool(document: &Document) -> Result<(), JsValue> {
+    let Some(container) = document.get_element_by_id("scanContainer") else {
+        return Ok(());
+    };
+
+    let title = document.create_element("h2")?;
+    title.set_class_name("dsp-control__scan-title");
+    title.set_text_content(Some("Scan range"));
+
+    let form = document.create_element("div")?;
+    form.set_class_name("dsp-control__scan-form");
+
+    let start = create_field(document, &form, "scanStart", "Start", "text", "0x0000")?;
+    let count = create_field(document, &form, "scanCount", "Count", "number", "16")?;
+    count.set_min("1");
+    let word_len = create_field(document, &form, "scanWordLen", "Word bytes", "number", "4")?;
+    word_len.set_min("1");
+    word_len.set_max("8");
+
+    let scan_button = create_button(document, &form, "Scan")?;
+    let csv_button = create_button(document, &form, "Export CSV")?;
+    let json_button = create_button(document, &form, "Export JSON")?;
+
+    let results = document.create_element("div")?;
+    results.set_id("scanResults");
+    results.set_class_name("dsp-control__scan-results");
+
+    container.append_child(&title)?;
+    container.append_child(&form)?;
+    container.append_child(&results)?;
+
+    let on_scan = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        let Some(start) = parse_address(&start.value()) else {
+            let _ = set_status("Invalid scan start address", true);
+            return;
+        };
+        let count = count.value().parse::<u16>().unwrap_or(0);
+        let word_len = word_len.value().parse::<u16>().unwrap_or(0);
+        if count == 0 || !(1..=8).contains(&word_len) {
+            let _ = set_status("Scan count must be at least 1, word bytes 1 to 8", true);
+            return;
+        }
+        if count as usize * word_len as usize > MAX_SCAN_LEN {
+            let _ = set_status(&format!("Scan is limited to {} bytes", MAX_SCAN_LEN), true);
+            return;
+        }
+
+        wasm_bindgen_futures::spawn_local(async move {
+            let _ = set_status(
+                &format!("Scanning {} addresses from 0x{:04X}...", count, start),
+                false,
+            );
+            match read_range(start, count, word_len).await {
+                Ok(data) => {
+                    let cells = scan_cells(start, word_len, &data);
+                    let changed = LAST_SCAN.with(|last| changed_addresses(&last.borrow(), &cells));
+                    info!(
+                        "Scanned {} addresses, {} changed",
+                        cells.len(),
+                        changed.len()
+                    );
+
+                    if let Err(e) = render_scan(&cells, &changed) {
+                        let _ = set_status(&format!("Failed to show scan: {:?}", e), true);
+                    }
+                    let _ = set_status(
+                        &format!(
+                            "Scanned 0x{:04X}-0x{:04X}, {} changed",
+                            start,
+                            start.wrapping_add(count - 1),
+                            changed.len()
+                        ),
+                        false,
+                    );
+                    LAST_SCAN.with(|last| *last.borrow_mut() = cells);
+                }
+                Err(e) => {
+                    let error_msg = e.as_string().unwrap_or_else(|| "Unknown error".to_string());
+                    let _ = set_status(&format!("Scan failed: {}", error_msg), true);
+                }
+            }
+        });
+    }) as Box<dyn FnMut(_)>);
+    scan_button.set_onclick(Some(on_scan.as_ref().unchecked_ref()));
+    on_scan.forget();
+
+    let on_csv = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        export_last_scan("scan.csv", "text/csv", scan_to_csv);
+    }) as Box<dyn FnMut(_)>);
+    csv_button.set_onclick(Some(on_csv.as_ref().unchecked_ref()));
+    on_csv.forget();
+
+    let on_json = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        export_last_scan("scan.json", "application/json", scan_to_json);
+    }) as Box<dyn FnMut(_)>);
+    json_button.set_onclick(Some(on_json.as_ref().unchecked_ref()));
+    on_json.forget();
+
+    Ok(())
+}
+
+/// Mostra la scansione come tabella indirizzo/hex/decimale
+fn render_scan(cells: &[ScanCell], changed: &HashSet<u16>) -> Result<(), JsValue> {
+    let document = get_document()?;
+    let Some(results) = document.get_element_by_id("scanResults") else {
+        return Ok(());
+    };
+
+    let table = document.create_element("table")?;
+    table.set_class_name("dsp-control__scan-table");
+
+    let header = document.create_element("tr")?;
+    for text in ["Address", "Hex", "Decimal"] {
+        let th = document.create_element("th")?;
+        th.set_attribute("scope", "col")?;
+        th.set_text_content(Some(text));
+        header.append_child(&th)?;
+    }
+    table.append_child(&header)?;
+
+    for cell in cells {
+        let row = document.create_element("tr")?;
+        let mut class = String::from("dsp-control__scan-row");
+        if !cell.is_zero() {
+            class.push_str(" dsp-control__scan-row--nonzero");
+        }
+        if changed.contains(&cell.address) {
+            class.push_str(" dsp-control__scan-row--changed");
+        }
+        row.set_class_name(&class);
+
+        for text in [
+            format!("0x{:04X}", cell.address),
+            format_hex_bytes(&cell.bytes),
+            cell.value().to_string(),
+        ] {
+            let td = document.create_element("td")?;
+            td.set_text_content(Some(&text));
+            row.append_child(&td)?;
+        }
+        table.append_child(&row)?;
+    }
+
+    results.set_inner_html("");
+    results.append_child(&table)?;
+    Ok(())
+}
+
+/// Scarica l'ultima scansione nel formato dato
+fn export_last_scan(filename: &str, mime: &str, format: fn(&[ScanCell]) -> String) {
+    let content = LAST_SCAN.with(|last| {
+        let last = last.borrow();
+        (!last.is_empty()).then(|| format(&last))
+    });
+
+    let Some(content) = content else {
+        let _ = set_status("Nothing to export, run a scan first", true);
+        return;
+    };
+    if let Err(e) = download(filename, mime, &content) {
+        let _ = set_status(&format!("Export failed: {:?}", e), true);
+    }
+}
+
+/// Fa scaricare `content` al browser come file
+fn download(filename: &str, mime: &str, content: &str) -> Result<(), JsValue> {
+    let document = get_document()?;
+
+    let options = BlobPropertyBag::new();
+    options.set_type(mime);
+    let blob = Blob::new_with_str_sequence_and_options(
+        &js_sys::Array::of1(&JsValue::from_str(content)),
+        &options,
+    )?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+
+    let anchor = document
+        .create_element("a")?
+        .dyn_into::<web_sys::HtmlAnchorElement>()?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+
+    Url::revoke_object_url(&url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_cells_and_changes() {
+        let previous = scan_cells(0x0010, 4, &[0, 0, 0, 1, 0, 0, 0, 0]);
+        let current = scan_cells(0x0010, 4, &[0, 0, 0, 1, 0xff, 0xff, 0xff, 0xfe, 0, 0, 0, 0]);
+
+        assert_eq!(current.len(), 3);
+        assert_eq!(current[1].address, 0x0011);
+        assert_eq!(current[1].value(), -2);
+        assert!(current[2].is_zero());
+
+        // 0x0012 non c'era nella scansione precedente
+        assert_eq!(
+            changed_addresses(&previous, &current),
+            HashSet::from([0x0011])
+        );
+    }
+
+    #[test]
+    fn test_scan_export() {
+        let cells = scan_cells(0x00ff, 2, &[0x00, 0x08, 0xff, 0xfe]);
+
+        assert_eq!(
+            scan_to_csv(&cells),
+            "address,hex,decimal\n0x00FF,00 08,8\n0x0100,FF FE,-2\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&scan_to_json(&cells)).unwrap();
+        assert_eq!(json[1]["addr"], "0x0100");
+        assert_eq!(json[1]["value"], -2);
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("0x00F0"), Some(0xf0));
+        assert_eq!(parse_address(" 240 "), Some(240));
+        assert_eq!(parse_address("0x10000"), None);
+        assert_eq!(parse_address("abc"), None);
+    }
+}
@@ -0,0 +1,322 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use crate::{get_dsp_registers, DspRegister};
+
+/// Ultimo stato noto di un registro
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RegisterState {
+    /// Ultimo valore letto o scritto, nell'unità del registro
+    pub value: Option<f64>,
+    /// Istante (Date::now) dell'ultima lettura riuscita
+    pub read_at: Option<f64>,
+    /// Istante (Date::now) dell'ultima scrittura riuscita
+    pub written_at: Option<f64>,
+}
+
+/// Lettura mostrata da un indicatore di livello, con isteresi e picco
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LevelMeter {
+    /// Valore mostrato, cambia solo oltre la deadband
+    pub shown: Option<f64>,
+    /// Picco mostrato, scende più lentamente del valore
+    pub peak: f64,
+    /// Istante (Date::now) in cui è stato preso il picco
+    pub peak_at: f64,
+}
+
+impl LevelMeter {
+    /// Nuova lettura all'istante `now`, true se il valore mostrato va aggiornato
+    ///
+    /// The peak is held for `hold_ms`, then falls by `decay` times `range`
+    /// per second, never below the current value.
+    pub fn update(
+        &mut self,
+        value: f64,
+        now: f64,
+        deadband: f64,
+        hold_ms: f64,
+        decay: f64,
+        range: f64,
+    ) -> bool {
+        let first = self.shown.is_none();
+        let moved = match self.shown {
+            Some(shown) => (value - shown).abs() > deadband,
+            None => true,
+        };
+        if moved {
+            self.shown = Some(value);
+        }
+
+        let held = now - self.peak_at;
+        let decayed = if held > hold_ms {
+            self.peak - (held - hold_ms) / 1000.0 * decay * range
+        } else {
+            self.peak
+        };
+        if first || value >= decayed {
+            self.peak = value;
+            self.peak_at = now;
+        } else if held > hold_ms {
+            // il decadimento riparte da qui alla prossima lettura
+            self.peak = decayed;
+            self.peak_at = now - hold_ms;
+        }
+        moved
+    }
+}
+
+/// Minimo e RMS delle ultime letture di un indicatore in dB
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelStats {
+    samples: VecDeque<f64>,
+}
+
+impl LevelStats {
+    /// Aggiunge una lettura e tiene le ultime `window`
+    ///
+    /// Readings below `floor`, like the `-inf` of silence, count as `floor`
+    /// so a quiet moment doesn't turn the RMS into `-inf` too.
+    pub fn push(&mut self, value: f64, floor: f64, window: usize) {
+        let value = if value.is_nan() {
+            floor
+        } else {
+            value.max(floor)
+        };
+        self.samples.push_back(value);
+        while self.samples.len() > window.max(1) {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.samples.iter().copied().reduce(f64::min)
+    }
+
+    /// RMS delle ampiezze, in dB
+    pub fn rms(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mean_square = self
+            .samples
+            .iter()
+            .map(|db| 10f64.powf(db / 10.0))
+            .sum::<f64>()
+            / self.samples.len() as f64;
+        Some(10.0 * mean_square.log10())
+    }
+}
+
+/// Stato dell'applicazione: i registri configurati e il loro ultimo stato
+///
+/// The register list is built once, on first use, and shared as an `Rc`
+/// so the auto-refresh doesn't rebuild it at every cycle. The DOM only
+/// displays this state.
+#[derive(Default)]
+pub struct AppState {
+    registers: Rc<[DspRegister]>,
+    values: HashMap<u16, RegisterState>,
+    meters: HashMap<u16, LevelMeter>,
+    stats: HashMap<u16, LevelStats>,
+}
+
+impl AppState {
+    pub fn new(registers: Vec<DspRegister>) -> Self {
+        Self {
+            registers: registers.into(),
+            values: HashMap::new(),
+            meters: HashMap::new(),
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Registri configurati, clonare l'`Rc` non copia la lista
+    pub fn registers(&self) -> Rc<[DspRegister]> {
+        self.registers.clone()
+    }
+
+    pub fn register_state(&self, address: u16) -> RegisterState {
+        self.values.get(&address).copied().unwrap_or_default()
+    }
+
+    /// Lettura riuscita all'istante `now`, `value` è NaN per i registri Raw
+    pub fn record_read(&mut self, address: u16, value: f64, now: f64) {
+        let state = self.values.entry(address).or_default();
+        if !value.is_nan() {
+            state.value = Some(value);
+        }
+        state.read_at = Some(now);
+    }
+
+    /// Scrittura riuscita all'istante `now`, `value` è NaN per i registri Raw
+    pub fn record_write(&mut self, address: u16, value: f64, now: f64) {
+        let state = self.values.entry(address).or_default();
+        if !value.is_nan() {
+            state.value = Some(value);
+        }
+        state.written_at = Some(now);
+    }
+
+    /// True se il registro è stato scritto da meno di `window_ms`
+    ///
+    /// A refresh in that window may carry a value read before the write landed.
+    pub fn recently_written(&self, address: u16, now: f64, window_ms: f64) -> bool {
+        self.values
+            .get(&address)
+            .and_then(|state| state.written_at)
+            .is_some_and(|written_at| now - written_at < window_ms)
+    }
+
+    /// Indicatore di livello di un registro in sola lettura
+    pub fn meter(&mut self, address: u16) -> &mut LevelMeter {
+        self.meters.entry(address).or_default()
+    }
+
+    /// Statistiche di un indicatore in dB
+    pub fn level_stats(&mut self, address: u16) -> &mut LevelStats {
+        self.stats.entry(address).or_default()
+    }
+
+    /// Azzera picchi e statistiche, quando l'auto-refresh si ferma
+    pub fn reset_levels(&mut self) {
+        self.meters.clear();
+        self.stats.clear();
+    }
+
+    /// Indirizzi letti almeno una volta
+    pub fn read_addresses(&self) -> Vec<u16> {
+        self.values
+            .iter()
+            .filter(|(_, state)| state.read_at.is_some())
+            .map(|(&address, _)| address)
+            .collect()
+    }
+}
+
+thread_local! {
+    static APP_STATE: RefCell<AppState> = RefCell::new(AppState::new(get_dsp_registers()));
+}
+
+/// Legge lo stato; `f` non deve chiamare codice che lo modifica
+pub fn with_state<R>(f: impl FnOnce(&AppState) -> R) -> R {
+    APP_STATE.with(|state| f(&state.borrow()))
+}
+
+pub fn update_state<R>(f: impl FnOnce(&mut AppState) -> R) -> R {
+    APP_STATE.with(|state| f(&mut state.borrow_mut()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_state() {
+        let mut state = AppState::default();
+        assert_eq!(state.register_state(0x0043), RegisterState::default());
+
+        state.record_write(0x0043, 0.5, 500.0);
+        assert_eq!(state.register_state(0x0043).value, Some(0.5));
+        assert!(state.read_addresses().is_empty());
+
+        state.record_read(0x0043, 0.25, 1000.0);
+        // un registro Raw aggiorna solo l'istante di lettura
+        state.record_read(0x0044, f64::NAN, 2000.0);
+
+        assert_eq!(
+            state.register_state(0x0043),
+            RegisterState {
+                value: Some(0.25),
+                read_at: Some(1000.0),
+                written_at: Some(500.0)
+            }
+        );
+        assert_eq!(state.register_state(0x0044).value, None);
+        let mut read = state.read_addresses();
+        read.sort();
+        assert_eq!(read, vec![0x0043, 0x0044]);
+    }
+
+    #[test]
+    fn test_recently_written() {
+        let mut state = AppState::default();
+        assert!(!state.recently_written(0x0043, 1000.0, 300.0));
+
+        state.record_write(0x0043, 0.5, 1000.0);
+        assert_eq!(state.register_state(0x0043).value, Some(0.5));
+        assert!(state.recently_written(0x0043, 1000.0, 300.0));
+        assert!(state.recently_written(0x0043, 1299.0, 300.0));
+        assert!(!state.recently_written(0x0043, 1300.0, 300.0));
+        assert!(!state.recently_written(0x0044, 1000.0, 300.0));
+
+        // una lettura non cancella la scrittura
+        state.record_read(0x0043, 0.25, 1100.0);
+        assert!(state.recently_written(0x0043, 1200.0, 300.0));
+
+        // un registro Raw registra solo l'istante
+        state.record_write(0x0044, f64::NAN, 1000.0);
+        assert_eq!(state.register_state(0x0044).value, None);
+        assert!(state.recently_written(0x0044, 1000.0, 300.0));
+    }
+
+    #[test]
+    fn test_level_meter() {
+        let mut meter = LevelMeter::default();
+        // la prima lettura si mostra sempre
+        assert!(meter.update(-20.0, 0.0, 0.5, 1000.0, 0.25, 96.0));
+        assert_eq!(meter.peak, -20.0);
+
+        // dentro la deadband il valore mostrato resta fermo
+        assert!(!meter.update(-20.4, 100.0, 0.5, 1000.0, 0.25, 96.0));
+        assert_eq!(meter.shown, Some(-20.0));
+        assert!(meter.update(-21.0, 200.0, 0.5, 1000.0, 0.25, 96.0));
+        assert_eq!(meter.shown, Some(-21.0));
+
+        // il picco resta per il tempo di hold...
+        meter.update(-10.0, 300.0, 0.5, 1000.0, 0.25, 96.0);
+        meter.update(-40.0, 1200.0, 0.5, 1000.0, 0.25, 96.0);
+        assert_eq!(meter.shown, Some(-40.0));
+        assert_eq!(meter.peak, -10.0);
+
+        // ...poi scende di un quarto della scala al secondo
+        meter.update(-40.0, 2300.0, 0.5, 1000.0, 0.25, 96.0);
+        assert_eq!(meter.peak, -10.0 - 24.0);
+        // senza mai scendere sotto il valore
+        meter.update(-40.0, 4300.0, 0.5, 1000.0, 0.25, 96.0);
+        assert_eq!(meter.peak, -40.0);
+    }
+
+    #[test]
+    fn test_level_stats() {
+        let mut stats = LevelStats::default();
+        assert_eq!(stats.rms(), None);
+
+        stats.push(-20.0, -96.0, 3);
+        stats.push(-20.0, -96.0, 3);
+        assert_eq!(stats.min(), Some(-20.0));
+        assert!((stats.rms().unwrap() + 20.0).abs() < 1e-9);
+
+        // il silenzio conta come il fondo scala, non come -inf
+        stats.push(f64::NEG_INFINITY, -96.0, 3);
+        assert_eq!(stats.min(), Some(-96.0));
+        let rms = stats.rms().unwrap();
+        assert!(rms.is_finite() && rms < -20.0 && rms > -22.0);
+
+        // restano solo le ultime letture
+        stats.push(-10.0, -96.0, 3);
+        stats.push(-10.0, -96.0, 3);
+        stats.push(f64::NAN, -96.0, 3);
+        assert_eq!(stats.min(), Some(-96.0));
+        stats.push(-10.0, -96.0, 1);
+        assert_eq!(stats.min(), Some(-10.0));
+
+        let mut state = AppState::default();
+        state.level_stats(61).push(-10.0, -96.0, 3);
+        state.meter(61).update(-10.0, 0.0, 0.0, 0.0, 0.25, 96.0);
+        state.reset_levels();
+        assert_eq!(state.level_stats(61).min(), None);
+        assert_eq!(*state.meter(61), LevelMeter::default());
+    }
+}